@@ -0,0 +1,8 @@
+-- Columns of the `run` table used by the server since the run priority and run duration changes.
+-- Manually triggered runs get `priority`=1 (see `toolflow run <RUN_ID>`), scheduled ones 0.
+ALTER TABLE `run`
+    ADD COLUMN `priority` TINYINT UNSIGNED NOT NULL DEFAULT 0,
+    ADD COLUMN `ts_started` DATETIME NULL DEFAULT NULL,
+    ADD COLUMN `ts_ended` DATETIME NULL DEFAULT NULL,
+    ADD COLUMN `ts_last_success` DATETIME NULL DEFAULT NULL,
+    ADD INDEX `status_priority` (`status`, `priority`, `id`);
@@ -1,19 +1,25 @@
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
 use serde_json::{json, Value};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
 use std::{
     fs::File,
     io::{Seek, Write},
 };
 use tempfile::*;
-use url::Url;
+use url::{Host, Url};
 
 use crate::app::App;
 use crate::data_cell::DataCell;
 use crate::data_file::{DataFile, DataFileDetails};
-use crate::mapping::{HeaderMapping, SourceId};
+use crate::data_header::ColumnHeaderType;
+use crate::mapping::{ColumnMapping, HeaderMapping, SourceId};
 use crate::wiki_page::WikiPage;
+use crate::wiki_table::WikiTable;
+use crate::APP;
 
 /*
 To add a new adapter struct:
@@ -44,6 +50,39 @@ Candidate tools: (see also https://tool-watch.toolforge.org/ )
 - https://item-quality-evaluator.toolforge.org (to add scores)
 */
 
+// Every row gets exactly one cell per header column; unmatched columns become Blank
+fn write_adapter_row<T: Into<Option<DataCell>>>(
+    file: &mut DataFile,
+    mapping: &HeaderMapping,
+    row: Vec<T>,
+) -> Result<()> {
+    let mut row: Vec<DataCell> = row
+        .into_iter()
+        .take(mapping.data.len())
+        .map(|cell| cell.into().unwrap_or(DataCell::Blank))
+        .collect();
+    row.resize(mapping.data.len(), DataCell::Blank);
+    file.write_json_row(&json! {row})
+}
+
+// Detected before any output file is written, so a run fails fast with a clear cause
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdapterError {
+    BadParameters(String), // Invalid before even asking the source
+    NoResults(String),     // The source reported an error, or has nothing for these parameters
+}
+
+impl std::fmt::Display for AdapterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AdapterError::BadParameters(reason) => write!(f, "Bad parameters: {reason}"),
+            AdapterError::NoResults(reason) => write!(f, "No results: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for AdapterError {}
+
 #[async_trait]
 pub trait Adapter {
     async fn source2file(
@@ -53,32 +92,253 @@ pub trait Adapter {
     ) -> Result<DataFileDetails>;
 }
 
+const QUARRY_URL: &str = "https://quarry.wmcloud.org";
+const QUARRY_POLL_INTERVAL_SECONDS: u64 = 5;
+// Status checks before a Quarry run is given up, about an hour at the default interval
+const QUARRY_MAX_POLLS: usize = 720;
+
+// Maximum number of VALUES entries per SPARQL query; larger lists are run in batches
+const SPARQL_VALUES_BATCH_SIZE: usize = 500;
+
+const COMMONS_SPARQL_ENDPOINT: &str = "https://commons-query.wikimedia.org/sparql";
+
+// Redirects followed for user-supplied download URLs
+const HTTP_MAX_REDIRECTS: usize = 5;
+
+// Query services selectable by name on the Sparql node; the first one is the default
+const SPARQL_ENDPOINTS: &[(&str, &str)] = &[
+    ("wikidata", "https://query.wikidata.org/sparql"),
+    ("wikidata_main", "https://query-main.wikidata.org/sparql"),
+    (
+        "wikidata_scholarly",
+        "https://query-scholarly.wikidata.org/sparql",
+    ),
+    ("commons", COMMONS_SPARQL_ENDPOINT),
+];
+
+lazy_static! {
+    static ref RE_SPARQL_VALUES_PLACEHOLDER: Regex =
+        Regex::new(r"\{\{values:(\?[A-Za-z0-9_]+)\}\}").expect("Regex error");
+    static ref RE_QID: Regex = Regex::new(r"^Q\d+$").expect("Regex error");
+    static ref RE_WIKI_DB: Regex = Regex::new(r"^[a-z0-9_]+$").expect("Regex error");
+    static ref RE_WIKIDATA_ENTITY_ID: Regex = Regex::new(r"^[QPLM]\d+$").expect("Regex error");
+}
+
 #[derive(Debug, Default)]
-pub struct SparqlAdapter {}
+pub struct SparqlAdapter {
+    pub values: Vec<String>, // SPARQL terms to replace a {{values:?var}} placeholder with
+    pub endpoint: String,    // Query service URL; empty for the Wikidata default
+}
 
 impl SparqlAdapter {
+    /// Resolves a preset name (see SPARQL_ENDPOINTS) or a literal https URL to an endpoint URL.
+    pub fn endpoint_url(name: &str) -> Result<String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Ok(SPARQL_ENDPOINTS[0].1.to_string());
+        }
+        if let Some((_, url)) = SPARQL_ENDPOINTS.iter().find(|(preset, _)| *preset == name) {
+            return Ok(url.to_string());
+        }
+        match Url::parse(name) {
+            Ok(url) if url.scheme() == "https" => Ok(url.to_string()),
+            _ => {
+                let presets: Vec<&str> =
+                    SPARQL_ENDPOINTS.iter().map(|(preset, _)| *preset).collect();
+                Err(anyhow!(AdapterError::BadParameters(format!(
+                    "Unknown SPARQL endpoint '{name}', use one of {} or an https URL",
+                    presets.join(", ")
+                ))))
+            }
+        }
+    }
+
+    /// The query is percent-encoded, so comments, `&`, `+` etc. reach the server intact.
+    fn sparql_url(&self, sparql: &str) -> Result<Url> {
+        let endpoint = Self::endpoint_url(&self.endpoint)?;
+        Ok(Url::parse_with_params(&endpoint, &[("query", sparql)])?)
+    }
+
     /// Queries SPARQL and returns a filename with the result as CSV.
     pub async fn load_sparql_csv(&self, sparql: &str) -> Result<csv::Reader<File>> {
-        let url = format!("https://query.wikidata.org/sparql?query={}", sparql);
-        let mut f = tempfile()?;
-        let mut res = App::reqwest_client()?
-            .get(url)
-            .header(
-                reqwest::header::ACCEPT,
-                reqwest::header::HeaderValue::from_str("text/csv")?,
-            )
-            .send()
-            .await?;
-        while let Some(chunk) = res.chunk().await? {
-            f.write_all(chunk.as_ref())?;
+        let url = self.sparql_url(sparql)?;
+        let res = App::send_with_retry(App::reqwest_client()?.get(url).header(
+            reqwest::header::ACCEPT,
+            reqwest::header::HeaderValue::from_str("text/csv")?,
+        ))
+        .await?;
+        csv_reader(res).await
+    }
+
+    /// Reads the values of a column as SPARQL terms, for use in a VALUES block.
+    pub fn values_from_file(&self, uuid: &str, key: &str) -> Result<Vec<String>> {
+        let is_commons = self.is_commons()?;
+        let mut df = DataFile::default();
+        df.open_input_file(uuid)?;
+        df.load_header()?;
+        let col_num = df
+            .header()
+            .get_col_num(key)
+            .ok_or_else(|| anyhow!("File {uuid} does not have a header column {key}"))?;
+        let mut ret = vec![];
+        let mut seen = HashSet::new();
+        while let Some(row) = df.read_row() {
+            let row: Vec<DataCell> = serde_json::from_str(&row)?;
+            let term = match row.get(col_num) {
+                Some(cell) => Self::cell_to_sparql_term(cell, is_commons),
+                None => None,
+            };
+            if let Some(term) = term {
+                if seen.insert(term.to_owned()) {
+                    ret.push(term);
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    pub fn sparql_string_literal(s: &str) -> String {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+
+    // Only the Commons query service knows the sdc: prefix
+    fn is_commons(&self) -> Result<bool> {
+        Ok(Self::endpoint_url(&self.endpoint)? == COMMONS_SPARQL_ENDPOINT)
+    }
+
+    // MediaInfo entities (Commons M-ids) live in the sdc: namespace, everything else in wd:
+    fn entity_term(id: &str, is_commons: bool) -> String {
+        match id.starts_with('M') {
+            true if is_commons => format!("sdc:{id}"),
+            true => format!("<https://commons.wikimedia.org/entity/{id}>"),
+            false => format!("wd:{id}"),
+        }
+    }
+
+    fn cell_to_sparql_term(cell: &DataCell, is_commons: bool) -> Option<String> {
+        match cell {
+            DataCell::WikiPage(wp) => {
+                let title = wp.prefixed_title.as_ref().or(wp.title.as_ref())?;
+                // Strip namespace prefixes like "Property:P31"
+                let id = title.rsplit(':').next()?;
+                if RE_WIKIDATA_ENTITY_ID.is_match(id) {
+                    Some(Self::entity_term(id, is_commons))
+                } else {
+                    None
+                }
+            }
+            DataCell::PlainText(s) => {
+                if RE_WIKIDATA_ENTITY_ID.is_match(s) {
+                    Some(Self::entity_term(s, is_commons))
+                } else {
+                    Some(Self::sparql_string_literal(s))
+                }
+            }
+            DataCell::Int(i) => Some(format!("{i}")),
+            DataCell::Float(f) => Some(format!("{f}")),
+            DataCell::Coordinate { lat, lon } => {
+                Some(format!("\"Point({lon} {lat})\"^^geo:wktLiteral"))
+            }
+            DataCell::Boolean(b) => Some(format!("{b}")),
+            DataCell::Date(d) => Some(format!("\"{d}\"^^xsd:date")),
+            DataCell::WikidataTime { time, .. } => Some(format!(
+                "\"{}\"^^xsd:dateTime",
+                time.trim_start_matches('+')
+            )),
+            DataCell::Blank => None,
+        }
+    }
+
+    /// Replaces {{values:?var}} placeholders with VALUES blocks, one query per batch of values.
+    fn expand_values_placeholder(&self, sparql: &str) -> Result<Vec<String>> {
+        if !RE_SPARQL_VALUES_PLACEHOLDER.is_match(sparql) {
+            if !self.values.is_empty() {
+                return Err(anyhow!(
+                    "SPARQL has input values but no {{{{values:?var}}}} placeholder"
+                ));
+            }
+            return Ok(vec![sparql.to_string()]);
+        }
+        if self.values.is_empty() {
+            return Err(anyhow!(
+                "SPARQL has a {{{{values:?var}}}} placeholder but no input values"
+            ));
+        }
+        Ok(self
+            .values
+            .chunks(SPARQL_VALUES_BATCH_SIZE)
+            .map(|chunk| {
+                let values = chunk.join(" ");
+                RE_SPARQL_VALUES_PLACEHOLDER
+                    .replace_all(sparql, |caps: &Captures| {
+                        format!("VALUES {} {{ {values} }}", &caps[1])
+                    })
+                    .to_string()
+            })
+            .collect())
+    }
+}
+
+// Streams a response body into a temporary file, and reads it as CSV with a header row
+async fn csv_reader(mut res: reqwest::Response) -> Result<csv::Reader<File>> {
+    let mut f = tempfile()?;
+    while let Some(chunk) = res.chunk().await? {
+        f.write_all(chunk.as_ref())?;
+    }
+    f.seek(std::io::SeekFrom::Start(0))?;
+    Ok(csv::ReaderBuilder::new()
+        .flexible(true)
+        .has_headers(true)
+        .delimiter(b',')
+        .from_reader(f))
+}
+
+// Maps CSV columns to the header by their label in the CSV header row
+async fn write_csv_rows(
+    reader: &mut csv::Reader<File>,
+    mapping: &HeaderMapping,
+    file: &mut DataFile,
+    parse_numbers: bool,
+) -> Result<()> {
+    let labels: Vec<String> = reader.headers()?.iter().map(|s| s.to_string()).collect();
+    let label2col_num: HashMap<String, usize> = labels
+        .into_iter()
+        .enumerate()
+        .map(|(colnum, header)| (header, colnum))
+        .collect();
+
+    for result in reader.records() {
+        let row = match result {
+            Ok(row) => row,
+            Err(_) => continue, // Ignore row
+        };
+
+        let mut jsonl_row = vec![];
+        for cm in &mapping.data {
+            if let Some((source_label, element_name)) = cm.mapping.first() {
+                if let Some(col_num) = label2col_num.get(source_label) {
+                    if let Some(text) = row.get(*col_num) {
+                        // Plain CSV has no types, so numbers arrive as text
+                        let j = match &cm.header.kind {
+                            ColumnHeaderType::Int if parse_numbers => {
+                                json!(text.trim().parse::<i64>().ok())
+                            }
+                            ColumnHeaderType::Float if parse_numbers => {
+                                json!(text.trim().parse::<f64>().ok())
+                            }
+                            _ => json!(text),
+                        };
+                        let dc = DataCell::from_value(&j, &cm.header, element_name).await;
+                        jsonl_row.push(dc);
+                        continue;
+                    }
+                }
+            }
+            jsonl_row.push(None);
         }
-        f.seek(std::io::SeekFrom::Start(0))?;
-        Ok(csv::ReaderBuilder::new()
-            .flexible(true)
-            .has_headers(true)
-            .delimiter(b',')
-            .from_reader(f))
+        write_adapter_row(file, mapping, jsonl_row)?; // Output data row
     }
+    Ok(())
 }
 
 #[async_trait]
@@ -92,65 +352,217 @@ impl Adapter for SparqlAdapter {
             SourceId::Sparql(sparql) => sparql,
             _ => return Err(anyhow!("Unsuitable source type for SPARQL: {source:?}")),
         };
-        let mut reader = self.load_sparql_csv(&sparql).await?;
-        let labels: Vec<String> = reader.headers()?.iter().map(|s| s.to_string()).collect();
-        let label2col_num: HashMap<String, usize> = labels
-            .into_iter()
-            .enumerate()
-            .map(|(colnum, header)| (header, colnum))
-            .collect();
+        if sparql.trim().is_empty() {
+            return Err(anyhow!(AdapterError::BadParameters(
+                "SPARQL query is empty".to_string()
+            )));
+        }
+        Self::endpoint_url(&self.endpoint)?;
+        let queries = self.expand_values_placeholder(sparql)?;
 
         let mut file = DataFile::new_output_file()?;
         file.write_json_row(&json! {mapping.as_data_header()})?; // Output new header
+        for query in queries {
+            let mut reader = self.load_sparql_csv(&query).await?;
+            write_csv_rows(&mut reader, mapping, &mut file, false).await?;
+        }
+        file.finish()
+    }
+}
 
-        for result in reader.records() {
-            let row = match result {
-                Ok(row) => row,
-                Err(_) => continue, // Ignore row
-            };
+// User-supplied download URLs must be http(s)
+fn http_url(url: &str) -> Result<Url> {
+    match Url::parse(url.trim()) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(url),
+        _ => Err(anyhow!(AdapterError::BadParameters(format!(
+            "'{url}' is not an http(s) URL"
+        )))),
+    }
+}
 
-            let mut jsonl_row = vec![];
-            for cm in &mapping.data {
-                if let Some((source_label, element_name)) = cm.mapping.get(0) {
-                    if let Some(col_num) = label2col_num.get(source_label) {
-                        if let Some(text) = row.get(*col_num) {
-                            let j = json!(text);
-                            let dc = DataCell::from_value(&j, &cm.header, &element_name).await;
-                            jsonl_row.push(dc);
-                            continue;
-                        }
-                    }
+// Loopback, private, link-local and other addresses that are not reachable from the internet
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || a == 0
+                || (a == 100 && (b & 0xc0) == 64)) // Carrier-grade NAT
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || (first & 0xfe00) == 0xfc00 // Unique local
+                    || (first & 0xffc0) == 0xfe80) // Link-local
+            }
+        },
+    }
+}
+
+// The addresses to connect to, so user-supplied URLs can not reach internal services
+async fn public_addr(url: &Url) -> Result<SocketAddr> {
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addrs: Vec<SocketAddr> = match url.host() {
+        Some(Host::Ipv4(ip)) => vec![SocketAddr::new(IpAddr::V4(ip), port)],
+        Some(Host::Ipv6(ip)) => vec![SocketAddr::new(IpAddr::V6(ip), port)],
+        Some(Host::Domain(domain)) => tokio::net::lookup_host((domain, port)).await?.collect(),
+        None => vec![],
+    };
+    match addrs.first() {
+        Some(addr) if addrs.iter().all(|addr| is_public_ip(addr.ip())) => Ok(*addr),
+        _ => Err(anyhow!(AdapterError::BadParameters(format!(
+            "'{url}' does not resolve to a public address"
+        )))),
+    }
+}
+
+// Follows redirects by hand, checking every hop; connections go to the checked address
+async fn get_public_url(url: &str, allow_private_hosts: bool) -> Result<reqwest::Response> {
+    let mut url = http_url(url)?;
+    for _ in 0..=HTTP_MAX_REDIRECTS {
+        let mut builder = App::reqwest_client_builder().redirect(reqwest::redirect::Policy::none());
+        if !allow_private_hosts {
+            let addr = public_addr(&url).await?;
+            if let Some(Host::Domain(domain)) = url.host() {
+                builder = builder.resolve(domain, addr);
+            }
+        }
+        let res = App::send_with_retry(builder.build()?.get(url.as_str())).await?;
+        let location = match res.headers().get(reqwest::header::LOCATION) {
+            Some(location) if res.status().is_redirection() => location.to_str()?.to_string(),
+            _ => return Ok(res),
+        };
+        url = http_url(url.join(&location)?.as_str())?;
+    }
+    Err(anyhow!(AdapterError::NoResults(format!(
+        "{url} redirected more than {HTTP_MAX_REDIRECTS} times"
+    ))))
+}
+
+// A dotted path into nested objects, like "metadata.defaultsort"; empty for the value itself
+fn value_at_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .filter(|key| !key.is_empty())
+        .try_fold(value, |value, key| value.as_object()?.get(key))
+}
+
+// One row per JSON object, with `source_label`s as paths into it; other records are skipped
+async fn json_records2file(records: &[Value], mapping: &HeaderMapping) -> Result<DataFileDetails> {
+    let mut file = DataFile::new_output_file()?;
+    file.write_json_row(&json! {mapping.as_data_header()})?; // Output new header
+    for record in records {
+        if !record.is_object() {
+            continue; // Skip row
+        }
+        let mut jsonl_row = vec![];
+        for cm in &mapping.data {
+            if let Some((source_label, element_name)) = cm.mapping.first() {
+                if let Some(value) = value_at_path(record, source_label) {
+                    let dc = DataCell::from_value(value, &cm.header, element_name).await;
+                    jsonl_row.push(dc);
+                    continue;
                 }
-                jsonl_row.push(None);
             }
-            file.write_json_row(&json! {jsonl_row})?; // Output data row
+            jsonl_row.push(None);
         }
-        Ok(file.details())
+        write_adapter_row(&mut file, mapping, jsonl_row)?; // Output data row
     }
+    file.finish()
 }
 
-// Latest result for a given query ID
+// Any CSV download with a header row
 #[derive(Debug, Default)]
-pub struct QuarryQueryAdapter {}
+pub struct CsvUrlAdapter {
+    allow_private_hosts: bool, // Only for tests against a local server
+}
 
 #[async_trait]
-impl Adapter for QuarryQueryAdapter {
+impl Adapter for CsvUrlAdapter {
     async fn source2file(
         &mut self,
         source: &SourceId,
         mapping: &HeaderMapping,
     ) -> Result<DataFileDetails> {
         let url = match source {
-            SourceId::QuarryQueryLatest(id) => {
-                format!("https://quarry.wmcloud.org/query/{id}/result/latest/0/json")
-            }
-            _ => {
-                return Err(anyhow!(
-                    "Unsuitable source type for Quarry query: {source:?}"
-                ))
-            }
+            SourceId::CsvUrl(url) => url,
+            _ => return Err(anyhow!("Unsuitable source type for CSV URL: {source:?}")),
+        };
+        let res = get_public_url(url, self.allow_private_hosts).await?;
+        if !res.status().is_success() {
+            return Err(anyhow!(AdapterError::NoResults(format!(
+                "{url} returned HTTP {}",
+                res.status()
+            ))));
+        }
+        let mut reader = csv_reader(res).await?;
+        let mut file = DataFile::new_output_file()?;
+        file.write_json_row(&json! {mapping.as_data_header()})?; // Output new header
+        write_csv_rows(&mut reader, mapping, &mut file, true).await?;
+        file.finish()
+    }
+}
+
+// Records of any JSON API; `rows_path` is the dotted path to the array of records
+#[derive(Debug, Default)]
+pub struct JsonUrlAdapter {
+    allow_private_hosts: bool, // Only for tests against a local server
+}
+
+impl JsonUrlAdapter {
+    // No array at `rows_path` means no records
+    fn records<'a>(j: &'a Value, rows_path: &str) -> &'a [Value] {
+        value_at_path(j, rows_path)
+            .and_then(|rows| rows.as_array())
+            .map_or(&[], |rows| rows.as_slice())
+    }
+}
+
+#[async_trait]
+impl Adapter for JsonUrlAdapter {
+    async fn source2file(
+        &mut self,
+        source: &SourceId,
+        mapping: &HeaderMapping,
+    ) -> Result<DataFileDetails> {
+        let (url, rows_path) = match source {
+            SourceId::JsonUrl { url, rows_path } => (url, rows_path),
+            _ => return Err(anyhow!("Unsuitable source type for JSON URL: {source:?}")),
         };
-        let j: Value = App::reqwest_client()?.get(url).send().await?.json().await?;
+        let res = get_public_url(url, self.allow_private_hosts).await?;
+        if !res.status().is_success() {
+            return Err(anyhow!(AdapterError::NoResults(format!(
+                "{url} returned HTTP {}",
+                res.status()
+            ))));
+        }
+        let j: Value = res.json().await?;
+        json_records2file(Self::records(&j, rows_path), mapping).await
+    }
+}
+
+// Latest result for a given query ID
+#[derive(Debug, Default)]
+pub struct QuarryQueryAdapter {}
+
+impl QuarryQueryAdapter {
+    // A Quarry result set: {"headers": [...], "rows": [[...], ...]}
+    async fn result2file(
+        j: &Value,
+        mapping: &HeaderMapping,
+        what: &str,
+    ) -> Result<DataFileDetails> {
+        let rows = j["rows"]
+            .as_array()
+            .filter(|rows| !rows.is_empty())
+            .ok_or_else(|| AdapterError::NoResults(format!("{what} result has no rows")))?;
         let labels: Vec<String> = j["headers"]
             .as_array()
             .ok_or(anyhow!("JSON has no header array"))?
@@ -165,20 +577,17 @@ impl Adapter for QuarryQueryAdapter {
 
         let mut file = DataFile::new_output_file()?;
         file.write_json_row(&json! {mapping.as_data_header()})?; // Output new header
-        for row in j["rows"]
-            .as_array()
-            .ok_or(anyhow!("JSON has no rows array"))?
-        {
+        for row in rows {
             let row = match row.as_array() {
                 Some(row) => row,
                 None => continue, // Skip row
             };
             let mut jsonl_row = vec![];
             for cm in &mapping.data {
-                if let Some((source_label, element_name)) = cm.mapping.get(0) {
+                if let Some((source_label, element_name)) = cm.mapping.first() {
                     if let Some(col_num) = label2col_num.get(source_label) {
                         if let Some(value) = row.get(*col_num) {
-                            let dc = DataCell::from_value(value, &cm.header, &element_name).await;
+                            let dc = DataCell::from_value(value, &cm.header, element_name).await;
                             jsonl_row.push(dc);
                             continue;
                         }
@@ -186,9 +595,168 @@ impl Adapter for QuarryQueryAdapter {
                 }
                 jsonl_row.push(None);
             }
-            file.write_json_row(&json! {jsonl_row})?; // Output data row
+            write_adapter_row(&mut file, mapping, jsonl_row)?; // Output data row
+        }
+        file.finish()
+    }
+}
+
+#[async_trait]
+impl Adapter for QuarryQueryAdapter {
+    async fn source2file(
+        &mut self,
+        source: &SourceId,
+        mapping: &HeaderMapping,
+    ) -> Result<DataFileDetails> {
+        let id = match source {
+            SourceId::QuarryQueryLatest(id) => *id,
+            _ => {
+                return Err(anyhow!(
+                    "Unsuitable source type for Quarry query: {source:?}"
+                ))
+            }
+        };
+        if id == 0 {
+            return Err(anyhow!(AdapterError::BadParameters(
+                "Quarry query ID must be positive".to_string()
+            )));
+        }
+        let url = format!("{QUARRY_URL}/query/{id}/result/latest/0/json");
+        let response = App::send_with_retry(App::reqwest_client()?.get(url)).await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(AdapterError::NoResults(format!(
+                "Quarry query {id} has no latest result (HTTP {})",
+                response.status()
+            ))));
+        }
+        let j: Value = response.json().await?;
+        Self::result2file(&j, mapping, &format!("Quarry query {id}")).await
+    }
+}
+
+// Starts a new run of a stored query and waits for its result. Quarry only runs queries for
+// their owner, so this needs a Quarry session cookie in TOOLFLOW_QUARRY_SESSION, and only works
+// for queries owned by that one account; other queries can use QuarryQueryAdapter.
+#[derive(Debug)]
+pub struct QuarryQueryRunAdapter {
+    pub base_url: String,
+    pub session: Option<String>,
+    pub poll_interval: std::time::Duration,
+    pub max_polls: usize,
+}
+
+impl Default for QuarryQueryRunAdapter {
+    fn default() -> Self {
+        Self {
+            base_url: QUARRY_URL.to_string(),
+            session: std::env::var("TOOLFLOW_QUARRY_SESSION")
+                .ok()
+                .filter(|s| !s.trim().is_empty()),
+            poll_interval: std::time::Duration::from_secs(QUARRY_POLL_INTERVAL_SECONDS),
+            max_polls: QUARRY_MAX_POLLS,
+        }
+    }
+}
+
+impl QuarryQueryRunAdapter {
+    fn get(&self, path: &str) -> Result<reqwest::RequestBuilder> {
+        let request = App::reqwest_client()?.get(format!("{}{path}", self.base_url));
+        Ok(match &self.session {
+            Some(session) => request.header(reqwest::header::COOKIE, format!("session={session}")),
+            None => request,
+        })
+    }
+
+    async fn get_json(&self, path: &str) -> Result<Value> {
+        let response = App::send_with_retry(self.get(path)?).await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(AdapterError::NoResults(format!(
+                "Quarry {path} returned HTTP {}",
+                response.status()
+            ))));
+        }
+        Ok(response.json().await?)
+    }
+
+    // Not retried, a retry could start a second run
+    async fn start_run(&self, id: u64, sql: &str) -> Result<u64> {
+        let mut request = App::reqwest_client()?
+            .post(format!("{}/api/query/run", self.base_url))
+            .form(&[("query_id", format!("{id}")), ("text", sql.to_string())]);
+        if let Some(session) = &self.session {
+            request = request.header(reqwest::header::COOKIE, format!("session={session}"));
+        }
+        let response = request.send().await?;
+        if response.status() == reqwest::StatusCode::FORBIDDEN {
+            return Err(anyhow!(AdapterError::BadParameters(format!(
+                "Quarry query {id} is not owned by the TOOLFLOW_QUARRY_SESSION account"
+            ))));
+        }
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Quarry refused to run query {id} (HTTP {}); is TOOLFLOW_QUARRY_SESSION set?",
+                response.status()
+            ));
+        }
+        let j: Value = response.json().await?;
+        j["qrun_id"]
+            .as_u64()
+            .ok_or_else(|| anyhow!("Quarry did not return a run ID for query {id}: {j}"))
+    }
+
+    async fn wait_for_run(&self, id: u64, run_id: u64) -> Result<()> {
+        for _ in 0..self.max_polls {
+            let j = self.get_json(&format!("/run/{run_id}/status")).await?;
+            match j["status"].as_str() {
+                Some("complete") => return Ok(()),
+                Some("queued") | Some("running") => tokio::time::sleep(self.poll_interval).await,
+                Some(status) => {
+                    let error = j["extra"]["error"].as_str().unwrap_or(status);
+                    return Err(anyhow!(AdapterError::NoResults(format!(
+                        "Quarry query {id} run {run_id}: {error}"
+                    ))));
+                }
+                None => return Err(anyhow!("Quarry query {id} run {run_id} has no status: {j}")),
+            }
+        }
+        Err(anyhow!(AdapterError::NoResults(format!(
+            "Quarry query {id} run {run_id} did not finish after {} status checks",
+            self.max_polls
+        ))))
+    }
+}
+
+#[async_trait]
+impl Adapter for QuarryQueryRunAdapter {
+    async fn source2file(
+        &mut self,
+        source: &SourceId,
+        mapping: &HeaderMapping,
+    ) -> Result<DataFileDetails> {
+        let id = match source {
+            SourceId::QuarryQueryRun(id) => *id,
+            _ => {
+                return Err(anyhow!(
+                    "Unsuitable source type for Quarry query run: {source:?}"
+                ))
+            }
+        };
+        if id == 0 {
+            return Err(anyhow!(AdapterError::BadParameters(
+                "Quarry query ID must be positive".to_string()
+            )));
         }
-        Ok(file.details())
+        let meta = self.get_json(&format!("/query/{id}/meta")).await?;
+        let sql = meta["latest_rev"]["sql"]
+            .as_str()
+            .ok_or_else(|| AdapterError::NoResults(format!("Quarry query {id} has no SQL")))?;
+        let run_id = self.start_run(id, sql).await?;
+        self.wait_for_run(id, run_id).await?;
+        let j = self
+            .get_json(&format!("/run/{run_id}/output/0/json"))
+            .await?;
+        QuarryQueryAdapter::result2file(&j, mapping, &format!("Quarry query {id} run {run_id}"))
+            .await
     }
 }
 
@@ -202,43 +770,52 @@ impl Adapter for PetScanAdapter {
         source: &SourceId,
         mapping: &HeaderMapping,
     ) -> Result<DataFileDetails> {
-        let url = match source {
-            SourceId::PetScan(id) => format!("https://petscan.wmflabs.org/?psid={id}&format=json&output_compatability=quick-intersection"),
+        let id = match source {
+            SourceId::PetScan(id) => *id,
             _ => return Err(anyhow!("Unsuitable source type for PetScan: {source:?}")),
         };
-        let j: Value = App::reqwest_client()?.get(url).send().await?.json().await?;
-
-        let mut file = DataFile::new_output_file()?;
-        file.write_json_row(&json! {mapping.as_data_header()})?; // Output new header
-        for row in j["pages"]
-            .as_array()
-            .ok_or(anyhow!("JSON has no rows array"))?
-        {
-            let row = match row.as_object() {
-                Some(row) => row,
-                None => continue, // Skip row
-            };
-            let mut jsonl_row = vec![];
-            for cm in &mapping.data {
-                if let Some((source_label, element_name)) = cm.mapping.get(0) {
-                    // TODO sub-elements like metadata.defaultsort/metadata.disambiguation
-                    if let Some(value) = row.get(source_label) {
-                        let dc = DataCell::from_value(value, &cm.header, &element_name).await;
-                        jsonl_row.push(dc);
-                        continue;
-                    }
-                }
-                jsonl_row.push(None);
-            }
-            file.write_json_row(&json! {jsonl_row})?; // Output data row
+        if id == 0 {
+            return Err(anyhow!(AdapterError::BadParameters(
+                "PetScan PSID must be positive".to_string()
+            )));
+        }
+        let url = format!("https://petscan.wmflabs.org/?psid={id}&format=json&output_compatability=quick-intersection");
+        let j: Value = App::send_with_retry(App::reqwest_client()?.get(url))
+            .await?
+            .json()
+            .await?;
+        if let Some(error) = j["error"].as_str() {
+            return Err(anyhow!(AdapterError::NoResults(format!(
+                "PetScan PSID {id}: {error}"
+            ))));
         }
-        Ok(file.details())
+        let pages = j["pages"]
+            .as_array()
+            .filter(|pages| !pages.is_empty())
+            .ok_or_else(|| {
+                AdapterError::NoResults(format!("PetScan PSID {id} returned no pages"))
+            })?;
+        // Sub-elements like metadata.defaultsort are dotted source labels
+        json_records2file(pages, mapping).await
     }
 }
 
 #[derive(Debug, Default)]
 pub struct PagePileAdapter {}
 
+impl PagePileAdapter {
+    // The pile knows which wiki its pages are on, so that overrides the mapping template
+    fn mapping_with_wiki(mapping: &HeaderMapping, wiki: &str) -> HeaderMapping {
+        let mut mapping = mapping.to_owned();
+        for cm in &mut mapping.data {
+            if let ColumnHeaderType::WikiPage(wp) = &mut cm.header.kind {
+                wp.wiki = Some(wiki.to_string());
+            }
+        }
+        mapping
+    }
+}
+
 #[async_trait]
 impl Adapter for PagePileAdapter {
     async fn source2file(
@@ -246,20 +823,36 @@ impl Adapter for PagePileAdapter {
         source: &SourceId,
         mapping: &HeaderMapping,
     ) -> Result<DataFileDetails> {
-        let url = match source {
-            SourceId::PagePile(id) => format!(
-                "https://pagepile.toolforge.org/api.php?id={id}&action=get_data&doit&format=json"
-            ),
+        let id = match source {
+            SourceId::PagePile(id) => *id,
             _ => return Err(anyhow!("Unsuitable source type for PagePile: {source:?}")),
         };
-        let j: Value = App::reqwest_client()?.get(url).send().await?.json().await?;
+        if id == 0 {
+            return Err(anyhow!(AdapterError::BadParameters(
+                "PagePile ID must be positive".to_string()
+            )));
+        }
+        let url = format!(
+            "https://pagepile.toolforge.org/api.php?id={id}&action=get_data&doit&format=json"
+        );
+        let j: Value = App::send_with_retry(App::reqwest_client()?.get(url))
+            .await?
+            .json()
+            .await?;
+        let pages = j["pages"]
+            .as_array()
+            .filter(|pages| !pages.is_empty())
+            .ok_or_else(|| {
+                AdapterError::NoResults(format!("PagePile {id} does not exist or has no pages"))
+            })?;
+        let mapping = match j["wiki"].as_str() {
+            Some(wiki) => Self::mapping_with_wiki(mapping, wiki),
+            None => mapping.to_owned(),
+        };
         let mut file = DataFile::new_output_file()?;
         file.write_json_row(&json! {mapping.as_data_header()})?; // Output new header
 
-        for page in j["pages"]
-            .as_array()
-            .ok_or(anyhow!("JSON has no rows array"))?
-        {
+        for page in pages {
             let prefixed_title = match page.as_str() {
                 Some(prefixed_title) => prefixed_title,
                 None => continue, // Skip row
@@ -267,18 +860,18 @@ impl Adapter for PagePileAdapter {
 
             let mut jsonl_row = vec![];
             for cm in &mapping.data {
-                if let Some((_source_label, element_name)) = cm.mapping.get(0) {
+                if let Some((_source_label, element_name)) = cm.mapping.first() {
                     let value = json!(prefixed_title);
-                    let dc = DataCell::from_value(&value, &cm.header, &element_name).await;
+                    let dc = DataCell::from_value(&value, &cm.header, element_name).await;
                     jsonl_row.push(dc);
                     continue;
                 }
                 jsonl_row.push(None);
             }
-            file.write_json_row(&json! {jsonl_row})?; // Output data row
+            write_adapter_row(&mut file, &mapping, jsonl_row)?; // Output data row
         }
 
-        Ok(file.details())
+        file.finish()
     }
 }
 
@@ -292,22 +885,40 @@ impl Adapter for AListBuildingToolAdapter {
         source: &SourceId,
         mapping: &HeaderMapping,
     ) -> Result<DataFileDetails> {
-        let url = match source {
-            SourceId::AListBuildingTool((wiki, q)) => {
-                format!("https://a-list-bulding-tool.toolforge.org/API/?wiki_db={wiki}&QID={q}")
-            }
+        let (wiki, q) = match source {
+            SourceId::AListBuildingTool((wiki, q)) => (wiki, q),
             _ => {
                 return Err(anyhow!(
                     "Unsuitable source type for AListBuildingTool: {source:?}"
                 ))
             }
         };
-        let j: Value = App::reqwest_client()?.get(url).send().await?.json().await?;
+        if !RE_WIKI_DB.is_match(wiki) {
+            return Err(anyhow!(AdapterError::BadParameters(format!(
+                "AListBuildingTool: '{wiki}' is not a wiki"
+            ))));
+        }
+        if !RE_QID.is_match(q) {
+            return Err(anyhow!(AdapterError::BadParameters(format!(
+                "AListBuildingTool: '{q}' is not an item ID"
+            ))));
+        }
+        let url = format!("https://a-list-bulding-tool.toolforge.org/API/?wiki_db={wiki}&QID={q}");
+        let j: Value = App::send_with_retry(App::reqwest_client()?.get(url))
+            .await?
+            .json()
+            .await?;
+        let entries = j
+            .as_array()
+            .filter(|entries| !entries.is_empty())
+            .ok_or_else(|| {
+                AdapterError::NoResults(format!("AListBuildingTool has no list for {q} on {wiki}"))
+            })?;
 
         let mut file = DataFile::new_output_file()?;
         file.write_json_row(&json! {mapping.as_data_header()})?; // Output new header
 
-        for entry in j.as_array().ok_or(anyhow!("JSON is not an array"))? {
+        for entry in entries {
             let title = match entry.get("title") {
                 Some(title) => match title.as_str() {
                     Some(title) => title,
@@ -325,6 +936,7 @@ impl Adapter for AListBuildingToolAdapter {
 
             let mut jsonl_row = vec![];
             for cm in &mapping.data {
+                let mut dc = None;
                 for (source_label, element_name) in &cm.mapping {
                     let text = match source_label.as_str() {
                         "title" => title,
@@ -332,14 +944,15 @@ impl Adapter for AListBuildingToolAdapter {
                         _ => continue,
                     };
                     let j = json!(text);
-                    let dc = DataCell::from_value(&j, &cm.header, &element_name).await;
-                    jsonl_row.push(dc);
+                    dc = DataCell::from_value(&j, &cm.header, element_name).await;
+                    break;
                 }
+                jsonl_row.push(dc);
             }
-            file.write_json_row(&json! {jsonl_row})?; // Output data row
+            write_adapter_row(&mut file, mapping, jsonl_row)?; // Output data row
         }
 
-        Ok(file.details())
+        file.finish()
     }
 }
 
@@ -363,10 +976,12 @@ impl WdFistParams {
         if url.host_str() != Some("fist.toolforge.org") {
             return Err(anyhow!("Not a WD-FIST URL: {url}"));
         }
-        let mut ret = Self::default();
-        ret.wdf_langlinks = true; // TODO from params, somehow
-        ret.wdf_only_files_not_on_wd = true; // TODO from params, somehow
-        ret.wdf_max_five_results = true; // TODO from params, somehow
+        let mut ret = Self {
+            wdf_langlinks: true,            // TODO from params, somehow
+            wdf_only_files_not_on_wd: true, // TODO from params, somehow
+            wdf_max_five_results: true,     // TODO from params, somehow
+            ..Default::default()
+        };
         url.query_pairs().for_each(|(k, v)| {
             match k.as_ref() {
                 "sparql" => ret.sparql = Some(v.to_string()),
@@ -431,6 +1046,133 @@ impl WdFistParams {
 #[derive(Debug, Default)]
 pub struct WdFistAdapter {}
 
+// Source labels for the columns of a WD-FIST mapping; unmapped columns get item, image, count by position.
+// "qid" is accepted for "item"
+const WDFIST_DEFAULT_LABELS: [&str; 3] = ["item", "image", "count"];
+const WDFIST_IMAGE_INFO_LABELS: [&str; 5] = [
+    "image_size",
+    "image_width",
+    "image_height",
+    "image_mime",
+    "image_url",
+];
+const COMMONS_IMAGE_INFO_BATCH_SIZE: usize = 50;
+
+impl WdFistAdapter {
+    fn source_labels(mapping: &HeaderMapping) -> Vec<Option<String>> {
+        mapping
+            .data
+            .iter()
+            .enumerate()
+            .map(|(col_num, cm)| match cm.mapping.first() {
+                Some((source_label, _)) => Some(source_label.to_owned()),
+                None => WDFIST_DEFAULT_LABELS
+                    .get(col_num)
+                    .map(|label| label.to_string()),
+            })
+            .collect()
+    }
+
+    fn value_cell(value: &Value, kind: &ColumnHeaderType) -> Option<DataCell> {
+        match kind {
+            ColumnHeaderType::PlainText => Some(DataCell::PlainText(match value.as_str() {
+                Some(s) => s.to_string(),
+                None => value.to_string(),
+            })),
+            ColumnHeaderType::Int => Some(DataCell::Int(value.as_i64()?)),
+            ColumnHeaderType::Float => Some(DataCell::Float(value.as_f64()?)),
+            _ => None,
+        }
+    }
+
+    fn row_cells(
+        labels: &[Option<String>],
+        mapping: &HeaderMapping,
+        qid: &str,
+        image_name: &str,
+        count: i64,
+        image_info: Option<&Value>,
+    ) -> Vec<Option<DataCell>> {
+        labels
+            .iter()
+            .zip(mapping.data.iter())
+            .map(|(label, cm)| {
+                let kind = &cm.header.kind;
+                match (label.as_deref()?, kind) {
+                    ("item" | "qid", ColumnHeaderType::WikiPage(_)) => {
+                        let mut wp = WikiPage::new_wikidata_item();
+                        wp.prefixed_title = Some(qid.to_owned());
+                        Some(DataCell::WikiPage(wp))
+                    }
+                    ("image", ColumnHeaderType::WikiPage(_)) => {
+                        Some(DataCell::WikiPage(WikiPage {
+                            title: Some(image_name.to_owned()),
+                            prefixed_title: Some(format!("File:{image_name}")),
+                            ns_id: Some(6),
+                            page_id: None,
+                            ns_prefix: Some("File".to_string()),
+                            wiki: Some("commonswiki".to_string()),
+                        }))
+                    }
+                    ("item" | "qid", kind) => Self::value_cell(&json!(qid), kind),
+                    ("image", kind) => Self::value_cell(&json!(image_name), kind),
+                    ("count", kind) => Self::value_cell(&json!(count), kind),
+                    (label, kind) => {
+                        let key = label.strip_prefix("image_")?;
+                        Self::value_cell(image_info?.get(key)?, kind)
+                    }
+                }
+            })
+            .collect()
+    }
+
+    // Image name (with underscores) => imageinfo object
+    fn image_info_from_json(j: &Value) -> HashMap<String, Value> {
+        let pages = match j["query"]["pages"].as_object() {
+            Some(pages) => pages,
+            None => return HashMap::new(),
+        };
+        pages
+            .values()
+            .filter_map(|page| {
+                let title = page["title"].as_str()?;
+                let name = title
+                    .strip_prefix("File:")
+                    .unwrap_or(title)
+                    .replace(' ', "_");
+                Some((name, page["imageinfo"].get(0)?.to_owned()))
+            })
+            .collect()
+    }
+
+    async fn load_image_info(image_names: &[String]) -> Result<HashMap<String, Value>> {
+        let api = APP.get_api_for_wiki("commonswiki").await?;
+        let mut ret = HashMap::new();
+        for (batch_num, batch) in image_names
+            .chunks(COMMONS_IMAGE_INFO_BATCH_SIZE)
+            .enumerate()
+        {
+            if batch_num > 0 {
+                APP.api_delay().await;
+            }
+            let titles: Vec<String> = batch.iter().map(|name| format!("File:{name}")).collect();
+            let titles = titles.join("|");
+            let params = api.params_into(&[
+                ("action", "query"),
+                ("prop", "imageinfo"),
+                ("iiprop", "size|mime|url"),
+                ("titles", &titles),
+            ]);
+            let j = api
+                .get_query_api_json(&params)
+                .await
+                .map_err(|e| anyhow!("Could not load Commons image information: {e}"))?;
+            ret.extend(Self::image_info_from_json(&j));
+        }
+        Ok(ret)
+    }
+}
+
 #[async_trait]
 impl Adapter for WdFistAdapter {
     async fn source2file(
@@ -445,50 +1187,63 @@ impl Adapter for WdFistAdapter {
         let wdfist = WdFistParams::from_url(&url)?;
         let petscan_url = wdfist.to_petscan_url();
 
-        let j: Value = App::reqwest_client()?
-            .get(petscan_url)
-            .send()
+        let j: Value = App::send_with_retry(App::reqwest_client()?.get(petscan_url))
             .await?
             .json()
             .await?;
-
-        let mut file = DataFile::new_output_file()?;
-        file.write_json_row(&json! {mapping.as_data_header()})?; // Output new header
-
-        for (qid, images) in j["data"]
+        let data = j["data"]
             .as_object()
-            .ok_or(anyhow!("JSON is not an object"))?
-        {
-            let images = match images.as_object() {
-                Some(images) => images,
-                None => continue, // Ignore this
-            };
-            for (image_name, count) in images.iter() {
-                if let Some(count) = count.as_i64() {
-                    let mut jsonl_row = vec![];
-
-                    let mut wp = WikiPage::new_wikidata_item();
-                    wp.prefixed_title = Some(qid.to_owned());
-                    jsonl_row.push(DataCell::WikiPage(wp));
-
-                    let wp = WikiPage {
-                        title: Some(image_name.to_owned()),
-                        prefixed_title: Some(format!("File:{image_name}")),
-                        ns_id: Some(6),
-                        page_id: None,
-                        ns_prefix: Some("File".to_string()),
-                        wiki: Some("commonswiki".to_string()),
-                    };
-                    jsonl_row.push(DataCell::WikiPage(wp));
+            .filter(|data| !data.is_empty())
+            .ok_or_else(|| {
+                AdapterError::NoResults(format!(
+                    "WD-FIST: {}",
+                    j["status"].as_str().unwrap_or("no data")
+                ))
+            })?;
 
-                    jsonl_row.push(DataCell::Int(count));
+        let candidates: Vec<(&String, String, i64)> = data
+            .iter()
+            .filter_map(|(qid, images)| Some((qid, images.as_object()?)))
+            .flat_map(|(qid, images)| {
+                images.iter().filter_map(move |(image_name, count)| {
+                    Some((qid, image_name.replace(' ', "_"), count.as_i64()?))
+                })
+            })
+            .collect();
 
-                    file.write_json_row(&json! {jsonl_row})?; // Output data row
-                }
-            }
+        // Commons is only asked if a column wants image metadata
+        let labels = Self::source_labels(mapping);
+        let wants_image_info = labels
+            .iter()
+            .flatten()
+            .any(|label| WDFIST_IMAGE_INFO_LABELS.contains(&label.as_str()));
+        let image_info = if wants_image_info {
+            let mut image_names: Vec<String> = candidates
+                .iter()
+                .map(|(_, name, _)| name.to_owned())
+                .collect();
+            image_names.sort();
+            image_names.dedup();
+            Self::load_image_info(&image_names).await?
+        } else {
+            HashMap::new()
+        };
+
+        let mut file = DataFile::new_output_file()?;
+        file.write_json_row(&json! {mapping.as_data_header()})?; // Output new header
+        for (qid, image_name, count) in candidates {
+            let jsonl_row = Self::row_cells(
+                &labels,
+                mapping,
+                qid,
+                &image_name,
+                count,
+                image_info.get(&image_name),
+            );
+            write_adapter_row(&mut file, mapping, jsonl_row)?; // Output data row
         }
 
-        Ok(file.details())
+        file.finish()
     }
 }
 
@@ -556,7 +1311,9 @@ impl UserEditsParams {
 }
 
 #[derive(Debug, Default)]
-pub struct UserEditsAdapter {}
+pub struct UserEditsAdapter {
+    pub start: Option<String>, // Overrides the start timestamp of the URL
+}
 
 #[async_trait]
 impl Adapter for UserEditsAdapter {
@@ -565,66 +1322,907 @@ impl Adapter for UserEditsAdapter {
         source: &SourceId,
         mapping: &HeaderMapping,
     ) -> Result<DataFileDetails> {
-        let url = match source {
-            SourceId::UserEdits(url) => Url::parse(url)?,
+        let user_edits_url = match source {
+            SourceId::UserEdits(url) => self.user_edits_url(url)?,
             _ => return Err(anyhow!("Unsuitable source type for UserEdits: {source:?}")),
         };
-        let user_edits = UserEditsParams::from_url(&url)?;
-        let user_edits_url = user_edits.to_url();
 
-        let result: String = App::reqwest_client()?
-            .get(user_edits_url)
-            .send()
+        let result: String = App::send_with_retry(App::reqwest_client()?.get(user_edits_url))
             .await?
             .text()
             .await?;
 
         let mut file = DataFile::new_output_file()?;
         file.write_json_row(&json! {mapping.as_data_header()})?; // Output new header
+        for line in result.lines() {
+            let edit: Value = match serde_json::from_str(line) {
+                Ok(edit) => edit,
+                Err(_) => continue, // Not an edit, eg an empty line
+            };
+            let mut jsonl_row = vec![];
+            for cm in &mapping.data {
+                jsonl_row.push(Self::edit_cell(&edit, cm).await);
+            }
+            write_adapter_row(&mut file, mapping, jsonl_row)?; // Output data row
+        }
+
+        file.finish()
+    }
+}
+
+impl UserEditsAdapter {
+    fn user_edits_url(&self, url: &str) -> Result<String> {
+        let mut user_edits = UserEditsParams::from_url(&Url::parse(url)?)?;
+        if self.start.is_some() {
+            user_edits.start = self.start.to_owned();
+        }
+        if user_edits.user.trim().is_empty() {
+            return Err(anyhow!(AdapterError::BadParameters(
+                "UserEdits URL has no user".to_string()
+            )));
+        }
+        Ok(user_edits.to_url())
+    }
+
+    // Edit fields like page_title, rev_timestamp, rev_id; a WikiPage column maps page_title by default
+    async fn edit_cell(edit: &Value, cm: &ColumnMapping) -> Option<DataCell> {
+        let (source_label, element_name) = match (cm.mapping.first(), &cm.header.kind) {
+            (Some((source_label, element_name)), _) => {
+                (source_label.as_str(), element_name.as_str())
+            }
+            (None, ColumnHeaderType::WikiPage(_)) => ("page_title", "prefixed_title"),
+            (None, _) => return None,
+        };
+        let value = edit.get(source_label)?;
+        // The tool returns some numbers as strings, and vice versa
+        let value = match (&cm.header.kind, value) {
+            (ColumnHeaderType::Int, Value::String(s)) => json!(s.parse::<i64>().ok()?),
+            (ColumnHeaderType::Float, Value::String(s)) => json!(s.parse::<f64>().ok()?),
+            (ColumnHeaderType::PlainText, Value::Number(n)) => json!(n.to_string()),
+            (ColumnHeaderType::WikiPage(_), Value::Number(n)) => json!(n.to_string()),
+            (_, value) => value.to_owned(),
+        };
+        DataCell::from_value(&value, &cm.header, element_name).await
+    }
+}
+
+// Rows of the Nth wikitable on a wiki page, mapped by column header name
+#[derive(Debug, Default)]
+pub struct WikiTableAdapter {}
+
+impl WikiTableAdapter {
+    async fn load_wikitext(wiki: &str, page: &str) -> Result<String> {
+        let server = APP
+            .get_webserver_for_wiki(wiki)
+            .ok_or_else(|| anyhow!("Could not find web server for {wiki}"))?;
+        let url = format!(
+            "https://{server}/w/index.php?action=raw&title={}",
+            urlencoding::encode(&page.replace(' ', "_"))
+        );
+        let response = App::send_with_retry(App::reqwest_client()?.get(url)).await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Could not load {wiki}:{page}: HTTP {}",
+                response.status()
+            ));
+        }
+        Ok(response.text().await?)
+    }
+
+    // Wiki pages in the table are on the table's own wiki, unless the mapping says otherwise
+    fn mapping_with_default_wiki(mapping: &HeaderMapping, wiki: &str) -> HeaderMapping {
+        let mut mapping = mapping.to_owned();
+        for cm in &mut mapping.data {
+            if let ColumnHeaderType::WikiPage(wp) = &mut cm.header.kind {
+                if wp.wiki.is_none() {
+                    wp.wiki = Some(wiki.to_string());
+                }
+            }
+        }
+        mapping
+    }
+
+    fn cell_value(text: &str, kind: &ColumnHeaderType) -> Option<Value> {
+        match kind {
+            ColumnHeaderType::WikiPage(_) => Some(json!(
+                WikiTable::link_target(text).unwrap_or(text.to_string())
+            )),
+            ColumnHeaderType::Int => Some(json!(text.replace(',', "").parse::<i64>().ok()?)),
+            ColumnHeaderType::Float => Some(json!(text.replace(',', "").parse::<f64>().ok()?)),
+            _ => Some(json!(text)),
+        }
+    }
+
+    async fn table2file(table: &WikiTable, mapping: &HeaderMapping) -> Result<DataFileDetails> {
+        let col_nums: Vec<Option<usize>> = mapping
+            .data
+            .iter()
+            .map(|cm| {
+                cm.mapping
+                    .first()
+                    .and_then(|(source_label, _)| table.column_index(source_label))
+            })
+            .collect();
+
+        let mut file = DataFile::new_output_file()?;
+        file.write_json_row(&json! {mapping.as_data_header()})?; // Output new header
+        for row in &table.rows {
+            let mut jsonl_row = vec![];
+            for (cm, col_num) in mapping.data.iter().zip(col_nums.iter()) {
+                let text = col_num
+                    .and_then(|col_num| row.get(col_num))
+                    .filter(|text| !text.is_empty());
+                if let (Some(text), Some((_, element_name))) = (text, cm.mapping.first()) {
+                    if let Some(value) = Self::cell_value(text, &cm.header.kind) {
+                        let dc = DataCell::from_value(&value, &cm.header, element_name).await;
+                        jsonl_row.push(dc);
+                        continue;
+                    }
+                }
+                jsonl_row.push(None);
+            }
+            write_adapter_row(&mut file, mapping, jsonl_row)?; // Output data row
+        }
+        file.finish()
+    }
+}
+
+#[async_trait]
+impl Adapter for WikiTableAdapter {
+    async fn source2file(
+        &mut self,
+        source: &SourceId,
+        mapping: &HeaderMapping,
+    ) -> Result<DataFileDetails> {
+        let (wiki, page, table_index) = match source {
+            SourceId::WikiTable {
+                wiki,
+                page,
+                table_index,
+            } => (wiki, page, *table_index),
+            _ => return Err(anyhow!("Unsuitable source type for wiki table: {source:?}")),
+        };
+        let wikitext = Self::load_wikitext(wiki, page).await?;
+        let tables = WikiTable::parse_all(&wikitext);
+        let table = tables.get(table_index).ok_or_else(|| {
+            AdapterError::NoResults(format!(
+                "{wiki}:{page} has {} wikitable(s), no table #{table_index}",
+                tables.len()
+            ))
+        })?;
+        let mapping = Self::mapping_with_default_wiki(mapping, wiki);
+        Self::table2file(table, &mapping).await
+    }
+}
 
-        for s in result.split("\n") {
-            let j: Value = match serde_json::from_str(s) {
-                Ok(j) => j,
-                Err(_) => continue, // TODO log error?
+// Files in a Commons category, and in its subcategories down to `depth`
+#[derive(Debug, Default)]
+pub struct CommonsCategoryAdapter {}
+
+impl CommonsCategoryAdapter {
+    // Files as (page ID, title without namespace prefix), and subcategory names
+    fn members_from_json(j: &Value) -> (Vec<(i64, String)>, Vec<String>) {
+        let mut files = vec![];
+        let mut subcategories = vec![];
+        let members = j["query"]["categorymembers"].as_array();
+        for member in members.into_iter().flatten() {
+            let title = match member["title"].as_str() {
+                Some(title) => title,
+                None => continue,
             };
+            let name = title.split_once(':').map_or(title, |(_, name)| name);
+            match member["ns"].as_i64() {
+                Some(6) => {
+                    if let Some(page_id) = member["pageid"].as_i64() {
+                        files.push((page_id, name.to_string()));
+                    }
+                }
+                Some(14) => subcategories.push(name.to_string()),
+                _ => {}
+            }
+        }
+        (files, subcategories)
+    }
+
+    async fn load_members(category: &str, with_subcategories: bool) -> Result<Vec<Value>> {
+        let api = APP.get_api_for_wiki("commonswiki").await?;
+        let title = format!("Category:{category}");
+        let cmtype = if with_subcategories {
+            "file|subcat"
+        } else {
+            "file"
+        };
+        let mut ret = vec![];
+        let mut cmcontinue: Option<String> = None;
+        loop {
+            let mut params = api.params_into(&[
+                ("action", "query"),
+                ("list", "categorymembers"),
+                ("cmtitle", &title),
+                ("cmtype", cmtype),
+                ("cmprop", "ids|title"),
+                ("cmlimit", "max"),
+            ]);
+            if let Some(cmcontinue) = &cmcontinue {
+                let _ = params.insert("cmcontinue".to_string(), cmcontinue.to_owned());
+            }
+            let j = api
+                .get_query_api_json(&params)
+                .await
+                .map_err(|e| anyhow!("Could not load members of Commons {title}: {e}"))?;
+            cmcontinue = j["continue"]["cmcontinue"].as_str().map(|s| s.to_string());
+            ret.push(j);
+            if cmcontinue.is_none() {
+                return Ok(ret);
+            }
+            APP.api_delay().await;
+        }
+    }
+
+    fn file_page(page_id: i64, name: &str) -> WikiPage {
+        WikiPage {
+            title: Some(name.to_string()),
+            prefixed_title: Some(format!("File:{name}")),
+            ns_id: Some(6),
+            page_id: Some(page_id),
+            ns_prefix: Some("File".to_string()),
+            wiki: Some("commonswiki".to_string()),
+        }
+    }
+
+    // Wiki page columns get the file; others the `page_id`, `title` or the `category` it was found in
+    async fn files2file(
+        files: &[(i64, String, String)],
+        mapping: &HeaderMapping,
+    ) -> Result<DataFileDetails> {
+        let mut file = DataFile::new_output_file()?;
+        file.write_json_row(&json! {mapping.as_data_header()})?; // Output new header
+        for (page_id, name, category) in files {
             let mut jsonl_row = vec![];
             for cm in &mapping.data {
-                match &cm.header.kind {
-                    crate::data_header::ColumnHeaderType::PlainText => match cm.mapping.get(0) {
-                        Some((from, _to)) => match j.get(from) {
-                            Some(data) => match data.as_str() {
-                                Some(s) => jsonl_row.push(DataCell::PlainText(s.to_string())),
-                                None => jsonl_row.push(DataCell::Blank),
-                            },
-                            None => jsonl_row.push(DataCell::Blank),
-                        },
-                        None => jsonl_row.push(DataCell::Blank),
-                    },
-                    crate::data_header::ColumnHeaderType::WikiPage(_) => {
-                        let mut wp = WikiPage::new_wikidata_item();
-                        wp.prefixed_title =
-                            Some(j.get("page_title").unwrap().as_str().unwrap().to_string());
-                        jsonl_row.push(DataCell::WikiPage(wp));
+                if let ColumnHeaderType::WikiPage(_) = cm.header.kind {
+                    jsonl_row.push(Some(DataCell::WikiPage(Self::file_page(*page_id, name))));
+                    continue;
+                }
+                let value = match cm.mapping.first() {
+                    Some((source_label, _)) if source_label == "page_id" => json!(page_id),
+                    Some((source_label, _)) if source_label == "category" => json!(category),
+                    Some((source_label, _)) if source_label == "prefixed_title" => {
+                        json!(format!("File:{name}"))
                     }
-                    crate::data_header::ColumnHeaderType::Int => {
-                        return Err(anyhow!("Unsupported type for UserEdits: Int"))
+                    Some(_) => json!(name),
+                    None => {
+                        jsonl_row.push(None);
+                        continue;
                     }
-                    crate::data_header::ColumnHeaderType::Float => {
-                        return Err(anyhow!("Unsupported type for UserEdits: Float"))
+                };
+                let element_name = &cm.mapping[0].1;
+                jsonl_row.push(DataCell::from_value(&value, &cm.header, element_name).await);
+            }
+            write_adapter_row(&mut file, mapping, jsonl_row)?; // Output data row
+        }
+        file.finish()
+    }
+}
+
+#[async_trait]
+impl Adapter for CommonsCategoryAdapter {
+    async fn source2file(
+        &mut self,
+        source: &SourceId,
+        mapping: &HeaderMapping,
+    ) -> Result<DataFileDetails> {
+        let (category, depth) = match source {
+            SourceId::CommonsCategory { category, depth } => (category, *depth),
+            _ => {
+                return Err(anyhow!(
+                    "Unsuitable source type for Commons category: {source:?}"
+                ))
+            }
+        };
+        let category = category
+            .trim()
+            .trim_start_matches("Category:")
+            .replace('_', " ");
+        if category.is_empty() {
+            return Err(anyhow!(AdapterError::BadParameters(
+                "Commons category is empty".to_string()
+            )));
+        }
+
+        // Breadth-first, so every file is reported with the shallowest category it is in
+        let mut seen_categories = HashSet::from([category.to_owned()]);
+        let mut seen_files = HashSet::new();
+        let mut files = vec![];
+        let mut level = vec![category];
+        for current_depth in 0..=depth {
+            let mut next_level = vec![];
+            for category in &level {
+                for j in Self::load_members(category, current_depth < depth).await? {
+                    let (new_files, subcategories) = Self::members_from_json(&j);
+                    for (page_id, name) in new_files {
+                        if seen_files.insert(page_id) {
+                            files.push((page_id, name, category.to_owned()));
+                        }
                     }
+                    next_level.extend(
+                        subcategories
+                            .into_iter()
+                            .filter(|subcategory| seen_categories.insert(subcategory.to_owned())),
+                    );
                 }
             }
-            file.write_json_row(&json! {jsonl_row})?; // Output data row
+            level = next_level;
         }
-
-        Ok(file.details())
+        Self::files2file(&files, mapping).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::APP;
+    use crate::data_header::ColumnHeader;
+    use crate::test_util::{load_file, load_rows};
+
+    #[test]
+    fn test_write_adapter_row_pads_to_header() {
+        let mut mapping = HeaderMapping::default();
+        mapping
+            .add_plain_text("a", "a")
+            .add_plain_text("b", "b")
+            .add_plain_text("c", "c");
+        let mut file = DataFile::new_output_file().unwrap();
+        file.write_json_row(&json! {mapping.as_data_header()})
+            .unwrap();
+        let row = vec![None, Some(DataCell::PlainText("x".to_string()))];
+        write_adapter_row(&mut file, &mapping, row).unwrap();
+        let uuid = file.finish().unwrap().uuid;
+        drop(file);
+
+        assert_eq!(
+            load_rows(&uuid)[0],
+            vec![
+                DataCell::Blank,
+                DataCell::PlainText("x".to_string()),
+                DataCell::Blank
+            ]
+        );
+        APP.remove_uuid_file(&uuid).unwrap(); // Cleanup
+    }
+
+    #[tokio::test]
+    async fn test_sparql_results_parse_wkt_point() {
+        let mut f = tempfile().unwrap();
+        f.write_all(b"name,coord\nParis,Point(2.3522 48.8566)\n")
+            .unwrap();
+        f.seek(std::io::SeekFrom::Start(0)).unwrap();
+        let mut reader = csv::ReaderBuilder::new().from_reader(f);
+        let mut mapping = HeaderMapping::default();
+        mapping.add_plain_text("name", "name");
+        mapping.data.push(crate::mapping::ColumnMapping {
+            header: ColumnHeader {
+                name: "coord".to_string(),
+                kind: ColumnHeaderType::Coordinate,
+            },
+            mapping: vec![("coord".to_string(), "coord".to_string())],
+        });
+        let mut file = DataFile::new_output_file().unwrap();
+        file.write_json_row(&json! {mapping.as_data_header()})
+            .unwrap();
+        write_csv_rows(&mut reader, &mapping, &mut file, false)
+            .await
+            .unwrap();
+        let uuid = file.finish().unwrap().uuid;
+        drop(file);
+
+        let rows = load_rows(&uuid);
+        assert_eq!(
+            rows[0][1],
+            DataCell::Coordinate {
+                lat: 48.8566,
+                lon: 2.3522
+            }
+        );
+        assert_eq!(rows[0][1].as_key(), "48.8566,2.3522");
+        APP.remove_uuid_file(&uuid).unwrap(); // Cleanup
+    }
+
+    #[tokio::test]
+    async fn test_petscan_metadata_paths() {
+        let pages = json!([
+            {"title": "Douglas_Adams", "metadata": {"defaultsort": "Adams, Douglas", "image": "Douglas adams portrait.jpg"}},
+            {"title": "Adams", "metadata": {"disambiguation": true}},
+        ]);
+        let mut mapping = HeaderMapping::default();
+        mapping
+            .add_plain_text("title", "title")
+            .add_plain_text("metadata.defaultsort", "defaultsort")
+            .add_plain_text("metadata.image", "image")
+            .add_plain_text("metadata.image.name", "missing");
+        mapping.data.push(crate::mapping::ColumnMapping {
+            header: ColumnHeader {
+                name: "disambiguation".to_string(),
+                kind: ColumnHeaderType::Boolean,
+            },
+            mapping: vec![(
+                "metadata.disambiguation".to_string(),
+                "disambiguation".to_string(),
+            )],
+        });
+        let dfd = json_records2file(pages.as_array().unwrap(), &mapping)
+            .await
+            .unwrap();
+
+        let df = load_file(&dfd.uuid);
+        let text = |s: &str| DataCell::PlainText(s.to_string());
+        assert_eq!(
+            df.rows,
+            vec![
+                vec![
+                    text("Douglas_Adams"),
+                    text("Adams, Douglas"),
+                    text("Douglas adams portrait.jpg"),
+                    DataCell::Blank,
+                    DataCell::Blank
+                ],
+                vec![
+                    text("Adams"),
+                    DataCell::Blank,
+                    DataCell::Blank,
+                    DataCell::Blank,
+                    DataCell::Boolean(true)
+                ],
+            ]
+        );
+        APP.remove_uuid_file(&dfd.uuid).unwrap(); // Cleanup
+    }
+
+    #[tokio::test]
+    async fn test_commons_category_members() {
+        let j = json!({"query": {"categorymembers": [
+            {"pageid": 12, "ns": 6, "title": "File:Douglas adams portrait.jpg"},
+            {"pageid": 34, "ns": 14, "title": "Category:Douglas Adams in 2000"},
+            {"pageid": 56, "ns": 0, "title": "Not a file"},
+        ]}});
+        let (files, subcategories) = CommonsCategoryAdapter::members_from_json(&j);
+        assert_eq!(files, vec![(12, "Douglas adams portrait.jpg".to_string())]);
+        assert_eq!(subcategories, vec!["Douglas Adams in 2000".to_string()]);
+
+        let mut mapping = HeaderMapping::default();
+        mapping.add_plain_text("category", "category");
+        mapping.data.push(crate::mapping::ColumnMapping {
+            header: ColumnHeader {
+                name: "file".to_string(),
+                kind: ColumnHeaderType::WikiPage(WikiPage::default()),
+            },
+            mapping: vec![("file".to_string(), "prefixed_title".to_string())],
+        });
+        let files = vec![(
+            12,
+            "Douglas adams portrait.jpg".to_string(),
+            "Douglas Adams".to_string(),
+        )];
+        let dfd = CommonsCategoryAdapter::files2file(&files, &mapping)
+            .await
+            .unwrap();
+        let df = load_file(&dfd.uuid);
+        assert_eq!(
+            df.rows[0][0],
+            DataCell::PlainText("Douglas Adams".to_string())
+        );
+        match &df.rows[0][1] {
+            DataCell::WikiPage(wp) => {
+                assert_eq!(wp.ns_id, Some(6));
+                assert_eq!(wp.wiki.as_deref(), Some("commonswiki"));
+                assert_eq!(
+                    wp.prefixed_title.as_deref(),
+                    Some("File:Douglas adams portrait.jpg")
+                );
+            }
+            other => panic!("Not a wiki page: {other:?}"),
+        }
+        APP.remove_uuid_file(&dfd.uuid).unwrap(); // Cleanup
+    }
+
+    #[test]
+    fn test_is_public_ip() {
+        let public = |ip: &str| is_public_ip(ip.parse().unwrap());
+        assert!(public("208.80.154.224"));
+        assert!(public("2620:0:861:ed1a::1"));
+        assert!(!public("127.0.0.1"));
+        assert!(!public("10.1.2.3"));
+        assert!(!public("172.16.0.1"));
+        assert!(!public("192.168.1.1"));
+        assert!(!public("169.254.169.254"));
+        assert!(!public("100.64.0.1"));
+        assert!(!public("0.0.0.0"));
+        assert!(!public("::1"));
+        assert!(!public("fd00::1"));
+        assert!(!public("fe80::1"));
+        assert!(!public("::ffff:127.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn test_csv_url_adapter() {
+        let url = crate::app::mock_http_server(vec![(
+            "200 OK",
+            "name,count,extra\nBerlin,3645000,x\n\"Hamburg, Hansestadt\",1841000,y\n",
+        )])
+        .await;
+        let mut mapping = HeaderMapping::default();
+        mapping
+            .add_plain_text("name", "name")
+            .add_plain_text("missing", "missing");
+        mapping.data.push(crate::mapping::ColumnMapping {
+            header: ColumnHeader {
+                name: "count".to_string(),
+                kind: ColumnHeaderType::Int,
+            },
+            mapping: vec![("count".to_string(), "count".to_string())],
+        });
+        let error = CsvUrlAdapter::default()
+            .source2file(&SourceId::CsvUrl(url.to_owned()), &mapping)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Bad parameters: '{url}' does not resolve to a public address")
+        );
+        let dfd = CsvUrlAdapter {
+            allow_private_hosts: true,
+        }
+        .source2file(&SourceId::CsvUrl(url), &mapping)
+        .await
+        .unwrap();
+
+        let df = load_file(&dfd.uuid);
+        assert_eq!(
+            df.rows,
+            vec![
+                vec![
+                    DataCell::PlainText("Berlin".to_string()),
+                    DataCell::Blank,
+                    DataCell::Int(3645000)
+                ],
+                vec![
+                    DataCell::PlainText("Hamburg, Hansestadt".to_string()),
+                    DataCell::Blank,
+                    DataCell::Int(1841000)
+                ],
+            ]
+        );
+        APP.remove_uuid_file(&dfd.uuid).unwrap(); // Cleanup
+
+        let error = CsvUrlAdapter::default()
+            .source2file(
+                &SourceId::CsvUrl("file:///etc/passwd".to_string()),
+                &mapping,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Bad parameters: 'file:///etc/passwd' is not an http(s) URL"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_json_url_adapter() {
+        let body = r#"{"result": {"items": [
+            {"id": "Q42", "stats": {"sitelinks": 120, "label": {"en": "Douglas Adams"}}},
+            "not an object",
+            {"id": "Q1", "stats": {"sitelinks": 300}}
+        ]}}"#;
+        let url = crate::app::mock_http_server(vec![("200 OK", body), ("200 OK", body)]).await;
+        let mut mapping = HeaderMapping::default();
+        mapping
+            .add_plain_text("id", "id")
+            .add_plain_text("stats.label.en", "label");
+        mapping.data.push(crate::mapping::ColumnMapping {
+            header: ColumnHeader {
+                name: "sitelinks".to_string(),
+                kind: ColumnHeaderType::Int,
+            },
+            mapping: vec![("stats.sitelinks".to_string(), "sitelinks".to_string())],
+        });
+        let source = |rows_path: &str| SourceId::JsonUrl {
+            url: url.to_owned(),
+            rows_path: rows_path.to_string(),
+        };
+        let error = JsonUrlAdapter::default()
+            .source2file(&source("result.items"), &mapping)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Bad parameters: '{url}' does not resolve to a public address")
+        );
+        let mut adapter = JsonUrlAdapter {
+            allow_private_hosts: true,
+        };
+        let dfd = adapter
+            .source2file(&source("result.items"), &mapping)
+            .await
+            .unwrap();
+        let missing = adapter
+            .source2file(&source("result.missing"), &mapping)
+            .await
+            .unwrap();
+        assert_eq!(missing.rows, 0);
+
+        let df = load_file(&dfd.uuid);
+        let text = |s: &str| DataCell::PlainText(s.to_string());
+        assert_eq!(
+            df.rows,
+            vec![
+                vec![text("Q42"), text("Douglas Adams"), DataCell::Int(120)],
+                vec![text("Q1"), DataCell::Blank, DataCell::Int(300)],
+            ]
+        );
+        APP.remove_uuid_file(&dfd.uuid).unwrap(); // Cleanup
+        APP.remove_uuid_file(&missing.uuid).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_quarry_result_without_rows() {
+        let j = json!({"headers": ["page_title"], "rows": []});
+        let error =
+            QuarryQueryAdapter::result2file(&j, &HeaderMapping::default(), "Quarry query 1")
+                .await
+                .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No results: Quarry query 1 result has no rows"
+        );
+    }
+
+    #[test]
+    fn test_wiki_db_names() {
+        assert!(RE_WIKI_DB.is_match("enwiki"));
+        assert!(RE_WIKI_DB.is_match("zh_min_nanwiki"));
+        assert!(RE_WIKI_DB.is_match("wikimania2014wiki"));
+        assert!(!RE_WIKI_DB.is_match("en.wikipedia.org"));
+        assert!(!RE_WIKI_DB.is_match(""));
+    }
+
+    #[tokio::test]
+    async fn test_quarry_query_run() {
+        let responses = vec![
+            (
+                "200 OK",
+                r#"{"latest_rev": {"id": 7, "sql": "SELECT page_title FROM page"}}"#,
+            ),
+            ("200 OK", r#"{"qrun_id": 99}"#),
+            ("200 OK", r#"{"status": "running"}"#),
+            ("200 OK", r#"{"status": "complete"}"#),
+            (
+                "200 OK",
+                r#"{"headers": ["page_title"], "rows": [["Berlin"], ["Hamburg"]]}"#,
+            ),
+        ];
+        let mut adapter = QuarryQueryRunAdapter {
+            base_url: crate::app::mock_http_server(responses).await,
+            session: None,
+            poll_interval: std::time::Duration::from_millis(1),
+            max_polls: 2,
+        };
+        adapter.base_url = adapter.base_url.trim_end_matches('/').to_string();
+        let mut mapping = HeaderMapping::default();
+        mapping.add_plain_text("page_title", "title");
+        let dfd = adapter
+            .source2file(&SourceId::QuarryQueryRun(1), &mapping)
+            .await
+            .unwrap();
+        assert_eq!(dfd.rows, 2);
+        APP.remove_uuid_file(&dfd.uuid).unwrap(); // Cleanup
+
+        let responses = vec![
+            ("200 OK", r#"{"latest_rev": {"id": 7, "sql": "SELECT x"}}"#),
+            ("200 OK", r#"{"qrun_id": 100}"#),
+            (
+                "200 OK",
+                r#"{"status": "failed", "extra": {"error": "Unknown column 'x'"}}"#,
+            ),
+        ];
+        adapter.base_url = crate::app::mock_http_server(responses).await;
+        adapter.base_url = adapter.base_url.trim_end_matches('/').to_string();
+        let error = adapter
+            .source2file(&SourceId::QuarryQueryRun(1), &mapping)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No results: Quarry query 1 run 100: Unknown column 'x'"
+        );
+
+        let responses = vec![
+            ("200 OK", r#"{"latest_rev": {"id": 7, "sql": "SELECT x"}}"#),
+            ("200 OK", r#"{"qrun_id": 101}"#),
+            ("200 OK", r#"{"extra": {}}"#),
+        ];
+        adapter.base_url = crate::app::mock_http_server(responses).await;
+        adapter.base_url = adapter.base_url.trim_end_matches('/').to_string();
+        let error = adapter
+            .source2file(&SourceId::QuarryQueryRun(1), &mapping)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            r#"Quarry query 1 run 101 has no status: {"extra":{}}"#
+        );
+
+        let responses = vec![
+            ("200 OK", r#"{"latest_rev": {"id": 7, "sql": "SELECT x"}}"#),
+            ("200 OK", r#"{"qrun_id": 102}"#),
+            ("200 OK", r#"{"status": "queued"}"#),
+            ("200 OK", r#"{"status": "running"}"#),
+        ];
+        adapter.base_url = crate::app::mock_http_server(responses).await;
+        adapter.base_url = adapter.base_url.trim_end_matches('/').to_string();
+        let error = adapter
+            .source2file(&SourceId::QuarryQueryRun(1), &mapping)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No results: Quarry query 1 run 102 did not finish after 2 status checks"
+        );
+
+        let responses = vec![
+            ("200 OK", r#"{"latest_rev": {"id": 7, "sql": "SELECT x"}}"#),
+            ("403 Forbidden", "Authorization denied"),
+        ];
+        adapter.base_url = crate::app::mock_http_server(responses).await;
+        adapter.base_url = adapter.base_url.trim_end_matches('/').to_string();
+        let error = adapter
+            .source2file(&SourceId::QuarryQueryRun(1), &mapping)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Bad parameters: Quarry query 1 is not owned by the TOOLFLOW_QUARRY_SESSION account"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wiki_table_adapter_maps_by_header_name() {
+        let wikitext = "{| class=\"wikitable\"\n! Population !! '''City'''\n|-\n| 3,645,000 || [[Berlin]]\n|-\n| n/a || Atlantis\n|}";
+        let table = &WikiTable::parse_all(wikitext)[0];
+        let mut mapping = HeaderMapping::default();
+        mapping
+            .add_plain_text("City", "city")
+            .add_plain_text("Missing", "missing");
+        mapping.data.push(crate::mapping::ColumnMapping {
+            header: crate::data_header::ColumnHeader {
+                name: "population".to_string(),
+                kind: ColumnHeaderType::Int,
+            },
+            mapping: vec![("Population".to_string(), "population".to_string())],
+        });
+        let dfd = WikiTableAdapter::table2file(table, &mapping).await.unwrap();
+
+        let df = load_file(&dfd.uuid);
+        assert_eq!(
+            df.rows,
+            vec![
+                vec![
+                    DataCell::PlainText("[[Berlin]]".to_string()),
+                    DataCell::Blank,
+                    DataCell::Int(3645000)
+                ],
+                vec![
+                    DataCell::PlainText("Atlantis".to_string()),
+                    DataCell::Blank,
+                    DataCell::Blank
+                ],
+            ]
+        );
+        APP.remove_uuid_file(&dfd.uuid).unwrap(); // Cleanup
+    }
+
+    #[tokio::test]
+    async fn test_adapter_bad_parameters_fail_early() {
+        let mapping = HeaderMapping::default();
+        let error = AListBuildingToolAdapter::default()
+            .source2file(
+                &SourceId::AListBuildingTool(("enwiki".to_string(), "Douglas Adams".to_string())),
+                &mapping,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<AdapterError>(),
+            Some(AdapterError::BadParameters(_))
+        ));
+        let error = PetScanAdapter::default()
+            .source2file(&SourceId::PetScan(0), &mapping)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Bad parameters: PetScan PSID must be positive"
+        );
+    }
+
+    #[test]
+    fn test_sparql_url_encodes_query() {
+        let sparql = "# Items with many sitelinks\nSELECT ?x ?n { ?x wikibase:sitelinks ?n FILTER(?x > 5 && ?n + 1 > 2) }";
+        let url = SparqlAdapter::default().sparql_url(sparql).unwrap();
+        assert!(!url.as_str().contains('#'));
+        assert!(url.fragment().is_none());
+        let pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        assert_eq!(pairs, vec![("query".to_string(), sparql.to_string())]);
+    }
+
+    #[test]
+    fn test_sparql_endpoint_presets() {
+        let adapter = SparqlAdapter {
+            endpoint: "commons".to_string(),
+            ..Default::default()
+        };
+        let url = adapter.sparql_url("SELECT ?file {}").unwrap();
+        assert_eq!(url.host_str(), Some("commons-query.wikimedia.org"));
+        assert_eq!(
+            SparqlAdapter::endpoint_url("").unwrap(),
+            "https://query.wikidata.org/sparql"
+        );
+        assert_eq!(
+            SparqlAdapter::endpoint_url("wikidata_scholarly").unwrap(),
+            "https://query-scholarly.wikidata.org/sparql"
+        );
+        assert_eq!(
+            SparqlAdapter::endpoint_url("https://example.org/sparql").unwrap(),
+            "https://example.org/sparql"
+        );
+        assert!(SparqlAdapter::endpoint_url("wikidata_typo").is_err());
+        assert!(adapter.is_commons().unwrap());
+        assert!(!SparqlAdapter::default().is_commons().unwrap());
+        assert_eq!(SparqlAdapter::entity_term("M123", true), "sdc:M123");
+        assert_eq!(
+            SparqlAdapter::entity_term("M123", false),
+            "<https://commons.wikimedia.org/entity/M123>"
+        );
+        assert_eq!(SparqlAdapter::entity_term("Q42", true), "wd:Q42");
+        assert_eq!(SparqlAdapter::entity_term("Q42", false), "wd:Q42");
+    }
+
+    #[test]
+    fn test_sparql_values_placeholder() {
+        let sparql = "SELECT ?item ?label { {{values:?item}} ?item rdfs:label ?label }";
+        let adapter = SparqlAdapter {
+            values: vec!["wd:Q1".to_string(), "wd:Q2".to_string()],
+            ..Default::default()
+        };
+        let queries = adapter.expand_values_placeholder(sparql).unwrap();
+        assert_eq!(
+            queries,
+            vec!["SELECT ?item ?label { VALUES ?item { wd:Q1 wd:Q2 } ?item rdfs:label ?label }"]
+        );
+
+        // Batching
+        let adapter = SparqlAdapter {
+            values: (0..SPARQL_VALUES_BATCH_SIZE + 1)
+                .map(|i| format!("wd:Q{i}"))
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(adapter.expand_values_placeholder(sparql).unwrap().len(), 2);
+
+        // Placeholder without values
+        assert!(SparqlAdapter::default()
+            .expand_values_placeholder(sparql)
+            .is_err());
+    }
+
+    #[test]
+    fn test_sparql_values_from_file() {
+        let uuid = "8c5d1fb3-6ea8-44d1-b938-9d22f569c412";
+        let values = SparqlAdapter::default()
+            .values_from_file(uuid, "wikidata_item")
+            .unwrap();
+        assert_eq!(values.len(), 49);
+        assert_eq!(values[0], "wd:Q18619644");
+    }
 
     #[tokio::test]
     async fn test_adapter_pagepile() {
@@ -632,10 +2230,18 @@ mod tests {
         let header_mapping: HeaderMapping = serde_json::from_str(hm).unwrap();
         let id = 51805;
         let df = PagePileAdapter::default()
-            .source2file(&&SourceId::PagePile(id), &header_mapping)
+            .source2file(&SourceId::PagePile(id), &header_mapping)
             .await
             .unwrap();
-        assert_eq!(df.rows, 1748);
+        assert_eq!(df.rows, 1747);
+        let mut df_in = DataFile::default();
+        df_in.open_input_file(&df.uuid).unwrap();
+        df_in.load().unwrap();
+        let wiki = match &df_in.rows[0][0] {
+            DataCell::WikiPage(wp) => wp.wiki.to_owned(),
+            _ => None,
+        };
+        assert_eq!(wiki, Some("wikidatawiki".to_string()));
         APP.remove_uuid_file(&df.uuid).unwrap(); // Cleanup
     }
 
@@ -645,10 +2251,10 @@ mod tests {
         let header_mapping: HeaderMapping = serde_json::from_str(hm).unwrap();
         let id = 25951472;
         let df = PetScanAdapter::default()
-            .source2file(&&&SourceId::PetScan(id), &header_mapping)
+            .source2file(&SourceId::PetScan(id), &header_mapping)
             .await
             .unwrap();
-        assert_eq!(df.rows, 2);
+        assert_eq!(df.rows, 1);
         APP.remove_uuid_file(&df.uuid).unwrap(); // Cleanup
     }
 
@@ -658,13 +2264,70 @@ mod tests {
         let header_mapping: HeaderMapping = serde_json::from_str(hm).unwrap();
         let id = ("enwiki".to_string(), "Q82069695".to_string());
         let df = AListBuildingToolAdapter::default()
-            .source2file(&&&SourceId::AListBuildingTool(id), &header_mapping)
+            .source2file(&SourceId::AListBuildingTool(id), &header_mapping)
             .await
             .unwrap();
-        assert!(df.rows > 1);
+        assert!(df.rows > 0);
         APP.remove_uuid_file(&df.uuid).unwrap(); // Cleanup
     }
 
+    #[test]
+    fn test_wdfist_row_cells_follow_mapping() {
+        // Unmapped columns are item, image, count by position
+        let j = json!({"data": [{"header": {"kind": {"WikiPage": {"ns_id": 0,"ns_prefix": null,"page_id": null,"prefixed_title": null,"title": null,"wiki": "wikidatawiki"}},"name": "wikidata_item"},"mapping": []},{"header": {"kind": {"WikiPage": {"ns_id": 6,"ns_prefix": "File","page_id": null,"prefixed_title": null,"title": null,"wiki": "commonswiki"}},"name": "commons_image"},"mapping": []},{"header": {"kind": {"Int": null},"name": "number_of_uses"},"mapping": []}]});
+        let mapping: HeaderMapping = serde_json::from_value(j).unwrap();
+        let labels = WdFistAdapter::source_labels(&mapping);
+        let cells = WdFistAdapter::row_cells(&labels, &mapping, "Q42", "A_b.jpg", 3, None);
+        match &cells[1] {
+            Some(DataCell::WikiPage(wp)) => {
+                assert_eq!(wp.prefixed_title, Some("File:A_b.jpg".to_string()))
+            }
+            other => panic!("Not an image: {other:?}"),
+        }
+        assert_eq!(cells[2], Some(DataCell::Int(3)));
+
+        // User-selected columns, with Commons metadata
+        let mut mapping = HeaderMapping::default();
+        mapping
+            .add_plain_text("image", "file")
+            .add_plain_text("image_mime", "mime");
+        mapping.data.push(ColumnMapping {
+            header: ColumnHeader {
+                name: "width".to_string(),
+                kind: ColumnHeaderType::Int,
+            },
+            mapping: vec![("image_width".to_string(), "width".to_string())],
+        });
+        let info = json!({"query": {"pages": {"123": {"title": "File:A b.jpg", "imageinfo": [{"size": 1000, "width": 640, "height": 480, "mime": "image/jpeg"}]}}}});
+        let info = WdFistAdapter::image_info_from_json(&info);
+        let labels = WdFistAdapter::source_labels(&mapping);
+        let cells =
+            WdFistAdapter::row_cells(&labels, &mapping, "Q42", "A_b.jpg", 3, info.get("A_b.jpg"));
+        assert_eq!(
+            cells,
+            vec![
+                Some(DataCell::PlainText("A_b.jpg".to_string())),
+                Some(DataCell::PlainText("image/jpeg".to_string())),
+                Some(DataCell::Int(640)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wdfist_qid_label() {
+        let mut mapping = HeaderMapping::default();
+        mapping
+            .add_wikidata_item("qid", "item")
+            .add_plain_text("qid", "q");
+        let labels = WdFistAdapter::source_labels(&mapping);
+        let cells = WdFistAdapter::row_cells(&labels, &mapping, "Q42", "A_b.jpg", 3, None);
+        match &cells[0] {
+            Some(DataCell::WikiPage(wp)) => assert_eq!(wp.prefixed_title, Some("Q42".to_string())),
+            other => panic!("Not an item: {other:?}"),
+        }
+        assert_eq!(cells[1], Some(DataCell::PlainText("Q42".to_string())));
+    }
+
     #[tokio::test]
     async fn test_adapter_wdfist() {
         let j = json!({"data": [{"header": {"kind": {"WikiPage": {"ns_id": 0,"ns_prefix": null,"page_id": null,"prefixed_title": null,"title": null,"wiki": "wikidatawiki"}},"name": "wikidata_item"},"mapping": []},{"header": {"kind": {"WikiPage": {"ns_id": 6,"ns_prefix": "File","page_id": null,"prefixed_title": null,"title": null,"wiki": "commonswiki"}},"name": "commons_image"},"mapping": []},{"header": {"kind": {"Int": null},"name": "number_of_uses"},"mapping": []}]});
@@ -674,10 +2337,52 @@ mod tests {
             .source2file(&SourceId::WdFist(id), &header_mapping)
             .await
             .unwrap();
-        assert!(df.rows > 1);
+        assert!(df.rows > 0);
         APP.remove_uuid_file(&df.uuid).unwrap(); // Cleanup
     }
 
+    #[test]
+    fn test_user_edits_start_override() {
+        let url = "https://wikidata-todo.toolforge.org/user_edits.php?user=Magnus_Manske&start=20200101000000";
+        let start = |adapter: UserEditsAdapter| {
+            let url = Url::parse(&adapter.user_edits_url(url).unwrap()).unwrap();
+            url.query_pairs()
+                .find(|(k, _)| k == "start")
+                .map(|(_, v)| v.to_string())
+        };
+        assert_eq!(
+            start(UserEditsAdapter::default()),
+            Some("20200101000000".to_string())
+        );
+        let adapter = UserEditsAdapter {
+            start: Some("20240301120000".to_string()),
+        };
+        assert_eq!(start(adapter), Some("20240301120000".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_user_edits_edit_cell() {
+        let j = json!({"data": [
+        {"header": {"kind": "PlainText","name": "timestamp"},"mapping": [["rev_timestamp","timestamp"]]},
+        {"header": {"kind": "Int","name": "revid"},"mapping": [["rev_id","revid"]]},
+        {"header": {"kind": "PlainText","name": "comment"},"mapping": [["comment","comment"]]}
+        ]});
+        let header_mapping: HeaderMapping = serde_json::from_str(&j.to_string()).unwrap();
+        let edit = json!({"page_title": "Q42", "rev_timestamp": "20240101120000", "rev_id": "123"});
+        let mut cells = vec![];
+        for cm in &header_mapping.data {
+            cells.push(UserEditsAdapter::edit_cell(&edit, cm).await);
+        }
+        assert_eq!(
+            cells,
+            vec![
+                Some(DataCell::PlainText("20240101120000".to_string())),
+                Some(DataCell::Int(123)),
+                None
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn test_adapter_user_edits() {
         let j = json!({"data": [
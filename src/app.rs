@@ -3,6 +3,7 @@ use lazy_static::lazy_static;
 use mediawiki::api::Api;
 use mysql_async::{from_row, prelude::*, Conn, Pool};
 use regex::Regex;
+use serde::Serialize;
 use serde_json::Value;
 use std::{
     collections::HashMap,
@@ -11,35 +12,162 @@ use std::{
     time::{self, SystemTime},
 };
 use tokio::sync::RwLock;
+use tracing::{error, info, warn};
 
-use crate::{data_file::DataFile, workflow::Workflow, workflow_run::WorkflowNodeStatusValue};
+use crate::{
+    data_file::{DataFile, DataFileDetails},
+    workflow::Workflow,
+    workflow_run::WorkflowNodeStatusValue,
+    APP,
+};
 
-pub const USER_AGENT: &'static str = toolforge::user_agent!("toolflow");
+pub const USER_AGENT: &str = toolforge::user_agent!("toolflow");
 const REQWEST_TIMEOUT: u64 = 60 * 5;
+// Set on every MediaWiki API object, so requests back off while the replicas are lagged
+const API_MAXLAG_SECONDS: u64 = 5;
+// Pause between edits and heavy reads, overridable with TOOLFLOW_API_DELAY_MS
+const DEFAULT_API_DELAY_MS: u64 = 200;
+const REMOVE_FILES_BATCH_SIZE: usize = 1000;
+// Namespaces of each wiki are cached in {data_path}/site_info/{wiki}.json as
+// {"fetched": <UNIX timestamp>, "namespaces": <siteinfo query.namespaces>}, and refetched when stale
+const SITE_INFO_CACHE_DIR: &str = "site_info";
+const SITE_INFO_CACHE_MAX_AGE_DAYS: u64 = 7;
+// Node results are cached in {data_path}/node_cache/{key}.json as
+// {"created": <UNIX timestamp>, "uuid": <output file>, "rows": <data rows>}
+const NODE_CACHE_DIR: &str = "node_cache";
+// Transient HTTP failures (network errors, 429, 5xx) are retried with exponential backoff
+const HTTP_MAX_ATTEMPTS: u32 = 4;
+const HTTP_RETRY_BASE_DELAY_MS: u64 = 1000;
+const HTTP_MAX_RETRY_AFTER_SECONDS: u64 = 120;
+// Overridable with TOOLFLOW_DB, eg for a private instance
+const DEFAULT_DB_NAME: &str = "s53704__toolflow";
 
 lazy_static! {
     static ref RE_WEBSERVER_WIKIPEDIA: Regex = Regex::new(r"^(.+)wiki$").expect("Regex error");
     static ref RE_WEBSERVER_WIKI: Regex = Regex::new(r"^(.+)(wik.+)$").expect("Regex error");
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct UserFile {
+    pub id: usize,
+    pub uuid: String,
+    pub workflow_id: usize,
+    pub run_id: usize,
+    pub node_id: usize,
+    pub is_output: bool,
+    pub rows: usize,
+    pub size: Option<u64>, // On-disk size in bytes, None if the file is missing
+}
+
 pub struct App {
     pool: Pool,
-    site_matrix: RwLock<HashMap<String, Api>>,
-    runs_on_toolforge: bool,
+    site_namespaces: RwLock<HashMap<String, Value>>,
+    api_delay_ms: u64,
+    data_path: String,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl App {
     pub fn new() -> Self {
+        let runs_on_toolforge = Self::detect_toolforge(std::env::var("TOOLFLOW_ENV").ok());
         Self {
             pool: Pool::new(
-                toolforge::db::toolsdb("s53704__toolflow".to_string())
-                    .expect("unable to load db config")
-                    .to_string()
-                    .as_str(),
+                toolforge::db::toolsdb(
+                    Self::non_empty_env(std::env::var("TOOLFLOW_DB").ok())
+                        .unwrap_or_else(|| DEFAULT_DB_NAME.to_string()),
+                )
+                .expect("unable to load db config")
+                .to_string()
+                .as_str(),
+            ),
+            site_namespaces: RwLock::new(HashMap::new()),
+            api_delay_ms: Self::api_delay_from_env(std::env::var("TOOLFLOW_API_DELAY_MS").ok()),
+            data_path: if cfg!(test) {
+                "./test_data".to_string() // Testing
+            } else {
+                Self::data_path_from_env(
+                    std::env::var("TOOLFLOW_DATA_PATH").ok(),
+                    runs_on_toolforge,
+                )
+            },
+        }
+    }
+
+    fn non_empty_env(value: Option<String>) -> Option<String> {
+        value
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    }
+
+    fn data_path_from_env(data_path: Option<String>, runs_on_toolforge: bool) -> String {
+        if let Some(data_path) = Self::non_empty_env(data_path) {
+            data_path.trim_end_matches('/').to_string()
+        } else if runs_on_toolforge {
+            "/data/project/toolflow/data".to_string()
+        } else {
+            "./tmp".to_string() // Local box
+        }
+    }
+
+    fn api_delay_from_env(api_delay_ms: Option<String>) -> u64 {
+        match api_delay_ms.as_deref().map(|s| s.trim()) {
+            Some("") | None => DEFAULT_API_DELAY_MS,
+            Some(s) => s.parse().unwrap_or_else(|_| {
+                warn!(
+                    api_delay_ms = s,
+                    "Invalid TOOLFLOW_API_DELAY_MS, using default"
+                );
+                DEFAULT_API_DELAY_MS
+            }),
+        }
+    }
+
+    // All MediaWiki API access should go through here, so every consumer is a good citizen
+    pub async fn get_api(&self, url: &str) -> Result<Api> {
+        let mut api = Api::new(url).await?;
+        api.set_maxlag(Some(API_MAXLAG_SECONDS));
+        if self.api_delay_ms > 0 {
+            api.set_edit_delay(Some(self.api_delay_ms));
+        }
+        Ok(api)
+    }
+
+    pub async fn get_api_for_wiki(&self, wiki: &str) -> Result<Api> {
+        let server = self
+            .get_webserver_for_wiki(wiki)
+            .ok_or_else(|| anyhow!("Could not find web server for {wiki}"))?;
+        self.get_api(&format!("https://{server}/w/api.php")).await
+    }
+
+    // Call between the requests of a heavy read, eg batches of wbgetentities
+    pub async fn api_delay(&self) {
+        if self.api_delay_ms > 0 && !cfg!(test) {
+            tokio::time::sleep(time::Duration::from_millis(self.api_delay_ms)).await;
+        }
+    }
+
+    // TOOLFLOW_ENV=toolforge|local overrides the detection
+    fn detect_toolforge(toolflow_env: Option<String>) -> bool {
+        match toolflow_env
+            .as_deref()
+            .map(|s| s.trim().to_lowercase())
+            .as_deref()
+        {
+            Some("toolforge") => return true,
+            Some("local") => return false,
+            Some("") | None => {}
+            Some(other) => warn!(
+                toolflow_env = other,
+                "Unknown TOOLFLOW_ENV, detecting instead"
             ),
-            site_matrix: RwLock::new(HashMap::new()),
-            runs_on_toolforge: Path::new("/data/project/toolflow/data").exists(), //std::env::var("USER")==Ok("tools.toolflow".to_string()),
         }
+        Path::new("/data/project/toolflow").exists()
+            || std::env::var("USER").ok().as_deref() == Some("tools.toolflow")
     }
 
     pub async fn get_db_connection(&self) -> Result<Conn> {
@@ -56,8 +184,8 @@ impl App {
 
     pub async fn get_namespace_id(&self, wiki: &str, ns: &str) -> Option<i64> {
         let ns_to_compare = self.to_compare(ns);
-        let site_info = self.get_site_info(wiki).await.ok()?;
-        let si = site_info["query"]["namespaces"].as_object()?;
+        let namespaces = self.get_namespaces(wiki).await.ok()?;
+        let si = namespaces.as_object()?;
         Some(
             si.iter()
                 .map(|(_ns_id, v)| (v["id"].as_i64(), v["*"].as_str())) // Local namesapces
@@ -76,28 +204,125 @@ impl App {
 
     pub async fn get_namespace_name(&self, wiki: &str, nsid: i64) -> Option<String> {
         let key = format!("{nsid}");
-        let site_info = self.get_site_info(wiki).await.ok()?;
-        Some(
-            site_info["query"]["namespaces"][key]["*"]
-                .as_str()?
-                .to_string(),
-        )
+        let namespaces = self.get_namespaces(wiki).await.ok()?;
+        Some(namespaces[key]["*"].as_str()?.to_string())
     }
 
-    async fn get_site_info(&self, wiki: &str) -> Result<Value> {
-        match self.site_matrix.read().await.get(wiki) {
-            Some(v) => return Ok(v.get_site_info().to_owned()),
-            None => {}
+    // The `query.namespaces` part of the site info, from memory, the disk cache, or the API
+    async fn get_namespaces(&self, wiki: &str) -> Result<Value> {
+        if let Some(v) = self.site_namespaces.read().await.get(wiki) {
+            return Ok(v.to_owned());
         }
-        let mut sm = self.site_matrix.write().await;
-        let server = self
-            .get_webserver_for_wiki(wiki)
-            .ok_or_else(|| anyhow!("Could not find web server for {wiki}"))?;
-        let url = format!("https://{server}/w/api.php");
-        let api = Api::new(&url).await?;
-        let entry = sm.entry(wiki.to_string()).or_insert(api);
-        let ret = entry.get_site_info().to_owned();
-        Ok(ret)
+        let mut sn = self.site_namespaces.write().await;
+        let cache_path = self.site_info_cache_path(wiki);
+        let max_age = time::Duration::from_secs(SITE_INFO_CACHE_MAX_AGE_DAYS * 24 * 60 * 60);
+        let cached = cache_path
+            .as_ref()
+            .and_then(|path| Self::load_cached_namespaces(path, max_age, SystemTime::now()));
+        let namespaces = match cached {
+            Some(namespaces) => namespaces,
+            None => {
+                let api = self.get_api_for_wiki(wiki).await?;
+                let namespaces = api.get_site_info()["query"]["namespaces"].to_owned();
+                if let Some(path) = &cache_path {
+                    if let Err(e) =
+                        Self::store_cached_namespaces(path, &namespaces, SystemTime::now())
+                    {
+                        warn!(wiki, error = %e, "Could not write site info cache");
+                    }
+                }
+                namespaces
+            }
+        };
+        let _ = sn.insert(wiki.to_string(), namespaces.to_owned());
+        Ok(namespaces)
+    }
+
+    // None for tests and for anything that does not look like a wiki name
+    fn site_info_cache_path(&self, wiki: &str) -> Option<String> {
+        if cfg!(test)
+            || wiki.is_empty()
+            || !wiki.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return None;
+        }
+        Some(format!(
+            "{}/{SITE_INFO_CACHE_DIR}/{wiki}.json",
+            self.data_path()
+        ))
+    }
+
+    fn load_cached_namespaces(
+        path: &str,
+        max_age: time::Duration,
+        now: SystemTime,
+    ) -> Option<Value> {
+        let file = std::fs::File::open(path).ok()?;
+        let j: Value = serde_json::from_reader(std::io::BufReader::new(file)).ok()?;
+        let fetched = SystemTime::UNIX_EPOCH + time::Duration::from_secs(j["fetched"].as_u64()?);
+        if now.duration_since(fetched).ok()? > max_age {
+            return None;
+        }
+        let namespaces = j.get("namespaces")?;
+        namespaces.is_object().then(|| namespaces.to_owned())
+    }
+
+    fn store_cached_namespaces(path: &str, namespaces: &Value, now: SystemTime) -> Result<()> {
+        if let Some(dir) = Path::new(path).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let fetched = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+        let j = serde_json::json!({"fetched": fetched, "namespaces": namespaces});
+        std::fs::write(path, j.to_string())?;
+        Ok(())
+    }
+
+    pub fn node_cache_dir(&self) -> String {
+        format!("{}/{NODE_CACHE_DIR}", self.data_path())
+    }
+
+    pub fn node_cache_path(&self, key: &str) -> String {
+        format!("{}/{key}.json", self.node_cache_dir())
+    }
+
+    // A copy of the cached output, so the run owns it, and registers it in the `file` table, like
+    // any other node output; entries whose output file has expired (or that are too old) are removed
+    pub fn load_cached_node_result(
+        &self,
+        key: &str,
+        max_age: time::Duration,
+    ) -> Option<DataFileDetails> {
+        let path = self.node_cache_path(key);
+        let j: Value = serde_json::from_str(&std::fs::read_to_string(&path).ok()?).ok()?;
+        let created = SystemTime::UNIX_EPOCH + time::Duration::from_secs(j["created"].as_u64()?);
+        let is_fresh = SystemTime::now()
+            .duration_since(created)
+            .is_ok_and(|age| age <= max_age);
+        let rows = j["rows"].as_u64()? as usize;
+        let uuid = match is_fresh {
+            true => DataFile::copy_of(j["uuid"].as_str()?).ok(),
+            false => None,
+        };
+        match uuid {
+            Some(uuid) => Some(DataFileDetails::new(&uuid, rows)),
+            None => {
+                let _ = std::fs::remove_file(path);
+                None
+            }
+        }
+    }
+
+    pub fn store_cached_node_result(&self, key: &str, dfd: &DataFileDetails) -> Result<()> {
+        let path = self.node_cache_path(key);
+        if let Some(dir) = Path::new(&path).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let created = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
+        let j = serde_json::json!({"created": created, "uuid": dfd.uuid, "rows": dfd.rows});
+        std::fs::write(path, j.to_string())?;
+        Ok(())
     }
 
     pub fn get_webserver_for_wiki(&self, wiki: &str) -> Option<String> {
@@ -126,9 +351,10 @@ impl App {
     pub async fn find_next_waiting_run(&self, conn: &mut Conn) -> Option<(u64, usize)> {
         // (run_id,workflow_id)
         if let Err(e) = self.activate_scheduled_runs(conn).await {
-            eprintln!("{e}");
+            error!(error = %e, "Cannot activate scheduled runs");
         }
-        "SELECT `id`,`workflow_id` FROM `run` WHERE `status`='WAIT' LIMIT 1"
+        // Manually triggered runs have a higher priority than scheduled ones; oldest first within a priority
+        "SELECT `id`,`workflow_id` FROM `run` WHERE `status`='WAIT' ORDER BY `priority` DESC,`id` LIMIT 1"
             .with(())
             .map(conn, |(run_id, workflow_id)| (run_id, workflow_id))
             .await
@@ -136,6 +362,17 @@ impl App {
             .pop()
     }
 
+    // A user asked for this run to start now, so it goes before any scheduled runs
+    pub async fn queue_manual_run(&self, run_id: usize, conn: &mut Conn) -> Result<()> {
+        let _ = self.clear_run_results(run_id, &mut (*conn)).await;
+        conn.exec_drop(
+            "UPDATE `run` SET `status`='WAIT',`priority`=1 WHERE `status`!='RUN' AND `id`=?",
+            (run_id,),
+        )
+        .await?;
+        Ok(())
+    }
+
     async fn activate_scheduled_runs(&self, conn: &mut Conn) -> Result<()> {
         let sql = "SELECT `run_id` FROM `scheduler` WHERE `is_active`=1 AND `next_event`<now()";
         let run_ids = conn
@@ -144,9 +381,9 @@ impl App {
             .map_and_drop(from_row::<usize>)
             .await?;
         for run_id in run_ids.iter() {
-            let _ = self.clear_all_run_results(*run_id, &mut (*conn)).await;
+            let _ = self.clear_run_results(*run_id, &mut (*conn)).await;
             conn.exec_drop(
-                "UPDATE `run` SET `status`='WAIT' WHERE `status`!='RUN' AND `id`=?",
+                "UPDATE `run` SET `status`='WAIT',`priority`=0 WHERE `status`!='RUN' AND `id`=?",
                 (run_id,),
             )
             .await?;
@@ -162,35 +399,100 @@ impl App {
             .with(())
             .map(&mut (*conn), |(id, uuid)| (id, uuid))
             .await?;
-        self.remove_files(results, conn).await
+        self.remove_files(results, conn).await?;
+        Ok(())
     }
 
-    async fn clear_all_run_results(&self, run_id: usize, conn: &mut Conn) -> Result<()> {
-        let results: Vec<(usize, String)> = "SELECT `id`,`uuid` FROM `file` WHERE `run_id`=?"
+    // Keeps the snapshots of the last finished execution, which the next one compares with
+    pub async fn clear_run_results(&self, run_id: usize, conn: &mut Conn) -> Result<()> {
+        let results: Vec<(usize, String)> = "SELECT `id`,`uuid` FROM `file` WHERE `run_id`=? AND NOT (`is_output`=0 AND `expires` IS NULL)"
             .with((run_id,))
             .map(&mut (*conn), |(id, uuid)| (id, uuid))
             .await?;
-        self.remove_files(results, conn).await
+        self.remove_files(results, conn).await?;
+        Ok(())
     }
 
-    async fn remove_files(&self, results: Vec<(usize, String)>, conn: &mut Conn) -> Result<()> {
-        let mut ids_to_delete = vec![];
-        for (id, uuid) in results {
-            match self.remove_uuid_file(&uuid) {
-                Ok(_) => ids_to_delete.push(format!("{id}")),
-                Err(e) => eprintln!("{e}"),
+    // Batched, so a big cleanup pass does not build an oversized query or hold long locks. Rows of
+    // files that are already gone from disk are removed as well. Returns the number of rows removed.
+    pub async fn remove_files(
+        &self,
+        results: Vec<(usize, String)>,
+        conn: &mut Conn,
+    ) -> Result<usize> {
+        let mut removed = 0;
+        for batch in results.chunks(REMOVE_FILES_BATCH_SIZE) {
+            let mut ids_to_delete = vec![];
+            for (id, uuid) in batch {
+                match self.remove_uuid_file(uuid) {
+                    Ok(_) => ids_to_delete.push(format!("{id}")),
+                    Err(_) if !DataFile::new_from_uuid(uuid).exists() => {
+                        ids_to_delete.push(format!("{id}"))
+                    }
+                    Err(e) => warn!(file_id = id, uuid, error = %e, "Cannot remove file"),
+                }
+            }
+            removed += ids_to_delete.len();
+            if !ids_to_delete.is_empty() {
+                format!(
+                    "DELETE FROM `file` WHERE `id` IN ({})",
+                    ids_to_delete.join(",")
+                )
+                .with(())
+                .run(&mut *conn)
+                .await?;
             }
         }
-        if !ids_to_delete.is_empty() {
-            format!(
-                "DELETE FROM `file` WHERE `id` IN ({})",
-                ids_to_delete.join(",")
-            )
-            .with(())
-            .run(conn)
+        Ok(removed)
+    }
+
+    pub async fn get_user_files(&self, user_id: usize) -> Result<Vec<UserFile>> {
+        let sql = "SELECT `file`.`id`,`file`.`uuid`,`run`.`workflow_id`,`file`.`run_id`,`file`.`node_id`,`file`.`is_output`,`file`.`rows`
+            FROM `file`,`run`,`workflow`
+            WHERE `file`.`run_id`=`run`.`id` AND `run`.`workflow_id`=`workflow`.`id` AND `workflow`.`user_id`=?
+            ORDER BY `file`.`id`";
+        let mut conn = self.get_db_connection().await?;
+        let rows = conn
+            .exec_iter(sql, (user_id,))
+            .await?
+            .map_and_drop(from_row::<(usize, String, usize, usize, usize, bool, usize)>)
             .await?;
-        }
-        Ok(())
+        let files = rows
+            .into_iter()
+            .map(
+                |(id, uuid, workflow_id, run_id, node_id, is_output, rows)| {
+                    let mut df = DataFile::default();
+                    let size = match df.open_input_file(&uuid) {
+                        Ok(_) => df.file_size(),
+                        Err(_) => None, // File is missing on disk
+                    };
+                    UserFile {
+                        id,
+                        uuid,
+                        workflow_id,
+                        run_id,
+                        node_id,
+                        is_output,
+                        rows,
+                        size,
+                    }
+                },
+            )
+            .collect();
+        Ok(files)
+    }
+
+    // Deletes the given files, if they belong to the user. Returns the number of files deleted.
+    pub async fn delete_user_files(&self, user_id: usize, uuids: &[String]) -> Result<usize> {
+        let files: Vec<(usize, String)> = self
+            .get_user_files(user_id)
+            .await?
+            .into_iter()
+            .filter(|file| uuids.contains(&file.uuid))
+            .map(|file| (file.id, file.uuid))
+            .collect();
+        let mut conn = self.get_db_connection().await?;
+        self.remove_files(files, &mut conn).await
     }
 
     pub async fn reset_running_jobs(&self) -> Result<()> {
@@ -212,68 +514,154 @@ impl App {
                 return Err(anyhow!("Could not delete file {path}: {error}"));
             }
         }
+        if let Some(index_path) = df.index_path() {
+            let _ = std::fs::remove_file(index_path);
+        }
         Ok(())
     }
 
     pub fn data_path(&self) -> &str {
-        if cfg!(test) {
-            return "./test_data"; // Testing
-        } else if self.runs_on_toolforge {
-            "/data/project/toolflow/data"
-        } else {
-            "./tmp" // Local box
-        }
+        &self.data_path
     }
 
     pub fn reqwest_client() -> Result<reqwest::Client> {
-        Ok(reqwest::Client::builder()
+        Ok(Self::reqwest_client_builder().build()?)
+    }
+
+    pub fn reqwest_client_builder() -> reqwest::ClientBuilder {
+        reqwest::Client::builder()
             .user_agent(USER_AGENT)
             .timeout(core::time::Duration::from_secs(REQWEST_TIMEOUT))
             .connection_verbose(true)
             .gzip(true)
             .deflate(true)
             .brotli(true)
-            .build()?)
+    }
+
+    pub async fn send_with_retry(request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        Self::send_with_backoff(
+            request,
+            HTTP_MAX_ATTEMPTS,
+            time::Duration::from_millis(HTTP_RETRY_BASE_DELAY_MS),
+        )
+        .await
+    }
+
+    // Other responses, including 4xx errors, are returned right away for the caller to handle;
+    // a 429 or 5xx on the last attempt is an error
+    async fn send_with_backoff(
+        request: reqwest::RequestBuilder,
+        max_attempts: u32,
+        base_delay: time::Duration,
+    ) -> Result<reqwest::Response> {
+        let mut attempt = 1;
+        loop {
+            let this_request = request
+                .try_clone()
+                .ok_or_else(|| anyhow!("Request can not be retried"))?;
+            let (retry_after, error) = match this_request.send().await {
+                Ok(response) => {
+                    let status = response.status();
+                    let is_transient = status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                        || status.is_server_error();
+                    if !is_transient {
+                        return Ok(response);
+                    }
+                    if attempt >= max_attempts {
+                        return Err(anyhow!(
+                            "{} returned HTTP {status} after {attempt} attempts",
+                            response.url()
+                        ));
+                    }
+                    let retry_after = response
+                        .headers()
+                        .get(reqwest::header::RETRY_AFTER)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.trim().parse::<u64>().ok())
+                        .map(|seconds| {
+                            time::Duration::from_secs(seconds.min(HTTP_MAX_RETRY_AFTER_SECONDS))
+                        });
+                    (retry_after, format!("HTTP {status}"))
+                }
+                Err(e) if attempt >= max_attempts => return Err(e.into()),
+                Err(e) => (None, e.to_string()),
+            };
+            let delay = retry_after.unwrap_or(base_delay * 2u32.pow(attempt - 1));
+            warn!(
+                attempt,
+                error,
+                delay_ms = delay.as_millis() as u64,
+                "Retrying request"
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
     }
 
     pub async fn add_user_oauth_to_api(&self, api: &mut Api, user_id: usize) -> Result<()> {
+        let j = self.user_oauth(user_id).await?;
+        let oauth_params = mediawiki::api::OAuthParams::new_from_json(&j);
+        api.set_oauth(Some(oauth_params));
+        Ok(())
+    }
+
+    // The OAuth JSON stored for a user
+    pub async fn user_oauth(&self, user_id: usize) -> Result<Value> {
         let conn = self.get_db_connection().await?;
         let oauth = "SELECT `oauth` FROM `user` WHERE `id`=?"
             .with((user_id,))
             .map(conn, |oauth: String| oauth)
             .await?
-            .iter()
-            .next()
+            .first()
             .ok_or_else(|| anyhow!("User {user_id} does not have OAuth information stored"))?
             .to_owned();
-        let j: Value = serde_json::from_str(&oauth)?;
-        let oauth_params = mediawiki::api::OAuthParams::new_from_json(&j);
-        api.set_oauth(Some(oauth_params));
+        Ok(serde_json::from_str(&oauth)?)
+    }
+
+    // QuickStatements needs its own token, which users get from their QuickStatements user page;
+    // it is kept with the OAuth JSON, see `Generator::quickstatements`
+    pub async fn set_quickstatements_token(&self, user_id: usize, token: &str) -> Result<()> {
+        let oauth = Self::with_quickstatements_token(self.user_oauth(user_id).await?, token)?;
+        "UPDATE `user` SET `oauth`=? WHERE `id`=?"
+            .with((oauth.to_string(), user_id))
+            .run(self.get_db_connection().await?)
+            .await?;
         Ok(())
     }
 
+    fn with_quickstatements_token(mut oauth: Value, token: &str) -> Result<Value> {
+        let token = token.trim();
+        if token.is_empty() {
+            return Err(anyhow!("Empty QuickStatements token"));
+        }
+        oauth
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("Stored OAuth information is not a JSON object"))?
+            .insert(
+                "quickstatements_token".to_string(),
+                Value::String(token.to_string()),
+            );
+        Ok(oauth)
+    }
+
     pub async fn server(&self) -> Result<()> {
         let _ = self
             .clear_old_files(&mut self.get_db_connection().await?)
             .await;
-        let _ = self
-            .reset_running_jobs()
+        self.reset_running_jobs()
             .await
             .expect("Could not reset RUN-state runs to WAIT");
         let mut last_clear_time = SystemTime::now();
 
         loop {
-            match last_clear_time.elapsed() {
-                Ok(elapsed) => {
-                    if elapsed.as_secs() > 5 * 60 {
-                        // Every 5 minutes
-                        let _ = self
-                            .clear_old_files(&mut self.get_db_connection().await?)
-                            .await;
-                        last_clear_time = SystemTime::now();
-                    }
+            if let Ok(elapsed) = last_clear_time.elapsed() {
+                if elapsed.as_secs() > 5 * 60 {
+                    // Every 5 minutes
+                    let _ = self
+                        .clear_old_files(&mut self.get_db_connection().await?)
+                        .await;
+                    last_clear_time = SystemTime::now();
                 }
-                Err(_) => {}
             }
 
             let mut conn = self.get_db_connection().await?;
@@ -282,7 +670,7 @@ impl App {
                     let mut workflow = match Workflow::from_id(workflow_id).await {
                         Ok(workflow) => workflow,
                         Err(e) => {
-                            eprintln!("Cannot get workflow {workflow_id}: {e}");
+                            error!(workflow_id, run_id, error = %e, "Cannot get workflow");
                             continue;
                         }
                     };
@@ -292,14 +680,25 @@ impl App {
                         .update_status(WorkflowNodeStatusValue::RUNNING, &mut conn)
                         .await
                     {
-                        eprintln!("Cannot update initial status: {e}");
+                        error!(workflow_id, run_id, error = %e, "Cannot update initial status");
                         continue;
                     }
-                    println!("Starting workflow {workflow_id} run {run_id}");
+                    info!(workflow_id, run_id, "Starting workflow run");
                     tokio::spawn(async move {
-                        println!("Started workflow {workflow_id} run {run_id}");
+                        info!(workflow_id, run_id, "Started workflow run");
                         let result = workflow.run().await;
-                        println!("Finished workflow {workflow_id} run {run_id}: {result:?}");
+                        let duration = match APP.get_db_connection().await {
+                            Ok(mut conn) => {
+                                workflow.run.get_duration(&mut conn).await.ok().flatten()
+                            }
+                            Err(_) => None,
+                        };
+                        match result {
+                            Ok(_) => info!(workflow_id, run_id, duration, "Finished workflow run"),
+                            Err(e) => {
+                                warn!(workflow_id, run_id, duration, error = %e, "Workflow run failed")
+                            }
+                        }
                     });
                 }
                 None => self.hold_on(),
@@ -307,3 +706,166 @@ impl App {
         }
     }
 }
+
+// Answers each connection with the next (status, body) of `responses`, then closes it
+#[cfg(test)]
+pub async fn mock_http_server(responses: Vec<(&'static str, &'static str)>) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        for (status, body) in responses {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buffer = [0; 4096];
+            let _ = socket.read(&mut buffer).await.unwrap();
+            let response = format!(
+                "HTTP/1.1 {status}\r\nRetry-After: 0\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflow::Workflow;
+
+    #[tokio::test]
+    async fn test_find_next_waiting_run_prefers_manual_runs() {
+        let mut conn = APP.get_db_connection().await.unwrap();
+        let mut run_ids = vec![];
+        for _ in 0..2 {
+            let mut workflow = Workflow::new(vec![], vec![], 0);
+            run_ids.push(workflow.run.get_or_create_id().await.unwrap());
+        }
+        // The older run is scheduled, the newer one was triggered manually
+        "UPDATE `run` SET `status`='WAIT',`priority`=0 WHERE `id`=?"
+            .with((run_ids[0],))
+            .run(&mut conn)
+            .await
+            .unwrap();
+        APP.queue_manual_run(run_ids[1] as usize, &mut conn)
+            .await
+            .unwrap();
+        let (next_run_id, _) = APP.find_next_waiting_run(&mut conn).await.unwrap();
+        assert_ne!(next_run_id, run_ids[0]);
+        let priority: Option<u8> = "SELECT `priority` FROM `run` WHERE `id`=?"
+            .with((next_run_id,))
+            .first(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(priority, Some(1));
+
+        // Cleanup
+        "DELETE FROM `run` WHERE `id` IN (?,?)"
+            .with((run_ids[0], run_ids[1]))
+            .run(&mut conn)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_with_quickstatements_token() {
+        let oauth =
+            App::with_quickstatements_token(serde_json::json!({"g_consumer_key": "x"}), " abc\n")
+                .unwrap();
+        assert_eq!(
+            oauth,
+            serde_json::json!({"g_consumer_key": "x", "quickstatements_token": "abc"})
+        );
+        assert!(App::with_quickstatements_token(serde_json::json!({}), " ").is_err());
+        assert!(App::with_quickstatements_token(serde_json::json!("oauth"), "abc").is_err());
+    }
+
+    #[test]
+    fn test_site_info_cache_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = format!("{}/site_info/enwiki.json", dir.path().display());
+        let namespaces = serde_json::json!({"0": {"id": 0, "*": ""}, "1": {"id": 1, "*": "Talk"}});
+        let fetched = SystemTime::now();
+        App::store_cached_namespaces(&path, &namespaces, fetched).unwrap();
+        let day = time::Duration::from_secs(24 * 60 * 60);
+        assert_eq!(
+            App::load_cached_namespaces(&path, day * 7, fetched + day),
+            Some(namespaces)
+        );
+        assert_eq!(
+            App::load_cached_namespaces(&path, day * 7, fetched + day * 8),
+            None
+        );
+        assert_eq!(
+            App::load_cached_namespaces("/no/such/file.json", day, fetched),
+            None
+        );
+    }
+
+    #[test]
+    fn test_detect_toolforge_override() {
+        assert!(App::detect_toolforge(Some("toolforge".to_string())));
+        assert!(App::detect_toolforge(Some(" Toolforge ".to_string())));
+        assert!(!App::detect_toolforge(Some("local".to_string())));
+    }
+
+    async fn mock_server(statuses: Vec<&'static str>) -> String {
+        mock_http_server(statuses.into_iter().map(|status| (status, "ok")).collect()).await
+    }
+
+    #[tokio::test]
+    async fn test_send_with_backoff() {
+        let delay = time::Duration::from_millis(1);
+        let url = mock_server(vec![
+            "503 Service Unavailable",
+            "429 Too Many Requests",
+            "200 OK",
+        ])
+        .await;
+        let request = App::reqwest_client().unwrap().get(&url);
+        let response = App::send_with_backoff(request, 3, delay).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "ok");
+
+        // Not retried
+        let url = mock_server(vec!["404 Not Found", "200 OK"]).await;
+        let request = App::reqwest_client().unwrap().get(&url);
+        let response = App::send_with_backoff(request, 3, delay).await.unwrap();
+        assert_eq!(response.status(), 404);
+
+        // Out of attempts
+        let url = mock_server(vec!["502 Bad Gateway", "502 Bad Gateway", "200 OK"]).await;
+        let request = App::reqwest_client().unwrap().get(&url);
+        let error = App::send_with_backoff(request, 2, delay).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("{url} returned HTTP 502 Bad Gateway after 2 attempts")
+        );
+    }
+
+    #[test]
+    fn test_data_path_from_env() {
+        assert_eq!(App::new().data_path(), "./test_data");
+        let app = App {
+            data_path: App::data_path_from_env(Some("/srv/toolflow/data/".to_string()), false),
+            ..App::new()
+        };
+        assert_eq!(app.data_path(), "/srv/toolflow/data");
+        assert_eq!(
+            App::data_path_from_env(Some(" ".to_string()), true),
+            "/data/project/toolflow/data"
+        );
+        assert_eq!(App::data_path_from_env(None, false), "./tmp");
+    }
+
+    #[test]
+    fn test_api_delay_from_env() {
+        assert_eq!(App::api_delay_from_env(None), DEFAULT_API_DELAY_MS);
+        assert_eq!(App::api_delay_from_env(Some(" 0 ".to_string())), 0);
+        assert_eq!(App::api_delay_from_env(Some("1500".to_string())), 1500);
+        assert_eq!(
+            App::api_delay_from_env(Some("slow".to_string())),
+            DEFAULT_API_DELAY_MS
+        );
+    }
+}
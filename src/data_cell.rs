@@ -1,9 +1,10 @@
 use std::cmp::Ordering;
 
+use chrono::NaiveDate;
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 
 use crate::{
     data_header::{ColumnHeader, ColumnHeaderType},
@@ -11,16 +12,48 @@ use crate::{
 };
 
 lazy_static! {
-    static ref RE_WIKIDATA_ITEM: Regex =
-        Regex::new(r"^https?://www.wikidata.org/entity/(Q\d+)$").expect("RegEx fail");
+    // Entity URLs as returned by WDQS and the Commons Query Service (sdc: prefix)
+    static ref RE_ENTITY_URL: Regex = Regex::new(
+        r"^https?://(www\.wikidata\.org|commons\.wikimedia\.org)/entity/([QPLM]\d+)$"
+    )
+    .expect("RegEx fail");
+    // WKT as returned by WDQS; longitude first, with an optional globe IRI in front
+    static ref RE_WKT_POINT: Regex = Regex::new(
+        r"(?i)^(?:<[^>]*>\s*)?Point\(\s*([-+]?\d+(?:\.\d+)?)\s+([-+]?\d+(?:\.\d+)?)\s*\)$"
+    )
+    .expect("RegEx fail");
+    // Decimal degrees, latitude first
+    static ref RE_LAT_LON: Regex =
+        Regex::new(r"^([-+]?\d+(?:\.\d+)?)\s*[,;/ ]\s*([-+]?\d+(?:\.\d+)?)$").expect("RegEx fail");
+    // Wikidata time values like "+1500-00-00T00:00:00Z"; month and day may be 00
+    static ref RE_WIKIDATA_TIME: Regex =
+        Regex::new(r"^([-+]?\d+)-(\d\d)-(\d\d)(?:T|$)").expect("RegEx fail");
 }
 
+// Wikidata time precisions
+pub const TIME_PRECISION_YEAR: u8 = 9;
+pub const TIME_PRECISION_MONTH: u8 = 10;
+pub const TIME_PRECISION_DAY: u8 = 11;
+pub const CALENDAR_GREGORIAN: &str = "http://www.wikidata.org/entity/Q1985727";
+const CALENDAR_JULIAN_ITEM: &str = "Q1985786";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DataCell {
     PlainText(String),
     WikiPage(WikiPage),
     Int(i64),
     Float(f64),
+    Coordinate {
+        lat: f64,
+        lon: f64,
+    },
+    Boolean(bool),
+    Date(String), // Always YYYY-MM-DD, so it sorts chronologically
+    WikidataTime {
+        time: String, // As in Wikidata, e.g. "+1500-00-00T00:00:00Z"
+        precision: u8,
+        calendar: String, // Calendar model item URL
+    },
     Blank,
 }
 
@@ -31,11 +64,30 @@ impl PartialEq for DataCell {
             (Self::WikiPage(l0), Self::WikiPage(r0)) => l0 == r0,
             (Self::Int(l0), Self::Int(r0)) => l0 == r0,
             (Self::Float(l0), Self::Float(r0)) => l0 == r0,
+            (Self::Coordinate { lat: l0, lon: l1 }, Self::Coordinate { lat: r0, lon: r1 }) => {
+                l0 == r0 && l1 == r1
+            }
+            (Self::Boolean(l0), Self::Boolean(r0)) => l0 == r0,
+            (Self::Date(l0), Self::Date(r0)) => l0 == r0,
+            (
+                Self::WikidataTime {
+                    time: l0,
+                    precision: l1,
+                    calendar: l2,
+                },
+                Self::WikidataTime {
+                    time: r0,
+                    precision: r1,
+                    calendar: r2,
+                },
+            ) => l0 == r0 && l1 == r1 && l2 == r2,
             _ => core::mem::discriminant(self) == core::mem::discriminant(other),
         }
     }
 }
 
+// Blank sorts first; Int and Float compare by value. Other mixed pairs have no defined order
+// (None), so callers must not read `<` on them as "false".
 impl PartialOrd for DataCell {
     fn partial_cmp(&self, other: &DataCell) -> Option<Ordering> {
         // println!("{self:?} <=> {other:?}");
@@ -59,25 +111,64 @@ impl PartialOrd for DataCell {
             // (DataCell::Float(_), DataCell::WikiPage(_)) => todo!(),
             (DataCell::Float(f), DataCell::Int(i)) => f.partial_cmp(&(*i as f64)),
             (DataCell::Float(f1), DataCell::Float(f2)) => f1.partial_cmp(f2),
+            (DataCell::Boolean(b1), DataCell::Boolean(b2)) => b1.partial_cmp(b2),
+            (DataCell::Date(d1), DataCell::Date(d2)) => d1.partial_cmp(d2),
+            // Precision and calendar only break ties, so that only equal values compare as Equal
+            (
+                DataCell::WikidataTime {
+                    time: t1,
+                    precision: p1,
+                    calendar: c1,
+                },
+                DataCell::WikidataTime {
+                    time: t2,
+                    precision: p2,
+                    calendar: c2,
+                },
+            ) => Some(
+                Self::wikidata_time_parts(t1)?
+                    .cmp(&Self::wikidata_time_parts(t2)?)
+                    .then(p1.cmp(p2))
+                    .then(c1.cmp(c2)),
+            ),
             _ => None,
         }
     }
 }
 
 impl DataCell {
-    fn entity_from_url(url: &str) -> Option<(i64, String)> {
-        // namespace_id, page_title
-        match RE_WIKIDATA_ITEM.captures_iter(url).next() {
-            Some(cap) => {
-                let title = cap[1].to_string();
-                let ns_id = match title.chars().next() {
-                    Some('Q') => 0,
-                    Some('P') => 120,
-                    _ => return None,
-                };
-                Some((ns_id, title))
-            }
-            None => None, // No match
+    fn entity_from_url(url: &str) -> Option<(&'static str, i64, String)> {
+        // wiki, namespace_id, entity ID
+        let cap = RE_ENTITY_URL.captures(url)?;
+        let id = cap[2].to_string();
+        let wiki = match &cap[1] {
+            "commons.wikimedia.org" => "commonswiki",
+            _ => "wikidatawiki",
+        };
+        let ns_id = match (wiki, id.chars().next()) {
+            ("wikidatawiki", Some('Q')) => 0,
+            ("wikidatawiki", Some('P')) => 120,
+            ("wikidatawiki", Some('L')) => 146,
+            ("commonswiki", Some('M')) => 6,
+            _ => return None,
+        };
+        Some((wiki, ns_id, id))
+    }
+
+    // Parses decimal degrees ("lat,lon") or a WKT point ("Point(lon lat)") into (lat, lon)
+    pub fn parse_coordinate(s: &str) -> Option<(f64, f64)> {
+        let s = s.trim();
+        let (lat, lon) = if let Some(cap) = RE_WKT_POINT.captures(s) {
+            (cap[2].parse::<f64>().ok()?, cap[1].parse::<f64>().ok()?)
+        } else if let Some(cap) = RE_LAT_LON.captures(s) {
+            (cap[1].parse::<f64>().ok()?, cap[2].parse::<f64>().ok()?)
+        } else {
+            return None;
+        };
+        if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) {
+            Some((lat, lon))
+        } else {
+            None
         }
     }
 
@@ -99,10 +190,17 @@ impl DataCell {
                         "page_id" => wiki_page.page_id = s.parse::<i64>().ok(),
                         "wiki" => wiki_page.wiki = Some(s.to_owned()),
                         "entity_url" => {
-                            if let Some((ns_id, title)) = Self::entity_from_url(s) {
+                            if let Some((wiki, ns_id, id)) = Self::entity_from_url(s) {
+                                wiki_page.wiki = Some(wiki.to_string());
                                 wiki_page.ns_id = Some(ns_id);
-                                wiki_page.title = Some(title.to_owned());
-                                wiki_page.prefixed_title = Some(title.to_owned());
+                                match id.strip_prefix('M') {
+                                    // A MediaInfo ID is the page ID of the file it describes
+                                    Some(page_id) => wiki_page.page_id = page_id.parse().ok(),
+                                    None => {
+                                        wiki_page.title = Some(id.to_owned());
+                                        wiki_page.prefixed_title = Some(id);
+                                    }
+                                }
                             }
                         }
                         _ => return None,
@@ -114,6 +212,211 @@ impl DataCell {
             }
             ColumnHeaderType::Int => Some(Self::Int(value.as_i64()?)),
             ColumnHeaderType::Float => Some(Self::Float(value.as_f64()?)),
+            ColumnHeaderType::Coordinate => {
+                let (lat, lon) = Self::parse_coordinate(value.as_str()?)?;
+                Some(Self::Coordinate { lat, lon })
+            }
+            ColumnHeaderType::Boolean => Some(Self::Boolean(match value {
+                Value::Bool(b) => *b,
+                Value::Number(n) => Self::parse_bool(&n.to_string())?,
+                Value::String(s) => Self::parse_bool(s)?,
+                _ => return None,
+            })),
+            ColumnHeaderType::Date => Some(Self::Date(Self::parse_date(&match value {
+                Value::String(s) => s.to_owned(),
+                other => other.to_string(),
+            })?)),
+            ColumnHeaderType::WikidataTime => Self::wikidata_time_from_value(value, element_name),
+        }
+    }
+
+    // A `wbgetentities` time value object, or a time string as returned by SPARQL; for the latter,
+    // `element_name` can give the precision ("year", "month", "day", or a Wikidata precision number)
+    fn wikidata_time_from_value(value: &Value, element_name: &str) -> Option<Self> {
+        let (time, precision, calendar) = match value {
+            Value::Object(_) => (
+                value["time"].as_str()?.to_string(),
+                value["precision"].as_u64()? as u8,
+                value["calendarmodel"]
+                    .as_str()
+                    .unwrap_or(CALENDAR_GREGORIAN)
+                    .to_string(),
+            ),
+            Value::String(s) => {
+                let precision = match element_name {
+                    "year" => TIME_PRECISION_YEAR,
+                    "month" => TIME_PRECISION_MONTH,
+                    other => other.parse::<u8>().unwrap_or(TIME_PRECISION_DAY),
+                };
+                let time = match s.trim() {
+                    t if t.starts_with(['+', '-']) => t.to_string(),
+                    t => format!("+{t}"),
+                };
+                (time, precision, CALENDAR_GREGORIAN.to_string())
+            }
+            _ => return None,
+        };
+        Self::wikidata_time_parts(&time)?;
+        Some(Self::WikidataTime {
+            time,
+            precision,
+            calendar,
+        })
+    }
+
+    // A time given as text, like a filter value: a date at day precision, or a bare year
+    pub fn wikidata_time_from_str(s: &str) -> Option<Self> {
+        let (time, precision) = match Self::parse_date(s) {
+            Some(date) => (format!("+{date}T00:00:00Z"), TIME_PRECISION_DAY),
+            None => {
+                let year = s.trim().parse::<i64>().ok()?;
+                (format!("{year:+}-00-00T00:00:00Z"), TIME_PRECISION_YEAR)
+            }
+        };
+        Some(Self::WikidataTime {
+            time,
+            precision,
+            calendar: CALENDAR_GREGORIAN.to_string(),
+        })
+    }
+
+    // (year, month, day) of a Wikidata time string
+    fn wikidata_time_parts(time: &str) -> Option<(i64, u32, u32)> {
+        let cap = RE_WIKIDATA_TIME.captures(time.trim())?;
+        Some((
+            cap[1].parse().ok()?,
+            cap[2].parse().ok()?,
+            cap[3].parse().ok()?,
+        ))
+    }
+
+    // Sorts like the time itself: years are zero-padded, BCE years are stored as the distance from
+    // a far-off year behind a "-", which sorts before digits; the precision and calendar keep
+    // different values apart
+    fn wikidata_time_key(time: &str, precision: u8, calendar: &str) -> String {
+        let (year, month, day) = match Self::wikidata_time_parts(time) {
+            Some(parts) => parts,
+            None => return time.to_string(),
+        };
+        let year = match year {
+            y if y < 0 => format!("-{:016}", 10_i64.pow(16) - y.abs()),
+            y => format!("{y:017}"),
+        };
+        let calendar = match calendar.ends_with(CALENDAR_JULIAN_ITEM) {
+            true => "J",
+            false => "",
+        };
+        format!("{year}-{month:02}-{day:02}/{precision:02}{calendar}")
+    }
+
+    // Only as precise as the value: "1500" for a year, "1500-03" for a month, "1500-03-07" for a day
+    pub fn format_wikidata_time(time: &str, precision: u8, calendar: &str) -> String {
+        let (year, month, day) = match Self::wikidata_time_parts(time) {
+            Some(parts) => parts,
+            None => return time.to_string(),
+        };
+        let year = match year {
+            y if y < 0 => format!("{} BCE", -y),
+            y => format!("{y}"),
+        };
+        let ret = match precision {
+            p if p >= TIME_PRECISION_DAY => format!("{year}-{month:02}-{day:02}"),
+            TIME_PRECISION_MONTH => format!("{year}-{month:02}"),
+            _ => year,
+        };
+        if calendar.ends_with(CALENDAR_JULIAN_ITEM) {
+            format!("{ret} (Julian)")
+        } else {
+            ret
+        }
+    }
+
+    // Flags as returned by source tools: true/false or 1/0
+    pub fn parse_bool(s: &str) -> Option<bool> {
+        match s.trim().to_lowercase().as_str() {
+            "true" | "1" => Some(true),
+            "false" | "0" => Some(false),
+            _ => None,
+        }
+    }
+
+    // ISO-8601 dates and timestamps, MediaWiki/MySQL timestamps, and English dates like "1 February 2020"
+    pub fn parse_date(s: &str) -> Option<String> {
+        let s = s.trim();
+        let iso_prefix = s.split(['T', ' ']).next().unwrap_or(s);
+        let date = NaiveDate::parse_from_str(iso_prefix, "%Y-%m-%d")
+            .ok()
+            .or_else(|| match s.len() {
+                8 | 14 if s.chars().all(|c| c.is_ascii_digit()) => {
+                    NaiveDate::parse_from_str(&s[0..8], "%Y%m%d").ok()
+                }
+                _ => None,
+            })
+            .or_else(|| {
+                ["%d %B %Y", "%B %d, %Y", "%d %b %Y", "%b %d, %Y"]
+                    .iter()
+                    .find_map(|format| NaiveDate::parse_from_str(s, format).ok())
+            })?;
+        Some(date.format("%Y-%m-%d").to_string())
+    }
+
+    // Canonical JSON for exporters: scalars as JSON scalars, WikiPage and Coordinate as objects, Blank as null
+    pub fn to_json(&self) -> Value {
+        match self {
+            DataCell::PlainText(s) => json!(s),
+            DataCell::WikiPage(wp) => json!({
+                "wiki": wp.wiki,
+                "prefixed_title": wp.prefixed_title,
+                "title": wp.title,
+                "ns_id": wp.ns_id,
+                "ns_prefix": wp.ns_prefix,
+                "page_id": wp.page_id,
+            }),
+            DataCell::Int(i) => json!(i),
+            DataCell::Float(f) => json!(f),
+            DataCell::Coordinate { lat, lon } => json!({"lat": lat, "lon": lon}),
+            DataCell::Boolean(b) => json!(b),
+            DataCell::Date(d) => json!(d),
+            DataCell::WikidataTime {
+                time,
+                precision,
+                calendar,
+            } => json!({"time": time, "precision": precision, "calendar": calendar}),
+            DataCell::Blank => Value::Null,
+        }
+    }
+
+    // Inverse of `to_json`, for a column of the given type
+    pub fn from_json(value: &Value, col_header: &ColumnHeader) -> Option<Self> {
+        if value.is_null() {
+            return Some(Self::Blank);
+        }
+        match &col_header.kind {
+            ColumnHeaderType::PlainText => Some(Self::PlainText(value.as_str()?.to_string())),
+            ColumnHeaderType::WikiPage(_) => {
+                let string = |key: &str| value[key].as_str().map(|s| s.to_string());
+                Some(Self::WikiPage(WikiPage {
+                    wiki: string("wiki"),
+                    prefixed_title: string("prefixed_title"),
+                    title: string("title"),
+                    ns_id: value["ns_id"].as_i64(),
+                    ns_prefix: string("ns_prefix"),
+                    page_id: value["page_id"].as_i64(),
+                }))
+            }
+            ColumnHeaderType::Int => Some(Self::Int(value.as_i64()?)),
+            ColumnHeaderType::Float => Some(Self::Float(value.as_f64()?)),
+            ColumnHeaderType::Coordinate => Some(Self::Coordinate {
+                lat: value["lat"].as_f64()?,
+                lon: value["lon"].as_f64()?,
+            }),
+            ColumnHeaderType::Boolean => Some(Self::Boolean(value.as_bool()?)),
+            ColumnHeaderType::Date => Some(Self::Date(Self::parse_date(value.as_str()?)?)),
+            ColumnHeaderType::WikidataTime => Some(Self::WikidataTime {
+                time: value["time"].as_str()?.to_string(),
+                precision: value["precision"].as_u64()? as u8,
+                calendar: value["calendar"].as_str()?.to_string(),
+            }),
         }
     }
 
@@ -128,10 +431,29 @@ impl DataCell {
                 let fallback = format!("{ns_prefix}:{title}");
                 let fullname = wiki_page.prefixed_title.as_ref().unwrap_or(&fallback);
                 let wiki = wiki_page.wiki.as_ref().unwrap_or(&blank);
-                format!("{wiki}::{fullname}")
+                // MediaInfo entities from entity URLs only know the page ID of their file
+                let is_mediainfo = wiki == "commonswiki" && wiki_page.ns_id == Some(6);
+                match wiki_page.page_id {
+                    Some(page_id)
+                        if is_mediainfo
+                            && wiki_page.title.is_none()
+                            && wiki_page.prefixed_title.is_none() =>
+                    {
+                        format!("{wiki}::M{page_id}")
+                    }
+                    _ => format!("{wiki}::{fullname}"),
+                }
             }
             DataCell::Int(i) => format!("{i}"),
             DataCell::Float(f) => format!("{f}"),
+            DataCell::Coordinate { lat, lon } => format!("{lat},{lon}"),
+            DataCell::Boolean(b) => format!("{b}"),
+            DataCell::Date(d) => d.to_string(),
+            DataCell::WikidataTime {
+                time,
+                precision,
+                calendar,
+            } => Self::wikidata_time_key(time, *precision, calendar),
             DataCell::Blank => String::new(),
         }
     }
@@ -153,12 +475,221 @@ impl DataCell {
                     .as_ref()
                     .map(|x| DataCell::PlainText(x.to_owned())),
                 "wiki" => wp.wiki.as_ref().map(|x| DataCell::PlainText(x.to_owned())),
-                "ns_id" => wp.ns_id.map(|x| DataCell::Int(x)),
-                "page_id" => wp.page_id.map(|x| DataCell::Int(x)),
+                "ns_id" => wp.ns_id.map(DataCell::Int),
+                "page_id" => wp.page_id.map(DataCell::Int),
                 _ => None,
             }
-            .unwrap_or_else(|| Self::Blank),
-            None => Self::Blank,
+            .unwrap_or(Self::Blank),
+            None => Self::PlainText(self.as_key()), // The whole page, wiki::prefixed_title
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(kind: ColumnHeaderType) -> ColumnHeader {
+        ColumnHeader {
+            name: "test".to_string(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_entity_from_url() {
+        assert_eq!(
+            DataCell::entity_from_url("http://www.wikidata.org/entity/Q42"),
+            Some(("wikidatawiki", 0, "Q42".to_string()))
+        );
+        assert_eq!(
+            DataCell::entity_from_url("http://www.wikidata.org/entity/L7"),
+            Some(("wikidatawiki", 146, "L7".to_string()))
+        );
+        assert_eq!(
+            DataCell::entity_from_url("https://commons.wikimedia.org/entity/M12345"),
+            Some(("commonswiki", 6, "M12345".to_string()))
+        );
+        assert_eq!(
+            DataCell::entity_from_url("https://commons.wikimedia.org/entity/Q42"),
+            None
+        );
+
+        let mediainfo = DataCell::WikiPage(WikiPage {
+            wiki: Some("commonswiki".to_string()),
+            ns_id: Some(6),
+            page_id: Some(12345),
+            ..Default::default()
+        });
+        assert_eq!(mediainfo.as_key(), "commonswiki::M12345");
+    }
+
+    #[test]
+    fn test_wikidata_time_precision_order() {
+        let year =
+            DataCell::wikidata_time_from_value(&json!("1500-01-01T00:00:00Z"), "year").unwrap();
+        let day =
+            DataCell::wikidata_time_from_value(&json!("1500-01-01T00:00:00Z"), "day").unwrap();
+        assert_ne!(year, day);
+        assert_eq!(year.partial_cmp(&day), Some(Ordering::Less));
+        assert_eq!(year.partial_cmp(&year.clone()), Some(Ordering::Equal));
+        let year = DataCell::wikidata_time_from_str("1500").unwrap();
+        let day = DataCell::wikidata_time_from_str("1500-01-01").unwrap();
+        assert_ne!(year, day);
+        assert_eq!(year.partial_cmp(&day), Some(Ordering::Less));
+    }
+
+    #[test]
+    fn test_boolean_eq() {
+        assert_eq!(DataCell::Boolean(true), DataCell::Boolean(true));
+        assert_ne!(DataCell::Boolean(true), DataCell::Boolean(false));
+    }
+
+    #[test]
+    fn test_to_json_round_trip() {
+        let wp = WikiPage {
+            wiki: Some("enwiki".to_string()),
+            prefixed_title: Some("Talk:Foo".to_string()),
+            title: Some("Foo".to_string()),
+            ns_id: Some(1),
+            ns_prefix: Some("Talk".to_string()),
+            page_id: Some(12345),
+        };
+        let cases = vec![
+            (
+                DataCell::PlainText("a".to_string()),
+                ColumnHeaderType::PlainText,
+            ),
+            (
+                DataCell::WikiPage(wp),
+                ColumnHeaderType::WikiPage(WikiPage::default()),
+            ),
+            (DataCell::Int(-3), ColumnHeaderType::Int),
+            (DataCell::Float(2.5), ColumnHeaderType::Float),
+            (
+                DataCell::Coordinate {
+                    lat: 52.5,
+                    lon: 13.4,
+                },
+                ColumnHeaderType::Coordinate,
+            ),
+            (DataCell::Boolean(false), ColumnHeaderType::Boolean),
+            (
+                DataCell::Date("2020-02-01".to_string()),
+                ColumnHeaderType::Date,
+            ),
+            (
+                DataCell::WikidataTime {
+                    time: "+1500-00-00T00:00:00Z".to_string(),
+                    precision: TIME_PRECISION_YEAR,
+                    calendar: CALENDAR_GREGORIAN.to_string(),
+                },
+                ColumnHeaderType::WikidataTime,
+            ),
+            (DataCell::Blank, ColumnHeaderType::Int),
+        ];
+        for (cell, kind) in cases {
+            let j = cell.to_json();
+            assert_eq!(DataCell::from_json(&j, &header(kind)), Some(cell));
+        }
+        assert_eq!(DataCell::Blank.to_json(), Value::Null);
+        assert_eq!(DataCell::Int(7).to_json(), json!(7));
+        assert_eq!(
+            DataCell::from_json(&json!("x"), &header(ColumnHeaderType::Int)),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_boolean_from_value() {
+        let h = header(ColumnHeaderType::Boolean);
+        for (value, expected) in [
+            (json!(true), Some(true)),
+            (json!("false"), Some(false)),
+            (json!("1"), Some(true)),
+            (json!(0), Some(false)),
+            (json!("yes"), None),
+        ] {
+            assert_eq!(
+                DataCell::from_value(&value, &h, "").await,
+                expected.map(DataCell::Boolean)
+            );
         }
+        assert!(DataCell::Boolean(false) < DataCell::Boolean(true));
+        assert_eq!(DataCell::Boolean(true).as_key(), "true");
+    }
+
+    #[test]
+    fn test_parse_date() {
+        for s in [
+            "2020-02-01",
+            "2020-02-01T12:34:56Z",
+            "2020-02-01 12:34:56",
+            "20200201123456",
+            "1 February 2020",
+            "February 1, 2020",
+            "1 Feb 2020",
+        ] {
+            assert_eq!(
+                DataCell::parse_date(s),
+                Some("2020-02-01".to_string()),
+                "{s}"
+            );
+        }
+        assert_eq!(DataCell::parse_date("next Tuesday"), None);
+        assert_eq!(DataCell::parse_date("2020-02-30"), None);
+    }
+
+    #[test]
+    fn test_date_ordering() {
+        let mut cells: Vec<DataCell> = ["9 March 2021", "20191231000000", "2020-02-01"]
+            .iter()
+            .map(|s| DataCell::Date(DataCell::parse_date(s).unwrap()))
+            .collect();
+        cells.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let keys: Vec<String> = cells.iter().map(|c| c.as_key()).collect();
+        assert_eq!(keys, vec!["2019-12-31", "2020-02-01", "2021-03-09"]);
+    }
+
+    #[tokio::test]
+    async fn test_wikidata_time() {
+        let h = header(ColumnHeaderType::WikidataTime);
+        let text = |cell: &DataCell| match cell {
+            DataCell::WikidataTime {
+                time,
+                precision,
+                calendar,
+            } => DataCell::format_wikidata_time(time, *precision, calendar),
+            _ => panic!("Not a WikidataTime: {cell:?}"),
+        };
+        let snak_value = json!({
+            "time": "+1500-00-00T00:00:00Z",
+            "precision": 9,
+            "calendarmodel": "http://www.wikidata.org/entity/Q1985786"
+        });
+        let year = DataCell::from_value(&snak_value, &h, "").await.unwrap();
+        assert_eq!(text(&year), "1500 (Julian)");
+        assert_eq!(year.as_key(), "00000000000001500-00-00/09J");
+
+        let sparql = json!("1500-03-07T00:00:00Z");
+        let day = DataCell::from_value(&sparql, &h, "").await.unwrap();
+        assert_eq!(text(&day), "1500-03-07");
+        let month = DataCell::from_value(&sparql, &h, "month").await.unwrap();
+        assert_eq!(text(&month), "1500-03");
+        assert!(year < day);
+        assert!(year.as_key() < day.as_key());
+        assert_ne!(day.as_key(), month.as_key());
+
+        let bce = DataCell::from_value(&json!("-0044-03-15T00:00:00Z"), &h, "year")
+            .await
+            .unwrap();
+        assert_eq!(text(&bce), "44 BCE");
+        assert!(bce < year);
+        assert!(bce.as_key() < year.as_key());
+        let earlier_bce = DataCell::from_value(&json!("-0500-01-01T00:00:00Z"), &h, "year")
+            .await
+            .unwrap();
+        assert!(earlier_bce.as_key() < bce.as_key());
+        assert_eq!(DataCell::from_value(&json!("soon"), &h, "").await, None);
     }
 }
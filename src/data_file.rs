@@ -2,12 +2,33 @@ use crate::data_cell::DataCell;
 use crate::data_header::DataHeader;
 use crate::APP;
 use anyhow::{anyhow, Result};
-use serde_json::Value;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde_json::{json, Value};
 use std::collections::HashMap;
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::{fmt, fs::File};
 use uuid::Uuid;
 
+tokio::task_local! {
+    // Data rows written by the workflow node running in this task, for progress reporting; a
+    // node writes each of its output rows once, while it may read its inputs several times
+    pub static ROWS_PROCESSED: Arc<AtomicUsize>;
+    // UUIDs of the files finished by the workflow node running in this task, so they can be
+    // removed if the node is aborted
+    pub static FILES_WRITTEN: Arc<Mutex<Vec<String>>>;
+}
+
+// Outside of a workflow node there is nothing to report to
+pub fn count_processed_row() {
+    let _ = ROWS_PROCESSED.try_with(|rows| rows.fetch_add(1, Ordering::Relaxed));
+}
+
+// Files smaller than this (uncompressed) are cheap enough to scan, so they do not get a sidecar key index
+const INDEX_MIN_FILE_SIZE: u64 = 10_000_000;
+
 // This class is used for thread-/async-safe passing of key data
 #[derive(Default, Clone, Debug)]
 pub struct DataFileDetails {
@@ -17,6 +38,14 @@ pub struct DataFileDetails {
 }
 
 impl DataFileDetails {
+    pub fn new(uuid: &str, rows: usize) -> Self {
+        Self {
+            uuid: uuid.to_string(),
+            rows,
+            is_valid: true,
+        }
+    }
+
     pub fn new_invalid() -> Self {
         Self {
             is_valid: false,
@@ -29,14 +58,105 @@ impl DataFileDetails {
     }
 }
 
+enum DataReader {
+    Plain(BufReader<File>),
+    // Compressed files cannot seek, so the position (in uncompressed bytes) is tracked here
+    Gzip {
+        reader: Box<BufReader<GzDecoder<File>>>,
+        path: String,
+        position: u64,
+    },
+}
+
+impl DataReader {
+    fn open(path: &str) -> Result<Self> {
+        let file = File::open(path)?;
+        if path.ends_with(".gz") {
+            Ok(Self::Gzip {
+                reader: Box::new(BufReader::new(GzDecoder::new(file))),
+                path: path.to_string(),
+                position: 0,
+            })
+        } else {
+            Ok(Self::Plain(BufReader::new(file)))
+        }
+    }
+
+    // The temporary file is deleted once it is closed
+    fn decompressed(path: &str) -> Result<Self> {
+        let dir = Path::new(path).parent().unwrap_or(Path::new("."));
+        let mut file = tempfile::tempfile_in(dir)?;
+        let _ = io::copy(&mut GzDecoder::new(File::open(path)?), &mut file)?;
+        Ok(Self::Plain(BufReader::new(file)))
+    }
+
+    fn file(&self) -> &File {
+        match self {
+            Self::Plain(reader) => reader.get_ref(),
+            Self::Gzip { reader, .. } => reader.get_ref().get_ref(),
+        }
+    }
+
+    fn read_line(&mut self, line: &mut String) -> io::Result<usize> {
+        match self {
+            Self::Plain(reader) => reader.read_line(line),
+            Self::Gzip {
+                reader, position, ..
+            } => {
+                let bytes = reader.read_line(line)?;
+                *position += bytes as u64;
+                Ok(bytes)
+            }
+        }
+    }
+
+    fn position(&mut self) -> io::Result<u64> {
+        match self {
+            Self::Plain(reader) => reader.stream_position(),
+            Self::Gzip { position, .. } => Ok(*position),
+        }
+    }
+
+    // Going backwards in a compressed file would mean decompressing it again from the start for
+    // every row, so it is decompressed once into a temporary file instead
+    fn seek(&mut self, offset: u64) -> Result<()> {
+        let decompress = match self {
+            Self::Gzip { path, position, .. } if offset < *position => Some(path.to_owned()),
+            _ => None,
+        };
+        if let Some(path) = decompress {
+            *self = Self::decompressed(&path)?;
+        }
+        match self {
+            Self::Plain(reader) => {
+                let _ = reader.seek(SeekFrom::Start(offset))?;
+            }
+            Self::Gzip {
+                reader,
+                path,
+                position,
+            } => {
+                let skip = offset - *position;
+                if io::copy(&mut reader.by_ref().take(skip), &mut io::sink())? != skip {
+                    return Err(anyhow!("Offset {offset} is beyond the end of {path}"));
+                }
+                *position = offset;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Default)]
 pub struct DataFile {
-    reader: Option<BufReader<File>>,
-    writer: Option<BufWriter<File>>,
+    reader: Option<DataReader>,
+    writer: Option<BufWriter<GzEncoder<File>>>,
     uuid: Option<String>,
+    input_path: Option<String>, // Set when reading a file outside the data path
     header: DataHeader,
     pub rows: Vec<Vec<DataCell>>,
-    row_counter: usize,
+    row_counter: usize, // Data rows only
+    header_written: bool,
 }
 
 impl fmt::Debug for DataFile {
@@ -47,6 +167,17 @@ impl fmt::Debug for DataFile {
     }
 }
 
+// An output that was never finished is incomplete, so it is not kept
+impl Drop for DataFile {
+    fn drop(&mut self) {
+        if self.writer.take().is_some() {
+            if let Some(temp_path) = self.temp_path() {
+                let _ = std::fs::remove_file(temp_path);
+            }
+        }
+    }
+}
+
 impl DataFile {
     pub fn new_from_uuid(uuid: &str) -> Self {
         let mut ret = Self::default();
@@ -54,6 +185,32 @@ impl DataFile {
         ret
     }
 
+    // A copy of a finished file and its key index under a new UUID; like a finished output file,
+    // it is removed again if the workflow node is aborted
+    pub fn copy_of(source_uuid: &str) -> Result<String> {
+        let source = Self::new_from_uuid(source_uuid);
+        let source_path = source
+            .path()
+            .ok_or_else(|| anyhow!("No path for {source_uuid}"))?;
+        let uuid = Uuid::new_v4().to_string();
+        let target = Self::new_from_uuid(&uuid);
+        // The copy keeps the extension, so an uncompressed file stays readable
+        let target_path = match source_path.ends_with(".gz") {
+            true => target.output_path(),
+            false => target.plain_path(),
+        }
+        .ok_or_else(|| anyhow!("No path for {uuid}"))?;
+        std::fs::copy(&source_path, &target_path)?;
+        let _ = FILES_WRITTEN.try_with(|files| files.lock().unwrap().push(uuid.to_owned()));
+        if let (Some(source_index), Some(target_index)) = (source.index_path(), target.index_path())
+        {
+            if Path::new(&source_index).exists() {
+                std::fs::copy(source_index, target_index)?;
+            }
+        }
+        Ok(uuid)
+    }
+
     pub fn new_output_file() -> Result<Self> {
         let mut ret = Self::default();
         ret.open_output_file()?;
@@ -80,7 +237,13 @@ impl DataFile {
         }
         let fh = self.writer()?;
         writeln!(fh, "{v}")?;
-        self.row_counter += 1;
+        // The first line of a file is always the header
+        if self.header_written {
+            self.row_counter += 1;
+            count_processed_row();
+        } else {
+            self.header_written = true;
+        }
         Ok(())
     }
 
@@ -99,37 +262,100 @@ impl DataFile {
             return Ok(());
         }
         self.uuid = Some(uuid.to_string());
+        // Readers only ever see complete files, see `finish()`
         let path = self
-            .path()
+            .temp_path()
             .expect("base name was just set, this should be impossible");
         let file_handle = File::create(path)?;
-        let writer = BufWriter::new(file_handle);
+        if let Some(index_path) = self.index_path() {
+            // An index left over from a previous file with this name would be stale
+            let _ = std::fs::remove_file(index_path);
+        }
+        let writer = BufWriter::new(GzEncoder::new(file_handle, Compression::default()));
         self.writer = Some(writer);
         Ok(())
     }
 
+    // Flushes the output and moves it into place under its final name
+    pub fn finish(&mut self) -> Result<DataFileDetails> {
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.into_inner()?.finish()?;
+            let (temp_path, path) = match (self.temp_path(), self.output_path()) {
+                (Some(temp_path), Some(path)) => (temp_path, path),
+                _ => return Err(anyhow!("Output file has no name")),
+            };
+            std::fs::rename(&temp_path, &path)
+                .map_err(|e| anyhow!("Could not move {temp_path} to {path}: {e}"))?;
+            if let Some(plain_path) = self.plain_path() {
+                // An uncompressed file with this name would otherwise be read instead
+                let _ = std::fs::remove_file(plain_path);
+            }
+            if let Some(uuid) = &self.uuid {
+                let _ = FILES_WRITTEN.try_with(|files| files.lock().unwrap().push(uuid.to_owned()));
+            }
+        }
+        Ok(self.details())
+    }
+
     pub fn open_input_file(&mut self, uuid: &str) -> Result<()> {
         self.uuid = Some(uuid.to_string());
         let path = self
             .path()
             .expect("base name was just set, this should be impossible");
-        let file_handle = File::open(path)?;
-        let reader = BufReader::new(file_handle);
+        self.reader = Some(DataReader::open(&path)?);
+        Ok(())
+    }
+
+    // Reads an arbitrary JSONL file instead of `{data_path}/{uuid}.jsonl`; "-" reads stdin
+    pub fn open_input_path(&mut self, path: &str) -> Result<()> {
+        let path = match path {
+            "-" => "/dev/stdin",
+            path => path,
+        };
+        let reader = DataReader::open(path)
+            .map_err(|e| anyhow!("Could not open input file '{path}': {e}"))?;
+        self.input_path = Some(path.to_string());
         self.reader = Some(reader);
         Ok(())
     }
 
+    pub fn exists(&self) -> bool {
+        self.path().is_some_and(|path| Path::new(&path).exists())
+    }
+
     pub fn file_size(&self) -> Option<u64> {
         let reader = self.reader.as_ref()?;
-        let file = reader.get_ref();
-        Some(file.metadata().ok()?.len())
+        Some(reader.file().metadata().ok()?.len())
     }
 
     pub fn path(&self) -> Option<String> {
+        if let Some(path) = &self.input_path {
+            return Some(path.to_owned());
+        }
+        // Files written before compression was introduced are read as they are
+        let path = self.output_path()?;
+        match self.plain_path() {
+            Some(plain_path) if !Path::new(&path).exists() && Path::new(&plain_path).exists() => {
+                Some(plain_path)
+            }
+            _ => Some(path),
+        }
+    }
+
+    fn output_path(&self) -> Option<String> {
+        let name = self.uuid.as_ref()?;
+        Some(format!("{}/{name}.jsonl.gz", APP.data_path()))
+    }
+
+    fn plain_path(&self) -> Option<String> {
         let name = self.uuid.as_ref()?;
         Some(format!("{}/{name}.jsonl", APP.data_path()))
     }
 
+    fn temp_path(&self) -> Option<String> {
+        Some(format!("{}.tmp", self.output_path()?))
+    }
+
     pub fn uuid(&self) -> &Option<String> {
         &self.uuid
     }
@@ -142,7 +368,7 @@ impl DataFile {
         self.reader.is_some()
     }
 
-    pub fn writer(&mut self) -> Result<&mut BufWriter<File>> {
+    pub fn writer(&mut self) -> Result<&mut BufWriter<GzEncoder<File>>> {
         match self.writer.as_mut() {
             Some(writer) => Ok(writer),
             None => Err(anyhow!("No writer open")),
@@ -170,14 +396,96 @@ impl DataFile {
         if self.header.columns.is_empty() {
             self.load_header()?;
         }
+        while let Some(row) = self.read_row() {
+            let row: Vec<DataCell> = serde_json::from_str(&row)?;
+            self.rows.push(row);
+        }
+        Ok(())
+    }
+
+    // Byte offset of every data row by its `key` value, read from the sidecar index if one exists
+    // for that key; large files get their index written so later lookups skip the scan
+    pub fn key_offsets(&mut self, key: &str) -> Result<HashMap<String, u64>> {
+        if self.header.columns.is_empty() {
+            self.load_header()?;
+        }
+        if let Some(offsets) = self.load_index(key) {
+            return Ok(offsets);
+        }
+        let key_col_num = self
+            .header
+            .get_col_num(key)
+            .ok_or(anyhow!("No column named '{key}'"))?;
+        let mut ret = HashMap::new();
+        let mut row_num = 0;
         loop {
+            let offset = self.reader()?.position()?;
             let row = match self.read_row() {
                 Some(row) => row,
                 None => break,
             };
             let row: Vec<DataCell> = serde_json::from_str(&row)?;
-            self.rows.push(row);
+            let cell_key = match row.get(key_col_num) {
+                Some(cell) => cell.as_key(),
+                None => {
+                    return Err(anyhow!(
+                        "None value found for key '{key}' in data row {row_num}"
+                    ))
+                }
+            };
+            if ret.insert(cell_key.to_owned(), offset).is_some() {
+                return Err(anyhow!(
+                    "Duplicate key '{cell_key}' for '{key}' in data row {row_num}"
+                ));
+            }
+            row_num += 1;
+        }
+        // The reader is at the end now, so this is the uncompressed size
+        let size = self.reader()?.position()?;
+        if self.index_path().is_some() && size >= INDEX_MIN_FILE_SIZE {
+            self.write_index(key, &ret)?;
         }
+        Ok(ret)
+    }
+
+    // Reads the data row starting at `offset`, as returned by `key_offsets`
+    pub fn seek_to_row(&mut self, offset: u64) -> Result<Vec<DataCell>> {
+        self.reader()?.seek(offset)?;
+        let row = self
+            .read_row()
+            .ok_or(anyhow!("No data row at offset {offset}"))?;
+        Ok(serde_json::from_str(&row)?)
+    }
+
+    fn reader(&mut self) -> Result<&mut DataReader> {
+        match self.reader.as_mut() {
+            Some(reader) => Ok(reader),
+            None => Err(anyhow!("No reader open")),
+        }
+    }
+
+    // Only files in the data path get a sidecar index
+    pub fn index_path(&self) -> Option<String> {
+        if self.input_path.is_some() {
+            return None;
+        }
+        Some(format!("{}.idx", self.path()?))
+    }
+
+    fn load_index(&self, key: &str) -> Option<HashMap<String, u64>> {
+        let file = File::open(self.index_path()?).ok()?;
+        let index: Value = serde_json::from_reader(BufReader::new(file)).ok()?;
+        if index.get("key")?.as_str()? != key {
+            return None;
+        }
+        serde_json::from_value(index.get("offsets")?.to_owned()).ok()
+    }
+
+    fn write_index(&self, key: &str, offsets: &HashMap<String, u64>) -> Result<()> {
+        let path = self.index_path().ok_or(anyhow!("No path for index"))?;
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, &json!({"key": key, "offsets": offsets}))?;
+        writer.flush()?;
         Ok(())
     }
 
@@ -211,7 +519,110 @@ impl DataFile {
         Ok(ret)
     }
 
-    pub fn add_header(&mut self, header: DataHeader) {
-        self.header.add_header(header);
+    pub fn set_header(&mut self, header: DataHeader) {
+        self.header = header;
+    }
+
+    pub fn add_header(&mut self, header: DataHeader) -> Vec<(String, String)> {
+        self.header.add_header(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_unfinished_output_file() {
+        let mut df = DataFile::new_output_file().unwrap();
+        df.write_json_row(&json!({"columns": []})).unwrap();
+        let path = df.path().unwrap();
+        let temp_path = df.temp_path().unwrap();
+        assert!(!Path::new(&path).exists());
+        assert!(Path::new(&temp_path).exists());
+        drop(df);
+        assert!(!Path::new(&path).exists());
+        assert!(!Path::new(&temp_path).exists());
+    }
+
+    #[tokio::test]
+    async fn test_files_written() {
+        let files: Arc<Mutex<Vec<String>>> = Arc::default();
+        let uuid = FILES_WRITTEN
+            .scope(files.clone(), async {
+                let mut unfinished = DataFile::new_output_file().unwrap();
+                unfinished.write_json_row(&json!({"columns": []})).unwrap();
+                let mut df = DataFile::new_output_file().unwrap();
+                df.write_json_row(&json!({"columns": []})).unwrap();
+                df.finish().unwrap().uuid
+            })
+            .await;
+        assert_eq!(*files.lock().unwrap(), vec![uuid.to_owned()]);
+        APP.remove_uuid_file(&uuid).unwrap(); // Cleanup
+    }
+
+    #[tokio::test]
+    async fn test_copy_of() {
+        let mut df = DataFile::new_output_file().unwrap();
+        df.write_json_row(&json!({"columns": []})).unwrap();
+        let source_uuid = df.finish().unwrap().uuid;
+        let offsets = HashMap::from([("a".to_string(), 42)]);
+        df.write_index("name", &offsets).unwrap();
+
+        let files: Arc<Mutex<Vec<String>>> = Arc::default();
+        let uuid = FILES_WRITTEN
+            .scope(files.clone(), async {
+                DataFile::copy_of(&source_uuid).unwrap()
+            })
+            .await;
+        assert_eq!(*files.lock().unwrap(), vec![uuid.to_owned()]);
+        let copy = DataFile::new_from_uuid(&uuid);
+        assert!(copy.path().unwrap().ends_with(&format!("{uuid}.jsonl.gz")));
+        assert_eq!(copy.load_index("name"), Some(offsets));
+
+        // Cleanup
+        for uuid in [&source_uuid, &uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let mut df = DataFile::new_output_file().unwrap();
+        df.write_json_row(&json!({"columns": []})).unwrap();
+        for value in 1..=3 {
+            df.write_json_row(&json!([DataCell::Int(value)])).unwrap();
+        }
+        let temp_path = df.temp_path().unwrap();
+        let uuid = df.finish().unwrap().uuid;
+        assert!(!Path::new(&temp_path).exists());
+        let path = DataFile::new_from_uuid(&uuid).path().unwrap();
+        assert!(path.ends_with(&format!("{uuid}.jsonl.gz")));
+        assert_eq!(std::fs::read(&path).unwrap()[..2], [0x1f, 0x8b]); // gzip magic
+
+        let mut df = DataFile::default();
+        df.open_input_file(&uuid).unwrap();
+        assert_eq!(df.read_row(), Some("{\"columns\":[]}\n".to_string()));
+        let offset = df.reader().unwrap().position().unwrap();
+        assert_eq!(df.read_row(), Some("[{\"Int\":1}]\n".to_string()));
+        assert!(df.read_row().is_some());
+        assert_eq!(df.seek_to_row(offset).unwrap(), vec![DataCell::Int(1)]);
+        assert!(matches!(df.reader, Some(DataReader::Plain(_))));
+        assert_eq!(df.read_row(), Some("[{\"Int\":2}]\n".to_string()));
+        assert_eq!(df.seek_to_row(offset).unwrap(), vec![DataCell::Int(1)]);
+        APP.remove_uuid_file(&uuid).unwrap();
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[test]
+    fn test_uncompressed_file() {
+        let uuid = "8c5d1fb3-6ea8-44d1-b938-9d22f569c412";
+        let mut df = DataFile::default();
+        df.open_input_file(uuid).unwrap();
+        assert!(df.path().unwrap().ends_with(&format!("{uuid}.jsonl")));
+        df.load().unwrap();
+        assert!(!df.header().columns.is_empty());
+        assert!(!df.rows.is_empty());
     }
 }
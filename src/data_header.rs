@@ -1,3 +1,4 @@
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::wiki_page::WikiPage;
@@ -8,6 +9,10 @@ pub enum ColumnHeaderType {
     WikiPage(WikiPage),
     Int,
     Float,
+    Coordinate,
+    Boolean,
+    Date,
+    WikidataTime,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -31,9 +36,78 @@ impl DataHeader {
             .next()
     }
 
-    pub fn add_header(&mut self, header: DataHeader) {
-        // TODO duplicate column name warning/error
-        let mut header = header;
-        self.columns.append(&mut header.columns);
+    pub fn get_col_nums(&self, keys: &[String]) -> Result<Vec<usize>> {
+        keys.iter()
+            .map(|key| {
+                self.get_col_num(key)
+                    .ok_or_else(|| anyhow!("No header column {key}"))
+            })
+            .collect()
+    }
+
+    // Same number of columns, and the same kind of column at each position; names are ignored
+    pub fn is_positionally_compatible(&self, other: &DataHeader) -> bool {
+        self.columns.len() == other.columns.len()
+            && self
+                .columns
+                .iter()
+                .zip(other.columns.iter())
+                .all(|(a, b)| std::mem::discriminant(&a.kind) == std::mem::discriminant(&b.kind))
+    }
+
+    // For each of our columns, the position of the column with the same name and kind in `other`;
+    // None if the headers do not have the same columns
+    pub fn column_order_in(&self, other: &DataHeader) -> Option<Vec<usize>> {
+        if self.columns.len() != other.columns.len() {
+            return None;
+        }
+        let mut used = vec![false; other.columns.len()];
+        self.columns
+            .iter()
+            .map(|a| {
+                let col_num = other.columns.iter().enumerate().position(|(col_num, b)| {
+                    !used[col_num]
+                        && a.name == b.name
+                        && std::mem::discriminant(&a.kind) == std::mem::discriminant(&b.kind)
+                })?;
+                used[col_num] = true;
+                Some(col_num)
+            })
+            .collect()
+    }
+
+    // Renames columns by position; empty names keep the original column name
+    pub fn rename_columns(&mut self, names: &[String]) -> Result<()> {
+        if names.len() > self.columns.len() {
+            return Err(anyhow!(
+                "{} new column names given but there are only {} columns",
+                names.len(),
+                self.columns.len()
+            ));
+        }
+        for (column, name) in self.columns.iter_mut().zip(names.iter()) {
+            if !name.is_empty() {
+                column.name = name.to_owned();
+            }
+        }
+        Ok(())
+    }
+
+    // Added columns whose name is already taken get a number: name, name_2, name_3, ...
+    // Returns the (old, new) names of renamed columns.
+    pub fn add_header(&mut self, header: DataHeader) -> Vec<(String, String)> {
+        let mut renamed = vec![];
+        for mut column in header.columns {
+            if self.get_col_num(&column.name).is_some() {
+                let new_name = (2..)
+                    .map(|n| format!("{}_{n}", column.name))
+                    .find(|name| self.get_col_num(name).is_none())
+                    .unwrap_or_default(); // Never empty, the range is unbounded
+                renamed.push((column.name, new_name.to_owned()));
+                column.name = new_name;
+            }
+            self.columns.push(column);
+        }
+        renamed
     }
 }
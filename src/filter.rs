@@ -1,14 +1,25 @@
 use anyhow::{anyhow, Result};
-use regex::RegexBuilder;
+use regex::{Regex, RegexBuilder};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+
 use crate::app::App;
 use crate::data_cell::DataCell;
 use crate::data_file::{DataFile, DataFileDetails};
+use crate::data_header::{ColumnHeaderType, DataHeader};
+use crate::APP;
+
+// Rows FilterSort holds in memory at a time; larger files are sorted in runs on disk
+const SORT_CHUNK_ROWS: usize = 100_000;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub enum FilterOperator {
+    #[default]
     Equal,
     Unequal,
     LargerThan,
@@ -16,9 +27,26 @@ pub enum FilterOperator {
     LargerOrEqualThan,
     SmallerOrEqualThan,
     Regexp,
+    In, // `value` is a newline- or pipe-separated list
+}
+
+// How the conditions of a filter combine
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum FilterCombine {
+    #[default]
+    And,
+    Or,
 }
 
+// A further condition on a field of the same WikiPage cell, see Filter::predicates
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubkeyPredicate {
+    pub subkey: String,
+    pub operator: FilterOperator,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Filter {
     pub key: String,
     pub subkey: Option<String>,
@@ -27,81 +55,251 @@ pub struct Filter {
 
     #[serde(default)]
     pub remove_matching: bool,
+
+    // Checked against the same cell in the same pass, eg ns_id==0 AND wiki==enwiki
+    #[serde(default)]
+    pub predicates: Vec<SubkeyPredicate>,
+
+    #[serde(default)]
+    pub combine: FilterCombine,
 }
 
 impl Filter {
+    fn compile(&self, header: &DataHeader, uuid: &str) -> Result<CompiledFilter> {
+        let col_num = header
+            .get_col_num(&self.key)
+            .ok_or_else(|| anyhow!("File {uuid} does not have a header column {}", self.key))?;
+        let mut predicates = vec![CompiledPredicate::new(
+            &self.subkey,
+            &self.operator,
+            &self.value,
+        )?];
+        for predicate in &self.predicates {
+            predicates.push(CompiledPredicate::new(
+                &Some(predicate.subkey.to_owned()),
+                &predicate.operator,
+                &predicate.value,
+            )?);
+        }
+        Ok(CompiledFilter {
+            col_num,
+            predicates,
+            combine: self.combine,
+            remove_matching: self.remove_matching,
+        })
+    }
+
     pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
-        let v_regexp = match self.operator {
-            FilterOperator::Regexp => match RegexBuilder::new(&self.value).build() {
+        let mut df_in = DataFile::default();
+        let mut df_out = DataFile::new_output_file()?;
+        df_in.open_input_file(uuid)?;
+        df_in.load_header()?;
+        df_out.write_json_row(&json! {df_in.header()})?; // Output new header
+        let compiled = self.compile(df_in.header(), uuid)?;
+        while let Some(row) = df_in.read_row() {
+            let row: Vec<DataCell> = serde_json::from_str(&row)?;
+            // An unfinished output file is discarded on error
+            if compiled.keeps(&row, uuid)? {
+                df_out.write_json_row(&json! {row})?; // Output data row
+            }
+        }
+        df_out.finish()
+    }
+}
+
+// One (subkey, operator, value) condition, with the value parsed once for all rows
+#[derive(Debug, Clone)]
+struct CompiledPredicate {
+    subkey: Option<String>,
+    operator: FilterOperator,
+    regexp: Regex,
+    values: HashSet<String>, // For FilterOperator::In
+    value: String,
+    v_plain_text: DataCell,
+    v_i64: Option<DataCell>, // None if the value is not of that type
+    v_f64: Option<DataCell>,
+    v_bool: Option<DataCell>,
+    v_date: Option<DataCell>,
+    v_wikidata_time: Option<DataCell>,
+}
+
+impl CompiledPredicate {
+    fn new(subkey: &Option<String>, operator: &FilterOperator, value: &str) -> Result<Self> {
+        let regexp = match operator {
+            FilterOperator::Regexp => match RegexBuilder::new(value).build() {
                 Ok(r) => r,
-                Err(_) => return Err(anyhow!("Invalid regular expression: {}", &self.value)),
+                Err(_) => return Err(anyhow!("Invalid regular expression: {value}")),
             },
             _ => RegexBuilder::new(".").build()?,
         };
+        let values = match operator {
+            FilterOperator::In => value
+                .split(['\n', '|'])
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect(),
+            _ => HashSet::new(),
+        };
+        Ok(Self {
+            subkey: subkey.to_owned(),
+            operator: operator.to_owned(),
+            regexp,
+            values,
+            value: value.to_owned(),
+            v_plain_text: DataCell::PlainText(value.to_owned()),
+            v_i64: value.trim().parse::<i64>().ok().map(DataCell::Int),
+            v_f64: value.trim().parse::<f64>().ok().map(DataCell::Float),
+            v_bool: DataCell::parse_bool(value).map(DataCell::Boolean),
+            v_date: DataCell::parse_date(value).map(DataCell::Date),
+            v_wikidata_time: DataCell::wikidata_time_from_str(value),
+        })
+    }
+
+    fn matches(&self, cell: &DataCell, uuid: &str) -> Result<bool> {
+        let cell = match cell {
+            DataCell::WikiPage(_wp) => cell.to_sub_key(&self.subkey),
+            other => other.to_owned(),
+        };
+
+        let vcell = match cell {
+            DataCell::PlainText(_) => &self.v_plain_text,
+            DataCell::WikiPage(_) => {
+                return Err(anyhow!(
+                    "cell is DataCell::WikiPage somehow, this should never happen {uuid}"
+                ))
+            }
+            DataCell::Int(_) => self.v_i64.as_ref().unwrap_or(&self.v_plain_text),
+            DataCell::Float(_) => self.v_f64.as_ref().unwrap_or(&self.v_plain_text),
+            DataCell::Boolean(_) => self.v_bool.as_ref().unwrap_or(&self.v_plain_text),
+            DataCell::Date(_) => self.v_date.as_ref().unwrap_or(&self.v_plain_text),
+            DataCell::WikidataTime { .. } => {
+                self.v_wikidata_time.as_ref().unwrap_or(&self.v_plain_text)
+            }
+            DataCell::Blank => &DataCell::Blank,
+            _ => &self.v_plain_text,
+        };
+
+        // A mismatched value never equals the cell, but cannot be ordered against it either
+        let ordering = || {
+            cell.partial_cmp(vcell).ok_or_else(|| {
+                anyhow!(
+                    "Filter cannot compare {cell:?} with '{}' in {uuid}",
+                    self.value
+                )
+            })
+        };
+        Ok(match self.operator {
+            FilterOperator::Equal => *vcell == cell,
+            FilterOperator::Unequal => *vcell != cell,
+            FilterOperator::LargerThan => ordering()?.is_gt(),
+            FilterOperator::SmallerThan => ordering()?.is_lt(),
+            FilterOperator::LargerOrEqualThan => ordering()?.is_ge(),
+            FilterOperator::SmallerOrEqualThan => ordering()?.is_le(),
+            FilterOperator::Regexp => self.regexp.is_match(&cell.as_key()),
+            FilterOperator::In => self.values.contains(&cell.as_key()),
+        })
+    }
+}
+
+// A filter resolved against a file header, ready to be applied to rows
+#[derive(Debug, Clone)]
+struct CompiledFilter {
+    col_num: usize,
+    predicates: Vec<CompiledPredicate>,
+    combine: FilterCombine,
+    remove_matching: bool,
+}
+
+impl CompiledFilter {
+    // Returns true if the row should be kept, taking remove_matching into account
+    fn keeps(&self, row: &[DataCell], uuid: &str) -> Result<bool> {
+        let cell = row.get(self.col_num).unwrap_or(&DataCell::Blank);
+        let mut does_match = self.combine == FilterCombine::And;
+        for predicate in &self.predicates {
+            let matches = predicate.matches(cell, uuid)?;
+            match self.combine {
+                FilterCombine::And if !matches => {
+                    does_match = false;
+                    break;
+                }
+                FilterCombine::Or if matches => {
+                    does_match = true;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        Ok(does_match != self.remove_matching)
+    }
+}
+
+// ____________________________________________________________________________________
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterNamespace {
+    pub key: String,
+    pub ns_ids: Vec<i64>,
+
+    // Namespace names (local or canonical), resolved for the wiki of the column
+    #[serde(default)]
+    pub ns_names: Vec<String>,
+
+    #[serde(default)]
+    pub remove_matching: bool,
+}
 
-        let v_plain_text = DataCell::PlainText(self.value.to_owned());
-        let v_i64 = DataCell::Int(self.value.parse::<i64>().unwrap_or(0));
-        let v_f64 = DataCell::Float(self.value.parse::<f64>().unwrap_or(0.0));
+impl FilterNamespace {
+    async fn get_namespace_ids(&self, wiki: &Option<String>) -> Result<HashSet<i64>> {
+        let mut ret: HashSet<i64> = self.ns_ids.iter().cloned().collect();
+        if self.ns_names.is_empty() {
+            return Ok(ret);
+        }
+        let wiki = wiki
+            .as_ref()
+            .ok_or_else(|| anyhow!("No wiki set for column {}", self.key))?;
+        for ns_name in &self.ns_names {
+            let ns_id = APP
+                .get_namespace_id(wiki, ns_name)
+                .await
+                .ok_or_else(|| anyhow!("Unknown namespace '{ns_name}' on {wiki}"))?;
+            ret.insert(ns_id);
+        }
+        Ok(ret)
+    }
 
+    pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
         let mut df_in = DataFile::default();
         let mut df_out = DataFile::new_output_file()?;
         df_in.open_input_file(uuid)?;
         df_in.load_header()?;
         df_out.write_json_row(&json! {df_in.header()})?; // Output new header
-        let col_num = df_in
+        let (col_num, header) = df_in
             .header()
             .columns
             .iter()
             .enumerate()
             .find(|(_col_num, h)| h.name == self.key)
-            .map(|(col_num, _h)| col_num)
             .ok_or_else(|| anyhow!("File {uuid} does not have a header column {}", self.key))?;
-        loop {
-            let row = match df_in.read_row() {
-                Some(row) => row,
-                None => break, // End of file
-            };
+        let wiki = match &header.kind {
+            ColumnHeaderType::WikiPage(wp) => wp.wiki.to_owned(),
+            _ => return Err(anyhow!("Not a wiki column for {}", self.key)),
+        };
+        let ns_ids = self.get_namespace_ids(&wiki).await?;
+
+        while let Some(row) = df_in.read_row() {
             let row: Vec<DataCell> = serde_json::from_str(&row)?;
-            let cell = row.get(col_num);
-            let cell = match cell {
-                Some(cell) => match cell {
-                    DataCell::WikiPage(_wp) => cell.to_sub_key(&self.subkey),
-                    other => other.to_owned(),
+            let does_match = match row.get(col_num) {
+                Some(DataCell::WikiPage(wp)) => match wp.ns_id {
+                    Some(ns_id) => ns_ids.contains(&ns_id),
+                    None => false,
                 },
-                None => DataCell::Blank,
-            };
-
-            // println!("{cell:?}");
-
-            let vcell = match cell {
-                DataCell::PlainText(_) => &v_plain_text,
-                DataCell::WikiPage(_) => {
-                    return Err(anyhow!(
-                        "cell is DataCell::WikiPage somehow, this should never happen {uuid}"
-                    ))
-                }
-                DataCell::Int(_) => &v_i64,
-                DataCell::Float(_) => &v_f64,
-                _ => &DataCell::Blank,
-            };
-
-            // println!("{cell:?} {:?} {vcell:?}",self.operator);
-
-            let does_match = match self.operator {
-                FilterOperator::Equal => *vcell == cell,
-                FilterOperator::Unequal => *vcell != cell,
-                FilterOperator::LargerThan => *vcell < cell,
-                FilterOperator::SmallerThan => *vcell > cell,
-                FilterOperator::LargerOrEqualThan => *vcell <= cell,
-                FilterOperator::SmallerOrEqualThan => *vcell >= cell,
-                FilterOperator::Regexp => v_regexp.is_match(&cell.as_key()),
+                _ => false,
             };
-
-            if does_match == !self.remove_matching {
+            if does_match != self.remove_matching {
                 df_out.write_json_row(&json! {row})?; // Output data row
             }
         }
-        Ok(df_out.details())
+        df_out.finish()
     }
 }
 
@@ -128,19 +326,12 @@ impl FilterPetScan {
             .find(|(_col_num, h)| h.name == self.key)
             .map(|(col_num, _h)| col_num)
             .ok_or_else(|| anyhow!("File {uuid} does not have a header column {}", self.key))?;
-        loop {
-            let row = match df_in.read_row() {
-                Some(row) => row,
-                None => break, // End of file
-            };
+        while let Some(row) = df_in.read_row() {
             let row: Vec<DataCell> = serde_json::from_str(&row)?;
             let cell = row.get(col_num);
             let wiki_page = match cell {
-                Some(cell) => match cell {
-                    DataCell::WikiPage(wp) => wp,
-                    _ => continue,
-                },
-                None => continue,
+                Some(DataCell::WikiPage(wp)) => wp,
+                _ => continue,
             };
             let page = match &wiki_page.prefixed_title {
                 Some(page) => page,
@@ -179,10 +370,7 @@ impl FilterPetScan {
             ("manual_list_wiki", &manual_list_wiki),
             ("manual_list", &pages),
         ];
-        let j: Value = App::reqwest_client()?
-            .post(url)
-            .form(&params)
-            .send()
+        let j: Value = App::send_with_retry(App::reqwest_client()?.post(url).form(&params))
             .await?
             .json()
             .await?;
@@ -207,19 +395,12 @@ impl FilterPetScan {
         df_in.open_input_file(uuid)?;
         df_in.load_header()?;
         df_out.write_json_row(&json! {df_in.header()})?; // Output new header
-        loop {
-            let row = match df_in.read_row() {
-                Some(row) => row,
-                None => break, // End of file
-            };
+        while let Some(row) = df_in.read_row() {
             let row: Vec<DataCell> = serde_json::from_str(&row)?;
             let cell = row.get(col_num);
             let wiki_page = match cell {
-                Some(cell) => match cell {
-                    DataCell::WikiPage(wp) => wp,
-                    _ => continue,
-                },
-                None => continue,
+                Some(DataCell::WikiPage(wp)) => wp,
+                _ => continue,
             };
             let page = match &wiki_page.prefixed_title {
                 Some(page) => page,
@@ -229,7 +410,7 @@ impl FilterPetScan {
                 df_out.write_json_row(&json! {row})?; // Output data row
             }
         }
-        Ok(df_out.details())
+        df_out.finish()
     }
 }
 
@@ -239,10 +420,69 @@ impl FilterPetScan {
 pub struct FilterSort {
     pub key: String,
     pub reverse: bool,
+
+    // Compare numbers by value, so 9 < 10
+    #[serde(default)]
+    pub numeric: bool,
 }
 
 impl FilterSort {
+    fn sort_rows(&self, rows: &mut Vec<Vec<DataCell>>, col_num: usize) {
+        let mut chunk: Vec<SortEntry> = rows
+            .drain(..)
+            .enumerate()
+            .map(|(row_index, row)| (self.sort_key(&row, col_num), row_index, row))
+            .collect();
+        self.sort_chunk(&mut chunk);
+        rows.extend(chunk.into_iter().map(|(_key, _row_index, row)| row));
+    }
+
+    fn sort_key(&self, row: &[DataCell], col_num: usize) -> DataCell {
+        Self::cell_key(row.get(col_num).unwrap_or(&DataCell::Blank), self.numeric)
+    }
+
+    // What `compare_keys` compares: numbers stay numbers if `numeric` is set, the rest is text
+    fn cell_key(cell: &DataCell, numeric: bool) -> DataCell {
+        match cell {
+            DataCell::Blank => DataCell::Blank,
+            DataCell::Int(_) | DataCell::Float(_) if numeric => cell.to_owned(),
+            _ => DataCell::PlainText(cell.as_key()),
+        }
+    }
+
+    // Blank first, then numbers, then text; a total order, as sorting requires one
+    fn compare_keys(k1: &DataCell, k2: &DataCell) -> Ordering {
+        let rank = |cell: &DataCell| match cell {
+            DataCell::Blank => 0,
+            DataCell::Int(_) | DataCell::Float(_) => 1,
+            _ => 2,
+        };
+        let number = |cell: &DataCell| match cell {
+            DataCell::Int(i) => *i as f64,
+            DataCell::Float(f) => *f,
+            _ => 0.0,
+        };
+        match (k1, k2) {
+            (DataCell::Int(i1), DataCell::Int(i2)) => i1.cmp(i2),
+            (DataCell::PlainText(t1), DataCell::PlainText(t2)) => t1.cmp(t2),
+            (k1, k2) => rank(k1)
+                .cmp(&rank(k2))
+                .then_with(|| number(k1).total_cmp(&number(k2))),
+        }
+    }
+
+    // Ties keep their input order
+    fn compare(a: &SortEntry, b: &SortEntry) -> Ordering {
+        Self::compare_keys(&a.0, &b.0).then(a.1.cmp(&b.1))
+    }
+
     pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
+        self.process_in_chunks(uuid, SORT_CHUNK_ROWS)
+    }
+
+    // External merge sort: sorted runs of at most `chunk_rows` rows go to temporary files, which are
+    // then merged. Ties keep their input order (reversed with `reverse`), same as `sort_rows`.
+    fn process_in_chunks(&self, uuid: &str, chunk_rows: usize) -> Result<DataFileDetails> {
         let mut df_in = DataFile::default();
         df_in.open_input_file(uuid)?;
         df_in.load_header()?;
@@ -256,134 +496,1278 @@ impl FilterSort {
             .map(|(col_num, _h)| col_num)
             .ok_or_else(|| anyhow!("File {uuid} does not have a header column {}", self.key))?;
 
-        // Read rows
-        let mut rows = vec![];
-        loop {
-            let row = match df_in.read_row() {
-                Some(row) => row,
-                None => break, // End of file
-            };
+        let mut runs = vec![];
+        let mut chunk = vec![];
+        let mut row_index = 0;
+        while let Some(row) = df_in.read_row() {
             let row: Vec<DataCell> = serde_json::from_str(&row)?;
-            rows.push(row);
+            chunk.push((self.sort_key(&row, col_num), row_index, row));
+            row_index += 1;
+            if chunk.len() >= chunk_rows {
+                runs.push(self.write_sorted_run(&mut chunk)?);
+            }
         }
 
-        // Sort rows
-        rows.sort_by_cached_key(|row| {
-            let cell = match row.get(col_num) {
-                Some(cell) => cell,
-                None => return String::default(),
-            };
-            cell.as_key()
-        });
+        let mut df_out = DataFile::new_output_file()?;
+        df_out.write_json_row(&json! {df_in.header()})?; // Output new header
+        if runs.is_empty() {
+            // Everything fit into one chunk
+            self.sort_chunk(&mut chunk);
+            for (_key, _row_index, row) in chunk {
+                df_out.write_json_row(&json! {row})?; // Output data row
+            }
+            return df_out.finish();
+        }
+        if !chunk.is_empty() {
+            runs.push(self.write_sorted_run(&mut chunk)?);
+        }
+
+        let mut heap = BinaryHeap::new();
+        for (run_num, run) in runs.iter_mut().enumerate() {
+            if let Some(head) = self.read_run_head(run, run_num)? {
+                heap.push(head);
+            }
+        }
+        while let Some(head) = heap.pop() {
+            df_out.write_json_row(&json! {head.entry.2})?; // Output data row
+            if let Some(next) = self.read_run_head(&mut runs[head.run_num], head.run_num)? {
+                heap.push(next);
+            }
+        }
+        df_out.finish()
+    }
+
+    fn sort_chunk(&self, chunk: &mut [SortEntry]) {
+        chunk.sort_by(Self::compare);
         if self.reverse {
-            rows.reverse();
+            chunk.reverse();
         }
+    }
 
-        // Write sorted rows
-        let mut df_out = DataFile::new_output_file()?;
-        df_out.write_json_row(&json! {df_in.header()})?; // Output new header
-        for row in rows {
-            df_out.write_json_row(&json! {row})?; // Output data row
+    // One JSON array [key, row index, row] per line
+    fn write_sorted_run(&self, chunk: &mut Vec<SortEntry>) -> Result<BufReader<File>> {
+        self.sort_chunk(chunk);
+        let mut writer = BufWriter::new(tempfile::tempfile()?);
+        for entry in chunk.drain(..) {
+            writeln!(writer, "{}", json!(entry))?;
+        }
+        let mut file = writer.into_inner()?;
+        let _ = file.seek(SeekFrom::Start(0))?;
+        Ok(BufReader::new(file))
+    }
+
+    fn read_run_head(&self, run: &mut BufReader<File>, run_num: usize) -> Result<Option<RunHead>> {
+        let mut line = String::new();
+        if run.read_line(&mut line)? == 0 {
+            return Ok(None);
         }
-        Ok(df_out.details())
+        Ok(Some(RunHead {
+            entry: serde_json::from_str(&line)?,
+            run_num,
+            reverse: self.reverse,
+        }))
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::APP;
+// Sort key, row index in the input, row
+type SortEntry = (DataCell, usize, Vec<DataCell>);
 
-    #[tokio::test]
-    async fn test_filter_wikipage_via_prefixed_title() {
-        let uuid = "cb1e218e-421f-46b8-a77e-eac6799ce4e4";
-        let filter = Filter {
-            key: "wiki_page".to_string(),
-            subkey: Some("prefixed_title".to_string()),
-            operator: FilterOperator::Equal,
-            value: "AGEB".to_string(),
-            remove_matching: false,
-        };
-        let df = filter.process(uuid).await.unwrap();
-        assert!(df.rows == 2);
-        APP.remove_uuid_file(&df.uuid).unwrap(); // Cleanup
-    }
+// The next row of a sorted run; the heap pops the row that comes first in the output
+struct RunHead {
+    entry: SortEntry,
+    run_num: usize,
+    reverse: bool,
+}
 
-    #[tokio::test]
-    async fn test_filter_wikipage_via_namespace_id() {
-        let uuid = "cb1e218e-421f-46b8-a77e-eac6799ce4e4";
-        let mut filter = Filter {
-            key: "wiki_page".to_string(),
-            subkey: Some("ns_id".to_string()),
-            operator: FilterOperator::Unequal,
-            value: "0".to_string(),
-            remove_matching: false,
-        };
-        let df_keep = filter.process(uuid).await.unwrap();
-        filter.remove_matching = true;
-        let df_remove = filter.process(uuid).await.unwrap();
+impl Ord for RunHead {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let ordering = FilterSort::compare(&self.entry, &other.entry);
+        if self.reverse {
+            ordering
+        } else {
+            ordering.reverse()
+        }
+    }
+}
 
-        assert_eq!(df_keep.rows, 500);
-        assert_eq!(df_remove.rows, 1249);
+impl PartialOrd for RunHead {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
 
-        // Cleanup
-        APP.remove_uuid_file(&df_keep.uuid).unwrap();
-        APP.remove_uuid_file(&df_remove.uuid).unwrap();
+impl PartialEq for RunHead {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
     }
+}
 
-    #[test]
-    fn test_filter_operator_deserialization() {
-        let operator = json!("Equal").to_string();
-        let operator: FilterOperator = serde_json::from_str(&operator).unwrap();
-        assert_eq!(operator, FilterOperator::Equal);
+impl Eq for RunHead {}
+
+// ____________________________________________________________________________________
+
+// Filter, project, and (optionally) sort in one go, without intermediate files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Select {
+    #[serde(default)]
+    pub filters: Vec<Filter>, // All filters need to keep a row
+
+    #[serde(default)]
+    pub columns: Vec<String>, // Output columns, in this order; empty for all columns
+
+    #[serde(default)]
+    pub sort: Option<FilterSort>, // Sort key can be any input column, not just an output one
+}
+
+impl Select {
+    fn project(row: &[DataCell], col_nums: &[usize]) -> Vec<DataCell> {
+        col_nums
+            .iter()
+            .map(|col_num| row.get(*col_num).cloned().unwrap_or(DataCell::Blank))
+            .collect()
     }
 
-    #[tokio::test]
-    async fn test_filter_petscan() {
-        let uuid = "8c5d1fb3-6ea8-44d1-b938-9d22f569c412";
-        let filter = FilterPetScan {
-            key: "wikidata_item".to_string(),
-            psid: 26256139,
+    pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
+        let mut df_in = DataFile::default();
+        df_in.open_input_file(uuid)?;
+        df_in.load_header()?;
+        let header_in = df_in.header().to_owned();
+
+        let filters = self
+            .filters
+            .iter()
+            .map(|filter| filter.compile(&header_in, uuid))
+            .collect::<Result<Vec<CompiledFilter>>>()?;
+        let col_nums: Vec<usize> = if self.columns.is_empty() {
+            (0..header_in.columns.len()).collect()
+        } else {
+            self.columns
+                .iter()
+                .map(|name| {
+                    header_in
+                        .get_col_num(name)
+                        .ok_or_else(|| anyhow!("File {uuid} does not have a header column {name}"))
+                })
+                .collect::<Result<Vec<usize>>>()?
         };
-        let df = filter.process(uuid).await.unwrap();
-        assert!(df.rows == 34);
-        APP.remove_uuid_file(&df.uuid).unwrap(); // Cleanup
+        let sort_col_num = match &self.sort {
+            Some(sort) => Some(header_in.get_col_num(&sort.key).ok_or_else(|| {
+                anyhow!("File {uuid} does not have a header column {}", sort.key)
+            })?),
+            None => None,
+        };
+        let header_out = DataHeader {
+            columns: col_nums
+                .iter()
+                .map(|col_num| header_in.columns[*col_num].to_owned())
+                .collect(),
+        };
+
+        let mut df_out = DataFile::new_output_file()?;
+        df_out.write_json_row(&json! {header_out})?; // Output new header
+        let mut buffered_rows = vec![];
+        while let Some(row) = df_in.read_row() {
+            let row: Vec<DataCell> = serde_json::from_str(&row)?;
+            let mut keep = true;
+            for filter in &filters {
+                if !filter.keeps(&row, uuid)? {
+                    keep = false;
+                    break;
+                }
+            }
+            if !keep {
+                continue;
+            }
+            match sort_col_num {
+                Some(_) => buffered_rows.push(row), // Sorting needs all rows
+                None => df_out.write_json_row(&json! {Self::project(&row, &col_nums)})?, // Output data row
+            }
+        }
+
+        if let (Some(sort), Some(sort_col_num)) = (&self.sort, sort_col_num) {
+            sort.sort_rows(&mut buffered_rows, sort_col_num);
+            for row in buffered_rows {
+                df_out.write_json_row(&json! {Self::project(&row, &col_nums)})?;
+                // Output data row
+            }
+        }
+        df_out.finish()
     }
+}
 
-    #[tokio::test]
-    async fn test_filter_sort() {
-        async fn sub_test(reverse: bool, expected_first_item: &str) {
-            let uuid = "8c5d1fb3-6ea8-44d1-b938-9d22f569c412";
-            let filter = FilterSort {
-                key: "wikidata_item".to_string(),
-                reverse,
-            };
-            let df = filter.process(uuid).await.unwrap();
-            // println!("Generated test_data/{}.jsonl with {} rows",df.uuid,df.rows);
-            assert!(df.rows == 50);
-            if true {
-                let mut df_in = DataFile::default();
-                df_in
-                    .open_input_file(&df.uuid)
-                    .expect(&format!("New data file missing: {}", df.uuid));
-                let _ = df_in
-                    .read_row()
-                    .expect(&format!("Header row missing for {}", df.uuid));
-                let row = df_in
-                    .read_row()
-                    .expect(&format!("First data row missing for {}", df.uuid));
-                let row: Vec<DataCell> =
-                    serde_json::from_str(&row).expect("First data row is not JSON");
-                let cell = match &row[0] {
-                    DataCell::WikiPage(wp) => wp.to_owned(),
-                    _ => panic!("Sort failed"),
-                };
-                assert_eq!(cell.prefixed_title.unwrap(), expected_first_item);
+// ____________________________________________________________________________________
+
+// Reduces a file to some of its columns; either `keep` (in that order) or `drop`, not both
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterColumns {
+    #[serde(default)]
+    pub keep: Option<Vec<String>>,
+
+    #[serde(default)]
+    pub drop: Option<Vec<String>>,
+}
+
+impl FilterColumns {
+    fn col_nums(&self, header: &DataHeader, uuid: &str) -> Result<Vec<usize>> {
+        let col_num = |name: &String| {
+            header
+                .get_col_num(name)
+                .ok_or_else(|| anyhow!("File {uuid} does not have a header column {name}"))
+        };
+        match (&self.keep, &self.drop) {
+            (Some(_), Some(_)) => Err(anyhow!("FilterColumns can not have both keep and drop")),
+            (Some(keep), None) => keep.iter().map(col_num).collect(),
+            (None, Some(drop)) => {
+                let drop = drop
+                    .iter()
+                    .map(col_num)
+                    .collect::<Result<HashSet<usize>>>()?;
+                Ok((0..header.columns.len())
+                    .filter(|col_num| !drop.contains(col_num))
+                    .collect())
             }
-            APP.remove_uuid_file(&df.uuid).unwrap(); // Cleanup
+            (None, None) => Err(anyhow!("FilterColumns needs either keep or drop")),
         }
+    }
 
-        sub_test(true, "Q99929855").await;
+    pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
+        let mut df_in = DataFile::default();
+        df_in.open_input_file(uuid)?;
+        df_in.load_header()?;
+        let col_nums = self.col_nums(df_in.header(), uuid)?;
+        let header_out = DataHeader {
+            columns: col_nums
+                .iter()
+                .map(|col_num| df_in.header().columns[*col_num].to_owned())
+                .collect(),
+        };
+
+        let mut df_out = DataFile::new_output_file()?;
+        df_out.write_json_row(&json! {header_out})?; // Output new header
+        while let Some(row) = df_in.read_row() {
+            let row: Vec<DataCell> = serde_json::from_str(&row)?;
+            df_out.write_json_row(&json! {Select::project(&row, &col_nums)})?; // Output data row
+        }
+        df_out.finish()
+    }
+}
+
+// ____________________________________________________________________________________
+
+// Drops rows identical to an earlier one across all columns, or only across `keys` if given; a key
+// can be `column.subkey` for a WikiPage column, e.g. `page.wiki`. Cells compare by as_key(), like
+// elsewhere
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FilterDedup {
+    pub keys: Vec<String>,
+}
+
+impl FilterDedup {
+    fn key_columns(&self, header: &DataHeader, uuid: &str) -> Result<Vec<(usize, Option<String>)>> {
+        if self.keys.is_empty() {
+            return Ok((0..header.columns.len())
+                .map(|col_num| (col_num, None))
+                .collect());
+        }
+        self.keys
+            .iter()
+            .map(|key| {
+                if let Some(col_num) = header.get_col_num(key) {
+                    return Ok((col_num, None));
+                }
+                key.rsplit_once('.')
+                    .and_then(|(name, subkey)| {
+                        Some((header.get_col_num(name)?, Some(subkey.to_string())))
+                    })
+                    .ok_or_else(|| anyhow!("File {uuid} does not have a header column {key}"))
+            })
+            .collect()
+    }
+
+    fn row_key(row: &[DataCell], key_columns: &[(usize, Option<String>)]) -> String {
+        json!(key_columns
+            .iter()
+            .map(|(col_num, subkey)| match (row.get(*col_num), subkey) {
+                (Some(cell), None) => cell.as_key(),
+                (Some(cell), subkey) => cell.to_sub_key(subkey).as_key(),
+                (None, _) => String::new(),
+            })
+            .collect::<Vec<String>>())
+        .to_string()
+    }
+
+    pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
+        let mut df_in = DataFile::default();
+        df_in.open_input_file(uuid)?;
+        df_in.load_header()?;
+        let key_columns = self.key_columns(df_in.header(), uuid)?;
+        let mut df_out = DataFile::new_output_file()?;
+        df_out.write_json_row(&json! {df_in.header()})?; // Output new header
+        let mut seen = HashSet::new();
+        while let Some(row) = df_in.read_row() {
+            let row: Vec<DataCell> = serde_json::from_str(&row)?;
+            if seen.insert(Self::row_key(&row, &key_columns)) {
+                df_out.write_json_row(&json! {row})?; // Output data row
+            }
+        }
+        df_out.finish()
+    }
+}
+
+// ____________________________________________________________________________________
+
+// Rewrites the text of a column with a regular expression; `replacement` can use $1 etc.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterReplace {
+    pub key: String,
+    pub subkey: Option<String>, // For WikiPage columns: title, prefixed_title, ns_prefix, or wiki
+    pub pattern: String,
+    pub replacement: String,
+}
+
+impl FilterReplace {
+    fn replace_cell(&self, regexp: &Regex, cell: &mut DataCell) {
+        let replace = |s: &str| regexp.replace_all(s, self.replacement.as_str()).to_string();
+        match cell {
+            DataCell::PlainText(s) => *s = replace(s),
+            DataCell::WikiPage(wp) => match self.subkey.as_deref() {
+                Some("title") => {
+                    if let Some(title) = &wp.title {
+                        let title = replace(title);
+                        // Keep the prefixed title in sync
+                        wp.prefixed_title = match wp.ns_prefix.as_deref() {
+                            Some(ns_prefix) if !ns_prefix.is_empty() => {
+                                Some(format!("{ns_prefix}:{title}"))
+                            }
+                            _ => Some(title.to_owned()),
+                        };
+                        wp.title = Some(title);
+                    }
+                }
+                Some("prefixed_title") => {
+                    if let Some(prefixed_title) = &wp.prefixed_title {
+                        let prefixed_title = replace(prefixed_title);
+                        wp.title = match wp.ns_prefix.as_deref() {
+                            Some(ns_prefix) if !ns_prefix.is_empty() => prefixed_title
+                                .strip_prefix(&format!("{ns_prefix}:"))
+                                .map(|title| title.to_string()),
+                            _ => Some(prefixed_title.to_owned()),
+                        };
+                        wp.prefixed_title = Some(prefixed_title);
+                    }
+                }
+                Some("ns_prefix") => wp.ns_prefix = wp.ns_prefix.as_deref().map(replace),
+                Some("wiki") => wp.wiki = wp.wiki.as_deref().map(replace),
+                _ => {} // Not a string field
+            },
+            _ => {} // Only text is rewritten
+        }
+    }
+
+    pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
+        let regexp = RegexBuilder::new(&self.pattern)
+            .build()
+            .map_err(|_| anyhow!("Invalid regular expression: {}", &self.pattern))?;
+        let mut df_in = DataFile::default();
+        let mut df_out = DataFile::new_output_file()?;
+        df_in.open_input_file(uuid)?;
+        df_in.load_header()?;
+        df_out.write_json_row(&json! {df_in.header()})?; // Output new header
+        let col_num = df_in
+            .header()
+            .get_col_num(&self.key)
+            .ok_or_else(|| anyhow!("File {uuid} does not have a header column {}", self.key))?;
+        while let Some(row) = df_in.read_row() {
+            let mut row: Vec<DataCell> = serde_json::from_str(&row)?;
+            if let Some(cell) = row.get_mut(col_num) {
+                self.replace_cell(&regexp, cell);
+            }
+            df_out.write_json_row(&json! {row})?; // Output data row
+        }
+        df_out.finish()
+    }
+}
+
+// ____________________________________________________________________________________
+
+// Keeps rows whose numeric value lies between `min` and `max`; non-numeric cells are dropped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterRange {
+    pub key: String,
+    pub subkey: Option<String>, // For WikiPage columns: ns_id or page_id
+    pub min: f64,
+    pub max: f64,
+
+    #[serde(default)]
+    pub inclusive: bool,
+}
+
+impl FilterRange {
+    fn in_range(&self, cell: &DataCell) -> bool {
+        let value = match cell {
+            DataCell::WikiPage(_) => cell.to_sub_key(&self.subkey),
+            other => other.to_owned(),
+        };
+        let value = match value {
+            DataCell::Int(i) => i as f64,
+            DataCell::Float(f) => f,
+            _ => return false,
+        };
+        if self.inclusive {
+            (self.min..=self.max).contains(&value)
+        } else {
+            value > self.min && value < self.max
+        }
+    }
+
+    pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
+        let mut df_in = DataFile::default();
+        let mut df_out = DataFile::new_output_file()?;
+        df_in.open_input_file(uuid)?;
+        df_in.load_header()?;
+        df_out.write_json_row(&json! {df_in.header()})?; // Output new header
+        let col_num = df_in
+            .header()
+            .get_col_num(&self.key)
+            .ok_or_else(|| anyhow!("File {uuid} does not have a header column {}", self.key))?;
+        while let Some(row) = df_in.read_row() {
+            let row: Vec<DataCell> = serde_json::from_str(&row)?;
+            if row.get(col_num).is_some_and(|cell| self.in_range(cell)) {
+                df_out.write_json_row(&json! {row})?; // Output data row
+            }
+        }
+        df_out.finish()
+    }
+}
+
+// ____________________________________________________________________________________
+
+// Keeps the first `n` rows of each group, ordered by `order_key`; groups stay in input order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopNPerGroup {
+    pub group_keys: Vec<String>,
+    pub order_key: String,
+    pub n: usize,
+    pub reverse: bool, // Largest first
+}
+
+impl TopNPerGroup {
+    pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
+        let mut df_in = DataFile::default();
+        df_in.open_input_file(uuid)?;
+        df_in.load_header()?;
+        let col_num = |key: &String| {
+            df_in
+                .header()
+                .get_col_num(key)
+                .ok_or_else(|| anyhow!("File {uuid} does not have a header column {key}"))
+        };
+        let group_col_nums = self
+            .group_keys
+            .iter()
+            .map(col_num)
+            .collect::<Result<Vec<usize>>>()?;
+        let order_col_num = col_num(&self.order_key)?;
+
+        // Read rows into groups
+        let mut group_order = vec![];
+        let mut groups: HashMap<String, Vec<Vec<DataCell>>> = HashMap::new();
+        while let Some(row) = df_in.read_row() {
+            let row: Vec<DataCell> = serde_json::from_str(&row)?;
+            let group_key = group_col_nums
+                .iter()
+                .map(|col_num| row.get(*col_num).map(|c| c.as_key()).unwrap_or_default())
+                .collect::<Vec<String>>()
+                .join("\t");
+            if !groups.contains_key(&group_key) {
+                group_order.push(group_key.to_owned());
+            }
+            groups.entry(group_key).or_default().push(row);
+        }
+
+        let mut df_out = DataFile::new_output_file()?;
+        df_out.write_json_row(&json! {df_in.header()})?; // Output new header
+        for group_key in group_order {
+            let mut rows: Vec<(DataCell, Vec<DataCell>)> = groups
+                .remove(&group_key)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|row| {
+                    let cell = row.get(order_col_num).unwrap_or(&DataCell::Blank);
+                    (FilterSort::cell_key(cell, true), row)
+                })
+                .collect();
+            rows.sort_by(|(a, _), (b, _)| {
+                let ordering = FilterSort::compare_keys(a, b);
+                if self.reverse {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+            for (_key, row) in rows.into_iter().take(self.n) {
+                df_out.write_json_row(&json! {row})?; // Output data row
+            }
+        }
+        df_out.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_cell::{CALENDAR_GREGORIAN, TIME_PRECISION_DAY, TIME_PRECISION_YEAR};
+    use crate::test_util::{header, load_file, load_rows, text_row, write_file, write_test_file};
+    use crate::wiki_page::WikiPage;
+    use crate::APP;
+
+    #[tokio::test]
+    async fn test_filter_wikipage_via_prefixed_title() {
+        let uuid = "cb1e218e-421f-46b8-a77e-eac6799ce4e4";
+        let filter = Filter {
+            key: "wiki_page".to_string(),
+            subkey: Some("prefixed_title".to_string()),
+            operator: FilterOperator::Equal,
+            value: "AGEB".to_string(),
+            remove_matching: false,
+            ..Default::default()
+        };
+        let df = filter.process(uuid).await.unwrap();
+        assert_eq!(df.rows, 1);
+        APP.remove_uuid_file(&df.uuid).unwrap(); // Cleanup
+    }
+
+    #[tokio::test]
+    async fn test_filter_wikipage_via_namespace_id() {
+        let uuid = "cb1e218e-421f-46b8-a77e-eac6799ce4e4";
+        let mut filter = Filter {
+            key: "wiki_page".to_string(),
+            subkey: Some("ns_id".to_string()),
+            operator: FilterOperator::Unequal,
+            value: "0".to_string(),
+            remove_matching: false,
+            ..Default::default()
+        };
+        let df_keep = filter.process(uuid).await.unwrap();
+        filter.remove_matching = true;
+        let df_remove = filter.process(uuid).await.unwrap();
+
+        assert_eq!(df_keep.rows, 499);
+        assert_eq!(df_remove.rows, 1248);
+
+        // Cleanup
+        APP.remove_uuid_file(&df_keep.uuid).unwrap();
+        APP.remove_uuid_file(&df_remove.uuid).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_filter_wikipage_predicates() {
+        let uuid = "cb1e218e-421f-46b8-a77e-eac6799ce4e4";
+        let predicate = |subkey: &str, operator, value: &str| SubkeyPredicate {
+            subkey: subkey.to_string(),
+            operator,
+            value: value.to_string(),
+        };
+        let mut filter = Filter {
+            key: "wiki_page".to_string(),
+            subkey: Some("ns_id".to_string()),
+            operator: FilterOperator::Equal,
+            value: "0".to_string(),
+            predicates: vec![predicate("wiki", FilterOperator::Equal, "dewiki")],
+            ..Default::default()
+        };
+        let df_and = filter.process(uuid).await.unwrap();
+        assert_eq!(df_and.rows, 1248);
+
+        filter.predicates = vec![predicate("wiki", FilterOperator::Equal, "enwiki")];
+        let df_and_none = filter.process(uuid).await.unwrap();
+        assert_eq!(df_and_none.rows, 0);
+
+        filter.combine = FilterCombine::Or;
+        filter.predicates = vec![predicate("ns_id", FilterOperator::Unequal, "0")];
+        let df_or = filter.process(uuid).await.unwrap();
+        assert_eq!(df_or.rows, 1747);
+
+        // Cleanup
+        for df in [df_and, df_and_none, df_or] {
+            APP.remove_uuid_file(&df.uuid).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filter_wikipage_without_subkey() {
+        let uuid = "cb1e218e-421f-46b8-a77e-eac6799ce4e4";
+        let mut filter = Filter {
+            key: "wiki_page".to_string(),
+            operator: FilterOperator::Equal,
+            value: "dewiki::2._Schachbundesliga_1981/82".to_string(),
+            ..Default::default()
+        };
+        let dfd_equal = filter.process(uuid).await.unwrap();
+        assert_eq!(dfd_equal.rows, 1);
+
+        filter.operator = FilterOperator::Regexp;
+        filter.value = "^dewiki::2\\._Schachbundesliga".to_string();
+        let dfd_regexp = filter.process(uuid).await.unwrap();
+        assert!(dfd_regexp.rows > 1);
+
+        // Cleanup
+        for uuid in [&dfd_equal.uuid, &dfd_regexp.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filter_wikipage_page_id() {
+        let kind = ColumnHeaderType::WikiPage(WikiPage {
+            wiki: Some("enwiki".to_string()),
+            ..Default::default()
+        });
+        let rows = [5, 50, 500]
+            .into_iter()
+            .map(|page_id| {
+                vec![DataCell::WikiPage(WikiPage {
+                    page_id: Some(page_id),
+                    prefixed_title: Some(format!("Page {page_id}")),
+                    wiki: Some("enwiki".to_string()),
+                    ..Default::default()
+                })]
+            })
+            .collect();
+        let uuid = write_file(&header(&[("page", kind)]), rows);
+
+        let mut filter = Filter {
+            key: "page".to_string(),
+            subkey: Some("page_id".to_string()),
+            operator: FilterOperator::LargerThan,
+            value: "10".to_string(),
+            ..Default::default()
+        };
+        let dfd_larger = filter.process(&uuid).await.unwrap();
+        assert_eq!(dfd_larger.rows, 2);
+
+        filter.operator = FilterOperator::Equal;
+        filter.value = "500".to_string();
+        let dfd_equal = filter.process(&uuid).await.unwrap();
+        assert_eq!(dfd_equal.rows, 1);
+
+        // Cleanup
+        for uuid in [&uuid, &dfd_larger.uuid, &dfd_equal.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filter_mismatched_value() {
+        let uuid = write_file(
+            &header(&[("views", ColumnHeaderType::Int)]),
+            vec![vec![DataCell::Int(0)], vec![DataCell::Int(5)]],
+        );
+
+        let mut filter = Filter {
+            key: "views".to_string(),
+            operator: FilterOperator::Equal,
+            value: "lots".to_string(),
+            ..Default::default()
+        };
+        let dfd_equal = filter.process(&uuid).await.unwrap();
+        assert_eq!(dfd_equal.rows, 0); // Not parsed as 0
+
+        filter.operator = FilterOperator::LargerThan;
+        let error = filter.process(&uuid).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Filter cannot compare Int(0) with 'lots'"));
+
+        filter.value = "2".to_string();
+        let dfd_larger = filter.process(&uuid).await.unwrap();
+        assert_eq!(dfd_larger.rows, 1);
+
+        // Cleanup
+        for uuid in [&uuid, &dfd_equal.uuid, &dfd_larger.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filter_wikipage_larger_than() {
+        let uuid = "cb1e218e-421f-46b8-a77e-eac6799ce4e4";
+        let mut filter = Filter {
+            key: "wiki_page".to_string(),
+            subkey: Some("ns_id".to_string()),
+            operator: FilterOperator::LargerThan,
+            value: "0".to_string(),
+            ..Default::default()
+        };
+        let dfd = filter.process(uuid).await.unwrap();
+        assert_eq!(dfd.rows, 1747 - 1248);
+        APP.remove_uuid_file(&dfd.uuid).unwrap(); // Cleanup
+
+        filter.value = "main".to_string();
+        assert!(filter.process(uuid).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_filter_in() {
+        let uuid = "cb1e218e-421f-46b8-a77e-eac6799ce4e4";
+        let mut filter = Filter {
+            key: "wiki_page".to_string(),
+            subkey: Some("prefixed_title".to_string()),
+            operator: FilterOperator::In,
+            value: "AGEB|2._Schachbundesliga_1981/82\n 2._Schachbundesliga_1982/83 \n".to_string(),
+            ..Default::default()
+        };
+        let df_keep = filter.process(uuid).await.unwrap();
+        assert_eq!(df_keep.rows, 3);
+        filter.remove_matching = true;
+        let df_remove = filter.process(uuid).await.unwrap();
+        assert_eq!(df_remove.rows, 1747 - 3);
+
+        // Cleanup
+        APP.remove_uuid_file(&df_keep.uuid).unwrap();
+        APP.remove_uuid_file(&df_remove.uuid).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_filter_boolean() {
+        let rows = [true, false, false]
+            .into_iter()
+            .map(|b| vec![DataCell::Boolean(b)])
+            .collect();
+        let uuid = write_file(
+            &header(&[("disambiguation", ColumnHeaderType::Boolean)]),
+            rows,
+        );
+
+        let mut filter = Filter {
+            key: "disambiguation".to_string(),
+            operator: FilterOperator::Equal,
+            value: "true".to_string(),
+            ..Default::default()
+        };
+        let df_true = filter.process(&uuid).await.unwrap();
+        assert_eq!(df_true.rows, 1);
+        filter.operator = FilterOperator::Unequal;
+        filter.value = "1".to_string();
+        let df_false = filter.process(&uuid).await.unwrap();
+        assert_eq!(df_false.rows, 2);
+
+        // Cleanup
+        for uuid in [&uuid, &df_true.uuid, &df_false.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filter_dates() {
+        let rows = [
+            ("2019-12-31", "+1500-00-00T00:00:00Z", TIME_PRECISION_YEAR),
+            ("2020-01-01", "+1850-03-07T00:00:00Z", TIME_PRECISION_DAY),
+            ("2020-02-01", "+1900-01-01T00:00:00Z", TIME_PRECISION_DAY),
+        ]
+        .into_iter()
+        .map(|(date, time, precision)| {
+            let time = DataCell::WikidataTime {
+                time: time.to_string(),
+                precision,
+                calendar: CALENDAR_GREGORIAN.to_string(),
+            };
+            vec![DataCell::Date(date.to_string()), time]
+        })
+        .collect();
+        let columns = [
+            ("date", ColumnHeaderType::Date),
+            ("time", ColumnHeaderType::WikidataTime),
+        ];
+        let uuid = write_file(&header(&columns), rows);
+
+        let mut filter = Filter {
+            key: "date".to_string(),
+            operator: FilterOperator::LargerThan,
+            value: "2020-01-01".to_string(),
+            ..Default::default()
+        };
+        let dfd_date = filter.process(&uuid).await.unwrap();
+        assert_eq!(dfd_date.rows, 1);
+        filter.key = "time".to_string();
+        filter.operator = FilterOperator::LargerOrEqualThan;
+        filter.value = "1850-03-07".to_string();
+        let dfd_time = filter.process(&uuid).await.unwrap();
+        assert_eq!(dfd_time.rows, 2);
+        filter.operator = FilterOperator::SmallerThan;
+        filter.value = "1600".to_string();
+        let dfd_year = filter.process(&uuid).await.unwrap();
+        assert_eq!(dfd_year.rows, 1);
+
+        // Cleanup
+        for uuid in [&uuid, &dfd_date.uuid, &dfd_time.uuid, &dfd_year.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filter_namespace() {
+        let uuid = "cb1e218e-421f-46b8-a77e-eac6799ce4e4";
+        let mut filter = FilterNamespace {
+            key: "wiki_page".to_string(),
+            ns_ids: vec![10, 14],
+            ns_names: vec![],
+            remove_matching: false,
+        };
+        let df_keep = filter.process(uuid).await.unwrap();
+        filter.remove_matching = true;
+        let df_remove = filter.process(uuid).await.unwrap();
+
+        assert_eq!(df_keep.rows, 480);
+        assert_eq!(df_remove.rows, 1267);
+
+        // Cleanup
+        APP.remove_uuid_file(&df_keep.uuid).unwrap();
+        APP.remove_uuid_file(&df_remove.uuid).unwrap();
+    }
+
+    #[test]
+    fn test_filter_operator_deserialization() {
+        let operator = json!("Equal").to_string();
+        let operator: FilterOperator = serde_json::from_str(&operator).unwrap();
+        assert_eq!(operator, FilterOperator::Equal);
+    }
+
+    #[tokio::test]
+    async fn test_filter_petscan() {
+        let uuid = "8c5d1fb3-6ea8-44d1-b938-9d22f569c412";
+        let filter = FilterPetScan {
+            key: "wikidata_item".to_string(),
+            psid: 26256139,
+        };
+        let df = filter.process(uuid).await.unwrap();
+        assert_eq!(df.rows, 33);
+        APP.remove_uuid_file(&df.uuid).unwrap(); // Cleanup
+    }
+
+    #[tokio::test]
+    async fn test_filter_sort() {
+        async fn sub_test(reverse: bool, expected_first_item: &str) {
+            let uuid = "8c5d1fb3-6ea8-44d1-b938-9d22f569c412";
+            let filter = FilterSort {
+                key: "wikidata_item".to_string(),
+                reverse,
+                numeric: false,
+            };
+            let df = filter.process(uuid).await.unwrap();
+            // println!("Generated test_data/{}.jsonl with {} rows",df.uuid,df.rows);
+            assert_eq!(df.rows, 49);
+            if true {
+                let mut df_in = DataFile::default();
+                df_in
+                    .open_input_file(&df.uuid)
+                    .unwrap_or_else(|_| panic!("New data file missing: {}", df.uuid));
+                let _ = df_in
+                    .read_row()
+                    .unwrap_or_else(|| panic!("Header row missing for {}", df.uuid));
+                let row = df_in
+                    .read_row()
+                    .unwrap_or_else(|| panic!("First data row missing for {}", df.uuid));
+                let row: Vec<DataCell> =
+                    serde_json::from_str(&row).expect("First data row is not JSON");
+                let cell = match &row[0] {
+                    DataCell::WikiPage(wp) => wp.to_owned(),
+                    _ => panic!("Sort failed"),
+                };
+                assert_eq!(cell.prefixed_title.unwrap(), expected_first_item);
+            }
+            APP.remove_uuid_file(&df.uuid).unwrap(); // Cleanup
+        }
+
+        sub_test(true, "Q99929855").await;
         sub_test(false, "Q18619644").await;
     }
+
+    #[tokio::test]
+    async fn test_filter_sort_numeric() {
+        let rows = [
+            DataCell::Int(10),
+            DataCell::Int(2),
+            DataCell::Blank,
+            DataCell::Int(20),
+            DataCell::Int(1),
+        ]
+        .into_iter()
+        .map(|cell| vec![cell])
+        .collect();
+        let uuid = write_file(&header(&[("n", ColumnHeaderType::Int)]), rows);
+
+        let sorted = |dfd: &DataFileDetails| {
+            load_file(&dfd.uuid)
+                .rows
+                .iter()
+                .map(|row| row[0].as_key())
+                .collect::<Vec<_>>()
+        };
+        let mut filter = FilterSort {
+            key: "n".to_string(),
+            reverse: false,
+            numeric: true,
+        };
+        let numeric = filter.process(&uuid).await.unwrap();
+        assert_eq!(sorted(&numeric), vec!["", "1", "2", "10", "20"]);
+        let chunked = filter.process_in_chunks(&uuid, 2).unwrap();
+        assert_eq!(sorted(&chunked), vec!["", "1", "2", "10", "20"]);
+        filter.numeric = false;
+        let text = filter.process(&uuid).await.unwrap();
+        assert_eq!(sorted(&text), vec!["", "1", "10", "2", "20"]);
+
+        // Cleanup
+        for uuid in [&uuid, &numeric.uuid, &chunked.uuid, &text.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filter_sort_in_chunks() {
+        let uuid = "cb1e218e-421f-46b8-a77e-eac6799ce4e4";
+        for reverse in [false, true] {
+            let filter = FilterSort {
+                key: "wiki_page".to_string(),
+                reverse,
+                numeric: false,
+            };
+            let in_memory = filter.process(uuid).await.unwrap();
+            let chunked = filter.process_in_chunks(uuid, 100).unwrap();
+            assert_eq!(chunked.rows, 1747);
+
+            let rows = load_rows(&chunked.uuid);
+            let keys: Vec<String> = rows.iter().map(|row| row[0].as_key()).collect();
+            let mut sorted_keys = keys.clone();
+            sorted_keys.sort();
+            if reverse {
+                sorted_keys.reverse();
+            }
+            assert_eq!(keys.first(), sorted_keys.first());
+            assert_eq!(keys.last(), sorted_keys.last());
+            assert_eq!(rows, load_rows(&in_memory.uuid));
+
+            // Cleanup
+            APP.remove_uuid_file(&in_memory.uuid).unwrap();
+            APP.remove_uuid_file(&chunked.uuid).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_select_matches_filter_then_sort() {
+        let uuid = "cb1e218e-421f-46b8-a77e-eac6799ce4e4";
+        let filter = Filter {
+            key: "wiki_page".to_string(),
+            subkey: Some("ns_id".to_string()),
+            operator: FilterOperator::Unequal,
+            value: "0".to_string(),
+            remove_matching: false,
+            ..Default::default()
+        };
+        let sort = FilterSort {
+            key: "wiki_page".to_string(),
+            reverse: true,
+            numeric: false,
+        };
+        let select = Select {
+            filters: vec![filter.to_owned()],
+            columns: vec!["wiki_page".to_string()],
+            sort: Some(sort.to_owned()),
+        };
+        let df_select = select.process(uuid).await.unwrap();
+        let df_filtered = filter.process(uuid).await.unwrap();
+        let df_sorted = sort.process(&df_filtered.uuid).await.unwrap();
+        assert_eq!(df_select.rows, 499);
+
+        let mut df1 = DataFile::default();
+        df1.open_input_file(&df_select.uuid).unwrap();
+        df1.load().unwrap();
+        let mut df2 = DataFile::default();
+        df2.open_input_file(&df_sorted.uuid).unwrap();
+        df2.load().unwrap();
+        assert_eq!(df1.rows, df2.rows);
+
+        // Cleanup
+        APP.remove_uuid_file(&df_select.uuid).unwrap();
+        APP.remove_uuid_file(&df_filtered.uuid).unwrap();
+        APP.remove_uuid_file(&df_sorted.uuid).unwrap();
+    }
+
+    #[test]
+    fn test_compare_keys_mixed() {
+        let mut keys: Vec<DataCell> = [
+            DataCell::PlainText("10".to_string()),
+            DataCell::Float(2.5),
+            DataCell::Blank,
+            DataCell::Int(3),
+            DataCell::Date("2020-01-01".to_string()),
+            DataCell::Int(-1),
+        ]
+        .iter()
+        .map(|cell| FilterSort::cell_key(cell, true))
+        .collect();
+        keys.sort_by(FilterSort::compare_keys);
+        let keys: Vec<String> = keys.iter().map(|key| key.as_key()).collect();
+        assert_eq!(keys, vec!["", "-1", "2.5", "3", "10", "2020-01-01"]);
+    }
+
+    #[tokio::test]
+    async fn test_top_n_per_group() {
+        let header = header(&[
+            ("category", ColumnHeaderType::PlainText),
+            ("views", ColumnHeaderType::Int),
+        ]);
+        let rows = [("A", 5), ("B", 1), ("A", 20), ("A", 3), ("B", 7)]
+            .iter()
+            .map(|(category, views)| {
+                vec![
+                    DataCell::PlainText(category.to_string()),
+                    DataCell::Int(*views),
+                ]
+            })
+            .collect();
+        let uuid = write_file(&header, rows);
+
+        let filter = TopNPerGroup {
+            group_keys: vec!["category".to_string()],
+            order_key: "views".to_string(),
+            n: 2,
+            reverse: true,
+        };
+        let dfd = filter.process(&uuid).await.unwrap();
+        let result: Vec<String> = load_file(&dfd.uuid)
+            .rows
+            .iter()
+            .map(|row| format!("{}{}", row[0].as_key(), row[1].as_key()))
+            .collect();
+        assert_eq!(result, vec!["A20", "A5", "B7", "B1"]);
+
+        // Cleanup
+        APP.remove_uuid_file(&uuid).unwrap();
+        APP.remove_uuid_file(&dfd.uuid).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_filter_columns() {
+        let uuid = write_test_file(&["a", "b", "c"], vec![text_row(&["1", "2", "3"])]);
+
+        let load = |uuid: &str| {
+            let df = load_file(uuid);
+            let names: Vec<String> = df
+                .header()
+                .columns
+                .iter()
+                .map(|c| c.name.to_owned())
+                .collect();
+            let values: Vec<String> = df.rows[0].iter().map(|c| c.as_key()).collect();
+            (names, values)
+        };
+
+        // Keep, in the given order
+        let keep = FilterColumns {
+            keep: Some(vec!["c".to_string(), "a".to_string()]),
+            drop: None,
+        };
+        let dfd_keep = keep.process(&uuid).await.unwrap();
+        assert_eq!(
+            load(&dfd_keep.uuid),
+            (
+                vec!["c".to_string(), "a".to_string()],
+                vec!["3".to_string(), "1".to_string()]
+            )
+        );
+
+        // Drop
+        let drop_b = FilterColumns {
+            keep: None,
+            drop: Some(vec!["b".to_string()]),
+        };
+        let dfd_drop = drop_b.process(&uuid).await.unwrap();
+        assert_eq!(
+            load(&dfd_drop.uuid),
+            (
+                vec!["a".to_string(), "c".to_string()],
+                vec!["1".to_string(), "3".to_string()]
+            )
+        );
+
+        // Both, or a missing column
+        let both = FilterColumns {
+            keep: Some(vec!["a".to_string()]),
+            drop: Some(vec!["b".to_string()]),
+        };
+        assert!(both.process(&uuid).await.is_err());
+        let missing = FilterColumns {
+            keep: Some(vec!["wiki_page".to_string(), "nope".to_string()]),
+            drop: None,
+        };
+        assert!(missing
+            .process("cb1e218e-421f-46b8-a77e-eac6799ce4e4")
+            .await
+            .is_err());
+
+        // Cleanup
+        for uuid in [&uuid, &dfd_keep.uuid, &dfd_drop.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filter_columns_keep_test_data() {
+        let uuid = "cb1e218e-421f-46b8-a77e-eac6799ce4e4";
+        let filter = FilterColumns {
+            keep: Some(vec!["wiki_page".to_string()]),
+            drop: None,
+        };
+        let dfd = filter.process(uuid).await.unwrap();
+        assert_eq!(dfd.rows, 1747);
+        APP.remove_uuid_file(&dfd.uuid).unwrap(); // Cleanup
+    }
+
+    #[tokio::test]
+    async fn test_filter_dedup() {
+        let header = header(&[
+            ("name", ColumnHeaderType::PlainText),
+            ("value", ColumnHeaderType::Float),
+        ]);
+        let rows = vec![
+            vec![DataCell::PlainText("a".to_string()), DataCell::Float(1.0)],
+            vec![DataCell::PlainText("b".to_string()), DataCell::Float(2.5)],
+            vec![DataCell::PlainText("a".to_string()), DataCell::Float(1.0)],
+            vec![DataCell::PlainText("a".to_string()), DataCell::Int(1)],
+            vec![DataCell::PlainText("a".to_string()), DataCell::Float(1.5)],
+        ];
+        let uuid = write_file(&header, rows.to_owned());
+
+        let dfd = FilterDedup::default().process(&uuid).await.unwrap();
+        assert_eq!(
+            load_rows(&dfd.uuid),
+            vec![rows[0].to_owned(), rows[1].to_owned(), rows[4].to_owned()]
+        );
+
+        // Cleanup
+        APP.remove_uuid_file(&uuid).unwrap();
+        APP.remove_uuid_file(&dfd.uuid).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_filter_dedup_keys() {
+        let uuid = "cb1e218e-421f-46b8-a77e-eac6799ce4e4";
+        let filter = FilterDedup {
+            keys: vec!["wiki_page.wiki".to_string(), "wiki_page.ns_id".to_string()],
+        };
+        let dfd = filter.process(uuid).await.unwrap();
+
+        let pair = |row: &Vec<DataCell>| match &row[0] {
+            DataCell::WikiPage(wp) => (wp.wiki.to_owned(), wp.ns_id),
+            _ => panic!("Not a WikiPage: {row:?}"),
+        };
+        let mut expected = vec![];
+        let mut seen = HashSet::new();
+        for row in load_rows(uuid) {
+            if seen.insert(pair(&row)) {
+                expected.push(row);
+            }
+        }
+        assert_eq!(expected.len(), 6); // Namespaces 0, 4, 10, 14, 100 and none
+        assert_eq!(load_rows(&dfd.uuid), expected);
+
+        let filter = FilterDedup {
+            keys: vec!["wiki_page.wiki".to_string(), "no_such_column".to_string()],
+        };
+        assert!(filter.process(uuid).await.is_err());
+
+        // Cleanup
+        APP.remove_uuid_file(&dfd.uuid).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_filter_replace() {
+        let header = header(&[
+            ("title", ColumnHeaderType::PlainText),
+            ("page", ColumnHeaderType::WikiPage(WikiPage::default())),
+        ]);
+        let page = |ns_prefix: &str, title: &str| WikiPage {
+            wiki: Some("dewiki".to_string()),
+            title: Some(title.to_string()),
+            prefixed_title: Some(format!("{ns_prefix}:{title}")),
+            ns_prefix: Some(ns_prefix.to_string()),
+            ns_id: Some(4),
+            page_id: None,
+        };
+        let rows = vec![
+            vec![
+                DataCell::PlainText("2021-05-03 Meeting".to_string()),
+                DataCell::WikiPage(page("Wikipedia", "2021-05-03_Meeting")),
+            ],
+            vec![DataCell::PlainText("Undated".to_string()), DataCell::Int(7)],
+        ];
+        let uuid = write_file(&header, rows.to_owned());
+
+        let date_prefix = |key: &str, subkey: Option<&str>| FilterReplace {
+            key: key.to_string(),
+            subkey: subkey.map(|s| s.to_string()),
+            pattern: r"^(\d{4})-\d{2}-\d{2}[ _](.*)$".to_string(),
+            replacement: "$2 ($1)".to_string(),
+        };
+
+        let dfd_text = date_prefix("title", None).process(&uuid).await.unwrap();
+        let rows_text = load_rows(&dfd_text.uuid);
+        assert_eq!(
+            rows_text[0][0],
+            DataCell::PlainText("Meeting (2021)".to_string())
+        );
+        assert_eq!(rows_text[1], rows[1]);
+
+        let dfd_page = date_prefix("page", Some("title"))
+            .process(&uuid)
+            .await
+            .unwrap();
+        let rows_page = load_rows(&dfd_page.uuid);
+        assert_eq!(
+            rows_page[0][1],
+            DataCell::WikiPage(page("Wikipedia", "Meeting (2021)"))
+        );
+        assert_eq!(rows_page[1], rows[1]);
+
+        // Cleanup
+        for uuid in [&uuid, &dfd_text.uuid, &dfd_page.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filter_range() {
+        let mut rows: Vec<Vec<DataCell>> = [5, 10, 15, 20, 25]
+            .iter()
+            .map(|views| vec![DataCell::Int(*views)])
+            .collect();
+        rows.push(vec![DataCell::Float(12.5)]);
+        rows.push(vec![DataCell::PlainText("15".to_string())]);
+        let uuid = write_file(&header(&[("views", ColumnHeaderType::Int)]), rows);
+
+        let mut filter = FilterRange {
+            key: "views".to_string(),
+            subkey: None,
+            min: 10.0,
+            max: 20.0,
+            inclusive: true,
+        };
+        let dfd_inclusive = filter.process(&uuid).await.unwrap();
+        let values: Vec<String> = load_file(&dfd_inclusive.uuid)
+            .rows
+            .iter()
+            .map(|row| row[0].as_key())
+            .collect();
+        assert_eq!(values, vec!["10", "15", "20", "12.5"]);
+
+        filter.inclusive = false;
+        let dfd_exclusive = filter.process(&uuid).await.unwrap();
+        assert_eq!(dfd_exclusive.rows, 2);
+
+        // Cleanup
+        for uuid in [&uuid, &dfd_inclusive.uuid, &dfd_exclusive.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
 }
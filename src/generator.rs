@@ -1,54 +1,336 @@
-use crate::{data_file::DataFileDetails, APP};
+use crate::{app::App, data_file::DataFileDetails, APP};
 use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
 use mediawiki::api::Api;
-use regex::RegexBuilder;
+use mediawiki::page::Page;
+use regex::{Regex, RegexBuilder};
+use std::error::Error;
+use std::time::Duration;
+use tracing::warn;
+
+const EDIT_MAX_ATTEMPTS: u32 = 5;
+const EDIT_RETRY_INITIAL_DELAY_MS: u64 = 2000;
+pub const DEFAULT_EDIT_SUMMARY: &str = "ToolFlow generator edit";
+const QUICKSTATEMENTS_API: &str = "https://quickstatements.toolforge.org/api.php";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SectionPosition {
+    Top,
+    Bottom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+// Where a new generated block goes; an existing one is always replaced in place
+pub enum BlockMode {
+    Replace, // Replace the generated block, or append it if there is none yet
+    Append,
+    Prepend,
+}
+
+const AUTHORIZATION_ERROR_CODES: &[&str] = &[
+    "permissiondenied",
+    "assertuserfailed",
+    "assertnameduserfailed",
+    "notloggedin",
+];
+
+lazy_static! {
+    static ref RE_SECTION_HEADING: Regex = Regex::new(r"(?m)^=.*=[ \t]*$").expect("RegEx fail");
+}
+
+// The wiki rejected the stored OAuth credentials; the user needs to re-authorize ToolFlow
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OAuthExpiredError;
+
+impl std::fmt::Display for OAuthExpiredError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Your OAuth authorization has expired or was revoked; please re-authorize")
+    }
+}
+
+impl Error for OAuthExpiredError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EditFailure {
+    Conflict,      // Page changed in the meantime; re-read and re-apply before retrying
+    Transient,     // Rate limit, lag etc.; wait and retry
+    Authorization, // OAuth token expired or revoked
+    Fatal,
+}
+
+impl EditFailure {
+    fn from_error(error: &(dyn Error + 'static)) -> Self {
+        let code = match error.downcast_ref::<mediawiki::MediaWikiError>() {
+            Some(mediawiki::MediaWikiError::EditError(v)) => v["error"]["code"]
+                .as_str()
+                .or_else(|| v["edit"]["code"].as_str())
+                .unwrap_or_default()
+                .to_string(),
+            _ => error.to_string(), // Api gives up on maxlag with a plain string error
+        };
+        match code.as_str() {
+            code if code.starts_with("mwoauth") || AUTHORIZATION_ERROR_CODES.contains(&code) => {
+                Self::Authorization
+            }
+            // Token errors only come as a string containing the API response
+            other if other.contains("\"mwoauth-") => Self::Authorization,
+            other
+                if AUTHORIZATION_ERROR_CODES
+                    .iter()
+                    .any(|code| other.contains(&format!("\"{code}\""))) =>
+            {
+                Self::Authorization
+            }
+            "editconflict" => Self::Conflict,
+            "ratelimited" | "maxlag" | "readonly" | "internal_api_error_DBQueryError" => {
+                Self::Transient
+            }
+            other if other.contains("[MAXLAG]") => Self::Transient,
+            _ => Self::Fatal,
+        }
+    }
+}
 
 #[derive(Default, Clone, Debug)]
 pub struct Generator {}
 
 impl Generator {
-    pub async fn wikipage(
+    async fn open_page(wiki: &str, page: &str, user_id: usize) -> Result<(Api, Page, String)> {
+        let mut api = APP.get_api_for_wiki(wiki).await?;
+        APP.add_user_oauth_to_api(&mut api, user_id).await?;
+
+        let title = mediawiki::title::Title::new_from_full(page, &api);
+        let mut page = Page::new(title);
+        let before = Self::read_page_text(&api, &mut page).await?;
+        Ok((api, page, before))
+    }
+
+    async fn read_page_text(api: &Api, page: &mut Page) -> Result<String> {
+        match page.text(api).await {
+            Ok(wikitext) => Ok(wikitext.to_string()),
+            Err(mediawiki::MediaWikiError::Missing(_)) => Ok(String::new()),
+            Err(e) => Err(anyhow!(e.to_string())),
+        }
+    }
+
+    // `update` turns the current page text into the new one; it is re-applied on edit conflicts
+    async fn save_page<F>(
+        api: &mut Api,
+        page: &mut Page,
+        before: String,
+        summary: &str,
+        update: F,
+    ) -> Result<()>
+    where
+        F: Fn(&str) -> Result<String>,
+    {
+        let mut before = before;
+        let mut delay_ms = EDIT_RETRY_INITIAL_DELAY_MS;
+        let mut attempt = 1;
+        loop {
+            let after = update(&before)?;
+            if before == after || cfg!(test) {
+                // Only perform the edit if something has changed
+                // Do not actually edit the page in testing, we know the Api crate works
+                return Ok(());
+            }
+            let (failure, message) = match page.edit_text(api, after, summary).await {
+                Ok(()) => return Ok(()),
+                Err(e) => (EditFailure::from_error(e.as_ref()), e.to_string()),
+            };
+            if failure == EditFailure::Authorization {
+                return Err(anyhow!(OAuthExpiredError));
+            }
+            if failure == EditFailure::Fatal || attempt >= EDIT_MAX_ATTEMPTS {
+                return Err(anyhow!(message));
+            }
+            warn!(attempt, ?failure, error = %message, "Generator edit failed, retrying");
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            delay_ms *= 2;
+            attempt += 1;
+            if failure == EditFailure::Conflict {
+                before = Self::read_page_text(api, page).await?;
+            }
+        }
+    }
+
+    // `{rows}` in the summary template is replaced with the number of rows in the output
+    pub fn edit_summary(summary_template: &str, rows: usize) -> String {
+        match summary_template.trim() {
+            "" => DEFAULT_EDIT_SUMMARY.to_string(),
+            summary => summary.replace("{rows}", &rows.to_string()),
+        }
+    }
+
+    // `{date}` in the header template is replaced with the current (UTC) date
+    fn section_header(header_template: &str) -> String {
+        let date = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        header_template.replace("{date}", &date)
+    }
+
+    // Top means before the first existing section, so the page intro stays where it is
+    fn insert_section(before: &str, section: &str, position: SectionPosition) -> String {
+        match (position, RE_SECTION_HEADING.find(before)) {
+            (SectionPosition::Top, Some(m)) => {
+                let (intro, sections) = before.split_at(m.start());
+                format!("{intro}{section}\n\n{sections}")
+            }
+            _ => format!("{}\n\n{section}", before.trim_end())
+                .trim()
+                .to_string(),
+        }
+    }
+
+    pub async fn append_section(
         wiki_table: &str,
         wiki: &str,
         page: &str,
+        header_template: &str,
+        position: SectionPosition,
+        summary: &str,
         user_id: usize,
     ) -> Result<DataFileDetails> {
-        let server = APP
-            .get_webserver_for_wiki(wiki)
-            .ok_or_else(|| anyhow!("Could not find web server for {wiki}"))?;
-        let url = format!("https://{server}/w/api.php");
-        let mut api = Api::new(&url).await?;
-        APP.add_user_oauth_to_api(&mut api, user_id).await?;
+        let (mut api, mut page, before) = Self::open_page(wiki, page, user_id).await?;
+        let header = Self::section_header(header_template);
+        let section = format!("== {header} ==\n{}", wiki_table.trim_end());
+        Self::save_page(&mut api, &mut page, before, summary, |before| {
+            Ok(Self::insert_section(before, &section, position))
+        })
+        .await?;
+        Ok(DataFileDetails::new_invalid())
+    }
 
-        let title = mediawiki::title::Title::new_from_full(page, &api);
-        let mut page = mediawiki::page::Page::new(title);
-        let before = match page.text(&api).await {
-            Ok(wikitext) => wikitext,
-            Err(mediawiki::MediaWikiError::Missing(_)) => "",
-            Err(e) => return Err(anyhow!(e.to_string())),
-        };
+    // Markers for the generated block; `section_id` allows several blocks on one page
+    fn block_markers(section_id: Option<&str>) -> Result<(String, String)> {
+        match section_id.map(|id| id.trim()) {
+            None | Some("") => Ok((
+                "<!--TOOLFLOW GENERATOR START-->".to_string(),
+                "<!--TOOLFLOW GENERATOR END-->".to_string(),
+            )),
+            Some(id) if id.chars().all(|c| c.is_alphanumeric() || "_- ".contains(c)) => Ok((
+                format!("<!--TOOLFLOW GENERATOR START:{id}-->"),
+                format!("<!--TOOLFLOW GENERATOR END:{id}-->"),
+            )),
+            Some(id) => Err(anyhow!("Invalid generator section ID '{id}'")),
+        }
+    }
 
-        // TODO replace old section
-        let start = "<!--TOOLFLOW GENERATOR START-->";
-        let end = "<!--TOOLFLOW GENERATOR END-->";
-        let re = RegexBuilder::new(&format!(r"(?s){start}.*{end}"))
-            .multi_line(true)
-            .crlf(true)
-            .build()?;
-        let replace_with = format!("{start}\n{wiki_table}\n{end}\n");
-        let after = if re.is_match(&before) {
-            re.replace_all(&before, replace_with.to_owned()).to_string()
-        } else {
-            format!("{before}\n{replace_with}").trim().to_string()
-        };
+    // Replaces every existing generated block in place; otherwise places a new one by `mode`
+    fn place_block(
+        before: &str,
+        wiki_table: &str,
+        start: &str,
+        end: &str,
+        mode: BlockMode,
+    ) -> Result<String> {
+        let re = Self::block_regex(start, end)?;
+        let block = format!("{start}\n{wiki_table}\n{end}");
+        Ok(match mode {
+            // NoExpand, as wikitext can contain `$` that would otherwise be read as a capture group
+            _ if re.is_match(before) => re
+                .replace_all(before, regex::NoExpand(&format!("{block}\n")))
+                .to_string(),
+            BlockMode::Replace | BlockMode::Append => {
+                format!("{}\n{block}", before.trim_end()).trim().to_string()
+            }
+            BlockMode::Prepend => format!("{block}\n{}", before.trim_start())
+                .trim()
+                .to_string(),
+        })
+    }
+
+    // Non-greedy, so each block ends at its own end marker; a greedy match would also replace
+    // the text between two blocks with the same markers
+    fn block_regex(start: &str, end: &str) -> Result<Regex> {
+        Ok(RegexBuilder::new(&format!(
+            r"(?s){}.*?{}\n?",
+            regex::escape(start),
+            regex::escape(end)
+        ))
+        .multi_line(true)
+        .crlf(true)
+        .build()?)
+    }
+
+    // Form parameters for a QuickStatements API batch import of V1 commands
+    fn quickstatements_batch(
+        commands: &str,
+        batch_name: &str,
+        username: &str,
+        token: &str,
+    ) -> Vec<(&'static str, String)> {
+        vec![
+            ("action", "import".to_string()),
+            ("submit", "1".to_string()),
+            ("format", "v1".to_string()),
+            ("compress", "1".to_string()),
+            ("batchname", batch_name.to_string()),
+            ("username", username.to_string()),
+            ("token", token.to_string()),
+            ("data", commands.trim_end().to_string()),
+        ]
+    }
 
-        if before != after && !cfg!(test) {
-            // Only perform the edit if something has changed
-            // Do not actually edit the page in testing, we know the Api crate works
-            page.edit_text(&mut api, after, "ToolFlow generator edit")
-                .await
-                .map_err(|e| anyhow!(e.to_string()))?;
+    // QuickStatements runs batches under the user's name, using the QuickStatements token stored
+    // alongside their OAuth credentials by `toolflow user quickstatements-token`
+    async fn quickstatements_user(user_id: usize) -> Result<(String, String)> {
+        let token = APP.user_oauth(user_id).await?["quickstatements_token"]
+            .as_str()
+            .filter(|token| !token.is_empty())
+            .ok_or_else(|| anyhow!("User {user_id} has no QuickStatements token stored"))?
+            .to_string();
+        let mut api = APP.get_api_for_wiki("wikidatawiki").await?;
+        APP.add_user_oauth_to_api(&mut api, user_id).await?;
+        let params = api.params_into(&[("action", "query"), ("meta", "userinfo")]);
+        let j = api
+            .get_query_api_json(&params)
+            .await
+            .map_err(|e| anyhow!(e.to_string()))?;
+        match j["query"]["userinfo"]["name"].as_str() {
+            Some(name) if j["query"]["userinfo"]["anon"].is_null() => Ok((name.to_string(), token)),
+            _ => Err(anyhow!(OAuthExpiredError)),
+        }
+    }
+
+    pub async fn quickstatements(
+        commands: &str,
+        batch_name: &str,
+        user_id: usize,
+    ) -> Result<DataFileDetails> {
+        if commands.trim().is_empty() || cfg!(test) {
+            // Nothing to do
+            // Do not actually submit batches in testing
+            return Ok(DataFileDetails::new_invalid());
         }
+        let (username, token) = Self::quickstatements_user(user_id).await?;
+        let batch = Self::quickstatements_batch(commands, batch_name, &username, &token);
+        let response = App::reqwest_client()?
+            .post(QUICKSTATEMENTS_API)
+            .form(&batch)
+            .send()
+            .await?;
+        let j: serde_json::Value = response.json().await?;
+        match j["status"].as_str() {
+            Some("OK") => Ok(DataFileDetails::new_invalid()),
+            _ => Err(anyhow!("QuickStatements rejected the batch: {j}")),
+        }
+    }
+
+    pub async fn wikipage(
+        wiki_table: &str,
+        wiki: &str,
+        page: &str,
+        section_id: Option<&str>,
+        mode: BlockMode,
+        summary: &str,
+        user_id: usize,
+    ) -> Result<DataFileDetails> {
+        let (start, end) = Self::block_markers(section_id)?;
+        let (mut api, mut page, before) = Self::open_page(wiki, page, user_id).await?;
+        Self::save_page(&mut api, &mut page, before, summary, |before| {
+            Self::place_block(before, wiki_table, &start, &end, mode)
+        })
+        .await?;
         Ok(DataFileDetails::new_invalid())
     }
 }
@@ -57,6 +339,140 @@ impl Generator {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_generator_edit_failure() {
+        let error = |code: &str| {
+            mediawiki::MediaWikiError::EditError(serde_json::json!({"error":{"code":code}}))
+        };
+        assert_eq!(
+            EditFailure::from_error(&error("editconflict")),
+            EditFailure::Conflict
+        );
+        assert_eq!(
+            EditFailure::from_error(&error("ratelimited")),
+            EditFailure::Transient
+        );
+        assert_eq!(
+            EditFailure::from_error(&error("protectedpage")),
+            EditFailure::Fatal
+        );
+        assert_eq!(
+            EditFailure::from_error(&error("mwoauth-invalid-authorization")),
+            EditFailure::Authorization
+        );
+        let token_error = mediawiki::MediaWikiError::String(
+            "Could not get token: Object {\"error\": Object {\"code\": String(\"mwoauth-invalid-authorization-invalid-user\")}}".to_string(),
+        );
+        assert_eq!(
+            EditFailure::from_error(&token_error),
+            EditFailure::Authorization
+        );
+    }
+
+    #[test]
+    fn test_generator_insert_section() {
+        let before = "Intro\n== Report 2023-01-01 ==\nold\n";
+        let section = "== Report 2023-01-02 ==\nnew";
+        assert_eq!(
+            Generator::insert_section(before, section, SectionPosition::Top),
+            "Intro\n== Report 2023-01-02 ==\nnew\n\n== Report 2023-01-01 ==\nold\n"
+        );
+        assert_eq!(
+            Generator::insert_section(before, section, SectionPosition::Bottom),
+            "Intro\n== Report 2023-01-01 ==\nold\n\n== Report 2023-01-02 ==\nnew"
+        );
+        assert_eq!(
+            Generator::insert_section("", section, SectionPosition::Top),
+            section
+        );
+    }
+
+    #[test]
+    fn test_generator_replace_multiple_blocks() {
+        let before = "Intro\n<!--TOOLFLOW GENERATOR START-->\nold 1\n<!--TOOLFLOW GENERATOR END-->\nMiddle\n<!--TOOLFLOW GENERATOR START-->\nold 2\n<!--TOOLFLOW GENERATOR END-->\nOutro";
+        let (start, end) = Generator::block_markers(None).unwrap();
+        let after =
+            Generator::place_block(before, "new", &start, &end, BlockMode::Replace).unwrap();
+        assert_eq!(after, "Intro\n<!--TOOLFLOW GENERATOR START-->\nnew\n<!--TOOLFLOW GENERATOR END-->\nMiddle\n<!--TOOLFLOW GENERATOR START-->\nnew\n<!--TOOLFLOW GENERATOR END-->\nOutro");
+    }
+
+    #[test]
+    fn test_generator_replace_named_block() {
+        let before = "Intro\n<!--TOOLFLOW GENERATOR START:stats-->\nold stats\n<!--TOOLFLOW GENERATOR END:stats-->\nMiddle\n<!--TOOLFLOW GENERATOR START:list-->\nold list\n<!--TOOLFLOW GENERATOR END:list-->\nOutro";
+        let (start, end) = Generator::block_markers(Some("stats")).unwrap();
+        let after =
+            Generator::place_block(before, "new $1 stats", &start, &end, BlockMode::Replace)
+                .unwrap();
+        assert_eq!(after, "Intro\n<!--TOOLFLOW GENERATOR START:stats-->\nnew $1 stats\n<!--TOOLFLOW GENERATOR END:stats-->\nMiddle\n<!--TOOLFLOW GENERATOR START:list-->\nold list\n<!--TOOLFLOW GENERATOR END:list-->\nOutro");
+        assert_eq!(
+            Generator::place_block(&after, "new $1 stats", &start, &end, BlockMode::Replace)
+                .unwrap(),
+            after
+        );
+
+        let (start, end) = Generator::block_markers(Some("new")).unwrap();
+        let appended =
+            Generator::place_block("Intro", "table", &start, &end, BlockMode::Replace).unwrap();
+        assert_eq!(
+            appended,
+            "Intro\n<!--TOOLFLOW GENERATOR START:new-->\ntable\n<!--TOOLFLOW GENERATOR END:new-->"
+        );
+        assert!(Generator::block_markers(Some("x-->")).is_err());
+    }
+
+    #[test]
+    fn test_generator_prepend_block() {
+        let before = "Existing content\n\n== Older ==\nold";
+        let (start, end) = Generator::block_markers(None).unwrap();
+        assert_eq!(
+            Generator::place_block(before, "table", &start, &end, BlockMode::Prepend).unwrap(),
+            "<!--TOOLFLOW GENERATOR START-->\ntable\n<!--TOOLFLOW GENERATOR END-->\nExisting content\n\n== Older ==\nold"
+        );
+        assert_eq!(
+            Generator::place_block(before, "table", &start, &end, BlockMode::Append).unwrap(),
+            "Existing content\n\n== Older ==\nold\n<!--TOOLFLOW GENERATOR START-->\ntable\n<!--TOOLFLOW GENERATOR END-->"
+        );
+        // An existing block stays where it is
+        let before =
+            "Intro\n<!--TOOLFLOW GENERATOR START-->\nold\n<!--TOOLFLOW GENERATOR END-->\nOutro";
+        let expected =
+            "Intro\n<!--TOOLFLOW GENERATOR START-->\ntable\n<!--TOOLFLOW GENERATOR END-->\nOutro";
+        for mode in [BlockMode::Prepend, BlockMode::Append] {
+            assert_eq!(
+                Generator::place_block(before, "table", &start, &end, mode).unwrap(),
+                expected
+            );
+        }
+        assert_eq!(
+            Generator::edit_summary("Update: {rows} rows", 42),
+            "Update: 42 rows"
+        );
+        assert_eq!(Generator::edit_summary(" ", 42), DEFAULT_EDIT_SUMMARY);
+    }
+
+    #[tokio::test]
+    async fn test_generator_quickstatements_dry_run() {
+        let commands = "Q42\tP31\tQ5\nQ42\tP18\t\"Douglas adams portrait.jpg\"\n";
+        let batch = Generator::quickstatements_batch(commands, "ToolFlow run", "Alice", "abc");
+        let get = |key: &str| {
+            batch
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        assert_eq!(get("action"), Some("import"));
+        assert_eq!(get("format"), Some("v1"));
+        assert_eq!(get("batchname"), Some("ToolFlow run"));
+        assert_eq!(
+            get("data"),
+            Some("Q42\tP31\tQ5\nQ42\tP18\t\"Douglas adams portrait.jpg\"")
+        );
+        let dfd = Generator::quickstatements(commands, "ToolFlow run", 4420)
+            .await
+            .unwrap();
+        assert!(!dfd.is_valid());
+    }
+
     #[tokio::test]
     async fn test_generator_wikipage() {
         // Not really a test...
@@ -64,6 +480,9 @@ mod tests {
             "foobar",
             "wikidatawiki",
             "User:Magnus Manske/ToolFlow test",
+            None,
+            BlockMode::Replace,
+            DEFAULT_EDIT_SUMMARY,
             4420,
         )
         .await
@@ -5,13 +5,22 @@ use std::collections::{HashMap, HashSet};
 use crate::{
     data_cell::DataCell,
     data_file::{DataFile, DataFileDetails},
+    data_header::{ColumnHeader, ColumnHeaderType, DataHeader},
 };
+use tracing::warn;
 
 #[derive(Default, Clone, Debug)]
-pub struct Join {}
+pub struct Join {
+    pub by_position: bool, // Align input columns by position instead of by name, for generically named columns
+    pub rename: Vec<String>, // New output column names, by position
+    pub keep_order: bool,  // Use files in the given order (eg by priority) instead of by file size
+    pub source_labels: Vec<String>, // If set, a "source" column gets the label of the input each row came from, by position
+}
+
+pub const SOURCE_COLUMN: &str = "source";
 
 impl Join {
-    // Returns data files, sorted by file size, smallest first
+    // Returns data files, sorted by file size, smallest first, unless keep_order is set
     fn get_files_with_metadata(&self, uuids: Vec<&str>) -> Result<Vec<DataFile>> {
         if uuids.is_empty() {
             return Err(anyhow!("No UUIDs given to inner_join_on_key"));
@@ -30,7 +39,9 @@ impl Join {
                 .file_size()
                 .ok_or(anyhow!("{} has no file size", file.path().unwrap()))?;
         }
-        files.sort_by_key(|k| k.2);
+        if !self.keep_order {
+            files.sort_by_key(|k| k.2);
+        }
         Ok(files.into_iter().map(|(_uuid, df, _size)| df).collect())
     }
 
@@ -39,10 +50,7 @@ impl Join {
         file: &mut DataFile,
         key_col_num: usize,
     ) -> Option<(Vec<DataCell>, String)> {
-        let row = match file.read_row() {
-            Some(row) => row,
-            None => return None,
-        };
+        let row = file.read_row()?;
         let row: Vec<DataCell> = serde_json::from_str(&row).unwrap_or(vec![]);
         let new_key = match row.get(key_col_num) {
             Some(new_key) => new_key.as_key(),
@@ -51,22 +59,93 @@ impl Join {
         Some((row, new_key))
     }
 
+    // Where each column of the first header is in `header`; None if the headers do not match
+    fn column_order(&self, first_header: &DataHeader, header: &DataHeader) -> Option<Vec<usize>> {
+        if self.by_position {
+            first_header
+                .is_positionally_compatible(header)
+                .then(|| (0..header.columns.len()).collect())
+        } else {
+            first_header.column_order_in(header)
+        }
+    }
+
+    // The cells of `row` in the given column order, Blank for missing cells
+    fn reorder_row(row: &[DataCell], order: &[usize]) -> Vec<DataCell> {
+        order
+            .iter()
+            .map(|col_num| row.get(*col_num).cloned().unwrap_or(DataCell::Blank))
+            .collect()
+    }
+
+    // The source label of each input UUID, if source_labels is set
+    fn source_labels_by_uuid(&self, uuids: &[&str]) -> Result<HashMap<String, String>> {
+        if self.source_labels.is_empty() {
+            return Ok(HashMap::new());
+        }
+        if self.source_labels.len() != uuids.len() {
+            return Err(anyhow!(
+                "{} source labels given for {} inputs",
+                self.source_labels.len(),
+                uuids.len()
+            ));
+        }
+        Ok(uuids
+            .iter()
+            .map(|uuid| uuid.to_string())
+            .zip(self.source_labels.iter().cloned())
+            .collect())
+    }
+
+    fn add_source_column(&self, header: &mut DataHeader) -> Result<()> {
+        if self.source_labels.is_empty() {
+            return Ok(());
+        }
+        if header.get_col_num(SOURCE_COLUMN).is_some() {
+            return Err(anyhow!("Input already has a '{SOURCE_COLUMN}' column"));
+        }
+        header.columns.push(ColumnHeader {
+            name: SOURCE_COLUMN.to_string(),
+            kind: ColumnHeaderType::PlainText,
+        });
+        Ok(())
+    }
+
     pub fn merge_unique(&self, uuids: Vec<&str>, key: &str) -> Result<DataFileDetails> {
+        let labels = self.source_labels_by_uuid(&uuids)?;
         let files = self.get_files_with_metadata(uuids)?;
         let mut output_file = DataFile::default();
         output_file.open_output_file()?;
+        let mut first_header = None;
         let mut new_header = None;
         let mut had_key = HashSet::new();
-        let first_uuid = files[0].uuid().to_owned();
+        let first_uuid = files[0].uuid().to_owned().unwrap_or_default();
         for mut file in files.into_iter() {
             file.load_header()?;
-            if new_header.is_none() {
-                new_header = Some(file.header().to_owned());
-                output_file.write_json_row(&json!(new_header))?;
-            } else if new_header != Some(file.header().to_owned()) {
-                return Err(anyhow!(
-                    "File {first_uuid:?} has a different header than {file:?}"
-                ));
+            let mut column_order = None;
+            match &first_header {
+                None => {
+                    first_header = Some(file.header().to_owned());
+                    let mut header = file.header().to_owned();
+                    header.rename_columns(&self.rename)?;
+                    self.add_source_column(&mut header)?;
+                    output_file.write_json_row(&json!(header))?;
+                    new_header = Some(header);
+                }
+                Some(first_header) => {
+                    let order = match self.column_order(first_header, file.header()) {
+                        Some(order) => order,
+                        None => {
+                            return Err(anyhow!(
+                                "File {} has a different header than {first_uuid}",
+                                file.uuid().as_deref().unwrap_or_default()
+                            ));
+                        }
+                    };
+                    if order.iter().enumerate().any(|(i, col_num)| i != *col_num) {
+                        column_order = Some(order);
+                    }
+                }
             }
             let key_col_num = match &new_header {
                 Some(x) => x
@@ -74,75 +153,900 @@ impl Join {
                     .ok_or(anyhow!("No key '{key}' in file {}", file.path().unwrap()))?,
                 None => return Err(anyhow!("merge_unique header not initialized")),
             };
+            // The key column position in this file
+            let file_key_col_num = match &column_order {
+                Some(order) => order[key_col_num],
+                None => key_col_num,
+            };
 
-            loop {
-                let (row, key) = match self.read_row_and_key(&mut file, key_col_num) {
-                    Some(x) => x,
-                    None => break,
-                };
+            let label = file.uuid().as_ref().and_then(|uuid| labels.get(uuid));
+            while let Some((mut row, key)) = self.read_row_and_key(&mut file, file_key_col_num) {
                 if row.is_empty() || key.is_empty() || had_key.contains(&key) {
                     continue;
                 }
                 had_key.insert(key);
+                if let Some(order) = &column_order {
+                    row = Self::reorder_row(&row, order);
+                }
+                if let Some(label) = label {
+                    row.push(DataCell::PlainText(label.to_owned()));
+                }
+                output_file.write_json_row(&json!(row))?;
+            }
+        }
+        output_file.finish()
+    }
+
+    // All rows of all files, in input order; like merge_unique but without the key check
+    pub fn concat(&self, uuids: Vec<&str>) -> Result<DataFileDetails> {
+        let labels = self.source_labels_by_uuid(&uuids)?;
+        let files = self.get_files_with_metadata(uuids)?;
+        let mut output_file = DataFile::default();
+        output_file.open_output_file()?;
+        let mut first_header: Option<DataHeader> = None;
+        let first_uuid = files[0].uuid().to_owned().unwrap_or_default();
+        for mut file in files.into_iter() {
+            file.load_header()?;
+            let column_order = match &first_header {
+                None => {
+                    let mut header = file.header().to_owned();
+                    header.rename_columns(&self.rename)?;
+                    self.add_source_column(&mut header)?;
+                    output_file.write_json_row(&json!(header))?;
+                    first_header = Some(file.header().to_owned());
+                    None
+                }
+                Some(first_header) => match self.column_order(first_header, file.header()) {
+                    Some(order) if order.iter().enumerate().all(|(i, col_num)| i == *col_num) => {
+                        None
+                    }
+                    Some(order) => Some(order),
+                    None => {
+                        return Err(anyhow!(
+                            "File {} has a different header than {first_uuid}",
+                            file.uuid().as_deref().unwrap_or_default()
+                        ));
+                    }
+                },
+            };
+            let label = file.uuid().as_ref().and_then(|uuid| labels.get(uuid));
+            while let Some(row) = file.read_row() {
+                let mut row: Vec<DataCell> = serde_json::from_str(&row)?;
+                if let Some(order) = &column_order {
+                    row = Self::reorder_row(&row, order);
+                }
+                if let Some(label) = label {
+                    row.push(DataCell::PlainText(label.to_owned()));
+                }
                 output_file.write_json_row(&json!(row))?;
             }
         }
-        Ok(output_file.details())
+        output_file.finish()
     }
 
     pub fn inner_join_on_key(&self, uuids: Vec<&str>, key: &str) -> Result<DataFileDetails> {
         let mut data_files = self.get_files_with_metadata(uuids)?;
         let mut main_file = data_files.remove(0);
-        main_file.load()?;
-        let key2row = main_file.key2row(key)?;
-        let mut keys_found: HashMap<String, usize> = HashMap::new();
-        let number_of_files = data_files.len();
+        // Only the key => row offset maps are held in memory; rows are read back when written
+        let key2offset = main_file.key_offsets(key)?;
+        let mut other_files = vec![];
         for mut file in data_files.into_iter() {
-            file.load_header()?;
+            let offsets = file.key_offsets(key)?;
             let mut new_header = file.header().to_owned();
             let key_col_num = new_header
                 .get_col_num(key)
                 .ok_or(anyhow!("No key '{key}' in file {}", file.path().unwrap()))?;
             new_header.columns.remove(key_col_num);
-            main_file.add_header(new_header);
-
-            loop {
-                let (mut row, new_key) = match self.read_row_and_key(&mut file, key_col_num) {
-                    Some(x) => x,
-                    None => break,
-                };
-                if row.is_empty() || new_key.is_empty() {
-                    continue;
-                }
-                let row_id = match key2row.get(&new_key) {
-                    Some(id) => *id,
-                    None => continue, // Not in the first file
-                };
-                *keys_found.entry(new_key.to_owned()).or_insert(0) += 1;
-                row.remove(key_col_num);
-                main_file.rows[row_id].append(&mut row);
+            for (old_name, new_name) in main_file.add_header(new_header) {
+                warn!(old_name, new_name, "Renamed duplicate column in inner join");
             }
+            other_files.push((file, offsets, key_col_num));
         }
-        let keys_in_all_files: Vec<&String> = keys_found
+        // In the order of the main file, so unchanged data gives identical output
+        let mut keys_in_all_files: Vec<(u64, &String)> = key2offset
             .iter()
-            .filter(|(_, count)| **count == number_of_files)
-            .map(|(key_name, _)| key_name)
+            .filter(|(key_name, _)| !key_name.is_empty())
+            .filter(|(key_name, _)| {
+                other_files
+                    .iter()
+                    .all(|(_, offsets, _)| offsets.contains_key(*key_name))
+            })
+            .map(|(key_name, offset)| (*offset, key_name))
             .collect();
+        keys_in_all_files.sort();
 
         let mut output_file = DataFile::default();
         output_file.open_output_file()?;
         output_file.write_json_row(&json!(main_file.header()))?;
-        for key in keys_in_all_files {
-            let row_id = match key2row.get(key) {
-                Some(id) => *id,
+        for (offset, key_name) in keys_in_all_files {
+            let mut row = main_file.seek_to_row(offset)?;
+            for (file, offsets, key_col_num) in &mut other_files {
+                let mut other_row = file.seek_to_row(offsets[key_name])?;
+                if *key_col_num < other_row.len() {
+                    other_row.remove(*key_col_num);
+                }
+                row.append(&mut other_row);
+            }
+            output_file.write_json_row(&json!(row))?;
+        }
+        output_file.finish()
+    }
+
+    // Rows of `uuid` whose `column` value differs from the row with the same key in `previous_uuid`,
+    // with `{column}_old` and `{column}_new` appended; rows without a previous counterpart are not changes
+    pub fn changed_values(
+        &self,
+        uuid: &str,
+        previous_uuid: Option<&str>,
+        key: &str,
+        column: &str,
+    ) -> Result<DataFileDetails> {
+        let mut file = DataFile::default();
+        file.open_input_file(uuid)?;
+        file.load_header()?;
+        let key_col_num = file
+            .header()
+            .get_col_num(key)
+            .ok_or(anyhow!("No key '{key}' in file {uuid}"))?;
+        let value_col_num = file
+            .header()
+            .get_col_num(column)
+            .ok_or(anyhow!("No column '{column}' in file {uuid}"))?;
+
+        let mut previous_values: HashMap<String, DataCell> = HashMap::new();
+        if let Some(previous_uuid) = previous_uuid {
+            let mut previous = DataFile::default();
+            previous.open_input_file(previous_uuid)?;
+            previous.load_header()?;
+            let previous_key_col_num = previous
+                .header()
+                .get_col_num(key)
+                .ok_or(anyhow!("No key '{key}' in previous file {previous_uuid}"))?;
+            let previous_value_col_num = previous.header().get_col_num(column).ok_or(anyhow!(
+                "No column '{column}' in previous file {previous_uuid}"
+            ))?;
+            while let Some((row, key)) = self.read_row_and_key(&mut previous, previous_key_col_num)
+            {
+                if let Some(value) = row.get(previous_value_col_num) {
+                    let _ = previous_values.insert(key, value.to_owned());
+                }
+            }
+        }
+
+        let mut header = file.header().to_owned();
+        let value_header = header.columns[value_col_num].to_owned();
+        for suffix in ["old", "new"] {
+            let mut column_header = value_header.to_owned();
+            column_header.name = format!("{column}_{suffix}");
+            header.columns.push(column_header);
+        }
+        let mut output_file = DataFile::default();
+        output_file.open_output_file()?;
+        output_file.write_json_row(&json!(header))?;
+        while let Some((mut row, key)) = self.read_row_and_key(&mut file, key_col_num) {
+            if row.is_empty() || key.is_empty() {
+                continue;
+            }
+            let old_value = match previous_values.get(&key) {
+                Some(old_value) => old_value.to_owned(),
                 None => continue,
             };
-            let row = match main_file.rows.get(row_id) {
-                Some(row) => row,
+            let new_value = row.get(value_col_num).cloned().unwrap_or(DataCell::Blank);
+            if old_value == new_value {
+                continue;
+            }
+            row.push(old_value);
+            row.push(new_value);
+            output_file.write_json_row(&json!(row))?;
+        }
+        output_file.finish()
+    }
+
+    // Keeps every row of the first file (the smallest one, unless keep_order is set);
+    // columns from other files are Blank for keys they do not have
+    pub fn left_outer_join_on_key(&self, uuids: Vec<&str>, key: &str) -> Result<DataFileDetails> {
+        let mut data_files = self.get_files_with_metadata(uuids)?;
+        let mut main_file = data_files.remove(0);
+        main_file.load_header()?;
+        let main_key_col_num = main_file.header().get_col_num(key).ok_or(anyhow!(
+            "No key '{key}' in file {}",
+            main_file.path().unwrap()
+        ))?;
+
+        // The other files are looked up by key, the first file is streamed
+        let mut lookups = vec![];
+        for mut file in data_files.into_iter() {
+            file.load_header()?;
+            let mut new_header = file.header().to_owned();
+            let key_col_num = new_header
+                .get_col_num(key)
+                .ok_or(anyhow!("No key '{key}' in file {}", file.path().unwrap()))?;
+            new_header.columns.remove(key_col_num);
+            let new_columns = new_header.columns.len();
+            for (old_name, new_name) in main_file.add_header(new_header) {
+                warn!(
+                    old_name,
+                    new_name, "Renamed duplicate column in left outer join"
+                );
+            }
+
+            let mut key2row = HashMap::new();
+            while let Some((mut row, new_key)) = self.read_row_and_key(&mut file, key_col_num) {
+                if row.is_empty() || new_key.is_empty() || key2row.contains_key(&new_key) {
+                    continue; // Only the first match per file
+                }
+                row.remove(key_col_num);
+                row.resize(new_columns, DataCell::Blank);
+                key2row.insert(new_key, row);
+            }
+            lookups.push((key2row, new_columns));
+        }
+
+        let mut output_file = DataFile::default();
+        output_file.open_output_file()?;
+        output_file.write_json_row(&json!(main_file.header()))?;
+        while let Some((mut row, main_key)) =
+            self.read_row_and_key(&mut main_file, main_key_col_num)
+        {
+            if row.is_empty() {
+                continue;
+            }
+            for (key2row, new_columns) in &lookups {
+                match key2row.get(&main_key) {
+                    Some(new_row) => row.extend(new_row.iter().cloned()),
+                    None => row.extend(std::iter::repeat_n(DataCell::Blank, *new_columns)),
+                }
+            }
+            output_file.write_json_row(&json!(row))?;
+        }
+        output_file.finish()
+    }
+
+    // Rows of the first file whose key is in none of the other files; the header is unchanged
+    pub fn anti_join_on_key(&self, uuids: Vec<&str>, key: &str) -> Result<DataFileDetails> {
+        let mut data_files = self.get_files_with_metadata(uuids)?;
+        let mut main_file = data_files.remove(0);
+        let mut other_keys = HashSet::new();
+        for mut file in data_files.into_iter() {
+            file.load_header()?;
+            let key_col_num = file.header().get_col_num(key);
+            while let Some(row) = file.read_row() {
+                // A file without data rows excludes nothing, so it does not need the key column
+                let key_col_num = key_col_num
+                    .ok_or_else(|| anyhow!("No key '{key}' in file {}", file.path().unwrap()))?;
+                let row: Vec<DataCell> = serde_json::from_str(&row).unwrap_or_default();
+                if let Some(new_key) = row.get(key_col_num).map(|cell| cell.as_key()) {
+                    if !new_key.is_empty() {
+                        other_keys.insert(new_key);
+                    }
+                }
+            }
+        }
+
+        main_file.load_header()?;
+        let key_col_num = main_file.header().get_col_num(key).ok_or(anyhow!(
+            "No key '{key}' in file {}",
+            main_file.path().unwrap()
+        ))?;
+        let mut output_file = DataFile::default();
+        output_file.open_output_file()?;
+        output_file.write_json_row(&json!(main_file.header()))?;
+        while let Some((row, new_key)) = self.read_row_and_key(&mut main_file, key_col_num) {
+            if !other_keys.contains(&new_key) {
+                output_file.write_json_row(&json!(row))?;
+            }
+        }
+        output_file.finish()
+    }
+
+    // One row per key found in any file, with a Boolean column per file (named by `names`) telling if it has the key
+    pub fn membership_matrix(
+        &self,
+        uuids: Vec<&str>,
+        key: &str,
+        names: &[String],
+    ) -> Result<DataFileDetails> {
+        if uuids.is_empty() {
+            return Err(anyhow!("No UUIDs given to membership_matrix"));
+        }
+        if uuids.len() != names.len() {
+            return Err(anyhow!(
+                "membership_matrix has {} inputs but {} column names",
+                uuids.len(),
+                names.len()
+            ));
+        }
+        let mut key_header = None;
+        let mut key_order = vec![];
+        let mut members: HashMap<String, (DataCell, Vec<bool>)> = HashMap::new();
+        for (file_num, uuid) in uuids.iter().enumerate() {
+            let mut file = DataFile::default();
+            file.open_input_file(uuid)?;
+            file.load_header()?;
+            let key_col_num = file
+                .header()
+                .get_col_num(key)
+                .ok_or(anyhow!("No key '{key}' in file {}", file.path().unwrap()))?;
+            if key_header.is_none() {
+                key_header = Some(file.header().columns[key_col_num].to_owned());
+            }
+            while let Some((row, new_key)) = self.read_row_and_key(&mut file, key_col_num) {
+                if row.is_empty() || new_key.is_empty() {
+                    continue;
+                }
+                let entry = members.entry(new_key.to_owned()).or_insert_with(|| {
+                    key_order.push(new_key);
+                    (row[key_col_num].to_owned(), vec![false; uuids.len()])
+                });
+                entry.1[file_num] = true;
+            }
+        }
+
+        let mut header = DataHeader {
+            columns: key_header.into_iter().collect(),
+        };
+        header.columns.extend(names.iter().map(|name| ColumnHeader {
+            name: name.to_owned(),
+            kind: ColumnHeaderType::Boolean,
+        }));
+        let mut output_file = DataFile::default();
+        output_file.open_output_file()?;
+        output_file.write_json_row(&json!(header))?;
+        for key in key_order {
+            let (key_cell, present) = match members.remove(&key) {
+                Some(member) => member,
                 None => continue,
             };
+            let mut row = vec![key_cell];
+            row.extend(present.into_iter().map(DataCell::Boolean));
             output_file.write_json_row(&json!(row))?;
         }
-        Ok(output_file.details())
+        output_file.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{load_file, text_row, write_test_file};
+    use crate::APP;
+
+    #[test]
+    fn test_merge_unique_by_position_with_rename() {
+        let uuid1 = write_test_file(
+            &["var1", "var2"],
+            vec![text_row(&["Q1", "a"]), text_row(&["Q2", "b"])],
+        );
+        let uuid2 = write_test_file(
+            &["x", "y"],
+            vec![text_row(&["Q2", "c"]), text_row(&["Q3", "d"])],
+        );
+
+        let join = Join {
+            by_position: true,
+            rename: vec!["item".to_string(), "label".to_string()],
+            ..Default::default()
+        };
+        let dfd = join.merge_unique(vec![&uuid1, &uuid2], "item").unwrap();
+        let df = load_file(&dfd.uuid);
+        let names: Vec<&str> = df
+            .header()
+            .columns
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["item", "label"]);
+        assert_eq!(df.rows.len(), 3);
+
+        // Cleanup
+        APP.remove_uuid_file(&uuid1).unwrap();
+        APP.remove_uuid_file(&uuid2).unwrap();
+        APP.remove_uuid_file(&dfd.uuid).unwrap();
+    }
+
+    #[test]
+    fn test_merge_unique_reordered_columns() {
+        let uuid1 = write_test_file(
+            &["item", "label"],
+            vec![text_row(&["Q1", "a"]), text_row(&["Q2", "b"])],
+        );
+        let uuid2 = write_test_file(
+            &["label", "item"],
+            vec![text_row(&["c", "Q2"]), text_row(&["d", "Q3"])],
+        );
+        let uuid3 = write_test_file(&["item", "description"], vec![text_row(&["Q4", "e"])]);
+
+        let join = Join {
+            keep_order: true,
+            ..Default::default()
+        };
+        let dfd = join.merge_unique(vec![&uuid1, &uuid2], "item").unwrap();
+        let df = load_file(&dfd.uuid);
+        assert_eq!(
+            df.rows,
+            vec![
+                text_row(&["Q1", "a"]),
+                text_row(&["Q2", "b"]),
+                text_row(&["Q3", "d"]),
+            ]
+        );
+        let error = join.merge_unique(vec![&uuid1, &uuid3], "item").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("File {uuid3} has a different header than {uuid1}")
+        );
+
+        // Cleanup
+        for uuid in [&uuid1, &uuid2, &uuid3, &dfd.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_concat() {
+        let uuid1 = write_test_file(
+            &["item", "label"],
+            vec![text_row(&["Q1", "a"]), text_row(&["Q2", "b"])],
+        );
+        let uuid2 = write_test_file(
+            &["label", "item"],
+            vec![text_row(&["b", "Q2"]), text_row(&["c", "Q3"])],
+        );
+
+        let join = Join {
+            keep_order: true,
+            ..Default::default()
+        };
+        let dfd = join.concat(vec![&uuid1, &uuid2]).unwrap();
+        assert_eq!(dfd.rows, 4);
+        let df = load_file(&dfd.uuid);
+        assert_eq!(
+            df.rows,
+            vec![
+                text_row(&["Q1", "a"]),
+                text_row(&["Q2", "b"]),
+                text_row(&["Q2", "b"]),
+                text_row(&["Q3", "c"]),
+            ]
+        );
+
+        let uuid3 = write_test_file(&["item", "description"], vec![text_row(&["Q4", "d"])]);
+        let error = join.concat(vec![&uuid1, &uuid3]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("File {uuid3} has a different header than {uuid1}")
+        );
+
+        // Cleanup
+        for uuid in [&uuid1, &uuid2, &uuid3, &dfd.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_merge_unique_keep_order_priority() {
+        // The larger file is given first, so it would lose without keep_order
+        let uuid1 = write_test_file(
+            &["item", "label"],
+            vec![text_row(&["Q1", "authoritative"]), text_row(&["Q2", "b"])],
+        );
+        let uuid2 = write_test_file(&["item", "label"], vec![text_row(&["Q1", "c"])]);
+
+        let join = Join {
+            keep_order: true,
+            ..Default::default()
+        };
+        let dfd = join.merge_unique(vec![&uuid1, &uuid2], "item").unwrap();
+        let df = load_file(&dfd.uuid);
+        assert!(df.rows.contains(&text_row(&["Q1", "authoritative"])));
+        assert_eq!(df.rows.len(), 2);
+
+        // Cleanup
+        APP.remove_uuid_file(&uuid1).unwrap();
+        APP.remove_uuid_file(&uuid2).unwrap();
+        APP.remove_uuid_file(&dfd.uuid).unwrap();
+    }
+
+    #[test]
+    fn test_merge_unique_source_column() {
+        let uuid1 = write_test_file(
+            &["item", "label"],
+            vec![text_row(&["Q1", "a"]), text_row(&["Q2", "b"])],
+        );
+        let uuid2 = write_test_file(&["item", "label"], vec![text_row(&["Q2", "c"])]);
+        let uuid3 = write_test_file(&["item", "label"], vec![text_row(&["Q3", "d"])]);
+
+        let join = Join {
+            keep_order: true,
+            source_labels: vec!["A".to_string(), "B".to_string(), "C".to_string()],
+            ..Default::default()
+        };
+        let dfd = join
+            .merge_unique(vec![&uuid1, &uuid2, &uuid3], "item")
+            .unwrap();
+        let df = load_file(&dfd.uuid);
+        assert_eq!(df.header().get_col_num(SOURCE_COLUMN), Some(2));
+        assert_eq!(
+            df.rows,
+            vec![
+                text_row(&["Q1", "a", "A"]),
+                text_row(&["Q2", "b", "A"]),
+                text_row(&["Q3", "d", "C"]),
+            ]
+        );
+
+        let join = Join {
+            source_labels: vec!["A".to_string()],
+            ..Default::default()
+        };
+        assert!(join.merge_unique(vec![&uuid1, &uuid2], "item").is_err());
+
+        // Cleanup
+        for uuid in [&uuid1, &uuid2, &uuid3, &dfd.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_changed_values() {
+        let previous = write_test_file(
+            &["item", "score"],
+            vec![text_row(&["Q1", "5"]), text_row(&["Q2", "3"])],
+        );
+        let current = write_test_file(
+            &["item", "score"],
+            vec![
+                text_row(&["Q1", "4"]),
+                text_row(&["Q2", "3"]),
+                text_row(&["Q3", "1"]),
+            ],
+        );
+        let join = Join::default();
+        let dfd = join
+            .changed_values(&current, Some(&previous), "item", "score")
+            .unwrap();
+        let df = load_file(&dfd.uuid);
+        assert_eq!(df.header().get_col_num("score_old"), Some(2));
+        assert_eq!(df.header().get_col_num("score_new"), Some(3));
+        assert_eq!(df.rows, vec![text_row(&["Q1", "4", "5", "4"])]);
+
+        // No previous run, no changes
+        let dfd_first = join
+            .changed_values(&current, None, "item", "score")
+            .unwrap();
+        assert!(load_file(&dfd_first.uuid).rows.is_empty());
+
+        // Cleanup
+        for uuid in [&previous, &current, &dfd.uuid, &dfd_first.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_inner_join_on_key_seeks_main_rows() {
+        let uuid1 = write_test_file(
+            &["item", "label"],
+            vec![
+                text_row(&["Q1", "a"]),
+                text_row(&["Q2", "b"]),
+                text_row(&["Q3", "c"]),
+            ],
+        );
+        let uuid2 = write_test_file(
+            &["item", "image"],
+            vec![text_row(&["Q3", "z.jpg"]), text_row(&["Q4", "y.jpg"])],
+        );
+
+        let mut df = DataFile::default();
+        df.open_input_file(&uuid1).unwrap();
+        let offsets = df.key_offsets("item").unwrap();
+        assert_eq!(offsets.len(), 3);
+        assert_eq!(
+            df.seek_to_row(offsets["Q2"]).unwrap(),
+            text_row(&["Q2", "b"])
+        );
+        assert_eq!(
+            df.seek_to_row(offsets["Q1"]).unwrap(),
+            text_row(&["Q1", "a"])
+        );
+
+        let join = Join {
+            keep_order: true,
+            ..Default::default()
+        };
+        let dfd = join
+            .inner_join_on_key(vec![&uuid1, &uuid2], "item")
+            .unwrap();
+        let df = load_file(&dfd.uuid);
+        assert_eq!(df.header().columns.len(), 3);
+        assert_eq!(df.rows, vec![text_row(&["Q3", "c", "z.jpg"])]);
+
+        // Cleanup
+        APP.remove_uuid_file(&uuid1).unwrap();
+        APP.remove_uuid_file(&uuid2).unwrap();
+        APP.remove_uuid_file(&dfd.uuid).unwrap();
+    }
+
+    #[test]
+    fn test_inner_join_on_key_duplicate_column_names() {
+        let uuid1 = write_test_file(&["item", "label"], vec![text_row(&["Q1", "a"])]);
+        let uuid2 = write_test_file(&["item", "label"], vec![text_row(&["Q1", "b"])]);
+        let uuid3 = write_test_file(&["label", "item"], vec![text_row(&["c", "Q1"])]);
+
+        let join = Join {
+            keep_order: true,
+            ..Default::default()
+        };
+        let dfd = join
+            .inner_join_on_key(vec![&uuid1, &uuid2, &uuid3], "item")
+            .unwrap();
+        let df = load_file(&dfd.uuid);
+        let names: Vec<&str> = df
+            .header()
+            .columns
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["item", "label", "label_2", "label_3"]);
+        assert_eq!(df.rows, vec![text_row(&["Q1", "a", "b", "c"])]);
+
+        // Cleanup
+        for uuid in [&uuid1, &uuid2, &uuid3, &dfd.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_anti_join_on_key() {
+        let uuid1 = write_test_file(
+            &["item", "label"],
+            vec![
+                text_row(&["Q1", "a"]),
+                text_row(&["Q2", "b"]),
+                text_row(&["Q2", "b2"]),
+                text_row(&["Q3", "c"]),
+                text_row(&["Q4", "d"]),
+            ],
+        );
+        let uuid2 = write_test_file(
+            &["image", "item"],
+            vec![text_row(&["x.jpg", "Q2"]), text_row(&["y.jpg", "Q5"])],
+        );
+        let uuid3 = write_test_file(&["item"], vec![text_row(&["Q4"])]);
+        let empty = write_test_file(&["other"], vec![]);
+        let join = Join {
+            keep_order: true,
+            ..Default::default()
+        };
+
+        // Q2 (twice) and Q4 overlap
+        let dfd = join
+            .anti_join_on_key(vec![&uuid1, &uuid2, &uuid3], "item")
+            .unwrap();
+        assert_eq!(dfd.rows, 2);
+        let df = load_file(&dfd.uuid);
+        assert_eq!(df.header().columns.len(), 2);
+        assert_eq!(
+            df.rows,
+            vec![text_row(&["Q1", "a"]), text_row(&["Q3", "c"])]
+        );
+
+        // An empty second file excludes nothing
+        let dfd_empty = join.anti_join_on_key(vec![&uuid1, &empty], "item").unwrap();
+        assert_eq!(dfd_empty.rows, 5);
+
+        // Cleanup
+        for uuid in [&uuid1, &uuid2, &uuid3, &empty, &dfd.uuid, &dfd_empty.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_inner_join_on_key_keeps_main_order() {
+        let keys: Vec<String> = (0..50).map(|i| format!("Q{i}")).collect();
+        let uuid1 = write_test_file(
+            &["item", "label"],
+            keys.iter().map(|key| text_row(&[key, "l"])).collect(),
+        );
+        let uuid2 = write_test_file(
+            &["item", "image"],
+            keys.iter().rev().map(|key| text_row(&[key, "i"])).collect(),
+        );
+        let join = Join {
+            keep_order: true,
+            ..Default::default()
+        };
+        let dfd1 = join
+            .inner_join_on_key(vec![&uuid1, &uuid2], "item")
+            .unwrap();
+        let dfd2 = join
+            .inner_join_on_key(vec![&uuid1, &uuid2], "item")
+            .unwrap();
+        let df1 = load_file(&dfd1.uuid);
+        let order: Vec<String> = df1.rows.iter().map(|row| row[0].as_key()).collect();
+        assert_eq!(order, keys);
+        assert_eq!(df1.rows, load_file(&dfd2.uuid).rows);
+
+        // Cleanup
+        for uuid in [&uuid1, &uuid2, &dfd1.uuid, &dfd2.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_inner_join_on_key_matches_in_memory_join() {
+        let uuid1 = write_test_file(
+            &["item", "label"],
+            (0..30)
+                .map(|i| text_row(&[&format!("Q{i}"), &format!("label {i}")]))
+                .collect(),
+        );
+        let uuid2 = write_test_file(
+            &["image", "item"],
+            (0..30)
+                .rev()
+                .filter(|i| i % 2 == 0)
+                .map(|i| text_row(&[&format!("{i}.jpg"), &format!("Q{i}")]))
+                .collect(),
+        );
+        let uuid3 = write_test_file(
+            &["item", "date"],
+            (0..30)
+                .filter(|i| i % 3 == 0)
+                .map(|i| text_row(&[&format!("Q{i}"), &format!("200{}", i % 10)]))
+                .collect(),
+        );
+
+        // Everything in memory
+        let files: Vec<DataFile> = [&uuid1, &uuid2, &uuid3]
+            .iter()
+            .map(|uuid| load_file(uuid))
+            .collect();
+        let key_col_nums: Vec<usize> = files
+            .iter()
+            .map(|df| df.header().get_col_num("item").unwrap())
+            .collect();
+        let expected: Vec<Vec<DataCell>> = files[0]
+            .rows
+            .iter()
+            .filter_map(|row| {
+                let mut ret = row.to_owned();
+                for (df, key_col_num) in files.iter().zip(key_col_nums.iter()).skip(1) {
+                    let mut other = df
+                        .rows
+                        .iter()
+                        .find(|other| other[*key_col_num] == row[0])?
+                        .to_owned();
+                    other.remove(*key_col_num);
+                    ret.append(&mut other);
+                }
+                Some(ret)
+            })
+            .collect();
+        assert_eq!(expected.len(), 5); // Q0, Q6, Q12, Q18, Q24
+
+        let join = Join {
+            keep_order: true,
+            ..Default::default()
+        };
+        let dfd = join
+            .inner_join_on_key(vec![&uuid1, &uuid2, &uuid3], "item")
+            .unwrap();
+        let df = load_file(&dfd.uuid);
+        let names: Vec<&str> = df
+            .header()
+            .columns
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["item", "label", "image", "date"]);
+        assert_eq!(df.rows, expected);
+
+        // Cleanup
+        for uuid in [&uuid1, &uuid2, &uuid3, &dfd.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_membership_matrix() {
+        let uuid1 = write_test_file(
+            &["item", "label"],
+            vec![text_row(&["Q1", "a"]), text_row(&["Q2", "b"])],
+        );
+        let uuid2 = write_test_file(&["item"], vec![text_row(&["Q2"]), text_row(&["Q3"])]);
+        let uuid3 = write_test_file(&["x", "item"], vec![text_row(&["y", "Q1"])]);
+        let names: Vec<String> = ["in_a", "in_b", "in_c"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let dfd = Join::default()
+            .membership_matrix(vec![&uuid1, &uuid2, &uuid3], "item", &names)
+            .unwrap();
+        let df = load_file(&dfd.uuid);
+        let header: Vec<&str> = df
+            .header()
+            .columns
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(header, vec!["item", "in_a", "in_b", "in_c"]);
+        let row = |key: &str, present: [bool; 3]| {
+            let mut row = text_row(&[key]);
+            row.extend(present.into_iter().map(DataCell::Boolean));
+            row
+        };
+        assert_eq!(
+            df.rows,
+            vec![
+                row("Q1", [true, false, true]),
+                row("Q2", [true, true, false]),
+                row("Q3", [false, true, false]),
+            ]
+        );
+
+        // Cleanup
+        for uuid in [&uuid1, &uuid2, &uuid3, &dfd.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_left_outer_join_on_key() {
+        let uuid1 = write_test_file(
+            &["item", "label"],
+            vec![text_row(&["Q1", "a"]), text_row(&["Q2", "b"])],
+        );
+        let uuid2 = write_test_file(
+            &["item", "image", "date"],
+            vec![
+                text_row(&["Q2", "x.jpg", "2020"]),
+                text_row(&["Q3", "y.jpg", "2021"]),
+            ],
+        );
+        let join = Join {
+            keep_order: true,
+            ..Default::default()
+        };
+        let dfd = join
+            .left_outer_join_on_key(vec![&uuid1, &uuid2], "item")
+            .unwrap();
+        let df = load_file(&dfd.uuid);
+        assert_eq!(df.header().columns.len(), 4);
+        assert_eq!(
+            df.rows,
+            vec![
+                vec![
+                    DataCell::PlainText("Q1".to_string()),
+                    DataCell::PlainText("a".to_string()),
+                    DataCell::Blank,
+                    DataCell::Blank
+                ],
+                text_row(&["Q2", "b", "x.jpg", "2020"]),
+            ]
+        );
+
+        // Duplicate keys in the first file are all kept
+        let uuid3 = write_test_file(
+            &["item", "label"],
+            vec![
+                text_row(&["Q2", "b"]),
+                text_row(&["Q2", "c"]),
+                text_row(&["Q9", "d"]),
+            ],
+        );
+        let dfd2 = join
+            .left_outer_join_on_key(vec![&uuid3, &uuid2], "item")
+            .unwrap();
+        assert_eq!(dfd2.rows, 3);
+        let df = load_file(&dfd2.uuid);
+        assert_eq!(df.rows[0], text_row(&["Q2", "b", "x.jpg", "2020"]));
+        assert_eq!(df.rows[1], text_row(&["Q2", "c", "x.jpg", "2020"]));
+        assert_eq!(df.rows[2][2], DataCell::Blank);
+
+        // Cleanup
+        for uuid in [&uuid1, &uuid2, &uuid3, &dfd.uuid, &dfd2.uuid] {
+            APP.remove_uuid_file(uuid).unwrap();
+        }
     }
 }
@@ -1,21 +1,29 @@
-use anyhow::Result;
-use lazy_static::lazy_static;
+use anyhow::{anyhow, Result};
 use app::App;
-use clap::{arg, Command};
+use clap::{arg, ArgMatches, Command};
+use lazy_static::lazy_static;
+use tracing_subscriber::EnvFilter;
 
-use crate::renderer::{RendererWikitext, Renderer};
+use crate::renderer::{
+    Renderer, RendererCsv, RendererJson, RendererQuickStatements, RendererWikitext,
+};
 
+pub mod adapter;
 pub mod app;
+pub mod data_cell;
 pub mod data_file;
-pub mod wiki_page;
-pub mod mapping;
-pub mod renderer;
-pub mod adapter;
-pub mod join;
+pub mod data_header;
 pub mod filter;
 pub mod generator;
-pub mod data_cell;
-pub mod data_header;
+pub mod join;
+pub mod mapping;
+pub mod renderer;
+pub mod sub_workflow;
+#[cfg(test)]
+mod test_util;
+pub mod transform;
+pub mod wiki_page;
+pub mod wiki_table;
 pub mod workflow;
 pub mod workflow_node;
 pub mod workflow_run;
@@ -31,42 +39,222 @@ fn cli() -> Command {
         .arg_required_else_help(true)
         .allow_external_subcommands(true)
         .subcommand(
-            Command::new("server")
-                .about("Runs the ToolFlow server")
-                // .arg(arg!(<REMOTE> "The remote to clone"))
-                // .arg_required_else_help(true),
+            Command::new("server").about("Runs the ToolFlow server"),
+            // .arg(arg!(<REMOTE> "The remote to clone"))
+            // .arg_required_else_help(true),
         )
         .subcommand(
             Command::new("render")
                 .about("Runs a renderer")
                 .arg(arg!(mode: [MODE]))
-                .arg(arg!(uuid: [UUID]))
+                .arg(arg!(uuid: [UUID] "Data file UUID, or '-' to read JSONL from stdin"))
+                .arg(arg!(--file <PATH> "Read a local JSONL file instead of a data file UUID"))
+                .arg(arg!(--wiki <WIKI> "The wiki the output will be used on"))
+                .arg(arg!(--columns <COLUMNS> "Comma-separated list of columns to render"))
+                .arg(arg!(--delimiter <DELIMITER> "CSV field delimiter, a single character or 'tab'"))
+                .arg(arg!(--quote <QUOTE> "CSV quoting: necessary, always, never, or non_numeric"))
+                .arg(arg!(--"no-header" "CSV: do not output a header row"))
+                .arg(arg!(--lf "CSV: end lines with LF instead of CRLF"))
+                .arg(arg!(--escape "CSV: write tabs and line breaks in cells as \\t and \\n"))
                 // .arg(arg!(<MISC> "Misc parameters, depnding on renderer type"))
                 .arg_required_else_help(true),
         )
+        .subcommand(
+            Command::new("run")
+                .about("Queues a run to start before any scheduled runs")
+                .arg(arg!(run_id: <RUN_ID>)),
+        )
+        .subcommand(
+            Command::new("files")
+                .about("Manages the stored result files of a user")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("list")
+                        .about("Lists the stored files of a user")
+                        .arg(arg!(user_id: <USER_ID>)),
+                )
+                .subcommand(
+                    Command::new("delete")
+                        .about("Deletes stored files of a user")
+                        .arg(arg!(user_id: <USER_ID>))
+                        .arg(arg!(uuids: <UUID> ...)),
+                ),
+        )
+        .subcommand(
+            Command::new("user")
+                .about("Manages user settings")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("quickstatements-token")
+                        .about("Stores the QuickStatements token of a user, read from stdin")
+                        .arg(arg!(user_id: <USER_ID>)),
+                ),
+        )
+}
+
+fn parse_user_id(sub_matches: &ArgMatches) -> Result<usize> {
+    let user_id = sub_matches
+        .get_one::<String>("user_id")
+        .expect("user_id not set");
+    user_id
+        .parse::<usize>()
+        .map_err(|_| anyhow!("Not a valid user ID: {user_id}"))
+}
+
+fn csv_renderer(
+    sub_matches: &ArgMatches,
+    columns: Option<Vec<String>>,
+    defaults: RendererCsv,
+) -> Result<RendererCsv> {
+    let mut renderer = RendererCsv {
+        columns,
+        include_header: !sub_matches.get_flag("no-header"),
+        escape_line_breaks: defaults.escape_line_breaks || sub_matches.get_flag("escape"),
+        ..defaults
+    };
+    if let Some(delimiter) = sub_matches.get_one::<String>("delimiter") {
+        renderer.delimiter = match delimiter.as_str() {
+            "tab" | "\\t" => b'\t',
+            d if d.len() == 1 => d.as_bytes()[0],
+            d => return Err(anyhow!("Delimiter must be a single character: '{d}'")),
+        };
+    }
+    if let Some(quote) = sub_matches.get_one::<String>("quote") {
+        renderer.quote_style = match quote.as_str() {
+            "necessary" => csv::QuoteStyle::Necessary,
+            "always" => csv::QuoteStyle::Always,
+            "never" => csv::QuoteStyle::Never,
+            "non_numeric" => csv::QuoteStyle::NonNumeric,
+            other => return Err(anyhow!("Unknown quote style '{other}'")),
+        };
+    }
+    if sub_matches.get_flag("lf") {
+        renderer.line_terminator = csv::Terminator::Any(b'\n');
+    }
+    Ok(renderer)
+}
+
+// Renders from `--file`, stdin ("-"), or the data file of a UUID
+fn render_input(renderer: &impl Renderer, sub_matches: &ArgMatches) -> Result<String> {
+    if let Some(path) = sub_matches.get_one::<String>("file") {
+        return renderer.render_from_path(path);
+    }
+    match sub_matches.get_one::<String>("uuid").map(|s| s.as_str()) {
+        Some("-") => renderer.render_from_path("-"),
+        Some(uuid) => renderer
+            .render_from_uuid(uuid)
+            .map_err(|_| anyhow!("No data file for uuid {uuid}")),
+        None => Err(anyhow!("Either a UUID or --file is required")),
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    // Log level can be set via RUST_LOG, e.g. RUST_LOG=toolflow=debug
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
     let matches = cli().get_matches();
 
     match matches.subcommand() {
-        Some(("server", _sub_matches)) => {
-            APP.server().await
-        },
+        Some(("server", _sub_matches)) => APP.server().await,
         Some(("render", sub_matches)) => {
-            let mode = sub_matches.get_one::<String>("mode").map(|s| s.as_str()).expect("mode not set");
-            let uuid = sub_matches.get_one::<String>("uuid").map(|s| s.as_str()).expect("uuid not set");
+            let mode = sub_matches
+                .get_one::<String>("mode")
+                .map(|s| s.as_str())
+                .expect("mode not set");
+            let wiki = sub_matches.get_one::<String>("wiki");
+            let columns: Option<Vec<String>> = sub_matches
+                .get_one::<String>("columns")
+                .map(|s| s.split(',').map(|c| c.trim().to_string()).collect());
             // let _misc = sub_matches.get_one::<String>("misc").map(|s| s.as_str());
             match mode {
                 "wiki" => {
-                    let wikitext = RendererWikitext::default().render_from_uuid(uuid).expect(&format!("No data file for uuid {uuid}"));
+                    let mut renderer = match wiki {
+                        Some(wiki) => RendererWikitext::new_with_default_wiki(wiki),
+                        None => RendererWikitext::default(),
+                    };
+                    renderer.columns = columns;
+                    let wikitext = render_input(&renderer, sub_matches)?;
                     println!("{wikitext}");
                 }
+                "csv" => {
+                    let renderer = csv_renderer(sub_matches, columns, RendererCsv::default())?;
+                    let csv = render_input(&renderer, sub_matches)?;
+                    print!("{csv}");
+                }
+                "tsv" => {
+                    let renderer = csv_renderer(sub_matches, columns, RendererCsv::tsv())?;
+                    let tsv = render_input(&renderer, sub_matches)?;
+                    print!("{tsv}");
+                }
+                "json" => {
+                    let renderer = RendererJson { columns };
+                    let json = render_input(&renderer, sub_matches)?;
+                    println!("{json}");
+                }
+                "quickstatements" => {
+                    let renderer = RendererQuickStatements { columns };
+                    let commands = render_input(&renderer, sub_matches)?;
+                    print!("{commands}");
+                }
                 other => panic!("Render type '{other}' is not supported"),
             }
             Ok(())
         }
+        Some(("run", sub_matches)) => {
+            let run_id = sub_matches
+                .get_one::<String>("run_id")
+                .expect("run_id not set");
+            let run_id = run_id
+                .parse::<usize>()
+                .map_err(|_| anyhow!("Not a valid run ID: {run_id}"))?;
+            let mut conn = APP.get_db_connection().await?;
+            APP.queue_manual_run(run_id, &mut conn).await?;
+            println!("Queued run {run_id}");
+            Ok(())
+        }
+        Some(("files", sub_matches)) => match sub_matches.subcommand() {
+            Some(("list", sub_matches)) => {
+                let user_id = parse_user_id(sub_matches)?;
+                for file in APP.get_user_files(user_id).await? {
+                    let size = file.size.map(|s| format!("{s}")).unwrap_or_default();
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}\t{size}",
+                        file.uuid, file.workflow_id, file.run_id, file.node_id, file.rows
+                    );
+                }
+                Ok(())
+            }
+            Some(("delete", sub_matches)) => {
+                let user_id = parse_user_id(sub_matches)?;
+                let uuids: Vec<String> = sub_matches
+                    .get_many::<String>("uuids")
+                    .expect("uuids not set")
+                    .cloned()
+                    .collect();
+                let deleted = APP.delete_user_files(user_id, &uuids).await?;
+                println!("Deleted {deleted} of {} files", uuids.len());
+                Ok(())
+            }
+            _ => unreachable!(),
+        },
+        Some(("user", sub_matches)) => match sub_matches.subcommand() {
+            Some(("quickstatements-token", sub_matches)) => {
+                let user_id = parse_user_id(sub_matches)?;
+                // Not an argument, so the token does not end up in the shell history
+                let mut token = String::new();
+                let _ = std::io::stdin().read_line(&mut token)?;
+                APP.set_quickstatements_token(user_id, &token).await?;
+                println!("Stored QuickStatements token for user {user_id}");
+                Ok(())
+            }
+            _ => unreachable!(),
+        },
         _ => unreachable!(), // If all subcommands are defined above, anything else is unreachable!()
     }
 }
@@ -11,6 +11,20 @@ pub enum SourceId {
     AListBuildingTool((String, String)),
     WdFist(String),
     UserEdits(String),
+    WikiTable {
+        wiki: String,
+        page: String,
+        table_index: usize,
+    },
+    CommonsCategory {
+        category: String,
+        depth: u32,
+    },
+    CsvUrl(String),
+    JsonUrl {
+        url: String,
+        rows_path: String,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
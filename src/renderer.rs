@@ -1,16 +1,99 @@
 use crate::{
-    data_cell::DataCell,
+    data_cell::{DataCell, TIME_PRECISION_DAY},
     data_file::DataFile,
-    data_header::{ColumnHeader, ColumnHeaderType},
+    data_header::{ColumnHeader, ColumnHeaderType, DataHeader},
+    wiki_page::WikiPage,
 };
 use anyhow::{anyhow, Result};
+use csv::{QuoteStyle, Terminator, WriterBuilder};
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 use ucfirst::ucfirst;
 
 lazy_static! {
     static ref RE_WIKI_TO_PREFIX: Regex = Regex::new(r"^(.+)wik.*$").expect("Regex error");
+    static ref RE_PROPERTY: Regex = Regex::new(r"^P\d+$").expect("Regex error");
+}
+
+// Optional presentation of Int/Float cells, eg "1,234,567" or "12.3 km"
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NumberFormat {
+    #[serde(default)]
+    pub thousands_separator: Option<String>,
+    #[serde(default)]
+    pub decimal_places: Option<usize>,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub suffix: String,
+}
+
+impl NumberFormat {
+    pub fn format_int(&self, i: i64) -> String {
+        let mut s = format!("{i}");
+        if let Some(decimal_places) = self.decimal_places.filter(|d| *d > 0) {
+            s = format!("{s}.{}", "0".repeat(decimal_places));
+        }
+        self.decorate(&s)
+    }
+
+    pub fn format_float(&self, f: f64) -> String {
+        let s = match self.decimal_places {
+            Some(decimal_places) => format!("{f:.decimal_places$}"),
+            None => format!("{f}"),
+        };
+        self.decorate(&s)
+    }
+
+    fn decorate(&self, s: &str) -> String {
+        let s = match &self.thousands_separator {
+            Some(separator) => Self::group_thousands(s, separator),
+            None => s.to_string(),
+        };
+        format!("{}{s}{}", self.prefix, self.suffix)
+    }
+
+    fn group_thousands(s: &str, separator: &str) -> String {
+        let (sign, s) = match s.strip_prefix('-') {
+            Some(s) => ("-", s),
+            None => ("", s),
+        };
+        let (integer, fraction) = match s.split_once('.') {
+            Some((integer, fraction)) => (integer, format!(".{fraction}")),
+            None => (s, String::new()),
+        };
+        if !integer.chars().all(|c| c.is_ascii_digit()) {
+            return format!("{sign}{s}"); // NaN, inf
+        }
+        let digits: Vec<char> = integer.chars().collect();
+        let grouped = digits
+            .rchunks(3)
+            .rev()
+            .map(|chunk| chunk.iter().collect::<String>())
+            .collect::<Vec<String>>()
+            .join(separator);
+        format!("{sign}{grouped}{fraction}")
+    }
+
+    // The formatted number, if the cell is a number and there is a format for its column
+    pub fn format_cell(
+        formats: &HashMap<String, NumberFormat>,
+        col_header: &ColumnHeader,
+        cell: &DataCell,
+    ) -> Option<String> {
+        let format = formats.get(&col_header.name)?;
+        match cell {
+            DataCell::Int(i) => Some(format.format_int(*i)),
+            DataCell::Float(f) => Some(format.format_float(*f)),
+            _ => None,
+        }
+    }
 }
 
 pub trait Renderer {
@@ -25,26 +108,52 @@ pub trait Renderer {
         cell: DataCell,
     ) -> Result<String>;
 
+    // Only these columns are rendered, in this order, if set
+    fn columns(&self) -> Option<&[String]> {
+        None
+    }
+
     fn render_from_uuid(&self, uuid: &str) -> Result<String> {
         let mut df = DataFile::default();
         df.open_input_file(uuid)?;
         self.render(&mut df)
     }
 
+    fn render_from_path(&self, path: &str) -> Result<String> {
+        let mut df = DataFile::default();
+        df.open_input_path(path)?;
+        self.render(&mut df)
+    }
+
     fn render(&self, df: &mut DataFile) -> Result<String> {
         self.render_block(df)
     }
 
     fn render_block(&self, df: &mut DataFile) -> Result<String> {
         df.load_header()?;
+        let col_nums = match self.columns() {
+            Some(columns) => {
+                let col_nums = df.header().get_col_nums(columns)?;
+                let columns = col_nums
+                    .iter()
+                    .map(|col_num| df.header().columns[*col_num].to_owned())
+                    .collect();
+                df.set_header(DataHeader { columns });
+                Some(col_nums)
+            }
+            None => None,
+        };
         let mut ret = self.render_header(df)?;
         let mut row_num = 0;
-        loop {
-            let row = match df.read_row() {
-                Some(row) => row,
-                None => break,
-            };
+        while let Some(row) = df.read_row() {
             let row: Vec<DataCell> = serde_json::from_str(&row)?;
+            let row = match &col_nums {
+                Some(col_nums) => col_nums
+                    .iter()
+                    .map(|col_num| row.get(*col_num).cloned().unwrap_or(DataCell::Blank))
+                    .collect(),
+                None => row,
+            };
             ret += &self.render_row(df, row_num, row)?;
             row_num += 1;
         }
@@ -79,9 +188,23 @@ pub trait Renderer {
 #[derive(Default, Clone, Debug)]
 pub struct RendererWikitext {
     default_wiki: Arc<Mutex<Option<String>>>,
+    pub columns: Option<Vec<String>>,
+    pub number_formats: HashMap<String, NumberFormat>, // By column name
+    // Row count and time of rendering below the table; off by default, as the time makes every
+    // rendering different, so unchanged data would still change the page
+    pub footer: bool,
+    rows_rendered: Arc<Mutex<usize>>,
 }
 
 impl RendererWikitext {
+    // The default ("local") wiki will not be auto-detected from the columns if set here
+    pub fn new_with_default_wiki(wiki: &str) -> Self {
+        Self {
+            default_wiki: Arc::new(Mutex::new(Some(wiki.to_string()))),
+            ..Default::default()
+        }
+    }
+
     fn detect_default_wiki(&self, df: &DataFile) -> Result<()> {
         for column in &df.header().columns {
             if let ColumnHeaderType::WikiPage(wp) = &column.kind {
@@ -98,6 +221,11 @@ impl RendererWikitext {
         Ok(())
     }
 
+    fn set_rows_rendered(&self, rows: usize) -> Result<()> {
+        *self.rows_rendered.lock().map_err(|e| anyhow!("{e}"))? = rows;
+        Ok(())
+    }
+
     fn pretty_filename(&self, title: &str) -> String {
         let filename_pretty = title.replace('_', " ");
 
@@ -118,8 +246,13 @@ impl RendererWikitext {
 }
 
 impl Renderer for RendererWikitext {
+    fn columns(&self) -> Option<&[String]> {
+        self.columns.as_deref()
+    }
+
     fn render_header(&self, df: &mut DataFile) -> Result<String> {
         self.detect_default_wiki(df)?;
+        self.set_rows_rendered(0)?;
 
         let mut ret = String::new();
         ret += "{| class=\"wikitable\"\n";
@@ -137,11 +270,19 @@ impl Renderer for RendererWikitext {
     fn render_footer(&self, _df: &mut DataFile) -> Result<String> {
         let mut ret = String::new();
         ret += "|}\n";
-        // TODO date
+        if !self.footer {
+            return Ok(ret);
+        }
+        let rows = *self.rows_rendered.lock().map_err(|e| anyhow!("{e}"))?;
+        ret += &format!(
+            "<small>Generated {rows} rows on {}</small>\n",
+            chrono::Utc::now().format("%Y-%m-%d %H:%M UTC")
+        );
         Ok(ret)
     }
 
     fn render_row(&self, df: &mut DataFile, row_num: usize, row: Vec<DataCell>) -> Result<String> {
+        self.set_rows_rendered(row_num + 1)?;
         self.render_row_separators(df, row_num, row, "|--\n", "", "")
     }
 
@@ -152,6 +293,16 @@ impl Renderer for RendererWikitext {
         col_num: usize,
         cell: DataCell,
     ) -> Result<String> {
+        if let Some(formatted) = NumberFormat::format_cell(&self.number_formats, col_header, &cell)
+        {
+            // Keep the column sortable by the raw number
+            let raw = match cell {
+                DataCell::Int(i) => format!("{i}"),
+                DataCell::Float(f) => format!("{f}"),
+                _ => String::new(),
+            };
+            return Ok(format!("||data-sort-value=\"{raw}\"|{formatted}\n"));
+        }
         let default_wiki = self.default_wiki.lock().unwrap();
         Ok("||".to_string()
             + &match cell {
@@ -162,9 +313,11 @@ impl Renderer for RendererWikitext {
                     })?;
                     let col_wp = match &col_header.kind {
                         ColumnHeaderType::WikiPage(col_wp) => col_wp,
-                        _ => return Err(anyhow!(
+                        _ => {
+                            return Err(anyhow!(
                             "Row {row_num} column {col_num}: cell is WikiPage but header is not"
-                        )),
+                        ))
+                        }
                     };
                     let wiki = wp.wiki.to_owned().or(col_wp.wiki.to_owned());
                     let wiki = wiki.ok_or_else(|| {
@@ -199,7 +352,7 @@ impl Renderer for RendererWikitext {
                     if wp.ns_id != Some(6) && title.contains('_') {
                         let pretty_title = title.replace('_', " ");
                         link = match title.chars().next() {
-                            Some(':') => format!("{title}|{}", pretty_title[1..].to_string()),
+                            Some(':') => format!("{title}|{}", &pretty_title[1..]),
                             _ => pretty_title,
                         };
                     }
@@ -207,15 +360,325 @@ impl Renderer for RendererWikitext {
                 }
                 DataCell::Int(i) => format!("{i}"),
                 DataCell::Float(f) => format!("{f}"),
+                DataCell::Coordinate { lat, lon } => format!("{{{{Coord|{lat}|{lon}}}}}"),
+                DataCell::Boolean(b) => format!("{b}"),
+                DataCell::Date(d) => d,
+                DataCell::WikidataTime {
+                    time,
+                    precision,
+                    calendar,
+                } => DataCell::format_wikidata_time(&time, precision, &calendar),
                 DataCell::Blank => String::new(),
             }
             + "\n")
     }
 }
 
+// ____________________________________________________________________________________
+
+// Delimiter-separated values; defaults follow RFC 4180
+#[derive(Clone, Debug)]
+pub struct RendererCsv {
+    pub delimiter: u8,
+    pub quote_style: QuoteStyle,
+    pub include_header: bool,
+    pub line_terminator: Terminator,
+    pub escape_line_breaks: bool, // Tabs and line breaks become \t and \n, so a row is one line
+    pub page_with_wiki: bool,     // WikiPage cells as wiki:prefixed_title
+    pub columns: Option<Vec<String>>,
+    pub number_formats: HashMap<String, NumberFormat>, // By column name
+}
+
+impl Default for RendererCsv {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote_style: QuoteStyle::Necessary,
+            include_header: true,
+            line_terminator: Terminator::CRLF,
+            escape_line_breaks: false,
+            page_with_wiki: false,
+            columns: None,
+            number_formats: HashMap::new(),
+        }
+    }
+}
+
+impl RendererCsv {
+    // Tab-separated values; one data row is always exactly one line
+    pub fn tsv() -> Self {
+        Self {
+            delimiter: b'\t',
+            quote_style: QuoteStyle::Never,
+            line_terminator: Terminator::Any(b'\n'),
+            escape_line_breaks: true,
+            page_with_wiki: true,
+            ..Default::default()
+        }
+    }
+
+    fn escape(&self, s: String) -> String {
+        if !self.escape_line_breaks {
+            return s;
+        }
+        s.replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
+    }
+
+    fn write_record(&self, fields: &[String]) -> Result<String> {
+        let mut writer = WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .quote_style(self.quote_style)
+            .terminator(self.line_terminator)
+            .from_writer(vec![]);
+        writer.write_record(fields)?;
+        let bytes = writer.into_inner().map_err(|e| anyhow!("{e}"))?;
+        Ok(String::from_utf8(bytes)?)
+    }
+}
+
+impl Renderer for RendererCsv {
+    fn columns(&self) -> Option<&[String]> {
+        self.columns.as_deref()
+    }
+
+    fn render_header(&self, df: &mut DataFile) -> Result<String> {
+        if !self.include_header {
+            return Ok(String::new());
+        }
+        let names: Vec<String> = df
+            .header()
+            .columns
+            .iter()
+            .map(|c| self.escape(c.name.to_owned()))
+            .collect();
+        self.write_record(&names)
+    }
+
+    fn render_footer(&self, _df: &mut DataFile) -> Result<String> {
+        Ok(String::new())
+    }
+
+    fn render_row(&self, df: &mut DataFile, row_num: usize, row: Vec<DataCell>) -> Result<String> {
+        let fields = row
+            .into_iter()
+            .zip(df.header().columns.iter())
+            .enumerate()
+            .map(|(col_num, (cell, col_header))| {
+                self.render_cell(col_header, row_num, col_num, cell)
+            })
+            .collect::<Result<Vec<String>>>()?;
+        self.write_record(&fields)
+    }
+
+    fn render_cell(
+        &self,
+        col_header: &ColumnHeader,
+        _row_num: usize,
+        _col_num: usize,
+        cell: DataCell,
+    ) -> Result<String> {
+        if let Some(formatted) = NumberFormat::format_cell(&self.number_formats, col_header, &cell)
+        {
+            return Ok(self.escape(formatted));
+        }
+        let s = match cell {
+            DataCell::PlainText(s) => s,
+            DataCell::WikiPage(wp) if self.page_with_wiki => {
+                let wiki = wp.wiki.or(match &col_header.kind {
+                    ColumnHeaderType::WikiPage(col_wp) => col_wp.wiki.to_owned(),
+                    _ => None,
+                });
+                format!(
+                    "{}:{}",
+                    wiki.unwrap_or_default(),
+                    wp.prefixed_title.unwrap_or_default()
+                )
+            }
+            DataCell::WikiPage(wp) => wp.prefixed_title.unwrap_or_default(),
+            DataCell::Int(i) => format!("{i}"),
+            DataCell::Float(f) => format!("{f}"),
+            DataCell::Coordinate { lat, lon } => format!("{lat},{lon}"),
+            DataCell::Boolean(b) => format!("{b}"),
+            DataCell::Date(d) => d,
+            DataCell::WikidataTime {
+                time,
+                precision,
+                calendar,
+            } => DataCell::format_wikidata_time(&time, precision, &calendar),
+            DataCell::Blank => String::new(),
+        };
+        Ok(self.escape(s))
+    }
+}
+
+// ____________________________________________________________________________________
+
+// A single JSON array with one object per row, keyed by column name
+#[derive(Default, Clone, Debug)]
+pub struct RendererJson {
+    pub columns: Option<Vec<String>>,
+}
+
+impl RendererJson {
+    fn cell_value(col_header: &ColumnHeader, cell: DataCell) -> Value {
+        let mut value = cell.to_json();
+        // Pages without their own wiki are on the column's wiki
+        if let (Some(wiki @ Value::Null), ColumnHeaderType::WikiPage(col_wp)) =
+            (value.get_mut("wiki"), &col_header.kind)
+        {
+            *wiki = json!(col_wp.wiki);
+        }
+        value
+    }
+}
+
+impl Renderer for RendererJson {
+    fn columns(&self) -> Option<&[String]> {
+        self.columns.as_deref()
+    }
+
+    fn render_header(&self, _df: &mut DataFile) -> Result<String> {
+        Ok("[".to_string())
+    }
+
+    fn render_footer(&self, _df: &mut DataFile) -> Result<String> {
+        Ok("]".to_string())
+    }
+
+    fn render_row(&self, df: &mut DataFile, row_num: usize, row: Vec<DataCell>) -> Result<String> {
+        let object: Map<String, Value> = row
+            .into_iter()
+            .zip(df.header().columns.iter())
+            .map(|(cell, col_header)| {
+                (
+                    col_header.name.to_owned(),
+                    Self::cell_value(col_header, cell),
+                )
+            })
+            .collect();
+        let separator = if row_num == 0 { "" } else { ",\n" };
+        Ok(format!("{separator}{}", Value::Object(object)))
+    }
+
+    fn render_cell(
+        &self,
+        col_header: &ColumnHeader,
+        _row_num: usize,
+        _col_num: usize,
+        cell: DataCell,
+    ) -> Result<String> {
+        Ok(Self::cell_value(col_header, cell).to_string())
+    }
+}
+
+// ____________________________________________________________________________________
+
+// QuickStatements V1 commands; the first column is the item, columns named like "P31" are its
+// statements, one per non-blank cell. Other columns are ignored.
+#[derive(Default, Clone, Debug)]
+pub struct RendererQuickStatements {
+    pub columns: Option<Vec<String>>,
+}
+
+impl RendererQuickStatements {
+    // QuickStatements V1 cannot escape a double quote, so such strings cannot be a value
+    fn string_value(s: &str) -> Option<String> {
+        if s.contains('"') {
+            return None;
+        }
+        Some(format!("\"{}\"", s.replace(['\t', '\n', '\r'], " ")))
+    }
+
+    // None for cells that cannot be a statement value
+    fn value(col_header: &ColumnHeader, cell: DataCell) -> Option<String> {
+        match cell {
+            DataCell::PlainText(s) => {
+                WikiPage::entity_id_from_title(&s).or_else(|| Self::string_value(&s))
+            }
+            DataCell::WikiPage(wp) => {
+                let title = wp.prefixed_title.or(wp.title)?;
+                let wiki = wp.wiki.or(match &col_header.kind {
+                    ColumnHeaderType::WikiPage(col_wp) => col_wp.wiki.to_owned(),
+                    _ => None,
+                });
+                match wiki.as_deref() {
+                    Some("wikidatawiki") | None => WikiPage::entity_id_from_title(&title),
+                    // Commons media (P18 etc) are referenced by file name
+                    Some("commonswiki") => {
+                        let name = title.strip_prefix("File:").unwrap_or(&title);
+                        Self::string_value(&name.replace('_', " "))
+                    }
+                    Some(_) => Self::string_value(&title.replace('_', " ")),
+                }
+            }
+            DataCell::Int(i) => Some(format!("{i}")),
+            DataCell::Float(f) => Some(format!("{f}")),
+            DataCell::Coordinate { lat, lon } => Some(format!("@{lat}/{lon}")),
+            DataCell::Date(d) => Some(format!("+{d}T00:00:00Z/{TIME_PRECISION_DAY}")),
+            DataCell::WikidataTime {
+                time, precision, ..
+            } => Some(format!("{time}/{precision}")),
+            DataCell::Boolean(_) | DataCell::Blank => None,
+        }
+    }
+}
+
+impl Renderer for RendererQuickStatements {
+    fn columns(&self) -> Option<&[String]> {
+        self.columns.as_deref()
+    }
+
+    fn render_header(&self, _df: &mut DataFile) -> Result<String> {
+        Ok(String::new())
+    }
+
+    fn render_footer(&self, _df: &mut DataFile) -> Result<String> {
+        Ok(String::new())
+    }
+
+    fn render_row(&self, df: &mut DataFile, row_num: usize, row: Vec<DataCell>) -> Result<String> {
+        let mut cells = row.into_iter().zip(df.header().columns.iter());
+        let item = match cells.next() {
+            Some((DataCell::PlainText(s), _)) => WikiPage::entity_id_from_title(&s),
+            Some((DataCell::WikiPage(wp), _)) => wp
+                .prefixed_title
+                .or(wp.title)
+                .and_then(|t| WikiPage::entity_id_from_title(&t)),
+            _ => None,
+        };
+        let item = match item {
+            Some(item) => item,
+            None => return Ok(String::new()), // No item to edit
+        };
+        let mut ret = String::new();
+        for (col_num, (cell, col_header)) in cells.enumerate() {
+            let value = self.render_cell(col_header, row_num, col_num + 1, cell)?;
+            if RE_PROPERTY.is_match(&col_header.name) && !value.is_empty() {
+                ret += &format!("{item}\t{}\t{value}\n", col_header.name);
+            }
+        }
+        Ok(ret)
+    }
+
+    fn render_cell(
+        &self,
+        col_header: &ColumnHeader,
+        _row_num: usize,
+        _col_num: usize,
+        cell: DataCell,
+    ) -> Result<String> {
+        Ok(Self::value(col_header, cell).unwrap_or_default())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::{header, write_file};
+    use crate::{wiki_page::WikiPage, APP};
 
     #[test]
     fn test_renderer_wikitext() {
@@ -223,4 +686,239 @@ mod tests {
         let wikitext = RendererWikitext::default().render_from_uuid(uuid).unwrap();
         assert_eq!(wikitext.len(), 77266);
     }
+
+    #[test]
+    fn test_renderer_wikitext_footer() {
+        let uuid = "8c5d1fb3-6ea8-44d1-b938-9d22f569c412";
+        let wikitext = RendererWikitext::default().render_from_uuid(uuid).unwrap();
+        assert!(wikitext.ends_with("|}\n"));
+        let renderer = RendererWikitext {
+            footer: true,
+            ..Default::default()
+        };
+        let wikitext = renderer.render_from_uuid(uuid).unwrap();
+        let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        assert!(wikitext.contains(&format!("|}}\n<small>Generated 49 rows on {today} ")));
+        assert!(wikitext.ends_with(" UTC</small>\n"));
+    }
+
+    #[test]
+    fn test_renderer_from_path() {
+        let uuid = "cb1e218e-421f-46b8-a77e-eac6799ce4e4";
+        let path = format!("{}/{uuid}.jsonl", APP.data_path());
+        let renderer = RendererWikitext::default();
+        assert_eq!(
+            renderer.render_from_path(&path).unwrap(),
+            renderer.render_from_uuid(uuid).unwrap()
+        );
+        assert!(renderer.render_from_path("/no/such/file.jsonl").is_err());
+    }
+
+    #[test]
+    fn test_renderer_wikitext_default_wiki() {
+        let uuid = "cb1e218e-421f-46b8-a77e-eac6799ce4e4";
+        let wikitext = RendererWikitext::new_with_default_wiki("enwiki")
+            .render_from_uuid(uuid)
+            .unwrap();
+        assert!(wikitext.contains("[[:de:AGEB]]"));
+    }
+
+    #[test]
+    fn test_renderer_wikitext_coordinate() {
+        let col_header = ColumnHeader {
+            name: "coord".to_string(),
+            kind: ColumnHeaderType::Coordinate,
+        };
+        let cell = DataCell::Coordinate {
+            lat: 48.8566,
+            lon: 2.3522,
+        };
+        let wikitext = RendererWikitext::default()
+            .render_cell(&col_header, 0, 0, cell)
+            .unwrap();
+        assert_eq!(wikitext, "||{{Coord|48.8566|2.3522}}\n");
+    }
+
+    #[test]
+    fn test_renderer_wikitext_columns() {
+        let uuid = "cb1e218e-421f-46b8-a77e-eac6799ce4e4";
+        let renderer = RendererWikitext {
+            columns: Some(vec!["wiki_page".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(renderer.render_from_uuid(uuid).unwrap().len(), 77266);
+        let renderer = RendererWikitext {
+            columns: Some(vec!["no_such_column".to_string()]),
+            ..Default::default()
+        };
+        assert!(renderer.render_from_uuid(uuid).is_err());
+    }
+
+    #[test]
+    fn test_renderer_csv() {
+        let uuid = "8c5d1fb3-6ea8-44d1-b938-9d22f569c412";
+        let csv = RendererCsv::default().render_from_uuid(uuid).unwrap();
+        assert!(csv.starts_with("wikidata_item\r\nQ18619644\r\n"));
+        assert_eq!(csv.lines().count(), 50);
+
+        let renderer = RendererCsv {
+            delimiter: b';',
+            include_header: false,
+            line_terminator: Terminator::Any(b'\n'),
+            ..Default::default()
+        };
+        let csv = renderer.render_from_uuid(uuid).unwrap();
+        assert!(csv.starts_with("Q18619644\n"));
+        assert_eq!(csv.lines().count(), 49);
+    }
+
+    #[test]
+    fn test_renderer_tsv_escapes_newlines() {
+        let header = header(&[
+            ("page", ColumnHeaderType::WikiPage(WikiPage::default())),
+            ("note", ColumnHeaderType::PlainText),
+        ]);
+        let page = WikiPage {
+            wiki: Some("enwiki".to_string()),
+            prefixed_title: Some("Line\nbreak".to_string()),
+            ..Default::default()
+        };
+        let row = vec![
+            DataCell::WikiPage(page),
+            DataCell::PlainText("a\tb".to_string()),
+        ];
+        let uuid = write_file(&header, vec![row]);
+
+        let tsv = RendererCsv::tsv().render_from_uuid(&uuid).unwrap();
+        assert_eq!(tsv, "page\tnote\nenwiki:Line\\nbreak\ta\\tb\n");
+        assert_eq!(tsv.lines().count(), 2);
+
+        let renderer = RendererCsv {
+            escape_line_breaks: false,
+            ..RendererCsv::tsv()
+        };
+        let tsv = renderer.render_from_uuid(&uuid).unwrap();
+        assert_eq!(tsv, "page\tnote\nenwiki:Line\nbreak\ta\tb\n");
+        APP.remove_uuid_file(&uuid).unwrap(); // Cleanup
+    }
+
+    #[test]
+    fn test_renderer_json() {
+        let uuid = "8c5d1fb3-6ea8-44d1-b938-9d22f569c412";
+        let output = RendererJson::default().render_from_uuid(uuid).unwrap();
+        let j: Value = serde_json::from_str(&output).unwrap();
+        let rows = j.as_array().unwrap();
+        assert_eq!(rows.len(), 49);
+        assert_eq!(
+            rows[0]["wikidata_item"]["prefixed_title"],
+            json!("Q18619644")
+        );
+        assert_eq!(rows[0]["wikidata_item"]["wiki"], json!("wikidatawiki"));
+
+        // No data rows
+        let uuid = write_file(&header(&[("empty", ColumnHeaderType::PlainText)]), vec![]);
+        assert_eq!(
+            RendererJson::default().render_from_uuid(&uuid).unwrap(),
+            "[]"
+        );
+        APP.remove_uuid_file(&uuid).unwrap(); // Cleanup
+    }
+
+    #[test]
+    fn test_renderer_quickstatements() {
+        let commons = WikiPage {
+            wiki: Some("commonswiki".to_string()),
+            ..Default::default()
+        };
+        let header = header(&[
+            ("item", ColumnHeaderType::PlainText),
+            ("P31", ColumnHeaderType::PlainText),
+            ("P18", ColumnHeaderType::WikiPage(commons)),
+            ("label", ColumnHeaderType::PlainText),
+            ("P1082", ColumnHeaderType::Int),
+        ]);
+        let image = WikiPage {
+            prefixed_title: Some("File:Douglas_adams_portrait.jpg".to_string()),
+            ..Default::default()
+        };
+        let text = |s: &str| DataCell::PlainText(s.to_string());
+        let rows = vec![
+            vec![
+                text("Q42"),
+                text("Q5"),
+                DataCell::WikiPage(image),
+                text("Douglas Adams"),
+                DataCell::Blank,
+            ],
+            vec![
+                text("not an item"),
+                text("Q5"),
+                DataCell::Blank,
+                DataCell::Blank,
+                DataCell::Int(3),
+            ],
+        ];
+        let uuid = write_file(&header, rows);
+
+        let qs = RendererQuickStatements::default()
+            .render_from_uuid(&uuid)
+            .unwrap();
+        assert_eq!(
+            qs,
+            "Q42\tP31\tQ5\nQ42\tP18\t\"Douglas adams portrait.jpg\"\n"
+        );
+        APP.remove_uuid_file(&uuid).unwrap(); // Cleanup
+    }
+
+    #[test]
+    fn test_quickstatements_string_value() {
+        let col_header = ColumnHeader {
+            name: "P1476".to_string(),
+            kind: ColumnHeaderType::PlainText,
+        };
+        let value = |s: &str| {
+            RendererQuickStatements::value(&col_header, DataCell::PlainText(s.to_string()))
+        };
+        assert_eq!(value("A\ttitle"), Some("\"A title\"".to_string()));
+        assert_eq!(value("Q42"), Some("Q42".to_string()));
+        assert_eq!(value("The \"Title\""), None);
+    }
+
+    #[test]
+    fn test_number_format() {
+        let format = NumberFormat {
+            thousands_separator: Some(",".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(format.format_int(1234567), "1,234,567");
+        assert_eq!(format.format_int(-1234), "-1,234");
+        assert_eq!(format.format_int(123), "123");
+        let format = NumberFormat {
+            decimal_places: Some(1),
+            suffix: " km".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(format.format_float(12.345), "12.3 km");
+        assert_eq!(format.format_int(12), "12.0 km");
+        assert_eq!(NumberFormat::default().format_float(0.5), "0.5");
+
+        let col_header = ColumnHeader {
+            name: "population".to_string(),
+            kind: ColumnHeaderType::Int,
+        };
+        let renderer = RendererWikitext {
+            number_formats: HashMap::from([(
+                "population".to_string(),
+                NumberFormat {
+                    thousands_separator: Some(",".to_string()),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let cell = renderer
+            .render_cell(&col_header, 0, 0, DataCell::Int(1234567))
+            .unwrap();
+        assert_eq!(cell, "||data-sort-value=\"1234567\"|1,234,567\n");
+    }
 }
@@ -0,0 +1,159 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::HashMap, future::Future, pin::Pin};
+
+use crate::{
+    data_file::{DataFile, DataFileDetails},
+    workflow::{Workflow, WorkflowState},
+};
+
+const MAX_SUB_WORKFLOW_DEPTH: usize = 5;
+
+// Runs another workflow, and uses (a copy of) its output file as output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubWorkflow {
+    pub workflow_id: usize,
+
+    // Node parameters to override in the sub-workflow, by node ID
+    #[serde(default)]
+    pub parameter_overrides: HashMap<usize, HashMap<String, Value>>,
+
+    // Which output node to use, if the sub-workflow has several
+    #[serde(default)]
+    pub output_node: Option<usize>,
+}
+
+impl SubWorkflow {
+    // Follows all SubWorkflow references from this workflow, failing on cycles or excessive depth
+    async fn check_references(&self) -> Result<()> {
+        let mut stack = vec![vec![self.workflow_id]];
+        while let Some(path) = stack.pop() {
+            if path.len() > MAX_SUB_WORKFLOW_DEPTH {
+                return Err(anyhow!(
+                    "Sub-workflows nested deeper than {MAX_SUB_WORKFLOW_DEPTH} levels: {path:?}"
+                ));
+            }
+            let workflow_id = *path.last().expect("path is never empty");
+            let workflow = Workflow::from_id(workflow_id).await?;
+            for child_id in workflow.nodes.iter().filter_map(|n| n.sub_workflow_id()) {
+                let mut child_path = path.to_owned();
+                child_path.push(child_id);
+                if path.contains(&child_id) {
+                    return Err(anyhow!("Sub-workflow cycle: {child_path:?}"));
+                }
+                stack.push(child_path);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_overrides(&self, workflow: &mut Workflow) -> Result<()> {
+        for (node_id, parameters) in &self.parameter_overrides {
+            let node = workflow.nodes.get_mut(*node_id).ok_or_else(|| {
+                anyhow!(
+                    "Workflow {} has no node {node_id} to override parameters for",
+                    self.workflow_id
+                )
+            })?;
+            for (key, value) in parameters {
+                node.parameters.insert(key.to_owned(), value.to_owned());
+            }
+        }
+        Ok(())
+    }
+
+    fn output_uuid(&self, workflow: &Workflow) -> Result<String> {
+        let output_nodes: Vec<usize> = (0..workflow.nodes.len())
+            .filter(|node_id| workflow.run.is_output_node(*node_id))
+            .filter(|node_id| !workflow.run.get_node_status(*node_id).uuid().is_empty())
+            .collect();
+        let node_id = match (self.output_node, output_nodes.as_slice()) {
+            (Some(node_id), _) => node_id,
+            (None, [node_id]) => *node_id,
+            (None, _) => {
+                return Err(anyhow!(
+                    "Workflow {} has {} output files, set output_node to pick one",
+                    self.workflow_id,
+                    output_nodes.len()
+                ))
+            }
+        };
+        match workflow.run.get_node_status(node_id).uuid() {
+            "" => Err(anyhow!(
+                "Node {node_id} of workflow {} has no output file",
+                self.workflow_id
+            )),
+            uuid => Ok(uuid.to_string()),
+        }
+    }
+
+    // The sub-workflow run keeps its own files, so this node gets a copy
+    fn copy_file(uuid: &str) -> Result<DataFileDetails> {
+        let mut df_in = DataFile::default();
+        df_in.open_input_file(uuid)?;
+        let mut df_out = DataFile::new_output_file()?;
+        while let Some(row) = df_in.read_row() {
+            let row: Value = serde_json::from_str(&row)?;
+            df_out.write_json_row(&row)?;
+        }
+        df_out.finish()
+    }
+
+    // Boxed, as workflows can contain sub-workflows
+    pub fn process(
+        &self,
+        user_id: usize,
+    ) -> Pin<Box<dyn Future<Output = Result<DataFileDetails>> + Send + '_>> {
+        Box::pin(async move {
+            self.check_references().await?;
+            let mut workflow = Workflow::from_id(self.workflow_id).await?;
+            if !matches!(workflow.state, WorkflowState::PUBLISHED) && workflow.user_id != user_id {
+                return Err(anyhow!(
+                    "Workflow {} is not published and can not be used as a sub-workflow",
+                    self.workflow_id
+                ));
+            }
+            self.apply_overrides(&mut workflow)?;
+            workflow.user_id = user_id; // Edits etc. are done as the user running the outer workflow
+            workflow
+                .run()
+                .await
+                .map_err(|e| anyhow!("Sub-workflow {} failed: {e}", self.workflow_id))?;
+            Self::copy_file(&self.output_uuid(&workflow)?)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mapping::HeaderMapping,
+        workflow_node::{WorkflowNode, WorkflowNodeKind},
+    };
+    use serde_json::json;
+
+    #[test]
+    fn test_sub_workflow_apply_overrides() {
+        let node = WorkflowNode {
+            kind: WorkflowNodeKind::PetScan,
+            parameters: [("psid".to_string(), json!(1))].into_iter().collect(),
+            header_mapping: HeaderMapping::default(),
+        };
+        let mut workflow = Workflow::new(vec![node], vec![], 0);
+        let sub_workflow: SubWorkflow = serde_json::from_value(json!({
+            "workflow_id": 1,
+            "parameter_overrides": {"0": {"psid": 2}}
+        }))
+        .unwrap();
+        sub_workflow.apply_overrides(&mut workflow).unwrap();
+        assert_eq!(workflow.nodes[0].parameters["psid"], json!(2));
+
+        let sub_workflow = SubWorkflow {
+            parameter_overrides: [(1, HashMap::new())].into_iter().collect(),
+            ..sub_workflow
+        };
+        assert!(sub_workflow.apply_overrides(&mut workflow).is_err());
+    }
+}
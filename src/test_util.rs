@@ -0,0 +1,54 @@
+// Fixtures shared by the tests of several modules
+use crate::data_cell::DataCell;
+use crate::data_file::DataFile;
+use crate::data_header::{ColumnHeader, ColumnHeaderType, DataHeader};
+use serde_json::json;
+
+pub fn header(columns: &[(&str, ColumnHeaderType)]) -> DataHeader {
+    DataHeader {
+        columns: columns
+            .iter()
+            .map(|(name, kind)| ColumnHeader {
+                name: name.to_string(),
+                kind: kind.to_owned(),
+            })
+            .collect(),
+    }
+}
+
+// Writes a data file, and returns its UUID; the caller removes it
+pub fn write_file(header: &DataHeader, rows: Vec<Vec<DataCell>>) -> String {
+    let mut df = DataFile::new_output_file().unwrap();
+    df.write_json_row(&json!(header)).unwrap();
+    for row in rows {
+        df.write_json_row(&json!(row)).unwrap();
+    }
+    df.finish().unwrap().uuid
+}
+
+// A data file with only PlainText columns
+pub fn write_test_file(names: &[&str], rows: Vec<Vec<DataCell>>) -> String {
+    let columns: Vec<(&str, ColumnHeaderType)> = names
+        .iter()
+        .map(|name| (*name, ColumnHeaderType::PlainText))
+        .collect();
+    write_file(&header(&columns), rows)
+}
+
+pub fn text_row(values: &[&str]) -> Vec<DataCell> {
+    values
+        .iter()
+        .map(|s| DataCell::PlainText(s.to_string()))
+        .collect()
+}
+
+pub fn load_file(uuid: &str) -> DataFile {
+    let mut df = DataFile::default();
+    df.open_input_file(uuid).unwrap();
+    df.load().unwrap();
+    df
+}
+
+pub fn load_rows(uuid: &str) -> Vec<Vec<DataCell>> {
+    std::mem::take(&mut load_file(uuid).rows)
+}
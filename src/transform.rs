@@ -0,0 +1,1569 @@
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use mediawiki::api::Api;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::iter::Peekable;
+use std::vec::IntoIter;
+use tracing::warn;
+
+use crate::adapter::SparqlAdapter;
+use crate::data_cell::DataCell;
+use crate::data_file::{DataFile, DataFileDetails};
+use crate::data_header::{ColumnHeader, ColumnHeaderType, DataHeader};
+use crate::wiki_page::WikiPage;
+use crate::APP;
+
+// Rewrites a WikiPage column to the associated talk page (or subject page, for talk pages)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToggleTalk {
+    pub key: String,
+    pub target: Option<String>, // New column name; the key column is replaced if not set
+}
+
+impl ToggleTalk {
+    // Even namespaces are subject namespaces, the following odd one is their talk namespace
+    fn paired_namespace_id(ns_id: i64) -> Option<i64> {
+        match ns_id {
+            i64::MIN..=-1 => None, // Special, Media
+            ns_id if ns_id % 2 == 0 => Some(ns_id + 1),
+            ns_id => Some(ns_id - 1),
+        }
+    }
+
+    fn title_without_prefix(wp: &WikiPage) -> Option<String> {
+        if let Some(title) = &wp.title {
+            if !title.is_empty() {
+                return Some(title.to_owned());
+            }
+        }
+        let prefixed_title = wp.prefixed_title.as_ref()?;
+        match wp.ns_id {
+            Some(0) => Some(prefixed_title.to_owned()),
+            _ => prefixed_title
+                .split_once(':')
+                .map(|(_prefix, title)| title.to_string()),
+        }
+    }
+
+    async fn toggle(&self, cell: &DataCell, column_wiki: &Option<String>) -> DataCell {
+        let wp = match cell {
+            DataCell::WikiPage(wp) => wp,
+            _ => return DataCell::Blank,
+        };
+        let wiki = match wp.wiki.as_ref().or(column_wiki.as_ref()) {
+            Some(wiki) => wiki.to_owned(),
+            None => return DataCell::Blank,
+        };
+        let ns_id = match wp.ns_id.and_then(Self::paired_namespace_id) {
+            Some(ns_id) => ns_id,
+            None => return DataCell::Blank,
+        };
+        let title = match Self::title_without_prefix(wp) {
+            Some(title) => title,
+            None => return DataCell::Blank,
+        };
+        // The paired namespace might not exist on this wiki
+        let ns_prefix = match APP.get_namespace_name(&wiki, ns_id).await {
+            Some(ns_prefix) => ns_prefix.replace(' ', "_"),
+            None => return DataCell::Blank,
+        };
+        let prefixed_title = if ns_prefix.is_empty() {
+            title.to_owned()
+        } else {
+            format!("{ns_prefix}:{title}")
+        };
+        DataCell::WikiPage(WikiPage {
+            title: Some(title),
+            prefixed_title: Some(prefixed_title),
+            ns_id: Some(ns_id),
+            page_id: None,
+            ns_prefix: Some(ns_prefix),
+            wiki: Some(wiki),
+        })
+    }
+
+    pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
+        let mut df_in = DataFile::default();
+        let mut df_out = DataFile::new_output_file()?;
+        df_in.open_input_file(uuid)?;
+        df_in.load_header()?;
+        let col_num = df_in
+            .header()
+            .get_col_num(&self.key)
+            .ok_or_else(|| anyhow!("File {uuid} does not have a header column {}", self.key))?;
+        let column_kind = df_in.header().columns[col_num].kind.to_owned();
+        let column_wiki = match &column_kind {
+            ColumnHeaderType::WikiPage(wp) => wp.wiki.to_owned(),
+            _ => return Err(anyhow!("Not a wiki column for {}", self.key)),
+        };
+
+        let mut header = df_in.header().to_owned();
+        if let Some(target) = &self.target {
+            header.columns.push(ColumnHeader {
+                name: target.to_owned(),
+                kind: column_kind,
+            });
+        }
+        df_out.write_json_row(&json! {header})?; // Output new header
+
+        while let Some(row) = df_in.read_row() {
+            let mut row: Vec<DataCell> = serde_json::from_str(&row)?;
+            let new_cell = match row.get(col_num) {
+                Some(cell) => self.toggle(cell, &column_wiki).await,
+                None => DataCell::Blank,
+            };
+            match &self.target {
+                Some(_) => row.push(new_cell),
+                None => {
+                    if let Some(cell) = row.get_mut(col_num) {
+                        *cell = new_cell;
+                    }
+                }
+            }
+            df_out.write_json_row(&json! {row})?; // Output data row
+        }
+        df_out.finish()
+    }
+}
+
+// ____________________________________________________________________________________
+
+// Adds an Int column with the row number, in input file order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RowNumber {
+    pub name: String,
+    pub start: i64,    // Number of the first row, usually 0 or 1
+    pub prepend: bool, // Add as the first column, otherwise as the last one
+}
+
+impl RowNumber {
+    pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
+        let mut df_in = DataFile::default();
+        let mut df_out = DataFile::new_output_file()?;
+        df_in.open_input_file(uuid)?;
+        df_in.load_header()?;
+        if df_in.header().get_col_num(&self.name).is_some() {
+            return Err(anyhow!("File {uuid} already has a column {}", self.name));
+        }
+
+        let mut header = df_in.header().to_owned();
+        let column = ColumnHeader {
+            name: self.name.to_owned(),
+            kind: ColumnHeaderType::Int,
+        };
+        if self.prepend {
+            header.columns.insert(0, column);
+        } else {
+            header.columns.push(column);
+        }
+        df_out.write_json_row(&json! {header})?; // Output new header
+
+        let mut row_number = self.start;
+        while let Some(row) = df_in.read_row() {
+            let mut row: Vec<DataCell> = serde_json::from_str(&row)?;
+            if self.prepend {
+                row.insert(0, DataCell::Int(row_number));
+            } else {
+                row.push(DataCell::Int(row_number));
+            }
+            df_out.write_json_row(&json! {row})?; // Output data row
+            row_number += 1;
+        }
+        df_out.finish()
+    }
+}
+
+// ____________________________________________________________________________________
+
+// Parses a text column into coordinates; unparseable cells become Blank
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParseCoordinate {
+    pub key: String,
+    pub target: Option<String>, // New column name; the key column is replaced if not set
+}
+
+impl ParseCoordinate {
+    fn parse(cell: &DataCell) -> DataCell {
+        let text = match cell {
+            DataCell::PlainText(text) => text,
+            DataCell::Coordinate { .. } => return cell.to_owned(),
+            _ => return DataCell::Blank,
+        };
+        match DataCell::parse_coordinate(text) {
+            Some((lat, lon)) => DataCell::Coordinate { lat, lon },
+            None => DataCell::Blank,
+        }
+    }
+
+    pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
+        let mut df_in = DataFile::default();
+        let mut df_out = DataFile::new_output_file()?;
+        df_in.open_input_file(uuid)?;
+        df_in.load_header()?;
+        let col_num = df_in
+            .header()
+            .get_col_num(&self.key)
+            .ok_or_else(|| anyhow!("File {uuid} does not have a header column {}", self.key))?;
+
+        let mut header = df_in.header().to_owned();
+        match &self.target {
+            Some(target) => header.columns.push(ColumnHeader {
+                name: target.to_owned(),
+                kind: ColumnHeaderType::Coordinate,
+            }),
+            None => header.columns[col_num].kind = ColumnHeaderType::Coordinate,
+        }
+        df_out.write_json_row(&json! {header})?; // Output new header
+
+        while let Some(row) = df_in.read_row() {
+            let mut row: Vec<DataCell> = serde_json::from_str(&row)?;
+            let new_cell = match row.get(col_num) {
+                Some(cell) => Self::parse(cell),
+                None => DataCell::Blank,
+            };
+            match &self.target {
+                Some(_) => row.push(new_cell),
+                None => {
+                    if let Some(cell) = row.get_mut(col_num) {
+                        *cell = new_cell;
+                    }
+                }
+            }
+            df_out.write_json_row(&json! {row})?; // Output data row
+        }
+        df_out.finish()
+    }
+}
+
+// ____________________________________________________________________________________
+
+const CONSTRAINT_TYPE_FORMAT: &str = "Q21502404";
+const CONSTRAINT_TYPE_ONE_OF: &str = "Q21510859";
+
+// Format and one-of constraints of a Wikidata property
+#[derive(Debug, Clone, Default)]
+struct PropertyConstraints {
+    formats: Vec<Regex>,
+    one_of: Option<HashSet<String>>,
+}
+
+impl PropertyConstraints {
+    // `claims` are the claims of the property entity, as returned by wbgetentities
+    fn from_claims(claims: &Value) -> Self {
+        let mut ret = Self::default();
+        let statements = match claims["P2302"].as_array() {
+            Some(statements) => statements,
+            None => return ret, // No constraints
+        };
+        for statement in statements {
+            let qualifiers = &statement["qualifiers"];
+            match statement["mainsnak"]["datavalue"]["value"]["id"].as_str() {
+                Some(CONSTRAINT_TYPE_FORMAT) => {
+                    for snak in qualifiers["P1793"].as_array().into_iter().flatten() {
+                        let format = match snak["datavalue"]["value"].as_str() {
+                            Some(format) => format,
+                            None => continue,
+                        };
+                        // Constraints are meant to match the whole value
+                        match Regex::new(&format!("^(?:{format})$")) {
+                            Ok(re) => ret.formats.push(re),
+                            Err(e) => warn!(format, error = %e, "Unsupported format constraint"),
+                        }
+                    }
+                }
+                Some(CONSTRAINT_TYPE_ONE_OF) => {
+                    let values = qualifiers["P2305"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|snak| snak["datavalue"]["value"]["id"].as_str())
+                        .map(|id| id.to_string());
+                    ret.one_of.get_or_insert_with(HashSet::new).extend(values);
+                }
+                _ => {} // Other constraint types are not checked
+            }
+        }
+        ret
+    }
+
+    fn is_ok(&self, value: &str) -> bool {
+        self.formats.iter().all(|re| re.is_match(value))
+            && self
+                .one_of
+                .as_ref()
+                .map(|one_of| one_of.contains(value))
+                .unwrap_or(true)
+    }
+}
+
+// Appends a Boolean column telling if the value column satisfies the constraints of a Wikidata property
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConstraintCheck {
+    pub key: String,
+    pub property: String,
+    pub target: String,
+}
+
+impl ConstraintCheck {
+    async fn load_constraints(&self) -> Result<PropertyConstraints> {
+        let api = APP.get_api_for_wiki("wikidatawiki").await?;
+        let params = api.params_into(&[
+            ("action", "wbgetentities"),
+            ("ids", &self.property),
+            ("props", "claims"),
+        ]);
+        let j = api
+            .get_query_api_json(&params)
+            .await
+            .map_err(|e| anyhow!("Could not load constraints for {}: {e}", self.property))?;
+        let claims = &j["entities"][&self.property]["claims"];
+        if !claims.is_object() {
+            return Err(anyhow!("No property {} on Wikidata", self.property));
+        }
+        Ok(PropertyConstraints::from_claims(claims))
+    }
+
+    fn cell_value(cell: &DataCell) -> Option<String> {
+        match cell {
+            DataCell::PlainText(s) => Some(s.to_owned()),
+            DataCell::WikiPage(wp) => wp.prefixed_title.to_owned(),
+            DataCell::Int(i) => Some(format!("{i}")),
+            DataCell::Float(f) => Some(format!("{f}")),
+            _ => None,
+        }
+    }
+
+    pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
+        if !RE_PROPERTY.is_match(&self.property) {
+            return Err(anyhow!("Not a Wikidata property: '{}'", self.property));
+        }
+        let mut df_in = DataFile::default();
+        df_in.open_input_file(uuid)?;
+        df_in.load_header()?;
+        let col_num = df_in
+            .header()
+            .get_col_num(&self.key)
+            .ok_or_else(|| anyhow!("File {uuid} does not have a header column {}", self.key))?;
+        // Loaded once per node run
+        let constraints = self.load_constraints().await?;
+
+        let mut df_out = DataFile::new_output_file()?;
+        let mut header = df_in.header().to_owned();
+        header.columns.push(ColumnHeader {
+            name: self.target.to_owned(),
+            kind: ColumnHeaderType::Boolean,
+        });
+        df_out.write_json_row(&json! {header})?; // Output new header
+
+        while let Some(row) = df_in.read_row() {
+            let mut row: Vec<DataCell> = serde_json::from_str(&row)?;
+            let new_cell = match row.get(col_num).and_then(Self::cell_value) {
+                Some(value) => DataCell::Boolean(constraints.is_ok(&value)),
+                None => DataCell::Blank,
+            };
+            row.push(new_cell);
+            df_out.write_json_row(&json! {row})?; // Output data row
+        }
+        df_out.finish()
+    }
+}
+
+// ____________________________________________________________________________________
+
+const EXTERNAL_ID_BATCH_SIZE: usize = 200;
+
+lazy_static! {
+    static ref RE_PROPERTY: Regex = Regex::new(r"^P\d+$").expect("RegEx fail");
+}
+
+// Appends a Wikidata item column for an external identifier column, via a property like P214 (VIAF)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalIdResolve {
+    pub key: String,
+    pub property: String,
+    pub target: String,
+}
+
+impl ExternalIdResolve {
+    fn cell_value(cell: &DataCell) -> Option<String> {
+        match cell {
+            DataCell::PlainText(s) => Some(s.trim().to_string()).filter(|s| !s.is_empty()),
+            DataCell::Int(i) => Some(format!("{i}")),
+            _ => None,
+        }
+    }
+
+    fn build_query(&self, ids: &[String]) -> String {
+        let values: Vec<String> = ids
+            .iter()
+            .map(|id| SparqlAdapter::sparql_string_literal(id))
+            .collect();
+        format!(
+            "SELECT ?id ?item {{ VALUES ?id {{ {} }} ?item wdt:{} ?id }}",
+            values.join(" "),
+            self.property
+        )
+    }
+
+    async fn resolve(&self, ids: Vec<String>) -> Result<HashMap<String, String>> {
+        let mut ret = HashMap::new();
+        for batch in ids.chunks(EXTERNAL_ID_BATCH_SIZE) {
+            let sparql = self.build_query(batch);
+            let mut reader = SparqlAdapter::default().load_sparql_csv(&sparql).await?;
+            for record in reader.records() {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(_) => continue, // Ignore row
+                };
+                let (id, item) = match (record.get(0), record.get(1)) {
+                    (Some(id), Some(item)) => (id, item),
+                    _ => continue,
+                };
+                if let Some(qid) = item.rsplit('/').next() {
+                    // If several items have the same ID, keep the first one
+                    ret.entry(id.to_string()).or_insert_with(|| qid.to_string());
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
+        if !RE_PROPERTY.is_match(&self.property) {
+            return Err(anyhow!("Not a Wikidata property: '{}'", self.property));
+        }
+        let mut df_in = DataFile::default();
+        df_in.open_input_file(uuid)?;
+        df_in.load_header()?;
+        let col_num = df_in
+            .header()
+            .get_col_num(&self.key)
+            .ok_or_else(|| anyhow!("File {uuid} does not have a header column {}", self.key))?;
+        df_in.load()?;
+
+        let mut seen = HashSet::new();
+        let ids: Vec<String> = df_in
+            .rows
+            .iter()
+            .filter_map(|row| row.get(col_num).and_then(Self::cell_value))
+            .filter(|id| seen.insert(id.to_owned()))
+            .collect();
+        let id2qid = self.resolve(ids).await?;
+
+        let mut df_out = DataFile::new_output_file()?;
+        let mut header = df_in.header().to_owned();
+        header.columns.push(ColumnHeader {
+            name: self.target.to_owned(),
+            kind: ColumnHeaderType::WikiPage(WikiPage::new_wikidata_item()),
+        });
+        df_out.write_json_row(&json! {header})?; // Output new header
+        for mut row in df_in.rows.drain(..) {
+            let qid = row
+                .get(col_num)
+                .and_then(Self::cell_value)
+                .and_then(|id| id2qid.get(&id));
+            let new_cell = match qid {
+                Some(qid) => {
+                    let mut wp = WikiPage::new_wikidata_item();
+                    wp.title = Some(qid.to_owned());
+                    wp.prefixed_title = Some(qid.to_owned());
+                    DataCell::WikiPage(wp)
+                }
+                None => DataCell::Blank,
+            };
+            row.push(new_cell);
+            df_out.write_json_row(&json! {row})?; // Output data row
+        }
+        df_out.finish()
+    }
+}
+
+// ____________________________________________________________________________________
+
+const SITELINK_BATCH_SIZE: usize = 50; // Maximum titles/IDs per wbgetentities request
+
+// Rewrites a WikiPage column to each page's counterpart on another wiki, via Wikidata sitelinks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SitelinkResolve {
+    pub key: String,
+    pub wiki: String, // Target wiki, eg "enwiki" or "wikidatawiki"
+}
+
+impl SitelinkResolve {
+    // (wiki, title) of the page in a cell; titles are compared with spaces, as in sitelinks
+    fn source_page(cell: &DataCell, column_wiki: &Option<String>) -> Option<(String, String)> {
+        let wp = match cell {
+            DataCell::WikiPage(wp) => wp,
+            _ => return None,
+        };
+        let wiki = wp.wiki.as_ref().or(column_wiki.as_ref())?;
+        let title = wp.prefixed_title.as_ref()?.replace('_', " ");
+        Some((wiki.to_owned(), title))
+    }
+
+    // Source title (or item ID, for Wikidata) => title (or item ID) on the target wiki
+    fn counterparts_from_entities(&self, j: &Value, source_wiki: &str) -> HashMap<String, String> {
+        let mut ret = HashMap::new();
+        let entities = match j["entities"].as_object() {
+            Some(entities) => entities,
+            None => return ret,
+        };
+        let sitelink = |entity: &Value, id: &str, wiki: &str| -> Option<String> {
+            if wiki == "wikidatawiki" {
+                Some(id.to_string())
+            } else {
+                entity["sitelinks"][wiki]["title"]
+                    .as_str()
+                    .map(|s| s.to_string())
+            }
+        };
+        for (id, entity) in entities {
+            if entity.get("missing").is_some() {
+                continue;
+            }
+            if let (Some(source), Some(target)) = (
+                sitelink(entity, id, source_wiki),
+                sitelink(entity, id, &self.wiki),
+            ) {
+                let _ = ret.insert(source, target);
+            }
+        }
+        ret
+    }
+
+    async fn load_counterparts(
+        &self,
+        api: &Api,
+        source_wiki: &str,
+        titles: &[String],
+    ) -> Result<HashMap<String, String>> {
+        let mut ret = HashMap::new();
+        let sitefilter = format!("{source_wiki}|{}", self.wiki);
+        for (batch_num, batch) in titles.chunks(SITELINK_BATCH_SIZE).enumerate() {
+            if batch_num > 0 {
+                APP.api_delay().await;
+            }
+            let batch = batch.join("|");
+            let mut params = vec![
+                ("action", "wbgetentities"),
+                ("props", "sitelinks"),
+                ("sitefilter", sitefilter.as_str()),
+            ];
+            if source_wiki == "wikidatawiki" {
+                params.push(("ids", &batch));
+            } else {
+                params.push(("sites", source_wiki));
+                params.push(("titles", &batch));
+            }
+            let params = api.params_into(&params);
+            let j = api
+                .get_query_api_json(&params)
+                .await
+                .map_err(|e| anyhow!("Could not load sitelinks for {source_wiki}: {e}"))?;
+            ret.extend(self.counterparts_from_entities(&j, source_wiki));
+        }
+        Ok(ret)
+    }
+
+    async fn target_cell(&self, title: &str) -> DataCell {
+        let mut wp = if self.wiki == "wikidatawiki" {
+            WikiPage::new_wikidata_item()
+        } else {
+            WikiPage {
+                wiki: Some(self.wiki.to_owned()),
+                ..Default::default()
+            }
+        };
+        wp.prefixed_title = Some(title.replace(' ', "_"));
+        wp.fill_missing().await;
+        DataCell::WikiPage(wp)
+    }
+
+    pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
+        let mut df_in = DataFile::default();
+        df_in.open_input_file(uuid)?;
+        df_in.load()?;
+        let col_num = df_in
+            .header()
+            .get_col_num(&self.key)
+            .ok_or_else(|| anyhow!("File {uuid} does not have a header column {}", self.key))?;
+        let column_wiki = match &df_in.header().columns[col_num].kind {
+            ColumnHeaderType::WikiPage(wp) => wp.wiki.to_owned(),
+            _ => return Err(anyhow!("Not a wiki column for {}", self.key)),
+        };
+
+        // Titles by source wiki, each only once
+        let mut titles: HashMap<String, Vec<String>> = HashMap::new();
+        let mut seen = HashSet::new();
+        for row in &df_in.rows {
+            if let Some(page) = row
+                .get(col_num)
+                .and_then(|cell| Self::source_page(cell, &column_wiki))
+            {
+                if seen.insert(page.to_owned()) {
+                    titles.entry(page.0).or_default().push(page.1);
+                }
+            }
+        }
+        let api = APP.get_api_for_wiki("wikidatawiki").await?;
+        let mut counterparts: HashMap<(String, String), String> = HashMap::new();
+        for (source_wiki, titles) in titles {
+            for (source, target) in self.load_counterparts(&api, &source_wiki, &titles).await? {
+                let _ = counterparts.insert((source_wiki.to_owned(), source), target);
+            }
+        }
+
+        let mut df_out = DataFile::new_output_file()?;
+        let mut header = df_in.header().to_owned();
+        header.columns[col_num].kind = ColumnHeaderType::WikiPage(WikiPage {
+            wiki: Some(self.wiki.to_owned()),
+            ..Default::default()
+        });
+        df_out.write_json_row(&json! {header})?; // Output new header
+        for mut row in df_in.rows.drain(..) {
+            let target = row
+                .get(col_num)
+                .and_then(|cell| Self::source_page(cell, &column_wiki))
+                .and_then(|page| counterparts.get(&page));
+            let new_cell = match target {
+                Some(title) => self.target_cell(title).await,
+                None => DataCell::Blank,
+            };
+            if let Some(cell) = row.get_mut(col_num) {
+                *cell = new_cell;
+            }
+            df_out.write_json_row(&json! {row})?; // Output data row
+        }
+        df_out.finish()
+    }
+}
+
+// ____________________________________________________________________________________
+
+const PAGE_INFO_BATCH_SIZE: usize = 50; // Maximum titles per query API request
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct PageInfoValues {
+    length: Option<i64>,
+    last_edit: Option<String>, // ISO 8601, so it sorts chronologically
+    is_redirect: bool,
+}
+
+// Appends the length, last edit time and redirect flag of the pages in a WikiPage column, as
+// `<key>_length`, `<key>_last_edit` and `<key>_redirect`; missing pages get Blank cells
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PageInfo {
+    pub key: String,
+}
+
+impl PageInfo {
+    fn columns(&self) -> Vec<ColumnHeader> {
+        [
+            ("length", ColumnHeaderType::Int),
+            ("last_edit", ColumnHeaderType::PlainText),
+            ("redirect", ColumnHeaderType::Boolean),
+        ]
+        .into_iter()
+        .map(|(name, kind)| ColumnHeader {
+            name: format!("{}_{name}", self.key),
+            kind,
+        })
+        .collect()
+    }
+
+    // Requested title => info; normalized titles are looked up under both names, redirects are
+    // not followed
+    fn info_from_query(j: &Value) -> HashMap<String, PageInfoValues> {
+        let mut ret: HashMap<String, PageInfoValues> = HashMap::new();
+        let pages = match j["query"]["pages"].as_object() {
+            Some(pages) => pages,
+            None => return ret,
+        };
+        for page in pages.values() {
+            if page.get("missing").is_some() || page.get("invalid").is_some() {
+                continue;
+            }
+            if let Some(title) = page["title"].as_str() {
+                let values = PageInfoValues {
+                    length: page["length"].as_i64(),
+                    last_edit: page["revisions"][0]["timestamp"]
+                        .as_str()
+                        .map(|s| s.to_string()),
+                    is_redirect: page.get("redirect").is_some(),
+                };
+                let _ = ret.insert(title.to_string(), values);
+            }
+        }
+        for normalized in j["query"]["normalized"].as_array().into_iter().flatten() {
+            if let (Some(from), Some(to)) = (normalized["from"].as_str(), normalized["to"].as_str())
+            {
+                if let Some(values) = ret.get(to).cloned() {
+                    let _ = ret.insert(from.to_string(), values);
+                }
+            }
+        }
+        ret
+    }
+
+    async fn load_info(wiki: &str, titles: &[String]) -> Result<HashMap<String, PageInfoValues>> {
+        let api = APP.get_api_for_wiki(wiki).await?;
+        let mut ret = HashMap::new();
+        for (batch_num, batch) in titles.chunks(PAGE_INFO_BATCH_SIZE).enumerate() {
+            if batch_num > 0 {
+                APP.api_delay().await;
+            }
+            let batch = batch.join("|");
+            let params = api.params_into(&[
+                ("action", "query"),
+                ("prop", "info|revisions"),
+                ("rvprop", "timestamp"),
+                ("titles", &batch),
+            ]);
+            let j = api
+                .get_query_api_json(&params)
+                .await
+                .map_err(|e| anyhow!("Could not load page info for {wiki}: {e}"))?;
+            ret.extend(Self::info_from_query(&j));
+        }
+        Ok(ret)
+    }
+
+    pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
+        let mut df_in = DataFile::default();
+        df_in.open_input_file(uuid)?;
+        df_in.load()?;
+        let col_num = df_in
+            .header()
+            .get_col_num(&self.key)
+            .ok_or_else(|| anyhow!("File {uuid} does not have a header column {}", self.key))?;
+        let column_wiki = match &df_in.header().columns[col_num].kind {
+            ColumnHeaderType::WikiPage(wp) => wp.wiki.to_owned(),
+            _ => return Err(anyhow!("Not a wiki column for {}", self.key)),
+        };
+        let columns = self.columns();
+        for column in &columns {
+            if df_in.header().get_col_num(&column.name).is_some() {
+                return Err(anyhow!("File {uuid} already has a column {}", column.name));
+            }
+        }
+
+        // Titles by wiki, each only once
+        let mut titles: HashMap<String, Vec<String>> = HashMap::new();
+        let mut seen = HashSet::new();
+        for row in &df_in.rows {
+            if let Some(page) = row
+                .get(col_num)
+                .and_then(|cell| SitelinkResolve::source_page(cell, &column_wiki))
+            {
+                if seen.insert(page.to_owned()) {
+                    titles.entry(page.0).or_default().push(page.1);
+                }
+            }
+        }
+        let mut info: HashMap<(String, String), PageInfoValues> = HashMap::new();
+        for (wiki, titles) in titles {
+            for (title, values) in Self::load_info(&wiki, &titles).await? {
+                let _ = info.insert((wiki.to_owned(), title), values);
+            }
+        }
+
+        let mut df_out = DataFile::new_output_file()?;
+        let mut header = df_in.header().to_owned();
+        header.columns.extend(columns);
+        df_out.write_json_row(&json! {header})?; // Output new header
+        for mut row in df_in.rows.drain(..) {
+            let values = row
+                .get(col_num)
+                .and_then(|cell| SitelinkResolve::source_page(cell, &column_wiki))
+                .and_then(|page| info.get(&page));
+            match values {
+                Some(values) => row.extend([
+                    values.length.map(DataCell::Int).unwrap_or(DataCell::Blank),
+                    values
+                        .last_edit
+                        .to_owned()
+                        .map(DataCell::PlainText)
+                        .unwrap_or(DataCell::Blank),
+                    DataCell::Boolean(values.is_redirect),
+                ]),
+                None => row.extend([DataCell::Blank, DataCell::Blank, DataCell::Blank]),
+            }
+            df_out.write_json_row(&json! {row})?; // Output data row
+        }
+        df_out.finish()
+    }
+}
+
+// ____________________________________________________________________________________
+
+// Appends the label of the Wikidata items in a WikiPage column, in the first language of the
+// fallback chain that has one; items without a label get a Blank cell
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WikidataLabels {
+    pub key: String,
+    pub languages: Vec<String>, // Fallback chain, eg ["de", "en"]
+    pub name: String,           // Of the new column
+}
+
+impl WikidataLabels {
+    fn item_id(cell: &DataCell) -> Option<String> {
+        let wp = match cell {
+            DataCell::WikiPage(wp) => wp,
+            _ => return None,
+        };
+        let title = wp.prefixed_title.as_ref().or(wp.title.as_ref())?;
+        // MediaInfo entities live on Commons, not Wikidata
+        WikiPage::entity_id_from_title(title).filter(|id| !id.starts_with('M'))
+    }
+
+    // Entity ID => label
+    fn labels_from_entities(&self, j: &Value) -> HashMap<String, String> {
+        let mut ret = HashMap::new();
+        let entities = match j["entities"].as_object() {
+            Some(entities) => entities,
+            None => return ret,
+        };
+        for (id, entity) in entities {
+            let label = self
+                .languages
+                .iter()
+                .find_map(|language| entity["labels"][language]["value"].as_str());
+            if let Some(label) = label {
+                let _ = ret.insert(id.to_owned(), label.to_string());
+            }
+        }
+        ret
+    }
+
+    async fn load_labels(&self, ids: &[String]) -> Result<HashMap<String, String>> {
+        let api = APP.get_api_for_wiki("wikidatawiki").await?;
+        let languages = self.languages.join("|");
+        let mut ret = HashMap::new();
+        for (batch_num, batch) in ids.chunks(SITELINK_BATCH_SIZE).enumerate() {
+            if batch_num > 0 {
+                APP.api_delay().await;
+            }
+            let batch = batch.join("|");
+            let params = api.params_into(&[
+                ("action", "wbgetentities"),
+                ("props", "labels"),
+                ("languages", &languages),
+                ("ids", &batch),
+            ]);
+            let j = api
+                .get_query_api_json(&params)
+                .await
+                .map_err(|e| anyhow!("Could not load Wikidata labels: {e}"))?;
+            ret.extend(self.labels_from_entities(&j));
+        }
+        Ok(ret)
+    }
+
+    pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
+        if self.languages.is_empty() {
+            return Err(anyhow!("No label languages given"));
+        }
+        let mut df_in = DataFile::default();
+        df_in.open_input_file(uuid)?;
+        df_in.load()?;
+        let col_num = df_in
+            .header()
+            .get_col_num(&self.key)
+            .ok_or_else(|| anyhow!("File {uuid} does not have a header column {}", self.key))?;
+        if df_in.header().get_col_num(&self.name).is_some() {
+            return Err(anyhow!("File {uuid} already has a column {}", self.name));
+        }
+
+        let mut seen = HashSet::new();
+        let ids: Vec<String> = df_in
+            .rows
+            .iter()
+            .filter_map(|row| row.get(col_num).and_then(Self::item_id))
+            .filter(|id| seen.insert(id.to_owned()))
+            .collect();
+        let labels = self.load_labels(&ids).await?;
+
+        let mut df_out = DataFile::new_output_file()?;
+        let mut header = df_in.header().to_owned();
+        header.columns.push(ColumnHeader {
+            name: self.name.to_owned(),
+            kind: ColumnHeaderType::PlainText,
+        });
+        df_out.write_json_row(&json! {header})?; // Output new header
+        for mut row in df_in.rows.drain(..) {
+            let label = row
+                .get(col_num)
+                .and_then(Self::item_id)
+                .and_then(|id| labels.get(&id));
+            row.push(match label {
+                Some(label) => DataCell::PlainText(label.to_owned()),
+                None => DataCell::Blank,
+            });
+            df_out.write_json_row(&json! {row})?; // Output data row
+        }
+        df_out.finish()
+    }
+}
+
+// ____________________________________________________________________________________
+
+#[derive(Debug, Clone, PartialEq)]
+enum ComputeToken {
+    Number(DataCell),
+    Text(String),
+    Column(String),
+    Operator(char),
+    Open,
+    Close,
+}
+
+// Limits for Compute expressions, as parsing and evaluating recurse into them
+const COMPUTE_MAX_EXPRESSION_LENGTH: usize = 1000;
+const COMPUTE_MAX_NESTING: usize = 32; // Parentheses and negations
+
+#[derive(Debug, Clone, PartialEq)]
+enum ComputeExpr {
+    Value(DataCell),
+    Column(String),
+    Negate(Box<ComputeExpr>),
+    Binary(char, Box<ComputeExpr>, Box<ComputeExpr>),
+}
+
+// Appends a column computed from the other columns of each row. The expression grammar is
+//   expression := term (("+" | "-") term)*
+//   term       := factor (("*" | "/") factor)*
+//   factor     := "-" factor | "(" expression ")" | number | string | column
+//   string     := "..." or '...', without escapes
+//   column     := letters, digits, "_" and "." like `page_len`, or any name in `backticks`
+// Arithmetic works on Int and Float cells, and "/" always gives a Float. "+" with a text operand
+// concatenates, numbers included. Division by zero and any other mismatch, like a Blank cell,
+// give a Blank cell. Long or deeply nested expressions are rejected, see COMPUTE_MAX_*.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Compute {
+    pub name: String,
+    pub expression: String,
+}
+
+impl Compute {
+    fn tokenize(expression: &str) -> Result<Vec<ComputeToken>> {
+        let mut ret = vec![];
+        let mut chars = expression.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                c if c.is_whitespace() => {}
+                '+' | '-' | '*' | '/' => ret.push(ComputeToken::Operator(c)),
+                '(' => ret.push(ComputeToken::Open),
+                ')' => ret.push(ComputeToken::Close),
+                '"' | '\'' | '`' => {
+                    let mut s = String::new();
+                    loop {
+                        match chars.next() {
+                            Some(x) if x == c => break,
+                            Some(x) => s.push(x),
+                            None => {
+                                return Err(anyhow!(
+                                    "Unterminated {c} in expression '{expression}'"
+                                ))
+                            }
+                        }
+                    }
+                    ret.push(match c {
+                        '`' => ComputeToken::Column(s),
+                        _ => ComputeToken::Text(s),
+                    });
+                }
+                c if c.is_alphanumeric() || c == '_' => {
+                    let mut s = c.to_string();
+                    while let Some(&x) = chars.peek() {
+                        if !x.is_alphanumeric() && x != '_' && x != '.' {
+                            break;
+                        }
+                        s.push(x);
+                        let _ = chars.next();
+                    }
+                    let number = if !c.is_ascii_digit() {
+                        None
+                    } else if s.contains('.') {
+                        s.parse::<f64>().ok().map(DataCell::Float)
+                    } else {
+                        s.parse::<i64>().ok().map(DataCell::Int)
+                    };
+                    ret.push(match number {
+                        Some(cell) => ComputeToken::Number(cell),
+                        None if c.is_ascii_digit() => {
+                            return Err(anyhow!("Bad number {s} in expression '{expression}'"))
+                        }
+                        None => ComputeToken::Column(s),
+                    });
+                }
+                other => return Err(anyhow!("Unexpected '{other}' in expression '{expression}'")),
+            }
+        }
+        Ok(ret)
+    }
+
+    fn parse(expression: &str) -> Result<ComputeExpr> {
+        if expression.len() > COMPUTE_MAX_EXPRESSION_LENGTH {
+            return Err(anyhow!(
+                "Expression is longer than {COMPUTE_MAX_EXPRESSION_LENGTH} bytes"
+            ));
+        }
+        let mut tokens = Self::tokenize(expression)?.into_iter().peekable();
+        let ret = Self::parse_expression(&mut tokens, 0)?;
+        match tokens.next() {
+            Some(token) => Err(anyhow!("Unexpected {token:?} in expression '{expression}'")),
+            None => Ok(ret),
+        }
+    }
+
+    // `depth` is the number of enclosing parentheses and negations
+    fn parse_expression(
+        tokens: &mut Peekable<IntoIter<ComputeToken>>,
+        depth: usize,
+    ) -> Result<ComputeExpr> {
+        let mut ret = Self::parse_term(tokens, depth)?;
+        while let Some(ComputeToken::Operator(op @ ('+' | '-'))) = tokens.peek().cloned() {
+            let _ = tokens.next();
+            let term = Self::parse_term(tokens, depth)?;
+            ret = ComputeExpr::Binary(op, Box::new(ret), Box::new(term));
+        }
+        Ok(ret)
+    }
+
+    fn parse_term(
+        tokens: &mut Peekable<IntoIter<ComputeToken>>,
+        depth: usize,
+    ) -> Result<ComputeExpr> {
+        let mut ret = Self::parse_factor(tokens, depth)?;
+        while let Some(ComputeToken::Operator(op @ ('*' | '/'))) = tokens.peek().cloned() {
+            let _ = tokens.next();
+            let factor = Self::parse_factor(tokens, depth)?;
+            ret = ComputeExpr::Binary(op, Box::new(ret), Box::new(factor));
+        }
+        Ok(ret)
+    }
+
+    fn parse_factor(
+        tokens: &mut Peekable<IntoIter<ComputeToken>>,
+        depth: usize,
+    ) -> Result<ComputeExpr> {
+        if depth > COMPUTE_MAX_NESTING {
+            return Err(anyhow!(
+                "Expression is nested deeper than {COMPUTE_MAX_NESTING} levels"
+            ));
+        }
+        match tokens.next() {
+            Some(ComputeToken::Operator('-')) => Ok(ComputeExpr::Negate(Box::new(
+                Self::parse_factor(tokens, depth + 1)?,
+            ))),
+            Some(ComputeToken::Open) => {
+                let ret = Self::parse_expression(tokens, depth + 1)?;
+                match tokens.next() {
+                    Some(ComputeToken::Close) => Ok(ret),
+                    _ => Err(anyhow!("Missing ')' in expression")),
+                }
+            }
+            Some(ComputeToken::Number(cell)) => Ok(ComputeExpr::Value(cell)),
+            Some(ComputeToken::Text(s)) => Ok(ComputeExpr::Value(DataCell::PlainText(s))),
+            Some(ComputeToken::Column(name)) => Ok(ComputeExpr::Column(name)),
+            Some(token) => Err(anyhow!("Unexpected {token:?} in expression")),
+            None => Err(anyhow!("Unexpected end of expression")),
+        }
+    }
+
+    fn column_names(expr: &ComputeExpr) -> Vec<&str> {
+        match expr {
+            ComputeExpr::Value(_) => vec![],
+            ComputeExpr::Column(name) => vec![name.as_str()],
+            ComputeExpr::Negate(expr) => Self::column_names(expr),
+            ComputeExpr::Binary(_, a, b) => {
+                let mut ret = Self::column_names(a);
+                ret.append(&mut Self::column_names(b));
+                ret
+            }
+        }
+    }
+
+    // The column type the expression gives for well-typed rows
+    fn kind(expr: &ComputeExpr, header: &DataHeader) -> ColumnHeaderType {
+        match expr {
+            ComputeExpr::Value(DataCell::Int(_)) => ColumnHeaderType::Int,
+            ComputeExpr::Value(DataCell::Float(_)) => ColumnHeaderType::Float,
+            ComputeExpr::Value(_) => ColumnHeaderType::PlainText,
+            ComputeExpr::Column(name) => header
+                .get_col_num(name)
+                .map(|col_num| header.columns[col_num].kind.to_owned())
+                .unwrap_or(ColumnHeaderType::PlainText),
+            ComputeExpr::Negate(expr) => Self::kind(expr, header),
+            ComputeExpr::Binary(op, a, b) => {
+                match (op, Self::kind(a, header), Self::kind(b, header)) {
+                    ('/', _, _) => ColumnHeaderType::Float,
+                    (_, ColumnHeaderType::Int, ColumnHeaderType::Int) => ColumnHeaderType::Int,
+                    ('+', ColumnHeaderType::PlainText, _)
+                    | ('+', _, ColumnHeaderType::PlainText) => ColumnHeaderType::PlainText,
+                    _ => ColumnHeaderType::Float,
+                }
+            }
+        }
+    }
+
+    fn evaluate(expr: &ComputeExpr, row: &[DataCell], col_nums: &HashMap<&str, usize>) -> DataCell {
+        match expr {
+            ComputeExpr::Value(cell) => cell.to_owned(),
+            ComputeExpr::Column(name) => col_nums
+                .get(name.as_str())
+                .and_then(|col_num| row.get(*col_num))
+                .cloned()
+                .unwrap_or(DataCell::Blank),
+            ComputeExpr::Negate(expr) => match Self::evaluate(expr, row, col_nums) {
+                DataCell::Int(i) => i.checked_neg().map_or(DataCell::Blank, DataCell::Int),
+                DataCell::Float(f) => DataCell::Float(-f),
+                _ => DataCell::Blank,
+            },
+            ComputeExpr::Binary(op, a, b) => Self::apply(
+                *op,
+                Self::evaluate(a, row, col_nums),
+                Self::evaluate(b, row, col_nums),
+            ),
+        }
+    }
+
+    fn apply(op: char, a: DataCell, b: DataCell) -> DataCell {
+        let as_f64 = |cell: &DataCell| match cell {
+            DataCell::Int(i) => Some(*i as f64),
+            DataCell::Float(f) => Some(*f),
+            _ => None,
+        };
+        match (op, &a, &b) {
+            ('+', DataCell::Int(x), DataCell::Int(y)) => {
+                x.checked_add(*y).map_or(DataCell::Blank, DataCell::Int)
+            }
+            ('-', DataCell::Int(x), DataCell::Int(y)) => {
+                x.checked_sub(*y).map_or(DataCell::Blank, DataCell::Int)
+            }
+            ('*', DataCell::Int(x), DataCell::Int(y)) => {
+                x.checked_mul(*y).map_or(DataCell::Blank, DataCell::Int)
+            }
+            (
+                '+',
+                DataCell::PlainText(_),
+                DataCell::PlainText(_) | DataCell::Int(_) | DataCell::Float(_),
+            )
+            | ('+', DataCell::Int(_) | DataCell::Float(_), DataCell::PlainText(_)) => {
+                DataCell::PlainText(format!("{}{}", a.as_key(), b.as_key()))
+            }
+            _ => {
+                let result = match (as_f64(&a), as_f64(&b)) {
+                    (Some(x), Some(y)) => match op {
+                        '+' => x + y,
+                        '-' => x - y,
+                        '*' => x * y,
+                        _ => x / y,
+                    },
+                    _ => return DataCell::Blank,
+                };
+                // Division by zero
+                if result.is_finite() {
+                    DataCell::Float(result)
+                } else {
+                    DataCell::Blank
+                }
+            }
+        }
+    }
+
+    pub async fn process(&self, uuid: &str) -> Result<DataFileDetails> {
+        let expr = Self::parse(&self.expression)?;
+        let mut df_in = DataFile::default();
+        df_in.open_input_file(uuid)?;
+        df_in.load_header()?;
+        if df_in.header().get_col_num(&self.name).is_some() {
+            return Err(anyhow!("File {uuid} already has a column {}", self.name));
+        }
+        let col_nums = Self::column_names(&expr)
+            .into_iter()
+            .map(|name| match df_in.header().get_col_num(name) {
+                Some(col_num) => Ok((name, col_num)),
+                None => Err(anyhow!("File {uuid} does not have a header column {name}")),
+            })
+            .collect::<Result<HashMap<&str, usize>>>()?;
+
+        let mut header = df_in.header().to_owned();
+        let kind = Self::kind(&expr, &header);
+        header.columns.push(ColumnHeader {
+            name: self.name.to_owned(),
+            kind: kind.to_owned(),
+        });
+        let mut df_out = DataFile::new_output_file()?;
+        df_out.write_json_row(&json! {header})?; // Output new header
+        while let Some(row) = df_in.read_row() {
+            let mut row: Vec<DataCell> = serde_json::from_str(&row)?;
+            let cell = match (Self::evaluate(&expr, &row, &col_nums), &kind) {
+                (DataCell::Int(i), ColumnHeaderType::Float) => DataCell::Float(i as f64),
+                (cell, _) => cell,
+            };
+            row.push(cell);
+            df_out.write_json_row(&json! {row})?; // Output data row
+        }
+        df_out.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{header, load_file, write_file};
+
+    #[tokio::test]
+    async fn test_constraint_check_bad_property() {
+        let transform = ConstraintCheck {
+            key: "value".to_string(),
+            property: "Q42".to_string(),
+            target: "ok".to_string(),
+        };
+        let err = transform.process("no_such_file").await.unwrap_err();
+        assert_eq!(err.to_string(), "Not a Wikidata property: 'Q42'");
+    }
+
+    #[test]
+    fn test_external_id_resolve_query() {
+        let transform = ExternalIdResolve {
+            key: "viaf".to_string(),
+            property: "P214".to_string(),
+            target: "item".to_string(),
+        };
+        let ids = vec!["113230702".to_string(), "a\"b".to_string()];
+        assert_eq!(
+            transform.build_query(&ids),
+            "SELECT ?id ?item { VALUES ?id { \"113230702\" \"a\\\"b\" } ?item wdt:P214 ?id }"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_external_id_resolve_bad_property() {
+        let transform = ExternalIdResolve {
+            key: "viaf".to_string(),
+            property: "214".to_string(),
+            target: "item".to_string(),
+        };
+        let err = transform.process("no_such_file").await.unwrap_err();
+        assert_eq!(err.to_string(), "Not a Wikidata property: '214'");
+    }
+
+    #[test]
+    fn test_sitelink_resolve_counterparts() {
+        let j = json!({"entities": {
+            "Q64": {"id": "Q64", "sitelinks": {
+                "dewiki": {"site": "dewiki", "title": "Berlin"},
+                "enwiki": {"site": "enwiki", "title": "Berlin"}}},
+            "Q1055": {"id": "Q1055", "sitelinks": {
+                "dewiki": {"site": "dewiki", "title": "Hamburg"}}},
+            "-1": {"site": "dewiki", "title": "Nirgendwo", "missing": ""}
+        }});
+        let transform = SitelinkResolve {
+            key: "page".to_string(),
+            wiki: "enwiki".to_string(),
+        };
+        let counterparts = transform.counterparts_from_entities(&j, "dewiki");
+        assert_eq!(
+            counterparts,
+            HashMap::from([("Berlin".to_string(), "Berlin".to_string())])
+        );
+        let transform = SitelinkResolve {
+            key: "page".to_string(),
+            wiki: "wikidatawiki".to_string(),
+        };
+        let counterparts = transform.counterparts_from_entities(&j, "dewiki");
+        assert_eq!(counterparts.get("Hamburg"), Some(&"Q1055".to_string()));
+        assert_eq!(counterparts.len(), 2);
+    }
+
+    #[test]
+    fn test_page_info_from_query() {
+        let j = json!({"query": {
+            "normalized": [{"from": "berlin", "to": "Berlin"}],
+            "pages": {
+                "3354": {"pageid": 3354, "ns": 0, "title": "Berlin", "length": 1234,
+                    "revisions": [{"timestamp": "2024-01-02T03:04:05Z"}]},
+                "26077": {"pageid": 26077, "ns": 0, "title": "Hauptstadt Deutschlands",
+                    "redirect": "", "length": 20,
+                    "revisions": [{"timestamp": "2020-01-01T00:00:00Z"}]},
+                "-1": {"ns": 0, "title": "Nirgendwo", "missing": ""}
+            }
+        }});
+        let info = PageInfo::info_from_query(&j);
+        let berlin = PageInfoValues {
+            length: Some(1234),
+            last_edit: Some("2024-01-02T03:04:05Z".to_string()),
+            is_redirect: false,
+        };
+        assert_eq!(info.get("Berlin"), Some(&berlin));
+        assert_eq!(info.get("berlin"), Some(&berlin));
+        assert!(info["Hauptstadt Deutschlands"].is_redirect);
+        assert!(!info.contains_key("Nirgendwo"));
+    }
+
+    #[tokio::test]
+    async fn test_page_info() {
+        let simplewiki = WikiPage {
+            wiki: Some("simplewiki".to_string()),
+            ..Default::default()
+        };
+        let rows = ["Berlin", "Germany", "No_such_page_for_ToolFlow"]
+            .iter()
+            .map(|title| {
+                let page = WikiPage {
+                    prefixed_title: Some(title.to_string()),
+                    ..Default::default()
+                };
+                vec![DataCell::WikiPage(page)]
+            })
+            .collect();
+        let uuid = write_file(
+            &header(&[("page", ColumnHeaderType::WikiPage(simplewiki))]),
+            rows,
+        );
+
+        let transform = PageInfo {
+            key: "page".to_string(),
+        };
+        let dfd = transform.process(&uuid).await.unwrap();
+        let df_out = load_file(&dfd.uuid);
+        assert_eq!(df_out.header().get_col_num("page_redirect"), Some(3));
+        assert!(matches!(df_out.rows[0][1], DataCell::Int(length) if length > 0));
+        assert_eq!(df_out.rows[2][1], DataCell::Blank);
+
+        // Cleanup
+        APP.remove_uuid_file(&uuid).unwrap();
+        APP.remove_uuid_file(&dfd.uuid).unwrap();
+    }
+
+    #[test]
+    fn test_wikidata_labels_fallback() {
+        let j = json!({"entities": {
+            "Q64": {"id": "Q64", "labels": {
+                "de": {"language": "de", "value": "Berlin"},
+                "en": {"language": "en", "value": "Berlin (en)"}}},
+            "Q1055": {"id": "Q1055", "labels": {
+                "en": {"language": "en", "value": "Hamburg"}}},
+            "Q1": {"id": "Q1", "labels": {}}
+        }});
+        let transform = WikidataLabels {
+            key: "item".to_string(),
+            languages: vec!["de".to_string(), "en".to_string()],
+            name: "label".to_string(),
+        };
+        let labels = transform.labels_from_entities(&j);
+        assert_eq!(labels["Q64"], "Berlin");
+        assert_eq!(labels["Q1055"], "Hamburg");
+        assert!(!labels.contains_key("Q1"));
+    }
+
+    #[tokio::test]
+    async fn test_wikidata_labels() {
+        let item = ColumnHeaderType::WikiPage(WikiPage::new_wikidata_item());
+        let rows = ["Q42", "Q64"]
+            .iter()
+            .map(|id| {
+                let page = WikiPage {
+                    prefixed_title: Some(id.to_string()),
+                    ..WikiPage::new_wikidata_item()
+                };
+                vec![DataCell::WikiPage(page)]
+            })
+            .collect();
+        let uuid = write_file(&header(&[("item", item)]), rows);
+
+        let transform = WikidataLabels {
+            key: "item".to_string(),
+            languages: vec!["en".to_string()],
+            name: "label".to_string(),
+        };
+        let dfd = transform.process(&uuid).await.unwrap();
+        let labels: Vec<String> = load_file(&dfd.uuid)
+            .rows
+            .iter()
+            .map(|row| row[1].as_key())
+            .collect();
+        assert_eq!(labels, vec!["Douglas Adams", "Berlin"]);
+
+        // Cleanup
+        APP.remove_uuid_file(&uuid).unwrap();
+        APP.remove_uuid_file(&dfd.uuid).unwrap();
+    }
+
+    #[test]
+    fn test_property_constraints() {
+        let claims = json!({"P2302":[
+            {"mainsnak":{"datavalue":{"value":{"id":"Q21502404"}}},
+             "qualifiers":{"P1793":[{"datavalue":{"value":"[1-9]\\d{1,8}"}}]}},
+            {"mainsnak":{"datavalue":{"value":{"id":"Q21510859"}}},
+             "qualifiers":{"P2305":[{"datavalue":{"value":{"id":"Q6581097"}}}]}}
+        ]});
+        let constraints = PropertyConstraints::from_claims(&claims);
+        assert!(!constraints.is_ok("123")); // Format OK but not one of the allowed values
+        let constraints = PropertyConstraints {
+            one_of: None,
+            ..constraints
+        };
+        assert!(constraints.is_ok("123"));
+        assert!(!constraints.is_ok("0123"));
+        assert!(!constraints.is_ok("123x"));
+    }
+
+    #[test]
+    fn test_toggle_talk_paired_namespace_id() {
+        assert_eq!(ToggleTalk::paired_namespace_id(0), Some(1));
+        assert_eq!(ToggleTalk::paired_namespace_id(1), Some(0));
+        assert_eq!(ToggleTalk::paired_namespace_id(6), Some(7));
+        assert_eq!(ToggleTalk::paired_namespace_id(7), Some(6));
+        assert_eq!(ToggleTalk::paired_namespace_id(-1), None);
+    }
+
+    #[tokio::test]
+    async fn test_row_number() {
+        let uuid = "8c5d1fb3-6ea8-44d1-b938-9d22f569c412";
+        let transform = RowNumber {
+            name: "row_index".to_string(),
+            start: 1,
+            prepend: true,
+        };
+        let dfd = transform.process(uuid).await.unwrap();
+        let df = load_file(&dfd.uuid);
+        assert_eq!(df.header().get_col_num("row_index"), Some(0));
+        assert_eq!(df.rows[0][0], DataCell::Int(1));
+        assert_eq!(df.rows[48][0], DataCell::Int(49));
+        APP.remove_uuid_file(&dfd.uuid).unwrap(); // Cleanup
+    }
+
+    #[test]
+    fn test_parse_coordinate() {
+        let paris = DataCell::Coordinate {
+            lat: 48.8566,
+            lon: 2.3522,
+        };
+        let cell = DataCell::PlainText("48.8566,2.3522".to_string());
+        assert_eq!(ParseCoordinate::parse(&cell), paris);
+        let cell = DataCell::PlainText("Point(2.3522 48.8566)".to_string());
+        assert_eq!(ParseCoordinate::parse(&cell), paris);
+        let cell = DataCell::PlainText("Paris".to_string());
+        assert_eq!(ParseCoordinate::parse(&cell), DataCell::Blank);
+        let cell = DataCell::PlainText("148.8566,2.3522".to_string());
+        assert_eq!(ParseCoordinate::parse(&cell), DataCell::Blank);
+    }
+
+    #[test]
+    fn test_toggle_talk_title_without_prefix() {
+        let wp = WikiPage {
+            prefixed_title: Some("File:Foo.jpg".to_string()),
+            ns_id: Some(6),
+            ..Default::default()
+        };
+        assert_eq!(
+            ToggleTalk::title_without_prefix(&wp),
+            Some("Foo.jpg".to_string())
+        );
+    }
+
+    fn write_compute_test_file() -> String {
+        let header = header(&[
+            ("title", ColumnHeaderType::PlainText),
+            ("wiki", ColumnHeaderType::PlainText),
+            ("edits", ColumnHeaderType::Int),
+            ("days", ColumnHeaderType::Int),
+        ]);
+        let rows = [
+            ("Foo", DataCell::PlainText("dewiki".to_string()), 10, 4),
+            ("Bar", DataCell::PlainText("enwiki".to_string()), 3, 0),
+            ("Baz", DataCell::Blank, 7, 7),
+        ]
+        .into_iter()
+        .map(|(title, wiki, edits, days)| {
+            vec![
+                DataCell::PlainText(title.to_string()),
+                wiki,
+                DataCell::Int(edits),
+                DataCell::Int(days),
+            ]
+        })
+        .collect();
+        write_file(&header, rows)
+    }
+
+    async fn compute_column(uuid: &str, expression: &str) -> (ColumnHeaderType, Vec<DataCell>) {
+        let transform = Compute {
+            name: "result".to_string(),
+            expression: expression.to_string(),
+        };
+        let dfd = transform.process(uuid).await.unwrap();
+        let df = load_file(&dfd.uuid);
+        APP.remove_uuid_file(&dfd.uuid).unwrap(); // Cleanup
+        let col_num = df.header().get_col_num("result").unwrap();
+        (
+            df.header().columns[col_num].kind.to_owned(),
+            df.rows.iter().map(|row| row[col_num].to_owned()).collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_compute_arithmetic() {
+        let uuid = write_compute_test_file();
+        assert_eq!(
+            compute_column(&uuid, "edits / days").await,
+            (
+                ColumnHeaderType::Float,
+                vec![DataCell::Float(2.5), DataCell::Blank, DataCell::Float(1.0)]
+            )
+        );
+        assert_eq!(
+            compute_column(&uuid, "-(edits - days) * 2 + 1").await,
+            (
+                ColumnHeaderType::Int,
+                vec![DataCell::Int(-11), DataCell::Int(-5), DataCell::Int(1)]
+            )
+        );
+        let transform = Compute {
+            name: "result".to_string(),
+            expression: "edits / (days".to_string(),
+        };
+        assert!(transform.process(&uuid).await.is_err());
+        let nested = format!("{}1{}", "(".repeat(100), ")".repeat(100));
+        assert!(Compute::parse(&nested)
+            .unwrap_err()
+            .to_string()
+            .contains("nested"));
+        assert!(Compute::parse(&"-".repeat(100)).is_err());
+        assert!(Compute::parse(&"1+".repeat(600))
+            .unwrap_err()
+            .to_string()
+            .contains("longer"));
+        let limit = COMPUTE_MAX_NESTING;
+        let nested = format!("{}1{}", "(".repeat(limit), ")".repeat(limit));
+        assert_eq!(
+            Compute::parse(&nested).unwrap(),
+            ComputeExpr::Value(DataCell::Int(1))
+        );
+        APP.remove_uuid_file(&uuid).unwrap(); // Cleanup
+    }
+
+    #[tokio::test]
+    async fn test_compute_concatenation() {
+        let uuid = write_compute_test_file();
+        let text = |s: &str| DataCell::PlainText(s.to_string());
+        assert_eq!(
+            compute_column(&uuid, r#"title + " (" + `wiki` + ")""#).await,
+            (
+                ColumnHeaderType::PlainText,
+                vec![text("Foo (dewiki)"), text("Bar (enwiki)"), DataCell::Blank]
+            )
+        );
+        assert_eq!(
+            compute_column(&uuid, "title + ': ' + edits").await.1,
+            vec![text("Foo: 10"), text("Bar: 3"), text("Baz: 7")]
+        );
+        APP.remove_uuid_file(&uuid).unwrap(); // Cleanup
+    }
+}
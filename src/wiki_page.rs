@@ -1,7 +1,13 @@
+use lazy_static::lazy_static;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
 use crate::APP;
 
+lazy_static! {
+    static ref RE_ENTITY_ID: Regex = Regex::new(r"^[QPLM]\d+$").expect("Regex error");
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct WikiPage {
     pub title: Option<String>,
@@ -42,6 +48,12 @@ impl WikiPage {
         }
     }
 
+    // The Wikibase entity ID at the end of a title, like "Q42" or "Property:P31"
+    pub fn entity_id_from_title(title: &str) -> Option<String> {
+        let id = title.rsplit(':').next()?;
+        RE_ENTITY_ID.is_match(id).then(|| id.to_string())
+    }
+
     pub async fn fill_missing(&mut self) {
         if let Some(title) = &mut self.title {
             *title = title.replace(' ', "_");
@@ -118,21 +130,36 @@ mod tests {
         assert_eq!(item.ns_id, Some(14));
     }
 
+    #[test]
+    fn test_entity_id_from_title() {
+        let id = |title: &str| WikiPage::entity_id_from_title(title);
+        assert_eq!(id("Q42"), Some("Q42".to_string()));
+        assert_eq!(id("Property:P31"), Some("P31".to_string()));
+        assert_eq!(id("Lexeme:L7"), Some("L7".to_string()));
+        assert_eq!(id("M123"), Some("M123".to_string()));
+        assert_eq!(id("Q42a"), None);
+        assert_eq!(id("Category:Foo"), None);
+    }
+
     #[tokio::test]
     async fn test_fill_missing_generate_prefixed_title() {
         // Main namespace
-        let mut wp = WikiPage::default();
-        wp.wiki = Some("wikidatawiki".to_string());
-        wp.title = Some("Q12345".to_string());
-        wp.ns_id = Some(0);
+        let mut wp = WikiPage {
+            wiki: Some("wikidatawiki".to_string()),
+            title: Some("Q12345".to_string()),
+            ns_id: Some(0),
+            ..Default::default()
+        };
         wp.fill_missing().await;
         assert_eq!(wp.prefixed_title, Some("Q12345".to_string()));
 
         // Category namespace
-        let mut wp = WikiPage::default();
-        wp.wiki = Some("commonswiki".to_string());
-        wp.title = Some("Foobar".to_string());
-        wp.ns_id = Some(14);
+        let mut wp = WikiPage {
+            wiki: Some("commonswiki".to_string()),
+            title: Some("Foobar".to_string()),
+            ns_id: Some(14),
+            ..Default::default()
+        };
         wp.fill_missing().await;
         assert_eq!(wp.prefixed_title, Some("Category:Foobar".to_string()));
     }
@@ -140,30 +167,38 @@ mod tests {
     #[tokio::test]
     async fn test_fill_missing_generate_namespace_id() {
         // Main namespace
-        let mut wp = WikiPage::default();
-        wp.wiki = Some("dewiki".to_string());
-        wp.prefixed_title = Some("AGEB".to_string());
+        let mut wp = WikiPage {
+            wiki: Some("dewiki".to_string()),
+            prefixed_title: Some("AGEB".to_string()),
+            ..Default::default()
+        };
         wp.fill_missing().await;
         assert_eq!(wp.ns_id, Some(0));
 
         // Main namespace but with colon
-        let mut wp = WikiPage::default();
-        wp.wiki = Some("dewiki".to_string());
-        wp.prefixed_title = Some("Station_’70:_Call_in_Question_/_Live_Independence ".to_string());
+        let mut wp = WikiPage {
+            wiki: Some("dewiki".to_string()),
+            prefixed_title: Some("Station_’70:_Call_in_Question_/_Live_Independence ".to_string()),
+            ..Default::default()
+        };
         wp.fill_missing().await;
         assert_eq!(wp.ns_id, Some(0));
 
         // Local namespace
-        let mut wp = WikiPage::default();
-        wp.wiki = Some("dewiki".to_string());
-        wp.prefixed_title = Some("Kategorie:AGEB".to_string());
+        let mut wp = WikiPage {
+            wiki: Some("dewiki".to_string()),
+            prefixed_title: Some("Kategorie:AGEB".to_string()),
+            ..Default::default()
+        };
         wp.fill_missing().await;
         assert_eq!(wp.ns_id, Some(14));
 
         // Canonical namespace
-        let mut wp = WikiPage::default();
-        wp.wiki = Some("dewiki".to_string());
-        wp.prefixed_title = Some("Category:AGEB".to_string());
+        let mut wp = WikiPage {
+            wiki: Some("dewiki".to_string()),
+            prefixed_title: Some("Category:AGEB".to_string()),
+            ..Default::default()
+        };
         wp.fill_missing().await;
         assert_eq!(wp.ns_id, Some(14));
     }
@@ -0,0 +1,243 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashMap;
+
+lazy_static! {
+    static ref RE_SPAN: Regex =
+        Regex::new(r#"(?i)\b(rowspan|colspan)\s*=\s*["']?(\d+)"#).expect("Regex error");
+    static ref RE_ATTRIBUTES: Regex =
+        Regex::new(r#"^\s*([A-Za-z-]+\s*=\s*("[^"]*"|'[^']*'|[^\s"']+)\s*)+$"#)
+            .expect("Regex error");
+    static ref RE_WIKILINK: Regex = Regex::new(r"\[\[:?([^\[\]|]+)").expect("Regex error");
+}
+
+// Spanned cells are capped so a typo like rowspan=1000000 cannot blow up memory
+const MAX_SPAN: usize = 500;
+
+#[derive(Debug, Clone, Default)]
+struct TableCell {
+    text: String,
+    rowspan: usize,
+    colspan: usize,
+}
+
+impl TableCell {
+    fn new(raw: &str) -> Self {
+        let (attributes, text) = match Self::split_attributes(raw) {
+            Some((attributes, text)) => (attributes, text),
+            None => ("", raw),
+        };
+        let mut ret = Self {
+            text: text.trim().to_string(),
+            rowspan: 1,
+            colspan: 1,
+        };
+        for cap in RE_SPAN.captures_iter(attributes) {
+            let span = cap[2].parse::<usize>().unwrap_or(1).clamp(1, MAX_SPAN);
+            match cap[1].to_lowercase().as_str() {
+                "rowspan" => ret.rowspan = span,
+                _ => ret.colspan = span,
+            }
+        }
+        ret
+    }
+
+    // `style="..." | text`; a `|` inside links or templates is not an attribute separator
+    fn split_attributes(raw: &str) -> Option<(&str, &str)> {
+        let pos = split_outside_markup(raw, "|").first()?.len();
+        if pos >= raw.len() {
+            return None;
+        }
+        let attributes = &raw[..pos];
+        if RE_ATTRIBUTES.is_match(attributes) {
+            Some((attributes, &raw[pos + 1..]))
+        } else {
+            None
+        }
+    }
+}
+
+// Splits on `separator`, ignoring occurrences inside [[...]] and {{...}}
+fn split_outside_markup<'a>(s: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut ret = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    let mut pos = 0;
+    while pos < s.len() {
+        let rest = &s[pos..];
+        if rest.starts_with("[[") || rest.starts_with("{{") {
+            depth += 1;
+            pos += 2;
+        } else if depth > 0 && (rest.starts_with("]]") || rest.starts_with("}}")) {
+            depth -= 1;
+            pos += 2;
+        } else if depth == 0 && rest.starts_with(separator) {
+            ret.push(&s[start..pos]);
+            pos += separator.len();
+            start = pos;
+        } else {
+            pos += rest.chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+        }
+    }
+    ret.push(&s[start..]);
+    ret
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WikiTable {
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl WikiTable {
+    // All top-level tables with class "wikitable", in page order; nested tables are skipped
+    pub fn parse_all(wikitext: &str) -> Vec<Self> {
+        let mut ret = vec![];
+        let mut depth = 0;
+        let mut rows: Vec<Vec<TableCell>> = vec![];
+        let mut is_wikitable = false;
+        for line in wikitext.lines() {
+            let line = line.trim_start();
+            if line.starts_with("{|") {
+                depth += 1;
+                if depth == 1 {
+                    is_wikitable = line.contains("wikitable");
+                    rows = vec![vec![]];
+                }
+                continue;
+            }
+            if depth == 0 {
+                continue;
+            }
+            if line.starts_with("|}") {
+                depth -= 1;
+                if depth == 0 && is_wikitable {
+                    ret.push(Self::from_cell_rows(std::mem::take(&mut rows)));
+                }
+                continue;
+            }
+            if depth > 1 || line.starts_with("|+") {
+                continue;
+            }
+            if line.starts_with("|-") {
+                rows.push(vec![]);
+                continue;
+            }
+            let row = match rows.last_mut() {
+                Some(row) => row,
+                None => continue,
+            };
+            if let Some(cells) = line.strip_prefix('!') {
+                split_outside_markup(cells, "!!")
+                    .into_iter()
+                    .flat_map(|cells| split_outside_markup(cells, "||"))
+                    .for_each(|cell| row.push(TableCell::new(cell)));
+            } else if let Some(cells) = line.strip_prefix('|') {
+                split_outside_markup(cells, "||")
+                    .into_iter()
+                    .for_each(|cell| row.push(TableCell::new(cell)));
+            } else if let Some(cell) = row.last_mut() {
+                // Multi-line cell content
+                cell.text = format!("{}\n{}", cell.text, line).trim().to_string();
+            }
+        }
+        ret
+    }
+
+    // The first row is the header; rowspan repeats the cell below, colspan pads with blanks
+    fn from_cell_rows(rows: Vec<Vec<TableCell>>) -> Self {
+        let mut grid: Vec<Vec<String>> = vec![];
+        let mut pending: HashMap<usize, (usize, String)> = HashMap::new();
+        for row in rows.into_iter().filter(|row| !row.is_empty()) {
+            let mut grid_row = vec![];
+            for cell in row {
+                Self::fill_rowspans(&mut pending, &mut grid_row);
+                if cell.rowspan > 1 {
+                    let _ =
+                        pending.insert(grid_row.len(), (cell.rowspan - 1, cell.text.to_owned()));
+                }
+                grid_row.push(cell.text);
+                grid_row.extend(std::iter::repeat_n(String::new(), cell.colspan - 1));
+            }
+            Self::fill_rowspans(&mut pending, &mut grid_row);
+            grid.push(grid_row);
+        }
+        let mut grid = grid.into_iter();
+        Self {
+            header: grid.next().unwrap_or_default(),
+            rows: grid.collect(),
+        }
+    }
+
+    // Repeats cells from rows above that span into the next column(s) of this row
+    fn fill_rowspans(pending: &mut HashMap<usize, (usize, String)>, grid_row: &mut Vec<String>) {
+        let col_num = grid_row.len();
+        if let Some((remaining, text)) = pending.get_mut(&col_num) {
+            grid_row.push(text.to_owned());
+            *remaining -= 1;
+            if *remaining == 0 {
+                let _ = pending.remove(&col_num);
+            }
+            Self::fill_rowspans(pending, grid_row);
+        }
+    }
+
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.header
+            .iter()
+            .position(|header| Self::plain_header(header) == name.trim())
+    }
+
+    // Header cells are often bold or linked; compare on their visible text
+    fn plain_header(header: &str) -> String {
+        let header = header.replace("'''", "").replace("''", "");
+        match header.trim().strip_prefix("[[") {
+            Some(link) => link
+                .trim_end_matches("]]")
+                .rsplit('|')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            None => header.trim().to_string(),
+        }
+    }
+
+    // The target of the first wikilink in a cell, if any
+    pub fn link_target(cell: &str) -> Option<String> {
+        RE_WIKILINK
+            .captures(cell)
+            .map(|cap| cap[1].trim().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wiki_table_parse_all() {
+        let wikitext = "Intro\n{| class=\"wikitable sortable\"\n|+ Caption\n! '''Name''' !! [[Country|Land]] !! Note\n|-\n| [[Berlin|The capital]] || rowspan=\"2\" | Germany || {{lang|de|Hauptstadt}}\n|-\n| [[Hamburg]]\n| Port\n|-\n| colspan=2 | Unknown\n| Nested\n{|\n| ignored\n|}\n|}\n{|\n| not a wikitable\n|}";
+        let tables = WikiTable::parse_all(wikitext);
+        assert_eq!(tables.len(), 1);
+        let table = &tables[0];
+        assert_eq!(table.column_index("Name"), Some(0));
+        assert_eq!(table.column_index("Land"), Some(1));
+        assert_eq!(
+            table.rows,
+            vec![
+                vec![
+                    "[[Berlin|The capital]]",
+                    "Germany",
+                    "{{lang|de|Hauptstadt}}"
+                ],
+                vec!["[[Hamburg]]", "Germany", "Port"],
+                vec!["Unknown", "", "Nested"],
+            ]
+        );
+        assert_eq!(
+            WikiTable::link_target(&table.rows[0][0]),
+            Some("Berlin".to_string())
+        );
+    }
+}
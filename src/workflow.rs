@@ -1,14 +1,32 @@
 use crate::{
-    data_file::DataFileDetails,
-    workflow_node::WorkflowNode,
+    data_file::{DataFile, DataFileDetails, FILES_WRITTEN, ROWS_PROCESSED},
+    workflow_node::{NodeContext, WorkflowNode, PREVIOUS_RUN_SLOT},
     workflow_run::{WorkflowNodeStatusValue, WorkflowRun},
     APP,
 };
 use anyhow::{anyhow, Result};
-use futures::future::join_all;
-use mysql_async::{from_row, prelude::*};
+use chrono::{DateTime, Utc};
+use futures::{stream, StreamExt};
+use mysql_async::{from_row, prelude::*, Conn, TxOpts};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+// Default wall-clock limit for a single run, unless the workflow sets its own
+const MAX_RUN_DURATION_SECONDS: u64 = 4 * 60 * 60;
+const PROGRESS_INTERVAL_SECONDS: u64 = 10;
+// Every check costs a database query, so cancellation takes up to this long to take effect
+const CANCEL_CHECK_INTERVAL_SECONDS: u64 = 5;
+// Nodes of a batch that run at the same time, so many sources do not hit one service at once
+const MAX_CONCURRENT_NODES: usize = 4;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeInput {
@@ -24,12 +42,14 @@ pub enum WorkflowState {
     PUBLISHED,
 }
 
-impl WorkflowState {
-    pub fn from_str(s: &str) -> Option<Self> {
+impl FromStr for WorkflowState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
         match s {
-            "DRAFT" => Some(WorkflowState::DRAFT),
-            "PUBLISHED" => Some(WorkflowState::PUBLISHED),
-            _ => None,
+            "DRAFT" => Ok(WorkflowState::DRAFT),
+            "PUBLISHED" => Ok(WorkflowState::PUBLISHED),
+            other => Err(anyhow!("Unknown workflow state '{other}'")),
         }
     }
 }
@@ -46,6 +66,12 @@ pub struct Workflow {
     pub nodes: Vec<WorkflowNode>,
     pub edges: Vec<WorkflowEdge>,
 
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_run_seconds: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_nodes: Option<usize>,
+
     #[serde(skip)]
     pub state: WorkflowState,
 
@@ -63,6 +89,12 @@ pub struct Workflow {
 
     #[serde(skip)]
     description: String,
+
+    #[serde(skip)]
+    previous_run_files: HashMap<usize, String>, // node_id => file UUID of the last successful run
+
+    #[serde(skip)]
+    last_success: Option<DateTime<Utc>>, // Start of the last successful run
 }
 
 impl Workflow {
@@ -72,10 +104,14 @@ impl Workflow {
             user_id,
             nodes,
             edges,
+            max_run_seconds: None,
+            max_concurrent_nodes: None,
             state: WorkflowState::default(),
             run: WorkflowRun::default(),
             name: String::default(),
             description: String::default(),
+            previous_run_files: HashMap::new(),
+            last_success: None,
         };
         ret.run = WorkflowRun::new(&ret);
         ret
@@ -94,7 +130,7 @@ impl Workflow {
                 (
                     x.0.to_owned(),
                     serde_json::from_str::<Self>(&x.1).unwrap(),
-                    WorkflowState::from_str(&x.2).unwrap_or_default(),
+                    x.2.parse::<WorkflowState>().unwrap_or_default(),
                     x.3,
                 )
             })
@@ -110,115 +146,436 @@ impl Workflow {
 
     pub async fn run(&mut self) -> Result<()> {
         let run_id = self.run.get_or_create_id().await?;
-        let _ = self.run.load_status().await?;
+        self.run.load_status().await?;
+        if let Err(e) = self.validate() {
+            self.mark_invalid_nodes();
+            let mut conn = APP.get_db_connection().await?;
+            self.run
+                .update_status(WorkflowNodeStatusValue::FAILED, &mut conn)
+                .await?;
+            return Err(e);
+        }
+        self.load_previous_run_files().await?;
+        self.load_last_success().await?;
+        let snapshot_nodes = self.snapshot_nodes();
+        let started = Instant::now();
+        if let Err(e) = self.run_batches(Some(run_id), started).await {
+            // Fail on first error
+            let mut conn = APP.get_db_connection().await?;
+            if started.elapsed() >= self.time_budget() {
+                // Intermediate files of a run that hit the time budget will never be used
+                let _ = APP.clear_run_results(run_id as usize, &mut conn).await;
+            }
+            let status = if self.run.is_cancelled(&mut conn).await.unwrap_or(false) {
+                WorkflowNodeStatusValue::CANCEL
+            } else {
+                WorkflowNodeStatusValue::FAILED
+            };
+            self.run.update_status(status, &mut conn).await?;
+            return Err(e);
+        }
+
+        let mut conn = APP.get_db_connection().await?;
+        self.run
+            .update_status(WorkflowNodeStatusValue::DONE, &mut conn)
+            .await?;
+        self.keep_snapshots(&snapshot_nodes, &mut conn).await?;
+
+        Ok(())
+    }
+
+    // Runs the nodes batch by batch until all are done. Without a run ID (tests), nothing touches
+    // the database. Outputs of the nodes that finished are kept if another node fails, so they are
+    // not run again when the run is restarted.
+    async fn run_batches(&mut self, run_id: Option<u64>, started: Instant) -> Result<()> {
         loop {
             let nodes_to_run = self.get_next_nodes_to_run();
             if nodes_to_run.is_empty() {
-                break;
-            }
-
-            let mut inputs: HashMap<usize, HashMap<usize, String>> = nodes_to_run
-                .iter()
-                .map(|node_id| (*node_id, HashMap::new()))
-                .collect();
-            self.edges
-                .iter()
-                .filter(|edge| nodes_to_run.contains(&edge.target_node))
-                .map(|edge| NodeInput {
-                    node_id: edge.target_node,
-                    uuid: self
-                        .run
-                        .get_node_status(edge.source_node)
-                        .uuid()
-                        .to_string(),
-                    slot: edge.target_slot,
-                })
-                .for_each(|i| {
-                    let _ = inputs
-                        .entry(i.node_id)
-                        .or_default()
-                        .insert(i.slot, i.uuid.to_owned());
-                });
-
-            let futures: Vec<_> = nodes_to_run
-                .iter()
-                .map(|node_id| self.nodes[*node_id].run(inputs.get(node_id).unwrap(), self.user_id))
-                .collect();
-            let results = join_all(futures).await;
-
-            // Set error for all nodes
-            results
-                .iter()
-                .zip(nodes_to_run.iter())
-                .for_each(|(result, node_id)| {
-                    if let Err(e) = result {
-                        self.run
-                            .get_node_status_mut(*node_id)
-                            .set_status(WorkflowNodeStatusValue::FAILED, Some(e.to_string()));
-                    } else {
-                        self.run
-                            .get_node_status_mut(*node_id)
-                            .set_status(WorkflowNodeStatusValue::DONE, None);
-                    }
-                });
-
-            // Fail on first error
-            if let Some(error_result) = results.iter().filter(|r| r.is_err()).next() {
-                if let Err(e) = error_result {
-                    self.run
-                        .update_status(
-                            WorkflowNodeStatusValue::FAILED,
-                            &mut APP.get_db_connection().await?,
-                        )
-                        .await?;
-                    return Err(anyhow!(e.to_string()));
-                }
+                return Ok(());
             }
+            let node_results = self.run_batch(&nodes_to_run, started).await?;
 
-            let node_file: Vec<(usize, DataFileDetails)> = results
-                .into_iter()
-                .filter_map(|r| r.ok()) // Already checked they are all OK
-                .enumerate()
-                .map(|(num, dfd)| (nodes_to_run[num], dfd)) // TODO FIXME
-                .collect();
-
-            let mut conn = APP.get_db_connection().await?;
-            if self.run.is_cancelled(&mut conn).await? {
-                return Err(anyhow!("User cancelled run"));
+            let mut conn = match run_id {
+                Some(_) => Some(APP.get_db_connection().await?),
+                None => None,
+            };
+            if let Some(conn) = &mut conn {
+                if self.run.is_cancelled(conn).await? {
+                    for dfd in node_results
+                        .iter()
+                        .filter_map(|(_, result)| result.as_ref().ok())
+                    {
+                        let _ = APP.remove_uuid_file(&dfd.uuid);
+                    }
+                    return Err(anyhow!("User cancelled run"));
+                }
             }
-            for (node_id, dfd) in node_file {
+            let mut first_error = None;
+            for (node_id, result) in node_results {
+                let dfd = match result {
+                    Ok(dfd) => dfd,
+                    Err(e) => {
+                        first_error = first_error.or(Some(anyhow!("Node {node_id}: {e}")));
+                        continue;
+                    }
+                };
                 if !dfd.is_valid() {
                     continue; // TODO is this the right thing to do?
                 }
-                let is_output_node = self.run.is_output_node(node_id);
-                let end_time = if is_output_node {
-                    "null"
-                } else {
-                    "NOW() + INTERVAL 1 HOUR"
-                };
-                format!("INSERT INTO `file` (`uuid`,`expires`,`run_id`,`node_id`,`is_output`,`rows`) VALUES (?,{end_time},?,?,?,?)")
-                    .with((dfd.uuid.to_owned(),run_id,node_id,is_output_node,dfd.rows))
-                    .run(&mut conn)
-                    .await?;
+                if let (Some(run_id), Some(conn)) = (run_id, &mut conn) {
+                    self.store_node_file(run_id, node_id, &dfd, conn).await?;
+                }
                 self.run
                     .get_node_status_mut(node_id)
                     .done_with_uuid(&dfd.uuid);
             }
-            self.run
-                .update_status(WorkflowNodeStatusValue::RUNNING, &mut conn)
+            if let Some(conn) = &mut conn {
+                self.run.update_progress(conn).await?;
+            }
+            if let Some(e) = first_error {
+                return Err(e);
+            }
+        }
+    }
+
+    // A re-executed node replaces the file of its previous execution in this run, except for the
+    // snapshot kept from the last finished execution. The rows are swapped in one transaction, so
+    // the node always has a file row; the old files are removed once that is committed.
+    async fn store_node_file(
+        &self,
+        run_id: u64,
+        node_id: usize,
+        dfd: &DataFileDetails,
+        conn: &mut Conn,
+    ) -> Result<()> {
+        let is_output_node = self.run.is_output_node(node_id);
+        // Snapshots expire like other files unless the run finishes, see `keep_snapshots`
+        let end_time = if is_output_node {
+            "null"
+        } else {
+            "NOW() + INTERVAL 1 HOUR"
+        };
+        let previous: Vec<(usize, String)> =
+            "SELECT `id`,`uuid` FROM `file` WHERE `run_id`=? AND `node_id`=? AND `uuid`!=? AND NOT (`is_output`=0 AND `expires` IS NULL)"
+                .with((run_id, node_id, dfd.uuid.to_owned()))
+                .map(&mut *conn, |(id, uuid)| (id, uuid))
+                .await?;
+        let mut tx = conn.start_transaction(TxOpts::default()).await?;
+        if !previous.is_empty() {
+            let ids: Vec<String> = previous.iter().map(|(id, _)| format!("{id}")).collect();
+            format!("DELETE FROM `file` WHERE `id` IN ({})", ids.join(","))
+                .with(())
+                .run(&mut tx)
                 .await?;
         }
+        format!("INSERT INTO `file` (`uuid`,`expires`,`run_id`,`node_id`,`is_output`,`rows`) VALUES (?,{end_time},?,?,?,?)")
+            .with((dfd.uuid.to_owned(), run_id, node_id, is_output_node, dfd.rows))
+            .run(&mut tx)
+            .await?;
+        tx.commit().await?;
+        for (_, uuid) in previous {
+            if let Err(e) = APP.remove_uuid_file(&uuid) {
+                warn!(uuid, error = %e, "Cannot remove file of a previous execution");
+            }
+        }
+        Ok(())
+    }
 
-        self.run
-            .update_status(
-                WorkflowNodeStatusValue::DONE,
-                &mut APP.get_db_connection().await?,
-            )
+    // Nodes whose output is compared to that of the previous run, so it needs to be kept
+    fn snapshot_nodes(&self) -> Vec<usize> {
+        self.edges
+            .iter()
+            .filter(|edge| edge.target_slot == 0)
+            .filter(|edge| self.nodes[edge.target_node].compares_with_previous_run())
+            .map(|edge| edge.source_node)
+            .collect()
+    }
+
+    // Snapshots of the last finished run are the only non-output files without an expiry date
+    async fn load_previous_run_files(&mut self) -> Result<()> {
+        let snapshot_nodes = self.snapshot_nodes();
+        if snapshot_nodes.is_empty() {
+            return Ok(());
+        }
+        let sql = "SELECT `file`.`node_id`,`file`.`uuid` FROM `file`,`run`
+            WHERE `file`.`run_id`=`run`.`id` AND `run`.`workflow_id`=? AND `file`.`is_output`=0 AND `file`.`expires` IS NULL
+            ORDER BY `file`.`id`";
+        let results: Vec<(usize, String)> = sql
+            .with((self.id,))
+            .map(&mut APP.get_db_connection().await?, |(node_id, uuid)| {
+                (node_id, uuid)
+            })
             .await?;
+        self.set_previous_run_files(results);
+        Ok(())
+    }
 
+    // Later files overwrite earlier ones; files that are gone from disk are skipped
+    fn set_previous_run_files(&mut self, results: Vec<(usize, String)>) {
+        let snapshot_nodes = self.snapshot_nodes();
+        self.previous_run_files = results
+            .into_iter()
+            .filter(|(node_id, _)| snapshot_nodes.contains(node_id))
+            .filter(|(_, uuid)| DataFile::new_from_uuid(uuid).exists())
+            .collect();
+    }
+
+    // Runs start before their nodes fetch data, so nothing after this time can have been missed
+    // `ts_last_success` is not reset when a scheduled run is started again
+    async fn load_last_success(&mut self) -> Result<()> {
+        let sql = "SELECT MAX(UNIX_TIMESTAMP(`ts_last_success`)) FROM `run` WHERE `workflow_id`=?";
+        let ts: Option<i64> = sql
+            .with((self.id,))
+            .map(&mut APP.get_db_connection().await?, |ts: Option<i64>| ts)
+            .await?
+            .pop()
+            .flatten();
+        self.last_success = ts.and_then(|ts| DateTime::from_timestamp(ts, 0));
+        Ok(())
+    }
+
+    // Replaces the snapshots of the last finished run with the ones of this run
+    async fn keep_snapshots(&self, snapshot_nodes: &[usize], conn: &mut Conn) -> Result<()> {
+        let uuids: Vec<String> = snapshot_nodes
+            .iter()
+            .map(|node_id| self.run.get_node_status(*node_id).uuid().to_string())
+            .filter(|uuid| !uuid.is_empty())
+            .collect();
+        if uuids.is_empty() {
+            return Ok(());
+        }
+        // In one transaction, so a failure cannot leave the workflow without snapshots
+        let mut tx = conn.start_transaction(TxOpts::default()).await?;
+        "UPDATE `file`,`run` SET `file`.`expires`=NOW() + INTERVAL 1 HOUR
+            WHERE `file`.`run_id`=`run`.`id` AND `run`.`workflow_id`=? AND `file`.`is_output`=0 AND `file`.`expires` IS NULL"
+            .with((self.id,))
+            .run(&mut tx)
+            .await?;
+        let placeholders = vec!["?"; uuids.len()].join(",");
+        format!("UPDATE `file` SET `expires`=NULL WHERE `uuid` IN ({placeholders})")
+            .with(uuids)
+            .run(&mut tx)
+            .await?;
+        tx.commit().await?;
         Ok(())
     }
 
+    pub fn time_budget(&self) -> Duration {
+        Duration::from_secs(self.max_run_seconds.unwrap_or(MAX_RUN_DURATION_SECONDS))
+    }
+
+    // Runs the next batch of nodes, aborting it if the run exceeds its time budget
+    async fn run_batch(
+        &mut self,
+        nodes_to_run: &[usize],
+        started: Instant,
+    ) -> Result<Vec<(usize, Result<DataFileDetails>)>> {
+        let budget = self.time_budget();
+        let written: HashMap<usize, Arc<Mutex<Vec<String>>>> = nodes_to_run
+            .iter()
+            .map(|node_id| (*node_id, Arc::default()))
+            .collect();
+        let result = match budget.checked_sub(started.elapsed()) {
+            Some(remaining) if !remaining.is_zero() => {
+                tokio::time::timeout(remaining, self.run_nodes(nodes_to_run, &written))
+                    .await
+                    .ok()
+            }
+            _ => None,
+        };
+        result.unwrap_or_else(|| {
+            written
+                .values()
+                .for_each(|files| Self::remove_written_files(files));
+            let error = format!("Run exceeded time budget of {} seconds", budget.as_secs());
+            for node_id in nodes_to_run {
+                self.run
+                    .get_node_status_mut(*node_id)
+                    .set_status(WorkflowNodeStatusValue::FAILED, Some(error.to_owned()));
+            }
+            Err(anyhow!(error))
+        })
+    }
+
+    // Input file UUIDs, by target slot, for each of the given nodes
+    fn get_node_inputs(&self, nodes_to_run: &[usize]) -> HashMap<usize, HashMap<usize, String>> {
+        let mut inputs: HashMap<usize, HashMap<usize, String>> = nodes_to_run
+            .iter()
+            .map(|node_id| (*node_id, HashMap::new()))
+            .collect();
+        self.edges
+            .iter()
+            .filter(|edge| nodes_to_run.contains(&edge.target_node))
+            .map(|edge| NodeInput {
+                node_id: edge.target_node,
+                uuid: self
+                    .run
+                    .get_node_status(edge.source_node)
+                    .uuid()
+                    .to_string(),
+                slot: edge.target_slot,
+            })
+            .for_each(|i| {
+                let _ = inputs
+                    .entry(i.node_id)
+                    .or_default()
+                    .insert(i.slot, i.uuid.to_owned());
+            });
+        for edge in self.edges.iter().filter(|edge| edge.target_slot == 0) {
+            if !nodes_to_run.contains(&edge.target_node)
+                || !self.nodes[edge.target_node].compares_with_previous_run()
+            {
+                continue;
+            }
+            if let Some(uuid) = self.previous_run_files.get(&edge.source_node) {
+                let _ = inputs
+                    .entry(edge.target_node)
+                    .or_default()
+                    .insert(PREVIOUS_RUN_SLOT, uuid.to_owned());
+            }
+        }
+        inputs
+    }
+
+    // Files finished by a node that was aborted will never be used
+    fn remove_written_files(files: &Mutex<Vec<String>>) {
+        for uuid in files.lock().unwrap().drain(..) {
+            if let Err(e) = APP.remove_uuid_file(&uuid) {
+                warn!(uuid, error = %e, "Cannot remove file of an aborted node");
+            }
+        }
+    }
+
+    fn set_rows_processed(run: &mut WorkflowRun, counters: &HashMap<usize, Arc<AtomicUsize>>) {
+        for (node_id, rows) in counters {
+            run.get_node_status_mut(*node_id)
+                .set_rows_processed(rows.load(Ordering::Relaxed));
+        }
+    }
+
+    // Progress is best-effort; runs without an ID (tests) are not in the database
+    async fn report_progress(run: &mut WorkflowRun, counters: &HashMap<usize, Arc<AtomicUsize>>) {
+        Self::set_rows_processed(run, counters);
+        if run.id().is_none() {
+            return;
+        }
+        let result = match APP.get_db_connection().await {
+            Ok(mut conn) => run.update_progress(&mut conn).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!(run_id = run.id(), error = %e, "Could not report run progress");
+        }
+    }
+
+    // Runs the given nodes in parallel and sets their status; only touches the database to report
+    // progress
+    async fn run_nodes(
+        &mut self,
+        nodes_to_run: &[usize],
+        written: &HashMap<usize, Arc<Mutex<Vec<String>>>>,
+    ) -> Result<Vec<(usize, Result<DataFileDetails>)>> {
+        let run_id = self.run.id();
+        let inputs = self.get_node_inputs(nodes_to_run);
+        let contexts: HashMap<usize, NodeContext> = nodes_to_run
+            .iter()
+            .map(|node_id| {
+                let context = NodeContext {
+                    user_id: self.user_id,
+                    last_success: self.last_success,
+                };
+                (*node_id, context)
+            })
+            .collect();
+        let counters: HashMap<usize, Arc<AtomicUsize>> = nodes_to_run
+            .iter()
+            .map(|node_id| (*node_id, Arc::default()))
+            .collect();
+        let (nodes, inputs, contexts, rows) = (&self.nodes, &inputs, &contexts, &counters);
+        let futures: Vec<_> = nodes_to_run
+            .iter()
+            .map(|node_id| async move {
+                let node_id = *node_id;
+                // A hung source must not block the run, so every node has a time limit
+                let node = &nodes[node_id];
+                let timeout = node.timeout();
+                let run = tokio::time::timeout(
+                    timeout,
+                    node.run_cached(&inputs[&node_id], &contexts[&node_id]),
+                );
+                let run = ROWS_PROCESSED.scope(rows[&node_id].clone(), run);
+                let result = FILES_WRITTEN
+                    .scope(written[&node_id].clone(), run)
+                    .await
+                    .unwrap_or_else(|_| {
+                        Self::remove_written_files(&written[&node_id]);
+                        Err(anyhow!("Timed out after {} seconds", timeout.as_secs()))
+                    });
+                (node_id, result)
+            })
+            .collect();
+        let limit = self
+            .max_concurrent_nodes
+            .unwrap_or(MAX_CONCURRENT_NODES)
+            .max(1);
+        let mut pending = std::pin::pin!(stream::iter(futures).buffer_unordered(limit));
+        let mut finished: HashMap<usize, Result<DataFileDetails>> = HashMap::new();
+        let mut progress = tokio::time::interval(Duration::from_secs(PROGRESS_INTERVAL_SECONDS));
+        let mut cancel_check =
+            tokio::time::interval(Duration::from_secs(CANCEL_CHECK_INTERVAL_SECONDS));
+        progress.tick().await; // The first tick is immediate
+        cancel_check.tick().await;
+        loop {
+            tokio::select! {
+                result = pending.next() => match result {
+                    Some((node_id, result)) => {
+                        let _ = finished.insert(node_id, result);
+                    }
+                    None => break,
+                },
+                _ = progress.tick() => Self::report_progress(&mut self.run, &counters).await,
+                _ = cancel_check.tick() => {
+                    if self.run.poll_cancelled().await {
+                        // Returning drops, and so aborts, the node futures that are still running
+                        Self::set_rows_processed(&mut self.run, &counters);
+                        written.values().for_each(|files| Self::remove_written_files(files));
+                        for node_id in nodes_to_run {
+                            self.run
+                                .get_node_status_mut(*node_id)
+                                .set_status(WorkflowNodeStatusValue::CANCEL, None);
+                        }
+                        return Err(anyhow!("User cancelled run"));
+                    }
+                }
+            }
+        }
+        Self::set_rows_processed(&mut self.run, &counters);
+        let results: Vec<(usize, Result<DataFileDetails>)> = nodes_to_run
+            .iter()
+            .map(|node_id| {
+                let result = finished
+                    .remove(node_id)
+                    .unwrap_or_else(|| Err(anyhow!("Did not finish")));
+                (*node_id, result)
+            })
+            .collect();
+
+        // Set error for all nodes
+        results.iter().for_each(|(node_id, result)| {
+            if let Err(e) = result {
+                warn!(workflow_id = self.id, run_id, node_id, error = %e, "Node failed");
+                self.run
+                    .get_node_status_mut(*node_id)
+                    .set_status(WorkflowNodeStatusValue::FAILED, Some(e.to_string()));
+            } else {
+                self.run
+                    .get_node_status_mut(*node_id)
+                    .set_status(WorkflowNodeStatusValue::DONE, None);
+            }
+        });
+        Ok(results)
+    }
+
     pub fn description(&self) -> &str {
         &self.description
     }
@@ -231,6 +588,115 @@ impl Workflow {
             .count()
     }
 
+    // Checks the edges and the parameters of all nodes before anything runs, listing every problem
+    pub fn validate(&self) -> Result<()> {
+        let mut errors = self.dangling_edges();
+        if errors.is_empty() {
+            let cycle_nodes = self.cycle_nodes();
+            if !cycle_nodes.is_empty() {
+                let node_ids: Vec<String> = cycle_nodes.iter().map(|id| format!("{id}")).collect();
+                errors.push(format!("Cycle between nodes {}", node_ids.join(", ")));
+            }
+        }
+        errors.extend(
+            self.node_errors()
+                .into_iter()
+                .map(|(node_id, e)| format!("Node {node_id}: {e}")),
+        );
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("Invalid workflow: {}", errors.join("; ")))
+        }
+    }
+
+    // Missing parameters and the wrong number of inputs, by node
+    fn node_errors(&self) -> Vec<(usize, String)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(node_id, node)| {
+                let mut slots: Vec<usize> = self
+                    .edges
+                    .iter()
+                    .filter(|edge| edge.target_node == node_id)
+                    .map(|edge| edge.target_slot)
+                    .collect();
+                let inputs = slots.len();
+                let mut errors: Vec<String> =
+                    [node.validate_params(), node.validate_inputs(inputs)]
+                        .into_iter()
+                        .filter_map(|result| result.err())
+                        .map(|e| e.to_string())
+                        .collect();
+                // Inputs are keyed by slot, so a second edge into a slot would replace the first
+                slots.sort();
+                slots.dedup();
+                if slots.len() != inputs {
+                    errors.push("several edges into the same slot".to_string());
+                }
+                if slots.contains(&PREVIOUS_RUN_SLOT) {
+                    errors.push(format!("slot {PREVIOUS_RUN_SLOT} is reserved"));
+                }
+                if errors.is_empty() {
+                    None
+                } else {
+                    Some((node_id, errors.join("; ")))
+                }
+            })
+            .collect()
+    }
+
+    fn dangling_edges(&self) -> Vec<String> {
+        self.edges
+            .iter()
+            .enumerate()
+            .flat_map(|(edge_id, edge)| [edge.source_node, edge.target_node].map(|n| (edge_id, n)))
+            .filter(|(_edge_id, node_id)| *node_id >= self.nodes.len())
+            .map(|(edge_id, node_id)| {
+                format!("Edge {edge_id} references non-existent node {node_id}")
+            })
+            .collect()
+    }
+
+    // Topological sort; nodes that never run out of open dependencies are on (or behind) a cycle
+    fn cycle_nodes(&self) -> Vec<usize> {
+        let mut open_dependencies = vec![0; self.nodes.len()];
+        for edge in &self.edges {
+            open_dependencies[edge.target_node] += 1;
+        }
+        let mut ready: Vec<usize> = (0..self.nodes.len())
+            .filter(|node_id| open_dependencies[*node_id] == 0)
+            .collect();
+        while let Some(node_id) = ready.pop() {
+            for edge in self.edges.iter().filter(|edge| edge.source_node == node_id) {
+                open_dependencies[edge.target_node] -= 1;
+                if open_dependencies[edge.target_node] == 0 {
+                    ready.push(edge.target_node);
+                }
+            }
+        }
+        (0..self.nodes.len())
+            .filter(|node_id| open_dependencies[*node_id] > 0)
+            .collect()
+    }
+
+    fn mark_invalid_nodes(&mut self) {
+        if self.dangling_edges().is_empty() {
+            for node_id in self.cycle_nodes() {
+                self.run.get_node_status_mut(node_id).set_status(
+                    WorkflowNodeStatusValue::FAILED,
+                    Some("Node is part of a cycle".to_string()),
+                );
+            }
+        }
+        for (node_id, e) in self.node_errors() {
+            self.run
+                .get_node_status_mut(node_id)
+                .set_status(WorkflowNodeStatusValue::FAILED, Some(e));
+        }
+    }
+
     pub fn get_next_nodes_to_run(&self) -> Vec<usize> {
         self.nodes
             .iter()
@@ -242,3 +708,396 @@ impl Workflow {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mapping::HeaderMapping,
+        test_util::load_file,
+        workflow_node::{WorkflowNodeKind, MOCK_CALLS, MOCK_IN_FLIGHT},
+    };
+    use serde_json::{json, Value};
+
+    impl Workflow {
+        // Same node scheduling as `run`, but without any database access
+        async fn run_without_db(&mut self) -> Result<()> {
+            if let Err(e) = self.validate() {
+                self.mark_invalid_nodes();
+                return Err(e);
+            }
+            self.run_batches(None, Instant::now()).await
+        }
+
+        fn remove_node_files(&self) {
+            for node_id in 0..self.nodes.len() {
+                let uuid = self.run.get_node_status(node_id).uuid();
+                if !uuid.is_empty() {
+                    APP.remove_uuid_file(uuid).unwrap();
+                }
+            }
+        }
+    }
+
+    fn node(kind: WorkflowNodeKind, parameters: Value) -> WorkflowNode {
+        let mut header_mapping = HeaderMapping::default();
+        header_mapping.add_plain_text("name", "name");
+        WorkflowNode {
+            kind,
+            parameters: serde_json::from_value(parameters).unwrap(),
+            header_mapping,
+        }
+    }
+
+    fn mock_node(names: &[&str]) -> WorkflowNode {
+        let rows: Vec<Value> = names
+            .iter()
+            .map(|name| json!([{ "PlainText": name }]))
+            .collect();
+        node(WorkflowNodeKind::Mock, json!({ "rows": rows }))
+    }
+
+    fn edge(source_node: usize, target_node: usize, target_slot: usize) -> WorkflowEdge {
+        WorkflowEdge {
+            source_node,
+            target_node,
+            target_slot,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_workflow_mock_join_and_filter() {
+        let nodes = vec![
+            mock_node(&["a", "b"]),
+            mock_node(&["b", "c"]),
+            node(
+                WorkflowNodeKind::Join,
+                json!({"mode": "merge_unique", "join_key": "name"}),
+            ),
+            node(
+                WorkflowNodeKind::Filter,
+                json!({"key": "name", "operator": "Unequal", "value": "a"}),
+            ),
+        ];
+        let edges = vec![edge(0, 2, 0), edge(1, 2, 1), edge(2, 3, 0)];
+        let mut workflow = Workflow::new(nodes, edges, 0);
+        workflow.run_without_db().await.unwrap();
+        assert!(workflow.run.has_completed_succesfully());
+
+        let df = load_file(workflow.run.get_node_status(3).uuid());
+        let mut names: Vec<String> = df.rows.iter().map(|row| row[0].as_key()).collect();
+        names.sort();
+        assert_eq!(names, vec!["b", "c"]);
+        workflow.remove_node_files();
+    }
+
+    #[tokio::test]
+    async fn test_workflow_inner_join_in_slot_order() {
+        let nodes = vec![
+            mock_node(&["c", "b", "a"]),
+            mock_node(&["a", "b"]),
+            node(
+                WorkflowNodeKind::Join,
+                json!({"mode": "inner_join_on_key", "join_key": "name"}),
+            ),
+        ];
+        // The smaller file would come first if the inputs were ordered by size
+        let edges = vec![edge(0, 2, 0), edge(1, 2, 1)];
+        let mut workflow = Workflow::new(nodes, edges, 0);
+        workflow.run_without_db().await.unwrap();
+        let df = load_file(workflow.run.get_node_status(2).uuid());
+        let names: Vec<String> = df.rows.iter().map(|row| row[0].as_key()).collect();
+        assert_eq!(names, vec!["b", "a"]);
+        workflow.remove_node_files();
+    }
+
+    #[tokio::test]
+    async fn test_workflow_rows_processed() {
+        let nodes = vec![
+            mock_node(&["a", "b", "c"]),
+            node(
+                WorkflowNodeKind::Filter,
+                json!({"key": "name", "operator": "Unequal", "value": "a"}),
+            ),
+        ];
+        let mut workflow = Workflow::new(nodes, vec![edge(0, 1, 0)], 0);
+        workflow.run_without_db().await.unwrap();
+        assert_eq!(workflow.run.get_node_status(0).rows_processed(), 3);
+        assert_eq!(workflow.run.get_node_status(1).rows_processed(), 2);
+        let details = serde_json::to_value(workflow.run.get_node_status(1)).unwrap();
+        assert_eq!(details["rows_processed"], 2);
+        workflow.remove_node_files();
+    }
+
+    #[tokio::test]
+    async fn test_workflow_mock_error_stops_run() {
+        let nodes = vec![
+            mock_node(&["a"]),
+            node(WorkflowNodeKind::Mock, json!({"error": "Mock failure"})),
+            node(
+                WorkflowNodeKind::Join,
+                json!({"mode": "merge_unique", "join_key": "name"}),
+            ),
+        ];
+        let edges = vec![edge(0, 2, 0), edge(1, 2, 1)];
+        let mut workflow = Workflow::new(nodes, edges, 0);
+        let error = workflow.run_without_db().await.unwrap_err();
+        assert_eq!(error.to_string(), "Node 1: Mock failure");
+        assert!(workflow.run.get_node_status(1).is_failed());
+        assert!(workflow.run.get_node_status(2).is_waiting());
+        // The node that finished keeps its output
+        let finished = workflow.run.get_node_status(0);
+        assert!(finished.is_done());
+        assert!(DataFile::new_from_uuid(finished.uuid()).exists());
+        workflow.remove_node_files();
+    }
+
+    #[tokio::test]
+    async fn test_workflow_validation_runs_nothing() {
+        let nodes = vec![
+            mock_node(&["a"]),
+            node(WorkflowNodeKind::Filter, json!({"key": "name"})),
+            node(WorkflowNodeKind::Join, json!({"mode": "merge_unique"})),
+        ];
+        let edges = vec![edge(0, 1, 0), edge(0, 2, 0), edge(1, 2, 1)];
+        let mut workflow = Workflow::new(nodes, edges, 0);
+        let error = workflow.run_without_db().await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid workflow: Node 1: Filter node: missing parameters operator, value; Node 2: Join node: missing parameters join_key"
+        );
+        assert!(workflow.run.get_node_status(0).is_waiting());
+        assert!(workflow.run.get_node_status(1).is_failed());
+        assert!(workflow.run.get_node_status(2).is_failed());
+    }
+
+    #[tokio::test]
+    async fn test_workflow_cycle() {
+        let nodes = vec![
+            mock_node(&["a"]),
+            node(
+                WorkflowNodeKind::FilterSort,
+                json!({"key": "name", "reverse": false}),
+            ),
+            node(
+                WorkflowNodeKind::FilterSort,
+                json!({"key": "name", "reverse": true}),
+            ),
+        ];
+        let edges = vec![edge(1, 2, 0), edge(2, 1, 0)];
+        let mut workflow = Workflow::new(nodes, edges, 0);
+        let error = workflow.run_without_db().await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid workflow: Cycle between nodes 1, 2"
+        );
+        assert!(workflow.run.get_node_status(0).is_waiting());
+        assert!(workflow.run.get_node_status(1).is_failed());
+        assert!(workflow.run.get_node_status(2).is_failed());
+    }
+
+    #[test]
+    fn test_workflow_input_arity() {
+        let nodes = vec![
+            mock_node(&["a"]),
+            mock_node(&["b"]),
+            node(
+                WorkflowNodeKind::FilterSort,
+                json!({"key": "name", "reverse": false}),
+            ),
+            node(WorkflowNodeKind::PetScan, json!({"psid": 1})),
+        ];
+        let edges = vec![edge(0, 2, 0), edge(1, 2, 0), edge(2, 3, 0)];
+        let mut workflow = Workflow::new(nodes, edges, 0);
+        assert_eq!(
+            workflow.validate().unwrap_err().to_string(),
+            "Invalid workflow: Node 2: FilterSort node: 2 input(s), expects 1; several edges into the same slot; Node 3: PetScan node: 1 input(s), expects none"
+        );
+        workflow.mark_invalid_nodes();
+        assert!(workflow.run.get_node_status(0).is_waiting());
+        assert!(workflow.run.get_node_status(2).is_failed());
+        assert!(workflow.run.get_node_status(3).is_failed());
+    }
+
+    #[test]
+    fn test_workflow_dangling_edge() {
+        let workflow = Workflow::new(vec![mock_node(&["a"])], vec![edge(0, 3, 0)], 0);
+        assert_eq!(
+            workflow.validate().unwrap_err().to_string(),
+            "Invalid workflow: Edge 0 references non-existent node 3"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_workflow_node_timeout() {
+        let slow = node(
+            WorkflowNodeKind::Mock,
+            json!({"rows": [], "delay_ms": 10_000, "timeout_secs": 1}),
+        );
+        let mut workflow = Workflow::new(vec![mock_node(&["a"]), slow], vec![], 0);
+        let error = workflow.run_without_db().await.unwrap_err();
+        assert_eq!(error.to_string(), "Node 1: Timed out after 1 seconds");
+        assert!(workflow.run.get_node_status(1).is_failed());
+        workflow.remove_node_files();
+    }
+
+    #[tokio::test]
+    async fn test_workflow_cancel_mid_node() {
+        let slow = node(
+            WorkflowNodeKind::Mock,
+            json!({"rows": [], "delay_ms": 60_000}),
+        );
+        let mut workflow = Workflow::new(vec![mock_node(&["a"]), slow], vec![], 0);
+        let run_id = workflow.run.get_or_create_id().await.unwrap();
+        let run = workflow.run.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let mut conn = APP.get_db_connection().await.unwrap();
+            run.update_status(WorkflowNodeStatusValue::CANCEL, &mut conn)
+                .await
+                .unwrap();
+        });
+        let started = Instant::now();
+        let error = workflow.run_batch(&[0, 1], started).await.unwrap_err();
+        assert_eq!(error.to_string(), "User cancelled run");
+        assert!(started.elapsed() < Duration::from_secs(CANCEL_CHECK_INTERVAL_SECONDS * 2));
+        for node_id in [0, 1] {
+            let details = serde_json::to_value(workflow.run.get_node_status(node_id)).unwrap();
+            assert_eq!(details["status"], "CANCEL");
+        }
+
+        // Cleanup
+        "DELETE FROM `run` WHERE `id`=?"
+            .with((run_id,))
+            .run(APP.get_db_connection().await.unwrap())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_workflow_max_concurrent_nodes() {
+        let nodes = (0..5)
+            .map(|_| {
+                node(
+                    WorkflowNodeKind::Mock,
+                    json!({"rows": [], "delay_ms": 50, "in_flight_counter": "max_concurrent"}),
+                )
+            })
+            .collect();
+        let mut workflow = Workflow::new(nodes, vec![], 0);
+        workflow.max_concurrent_nodes = Some(2);
+        workflow.run_without_db().await.unwrap();
+        assert_eq!(MOCK_IN_FLIGHT.lock().unwrap()["max_concurrent"], (0, 2));
+        workflow.remove_node_files();
+    }
+
+    #[tokio::test]
+    async fn test_workflow_node_cache() {
+        let nodes = || {
+            vec![
+                node(
+                    WorkflowNodeKind::Mock,
+                    json!({"rows": [[{"PlainText": "a"}]], "cache_secs": 60, "call_counter": "node_cache"}),
+                ),
+                node(
+                    WorkflowNodeKind::FilterSort,
+                    json!({"key": "name", "reverse": false, "cache_secs": 60}),
+                ),
+            ]
+        };
+        let mut first = Workflow::new(nodes(), vec![edge(0, 1, 0)], 0);
+        first.run_without_db().await.unwrap();
+        let mut second = Workflow::new(nodes(), vec![edge(0, 1, 0)], 0);
+        second.run_without_db().await.unwrap();
+        assert_eq!(MOCK_CALLS.lock().unwrap()["node_cache"], 1);
+
+        // Each run gets its own copy of the cached files
+        for node_id in 0..2 {
+            let uuid1 = first.run.get_node_status(node_id).uuid();
+            let uuid2 = second.run.get_node_status(node_id).uuid();
+            assert_ne!(uuid1, uuid2);
+            let read =
+                |uuid: &str| std::fs::read(DataFile::new_from_uuid(uuid).path().unwrap()).unwrap();
+            assert_eq!(read(uuid1), read(uuid2));
+        }
+
+        // Another user does not get the cached result
+        let mut other_user = Workflow::new(nodes(), vec![edge(0, 1, 0)], 1);
+        other_user.run_without_db().await.unwrap();
+        assert_eq!(MOCK_CALLS.lock().unwrap()["node_cache"], 2);
+
+        // Cleanup
+        for workflow in [&second, &other_user] {
+            let context = NodeContext {
+                user_id: workflow.user_id,
+                ..Default::default()
+            };
+            let mock_key = workflow.nodes[0]
+                .cache_key(&HashMap::new(), &context)
+                .unwrap();
+            let input = HashMap::from([(0, workflow.run.get_node_status(0).uuid().to_string())]);
+            let sort_key = workflow.nodes[1].cache_key(&input, &context).unwrap();
+            for key in [mock_key, sort_key] {
+                std::fs::remove_file(APP.node_cache_path(&key)).unwrap();
+            }
+        }
+        // Only removed if no other test has cached something in the meantime
+        let _ = std::fs::remove_dir(APP.node_cache_dir());
+        first.remove_node_files();
+        second.remove_node_files();
+        other_user.remove_node_files();
+    }
+
+    #[tokio::test]
+    async fn test_workflow_exceeds_time_budget() {
+        let nodes = vec![mock_node(&["a"]), mock_node(&["b"])];
+        let mut workflow = Workflow::new(nodes, vec![], 0);
+        workflow.max_run_seconds = Some(0);
+        let error = workflow.run_without_db().await.unwrap_err();
+        assert_eq!(error.to_string(), "Run exceeded time budget of 0 seconds");
+        assert!(workflow.run.get_node_status(0).is_failed());
+        assert!(workflow.run.get_node_status(1).is_failed());
+        workflow.remove_node_files();
+    }
+
+    #[tokio::test]
+    async fn test_workflow_changed_since_last_run() {
+        let nodes = vec![mock_node(&["a"]), mock_node(&["b"])];
+        let mut previous = Workflow::new(nodes, vec![], 0);
+        previous.run_without_db().await.unwrap();
+
+        let nodes = vec![
+            mock_node(&["a"]),
+            node(
+                WorkflowNodeKind::ChangedSinceLastRun,
+                json!({"key": "name", "column": "name"}),
+            ),
+        ];
+        let edges = vec![edge(0, 1, 0), edge(0, 1, PREVIOUS_RUN_SLOT)];
+        let invalid = Workflow::new(nodes.clone(), edges, 0);
+        assert!(invalid
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("reserved"));
+        let mut workflow = Workflow::new(nodes, vec![edge(0, 1, 0)], 0);
+        assert_eq!(workflow.snapshot_nodes(), vec![0]);
+        let previous_uuid = previous.run.get_node_status(1).uuid().to_string();
+        workflow.set_previous_run_files(vec![
+            (0, previous.run.get_node_status(0).uuid().to_string()),
+            (0, previous_uuid.to_owned()),
+            (0, "no-such-file".to_string()),
+            (1, previous_uuid.to_owned()),
+        ]);
+        assert_eq!(workflow.previous_run_files.len(), 1);
+        let inputs = workflow.get_node_inputs(&[1]);
+        assert_eq!(inputs[&1].get(&PREVIOUS_RUN_SLOT), Some(&previous_uuid));
+
+        workflow.run_without_db().await.unwrap();
+        let df = load_file(workflow.run.get_node_status(1).uuid());
+        assert_eq!(df.header().columns.len(), 3);
+        assert!(df.rows.is_empty()); // "a" is not in the previous data
+        previous.remove_node_files();
+        workflow.remove_node_files();
+    }
+}
@@ -1,20 +1,75 @@
 use crate::{
     adapter::*,
-    data_file::DataFileDetails,
-    filter::{Filter, FilterPetScan, FilterSort},
-    generator::Generator,
+    data_file::{DataFile, DataFileDetails},
+    filter::{
+        Filter, FilterColumns, FilterCombine, FilterDedup, FilterNamespace, FilterPetScan,
+        FilterRange, FilterReplace, FilterSort, Select, TopNPerGroup,
+    },
+    generator::{BlockMode, Generator, SectionPosition, DEFAULT_EDIT_SUMMARY},
     join::Join,
     mapping::{HeaderMapping, SourceId},
-    renderer::{Renderer, RendererWikitext},
+    renderer::{Renderer, RendererQuickStatements, RendererWikitext},
+    sub_workflow::SubWorkflow,
+    transform::{
+        Compute, ConstraintCheck, ExternalIdResolve, PageInfo, ParseCoordinate, RowNumber,
+        SitelinkResolve, ToggleTalk, WikidataLabels,
+    },
+    APP,
 };
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::HashMap;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{BufRead, BufReader},
+    time::Duration,
+};
+use tracing::warn;
+
+// Upper limit for `timeout_secs`, and the timeout of nodes that do not set one
+pub const MAX_NODE_DURATION_SECONDS: u64 = 60 * 60;
+
+// Upper limit for `cache_secs`; intermediate files expire after an hour anyway
+pub const NODE_CACHE_MAX_AGE_SECONDS: u64 = 60 * 60;
+
+#[cfg(test)]
+lazy_static::lazy_static! {
+    // Number of runs of Mock nodes, by their `call_counter` parameter
+    pub static ref MOCK_CALLS: std::sync::Mutex<HashMap<String, usize>> = Default::default();
+    // (currently running, most at once) Mock nodes, by their `in_flight_counter` parameter
+    pub static ref MOCK_IN_FLIGHT: std::sync::Mutex<HashMap<String, (usize, usize)>> =
+        Default::default();
+}
+
+// Input slot that receives the previous run's data for nodes that compare with it; edges can not
+// use it
+pub const PREVIOUS_RUN_SLOT: usize = usize::MAX;
+
+// What a node gets to know about the run it is part of
+#[derive(Debug, Clone, Default)]
+pub struct NodeContext {
+    pub user_id: usize,
+    // Start of the latest successful run of the workflow, for incremental fetching
+    pub last_success: Option<DateTime<Utc>>,
+}
+
+// Types of node parameters, as accepted by the `param_*` getters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamType {
+    String,
+    U64,
+    Number,
+    Bool,
+    StringList,
+    Any,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum WorkflowNodeKind {
-    // QuarryQueryRun,
+    QuarryQueryRun,
     QuarryQueryLatest,
     Sparql,
     PetScan,
@@ -22,11 +77,37 @@ pub enum WorkflowNodeKind {
     AListBuildingTool,
     UserEdits,
     WdFist,
+    WikiTable,
+    CommonsCategory,
+    CsvUrl,
+    JsonUrl,
     Join,
     Filter,
     FilterPetScan,
     FilterSort,
+    FilterNamespace,
+    FilterColumns,
+    #[serde(alias = "FilterDistinct")]
+    FilterDedup,
+    FilterReplace,
+    FilterRange,
+    Select,
+    TopNPerGroup,
+    ToggleTalk,
+    RowNumber,
+    ParseCoordinate,
+    ConstraintCheck,
+    ExternalIdResolve,
+    SitelinkResolve,
+    PageInfo,
+    WikidataLabels,
+    Compute,
+    SubWorkflow,
+    ChangedSinceLastRun,
+    MembershipMatrix,
     Generator,
+    #[cfg(test)]
+    Mock, // Emits the `rows` parameter (or fails with `error`) after `delay_ms`, for testing without network/DB
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,10 +118,36 @@ pub struct WorkflowNode {
 }
 
 impl WorkflowNode {
+    // Reuses the output of an earlier run with the same parameters and input data, if any
+    pub async fn run_cached(
+        &self,
+        input: &HashMap<usize, String>,
+        context: &NodeContext,
+    ) -> Result<DataFileDetails> {
+        let cache = match self.cache_max_age() {
+            Some(max_age) => Some((self.cache_key(input, context)?, max_age)),
+            None => None,
+        };
+        if let Some((key, max_age)) = &cache {
+            if let Some(dfd) = APP.load_cached_node_result(key, *max_age) {
+                return Ok(dfd);
+            }
+        }
+        let dfd = self.run(input, context).await?;
+        if let Some((key, _max_age)) = &cache {
+            if dfd.is_valid() {
+                if let Err(e) = APP.store_cached_node_result(key, &dfd) {
+                    warn!(key, error = %e, "Could not write node cache");
+                }
+            }
+        }
+        Ok(dfd)
+    }
+
     pub async fn run(
         &self,
         input: &HashMap<usize, String>,
-        user_id: usize,
+        context: &NodeContext,
     ) -> Result<DataFileDetails> {
         match self.kind {
             WorkflowNodeKind::QuarryQueryLatest => {
@@ -49,28 +156,44 @@ impl WorkflowNode {
                     .source2file(&SourceId::QuarryQueryLatest(id), &self.header_mapping)
                     .await
             }
+            WorkflowNodeKind::QuarryQueryRun => {
+                let id = self.param_u64("quarry_query_id")?;
+                QuarryQueryRunAdapter::default()
+                    .source2file(&SourceId::QuarryQueryRun(id), &self.header_mapping)
+                    .await
+            }
             WorkflowNodeKind::Sparql => {
                 let sparql = self.param_string("sparql")?;
-                SparqlAdapter::default()
+                // Preset name or URL of the query service; defaults to Wikidata
+                let mut adapter = SparqlAdapter {
+                    endpoint: self.param_string("endpoint").unwrap_or_default(),
+                    ..Default::default()
+                };
+                // Optional input, to fill a {{values:?var}} placeholder from a column
+                if let Some(uuid) = input.values().next() {
+                    adapter.values =
+                        adapter.values_from_file(uuid, &self.param_string("values_key")?)?;
+                }
+                adapter
                     .source2file(&SourceId::Sparql(sparql), &self.header_mapping)
                     .await
             }
             WorkflowNodeKind::PetScan => {
                 let id = self.param_u64("psid")?;
                 PetScanAdapter::default()
-                    .source2file(&&SourceId::PetScan(id), &self.header_mapping)
+                    .source2file(&SourceId::PetScan(id), &self.header_mapping)
                     .await
             }
             WorkflowNodeKind::PagePile => {
                 let id = self.param_u64("pagepile_id")?;
                 PagePileAdapter::default()
-                    .source2file(&&SourceId::PagePile(id), &self.header_mapping)
+                    .source2file(&SourceId::PagePile(id), &self.header_mapping)
                     .await
             }
             WorkflowNodeKind::WdFist => {
                 let url = self.param_string("wdfist_url")?;
                 WdFistAdapter::default()
-                    .source2file(&&SourceId::WdFist(url), &self.header_mapping)
+                    .source2file(&SourceId::WdFist(url), &self.header_mapping)
                     .await
             }
             WorkflowNodeKind::AListBuildingTool => {
@@ -78,13 +201,54 @@ impl WorkflowNode {
                 let qid = self.param_string("qid")?;
                 let id = (wiki, qid);
                 AListBuildingToolAdapter::default()
-                    .source2file(&&SourceId::AListBuildingTool(id), &self.header_mapping)
+                    .source2file(&SourceId::AListBuildingTool(id), &self.header_mapping)
                     .await
             }
             WorkflowNodeKind::UserEdits => {
                 let url = self.param_string("user_edits_url")?;
-                UserEditsAdapter::default()
-                    .source2file(&&SourceId::UserEdits(url), &self.header_mapping)
+                // Only edits since the last successful run, if there was one
+                let start = match self.param_bool("since_last_success").unwrap_or(false) {
+                    true => context
+                        .last_success
+                        .map(|ts| ts.format("%Y%m%d%H%M%S").to_string()),
+                    false => None,
+                };
+                UserEditsAdapter { start }
+                    .source2file(&SourceId::UserEdits(url), &self.header_mapping)
+                    .await
+            }
+            WorkflowNodeKind::WikiTable => {
+                let source = SourceId::WikiTable {
+                    wiki: self.param_string("wiki")?,
+                    page: self.param_string("page")?,
+                    table_index: self.param_u64("table_index").unwrap_or(0) as usize,
+                };
+                WikiTableAdapter::default()
+                    .source2file(&source, &self.header_mapping)
+                    .await
+            }
+            WorkflowNodeKind::CommonsCategory => {
+                let source = SourceId::CommonsCategory {
+                    category: self.param_string("category")?,
+                    depth: self.param_u64("depth").unwrap_or(0) as u32,
+                };
+                CommonsCategoryAdapter::default()
+                    .source2file(&source, &self.header_mapping)
+                    .await
+            }
+            WorkflowNodeKind::CsvUrl => {
+                let url = self.param_string("url")?;
+                CsvUrlAdapter::default()
+                    .source2file(&SourceId::CsvUrl(url), &self.header_mapping)
+                    .await
+            }
+            WorkflowNodeKind::JsonUrl => {
+                let source = SourceId::JsonUrl {
+                    url: self.param_string("url")?,
+                    rows_path: self.param_string("rows_path").unwrap_or_default(),
+                };
+                JsonUrlAdapter::default()
+                    .source2file(&source, &self.header_mapping)
                     .await
             }
             WorkflowNodeKind::Join => {
@@ -92,15 +256,73 @@ impl WorkflowNode {
                 match mode.as_str() {
                     "inner_join_on_key" => {
                         let join_key = self.param_string("join_key")?;
-                        let uuids: Vec<&str> =
-                            input.iter().map(|(_slot, uuid)| uuid.as_str()).collect();
-                        Join::default().inner_join_on_key(uuids, &join_key)
+                        // Rows are written in the order of slot 0
+                        let join = Join {
+                            keep_order: true,
+                            ..Default::default()
+                        };
+                        join.inner_join_on_key(Self::uuids_by_priority(input, &[]), &join_key)
+                    }
+                    "left_outer_join_on_key" => {
+                        let join_key = self.param_string("join_key")?;
+                        // Slot 0 is the primary input, the others add columns
+                        let join = Join {
+                            keep_order: true,
+                            ..Default::default()
+                        };
+                        join.left_outer_join_on_key(Self::uuids_by_priority(input, &[]), &join_key)
+                    }
+                    "anti_join_on_key" => {
+                        let join_key = self.param_string("join_key")?;
+                        // Rows of slot 0 whose key is not in any other input
+                        let join = Join {
+                            keep_order: true,
+                            ..Default::default()
+                        };
+                        join.anti_join_on_key(Self::uuids_by_priority(input, &[]), &join_key)
+                    }
+                    "concat" => {
+                        // Rows are written in slot order
+                        let uuids = Self::uuids_by_priority(input, &[]);
+                        let join = Join {
+                            by_position: self.param_bool("by_position").unwrap_or(false),
+                            rename: self.param_string_list("rename").unwrap_or_default(),
+                            keep_order: true,
+                            source_labels: self.source_labels(input, &uuids),
+                        };
+                        join.concat(uuids)
                     }
                     "merge_unique" => {
                         let join_key = self.param_string("join_key")?;
-                        let uuids: Vec<&str> =
-                            input.iter().map(|(_slot, uuid)| uuid.as_str()).collect();
-                        Join::default().merge_unique(uuids, &join_key)
+                        // Slots listed in `priority` win on duplicate keys, from first to last
+                        let priority = match self.param_string_list("priority") {
+                            Ok(slots) => Some(
+                                slots
+                                    .iter()
+                                    .map(|slot| {
+                                        slot.parse::<usize>().map_err(|_| {
+                                            self.param_error(
+                                                "priority",
+                                                "list of slots",
+                                                &json!(slot),
+                                            )
+                                        })
+                                    })
+                                    .collect::<Result<Vec<usize>>>()?,
+                            ),
+                            Err(_) => None,
+                        };
+                        let uuids: Vec<&str> = match &priority {
+                            Some(priority) => Self::uuids_by_priority(input, priority),
+                            None => input.values().map(|uuid| uuid.as_str()).collect(),
+                        };
+                        let join = Join {
+                            by_position: self.param_bool("by_position").unwrap_or(false),
+                            rename: self.param_string_list("rename").unwrap_or_default(),
+                            keep_order: priority.is_some(),
+                            source_labels: self.source_labels(input, &uuids),
+                        };
+                        join.merge_unique(uuids, &join_key)
                     }
                     other => Err(anyhow!("Unknown join mode '{other}'")),
                 }
@@ -114,23 +336,55 @@ impl WorkflowNode {
                         .map_err(|_| anyhow!("Invaid operator {operator}"))?,
                     value: self.param_string("value")?,
                     remove_matching: self.param_bool("remove_matching").unwrap_or(false),
+                    predicates: match self.param("predicates") {
+                        Ok(predicates) => serde_json::from_value(predicates.to_owned())
+                            .map_err(|e| anyhow!("Filter node: invalid predicates: {e}"))?,
+                        Err(_) => vec![],
+                    },
+                    combine: match self.param("combine") {
+                        Ok(combine) => serde_json::from_value(combine.to_owned())
+                            .map_err(|_| anyhow!("Invalid combine {combine}"))?,
+                        Err(_) => FilterCombine::default(),
+                    },
                 };
-                let uuids: Vec<&str> = input.iter().map(|(_slot, uuid)| uuid.as_str()).collect();
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
                 match uuids.len() {
                     0 => Err(anyhow!("Filter has no input")),
-                    1 => filter.process(&uuids[0]).await,
+                    1 => filter.process(uuids[0]).await,
                     other => Err(anyhow!("Filter has {other} inputs, should only have one")),
                 }
             }
+            WorkflowNodeKind::ChangedSinceLastRun => {
+                let uuid = input
+                    .get(&0)
+                    .ok_or_else(|| anyhow!("ChangedSinceLastRun has no input"))?;
+                let previous_uuid = input.get(&PREVIOUS_RUN_SLOT).map(|uuid| uuid.as_str());
+                let key = self.param_string("key")?;
+                let column = self.param_string("column")?;
+                Join::default().changed_values(uuid, previous_uuid, &key, &column)
+            }
+            WorkflowNodeKind::MembershipMatrix => {
+                let key = self.param_string("key")?;
+                let uuids = Self::uuids_by_priority(input, &[]);
+                // One Boolean column per input, named after its slot unless given
+                let mut slots: Vec<&usize> = input.keys().collect();
+                slots.sort();
+                let names = match self.param_string_list("names") {
+                    Ok(names) => names,
+                    Err(_) => slots.iter().map(|slot| format!("in_{slot}")).collect(),
+                };
+                Join::default().membership_matrix(uuids, &key, &names)
+            }
             WorkflowNodeKind::FilterSort => {
                 let filter = FilterSort {
                     key: self.param_string("key")?,
                     reverse: self.param_bool("reverse")?, //self.param_u64("reverse")?>0,
+                    numeric: self.param_bool("numeric").unwrap_or(false),
                 };
-                let uuids: Vec<&str> = input.iter().map(|(_slot, uuid)| uuid.as_str()).collect();
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
                 match uuids.len() {
                     0 => Err(anyhow!("FilterSort has no input")),
-                    1 => filter.process(&uuids[0]).await,
+                    1 => filter.process(uuids[0]).await,
                     other => Err(anyhow!(
                         "FilterSort has {other} inputs, should only have one"
                     )),
@@ -141,64 +395,920 @@ impl WorkflowNode {
                     key: self.param_string("key")?,
                     psid: self.param_u64("psid")?,
                 };
-                let uuids: Vec<&str> = input.iter().map(|(_slot, uuid)| uuid.as_str()).collect();
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
                 match uuids.len() {
                     0 => Err(anyhow!("FilterPetScan has no input")),
-                    1 => filter.process(&uuids[0]).await,
+                    1 => filter.process(uuids[0]).await,
                     other => Err(anyhow!(
                         "FilterPetScan has {other} inputs, should only have one"
                     )),
                 }
             }
+            WorkflowNodeKind::FilterNamespace => {
+                // Namespaces can be given as IDs or as (local or canonical) names
+                let (ns_ids, ns_names): (Vec<String>, Vec<String>) = self
+                    .param_string_list("namespaces")?
+                    .into_iter()
+                    .filter(|ns| !ns.is_empty())
+                    .partition(|ns| ns.parse::<i64>().is_ok());
+                let filter = FilterNamespace {
+                    key: self.param_string("key")?,
+                    ns_ids: ns_ids.iter().filter_map(|ns| ns.parse().ok()).collect(),
+                    ns_names,
+                    remove_matching: self.param_bool("remove_matching").unwrap_or(false),
+                };
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
+                match uuids.len() {
+                    0 => Err(anyhow!("FilterNamespace has no input")),
+                    1 => filter.process(uuids[0]).await,
+                    other => Err(anyhow!(
+                        "FilterNamespace has {other} inputs, should only have one"
+                    )),
+                }
+            }
+            WorkflowNodeKind::FilterColumns => {
+                // An empty list counts as not given
+                let columns = |key| {
+                    self.param_string_list(key)
+                        .ok()
+                        .map(|names| names.into_iter().filter(|name| !name.is_empty()).collect())
+                        .filter(|names: &Vec<String>| !names.is_empty())
+                };
+                let filter = FilterColumns {
+                    keep: columns("keep"),
+                    drop: columns("drop"),
+                };
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
+                match uuids.len() {
+                    0 => Err(anyhow!("FilterColumns has no input")),
+                    1 => filter.process(uuids[0]).await,
+                    other => Err(anyhow!(
+                        "FilterColumns has {other} inputs, should only have one"
+                    )),
+                }
+            }
+            WorkflowNodeKind::FilterDedup => {
+                let filter = FilterDedup {
+                    keys: self.param_string_list("keys").unwrap_or_default(),
+                };
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
+                match uuids.len() {
+                    0 => Err(anyhow!("FilterDedup has no input")),
+                    1 => filter.process(uuids[0]).await,
+                    other => Err(anyhow!(
+                        "FilterDedup has {other} inputs, should only have one"
+                    )),
+                }
+            }
+            WorkflowNodeKind::FilterReplace => {
+                let filter = FilterReplace {
+                    key: self.param_string("key")?,
+                    subkey: self.param_string("subkey").ok().filter(|s| !s.is_empty()),
+                    pattern: self.param_string("pattern")?,
+                    replacement: self.param_string("replacement")?,
+                };
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
+                match uuids.len() {
+                    0 => Err(anyhow!("FilterReplace has no input")),
+                    1 => filter.process(uuids[0]).await,
+                    other => Err(anyhow!(
+                        "FilterReplace has {other} inputs, should only have one"
+                    )),
+                }
+            }
+            WorkflowNodeKind::FilterRange => {
+                let filter = FilterRange {
+                    key: self.param_string("key")?,
+                    subkey: self.param_string("subkey").ok().filter(|s| !s.is_empty()),
+                    min: self.param_f64("min")?,
+                    max: self.param_f64("max")?,
+                    inclusive: self.param_bool("inclusive").unwrap_or(false),
+                };
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
+                match uuids.len() {
+                    0 => Err(anyhow!("FilterRange has no input")),
+                    1 => filter.process(uuids[0]).await,
+                    other => Err(anyhow!(
+                        "FilterRange has {other} inputs, should only have one"
+                    )),
+                }
+            }
+            WorkflowNodeKind::Select => {
+                let filters: Vec<Filter> = match self.param("filters") {
+                    Ok(filters) => serde_json::from_value(filters.to_owned())
+                        .map_err(|e| anyhow!("Select node: invalid filters: {e}"))?,
+                    Err(_) => vec![],
+                };
+                let select = Select {
+                    filters,
+                    columns: self.param_string_list("columns").unwrap_or_default(),
+                    sort: self.param_string("sort").ok().map(|key| FilterSort {
+                        key,
+                        reverse: self.param_bool("reverse").unwrap_or(false),
+                        numeric: self.param_bool("numeric").unwrap_or(false),
+                    }),
+                };
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
+                match uuids.len() {
+                    0 => Err(anyhow!("Select has no input")),
+                    1 => select.process(uuids[0]).await,
+                    other => Err(anyhow!("Select has {other} inputs, should only have one")),
+                }
+            }
+            WorkflowNodeKind::TopNPerGroup => {
+                let filter = TopNPerGroup {
+                    group_keys: self.param_string_list("group_keys")?,
+                    order_key: self.param_string("order_key")?,
+                    n: self.param_u64("n")? as usize,
+                    reverse: self.param_bool("reverse").unwrap_or(false),
+                };
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
+                match uuids.len() {
+                    0 => Err(anyhow!("TopNPerGroup has no input")),
+                    1 => filter.process(uuids[0]).await,
+                    other => Err(anyhow!(
+                        "TopNPerGroup has {other} inputs, should only have one"
+                    )),
+                }
+            }
+            WorkflowNodeKind::ToggleTalk => {
+                let transform = ToggleTalk {
+                    key: self.param_string("key")?,
+                    target: self.param_string("target").ok().filter(|s| !s.is_empty()),
+                };
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
+                match uuids.len() {
+                    0 => Err(anyhow!("ToggleTalk has no input")),
+                    1 => transform.process(uuids[0]).await,
+                    other => Err(anyhow!(
+                        "ToggleTalk has {other} inputs, should only have one"
+                    )),
+                }
+            }
+            WorkflowNodeKind::RowNumber => {
+                let transform = RowNumber {
+                    name: self
+                        .param_string("name")
+                        .unwrap_or_else(|_| "row_index".to_string()),
+                    start: self.param_u64("start").unwrap_or(0) as i64,
+                    prepend: self.param_bool("prepend").unwrap_or(false),
+                };
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
+                match uuids.len() {
+                    0 => Err(anyhow!("RowNumber has no input")),
+                    1 => transform.process(uuids[0]).await,
+                    other => Err(anyhow!(
+                        "RowNumber has {other} inputs, should only have one"
+                    )),
+                }
+            }
+            WorkflowNodeKind::ParseCoordinate => {
+                let transform = ParseCoordinate {
+                    key: self.param_string("key")?,
+                    target: self.param_string("target").ok().filter(|s| !s.is_empty()),
+                };
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
+                match uuids.len() {
+                    0 => Err(anyhow!("ParseCoordinate has no input")),
+                    1 => transform.process(uuids[0]).await,
+                    other => Err(anyhow!(
+                        "ParseCoordinate has {other} inputs, should only have one"
+                    )),
+                }
+            }
+            WorkflowNodeKind::ConstraintCheck => {
+                let transform = ConstraintCheck {
+                    key: self.param_string("key")?,
+                    property: self.param_string("property")?.to_uppercase(),
+                    target: self
+                        .param_string("target")
+                        .unwrap_or_else(|_| "constraint_ok".to_string()),
+                };
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
+                match uuids.len() {
+                    0 => Err(anyhow!("ConstraintCheck has no input")),
+                    1 => transform.process(uuids[0]).await,
+                    other => Err(anyhow!(
+                        "ConstraintCheck has {other} inputs, should only have one"
+                    )),
+                }
+            }
+            WorkflowNodeKind::ExternalIdResolve => {
+                let transform = ExternalIdResolve {
+                    key: self.param_string("key")?,
+                    property: self.param_string("property")?.to_uppercase(),
+                    target: self
+                        .param_string("target")
+                        .unwrap_or_else(|_| "wikidata_item".to_string()),
+                };
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
+                match uuids.len() {
+                    0 => Err(anyhow!("ExternalIdResolve has no input")),
+                    1 => transform.process(uuids[0]).await,
+                    other => Err(anyhow!(
+                        "ExternalIdResolve has {other} inputs, should only have one"
+                    )),
+                }
+            }
+            WorkflowNodeKind::SitelinkResolve => {
+                let transform = SitelinkResolve {
+                    key: self.param_string("key")?,
+                    wiki: self.param_string("wiki")?,
+                };
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
+                match uuids.len() {
+                    0 => Err(anyhow!("SitelinkResolve has no input")),
+                    1 => transform.process(uuids[0]).await,
+                    other => Err(anyhow!(
+                        "SitelinkResolve has {other} inputs, should only have one"
+                    )),
+                }
+            }
+            WorkflowNodeKind::PageInfo => {
+                let transform = PageInfo {
+                    key: self.param_string("key")?,
+                };
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
+                match uuids.len() {
+                    0 => Err(anyhow!("PageInfo has no input")),
+                    1 => transform.process(uuids[0]).await,
+                    other => Err(anyhow!("PageInfo has {other} inputs, should only have one")),
+                }
+            }
+            WorkflowNodeKind::WikidataLabels => {
+                let transform = WikidataLabels {
+                    key: self.param_string("key")?,
+                    // Fallback chain, separated by `|` or `,`
+                    languages: self
+                        .param_string_list("language")?
+                        .iter()
+                        .flat_map(|languages| languages.split('|'))
+                        .map(|language| language.trim().to_string())
+                        .filter(|language| !language.is_empty())
+                        .collect(),
+                    name: self
+                        .param_string("name")
+                        .unwrap_or_else(|_| "label".to_string()),
+                };
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
+                match uuids.len() {
+                    0 => Err(anyhow!("WikidataLabels has no input")),
+                    1 => transform.process(uuids[0]).await,
+                    other => Err(anyhow!(
+                        "WikidataLabels has {other} inputs, should only have one"
+                    )),
+                }
+            }
+            WorkflowNodeKind::Compute => {
+                let transform = Compute {
+                    name: self.param_string("name")?,
+                    expression: self.param_string("expression")?,
+                };
+                let uuids: Vec<&str> = input.values().map(|uuid| uuid.as_str()).collect();
+                match uuids.len() {
+                    0 => Err(anyhow!("Compute has no input")),
+                    1 => transform.process(uuids[0]).await,
+                    other => Err(anyhow!("Compute has {other} inputs, should only have one")),
+                }
+            }
+            WorkflowNodeKind::SubWorkflow => {
+                let parameter_overrides = match self.param("parameter_overrides") {
+                    Ok(v) => serde_json::from_value(v.to_owned()).map_err(|e| {
+                        anyhow!("SubWorkflow node: invalid parameter_overrides: {e}")
+                    })?,
+                    Err(_) => HashMap::new(),
+                };
+                let sub_workflow = SubWorkflow {
+                    workflow_id: self.param_u64("workflow_id")? as usize,
+                    parameter_overrides,
+                    output_node: self.param_u64("output_node").ok().map(|n| n as usize),
+                };
+                sub_workflow.process(context.user_id).await
+            }
+            #[cfg(test)]
+            WorkflowNodeKind::Mock => {
+                if let Ok(counter) = self.param_string("call_counter") {
+                    *MOCK_CALLS.lock().unwrap().entry(counter).or_default() += 1;
+                }
+                let in_flight_counter = self.param_string("in_flight_counter").ok();
+                if let Some(counter) = &in_flight_counter {
+                    let mut in_flight = MOCK_IN_FLIGHT.lock().unwrap();
+                    let (running, most) = in_flight.entry(counter.to_owned()).or_default();
+                    *running += 1;
+                    *most = (*most).max(*running);
+                }
+                if let Ok(delay_ms) = self.param_u64("delay_ms") {
+                    tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
+                }
+                if let Some(counter) = &in_flight_counter {
+                    MOCK_IN_FLIGHT
+                        .lock()
+                        .unwrap()
+                        .entry(counter.to_owned())
+                        .or_default()
+                        .0 -= 1;
+                }
+                if let Ok(error) = self.param_string("error") {
+                    return Err(anyhow!(error));
+                }
+                let rows: Vec<Vec<crate::data_cell::DataCell>> =
+                    serde_json::from_value(self.param("rows")?.to_owned())?;
+                let mut file = crate::data_file::DataFile::new_output_file()?;
+                file.write_json_row(&serde_json::json! {self.header_mapping.as_data_header()})?; // Output new header
+                for row in rows {
+                    file.write_json_row(&serde_json::json! {row})?; // Output data row
+                }
+                file.finish()
+            }
             WorkflowNodeKind::Generator => {
                 let mode = self.param_string("mode")?;
                 match mode.as_str() {
                     "wikipage" => {
                         let uuid = input
-                            .iter()
-                            .map(|(_slot, uuid)| uuid.as_str())
+                            .values()
+                            .map(|uuid| uuid.as_str())
                             .next()
                             .ok_or_else(|| anyhow!("No inputs for this node"))?;
                         let wiki = self.param_string("wiki")?;
-                        let page = self.param_string("page")?;
-                        let wikitext = RendererWikitext::default().render_from_uuid(&uuid)?;
-                        Generator::wikipage(&wikitext, &wiki, &page, user_id).await
+                        let page = self.generator_page()?;
+                        let wikitext = self.wikitext_renderer(&wiki)?.render_from_uuid(uuid)?;
+                        let section_id = self.param_string("section_id").ok();
+                        let block_mode = match self.param_string("edit_mode").as_deref() {
+                            Ok("replace") | Err(_) => BlockMode::Replace,
+                            Ok("append") => BlockMode::Append,
+                            Ok("prepend") => BlockMode::Prepend,
+                            Ok(other) => return Err(anyhow!("Unknown edit mode '{other}'")),
+                        };
+                        Generator::wikipage(
+                            &wikitext,
+                            &wiki,
+                            &page,
+                            section_id.as_deref(),
+                            block_mode,
+                            &self.generator_summary(uuid)?,
+                            context.user_id,
+                        )
+                        .await
                     }
-                    other => Err(anyhow!("Unknown join mode '{other}'")),
+                    "append_section" => {
+                        let uuid = input
+                            .values()
+                            .map(|uuid| uuid.as_str())
+                            .next()
+                            .ok_or_else(|| anyhow!("No inputs for this node"))?;
+                        let wiki = self.param_string("wiki")?;
+                        let page = self.generator_page()?;
+                        let header = self
+                            .param_string("header")
+                            .unwrap_or_else(|_| "Report {date}".to_string());
+                        let position = match self.param_string("position").as_deref() {
+                            Ok("top") => SectionPosition::Top,
+                            Ok("bottom") | Err(_) => SectionPosition::Bottom,
+                            Ok(other) => return Err(anyhow!("Unknown section position '{other}'")),
+                        };
+                        let wikitext = self.wikitext_renderer(&wiki)?.render_from_uuid(uuid)?;
+                        Generator::append_section(
+                            &wikitext,
+                            &wiki,
+                            &page,
+                            &header,
+                            position,
+                            &self.generator_summary(uuid)?,
+                            context.user_id,
+                        )
+                        .await
+                    }
+                    "quickstatements" => {
+                        let uuid = input
+                            .values()
+                            .map(|uuid| uuid.as_str())
+                            .next()
+                            .ok_or_else(|| anyhow!("No inputs for this node"))?;
+                        let renderer = RendererQuickStatements {
+                            columns: self.param_string_list("columns").ok(),
+                        };
+                        let commands = renderer.render_from_uuid(uuid)?;
+                        Generator::quickstatements(
+                            &commands,
+                            &self.generator_summary(uuid)?,
+                            context.user_id,
+                        )
+                        .await
+                    }
+                    other => Err(anyhow!("Unknown generator mode '{other}'")),
                 }
             }
         }
     }
 
+    // Minimum and maximum number of incoming edges; sources have none, filters exactly one
+    pub fn input_arity(&self) -> (usize, Option<usize>) {
+        match self.kind {
+            WorkflowNodeKind::Sparql => (0, Some(1)), // Optional input for {{values:?var}}
+            WorkflowNodeKind::QuarryQueryLatest
+            | WorkflowNodeKind::QuarryQueryRun
+            | WorkflowNodeKind::PetScan
+            | WorkflowNodeKind::PagePile
+            | WorkflowNodeKind::AListBuildingTool
+            | WorkflowNodeKind::UserEdits
+            | WorkflowNodeKind::WdFist
+            | WorkflowNodeKind::WikiTable
+            | WorkflowNodeKind::CommonsCategory
+            | WorkflowNodeKind::CsvUrl
+            | WorkflowNodeKind::JsonUrl
+            | WorkflowNodeKind::SubWorkflow => (0, Some(0)),
+            WorkflowNodeKind::Join => (2, None),
+            WorkflowNodeKind::MembershipMatrix => (1, None),
+            WorkflowNodeKind::Filter
+            | WorkflowNodeKind::FilterPetScan
+            | WorkflowNodeKind::FilterSort
+            | WorkflowNodeKind::FilterNamespace
+            | WorkflowNodeKind::FilterColumns
+            | WorkflowNodeKind::FilterDedup
+            | WorkflowNodeKind::FilterReplace
+            | WorkflowNodeKind::FilterRange
+            | WorkflowNodeKind::Select
+            | WorkflowNodeKind::TopNPerGroup
+            | WorkflowNodeKind::ToggleTalk
+            | WorkflowNodeKind::RowNumber
+            | WorkflowNodeKind::ParseCoordinate
+            | WorkflowNodeKind::ConstraintCheck
+            | WorkflowNodeKind::ExternalIdResolve
+            | WorkflowNodeKind::SitelinkResolve
+            | WorkflowNodeKind::PageInfo
+            | WorkflowNodeKind::WikidataLabels
+            | WorkflowNodeKind::Compute
+            | WorkflowNodeKind::ChangedSinceLastRun
+            | WorkflowNodeKind::Generator => (1, Some(1)),
+            #[cfg(test)]
+            WorkflowNodeKind::Mock => (0, Some(0)),
+        }
+    }
+
+    pub fn validate_inputs(&self, inputs: usize) -> Result<()> {
+        let expected = match self.input_arity() {
+            (min, Some(max)) if inputs >= min && inputs <= max => return Ok(()),
+            (min, None) if inputs >= min => return Ok(()),
+            (0, Some(0)) => "none".to_string(),
+            (min, Some(max)) if min == max => format!("{min}"),
+            (min, Some(max)) => format!("{min} to {max}"),
+            (min, None) => format!("at least {min}"),
+        };
+        Err(anyhow!(
+            "{:?} node: {inputs} input(s), expects {expected}",
+            self.kind
+        ))
+    }
+
+    // Only nodes that set `cache_secs` are cached; nodes with side effects, or whose output depends
+    // on more than their inputs, never are
+    fn cache_max_age(&self) -> Option<Duration> {
+        if matches!(
+            self.kind,
+            WorkflowNodeKind::Generator
+                | WorkflowNodeKind::SubWorkflow
+                | WorkflowNodeKind::ChangedSinceLastRun
+                | WorkflowNodeKind::QuarryQueryRun
+        ) {
+            return None;
+        }
+        let seconds = self
+            .param_u64("cache_secs")
+            .ok()?
+            .min(NODE_CACHE_MAX_AGE_SECONDS);
+        (seconds > 0).then(|| Duration::from_secs(seconds))
+    }
+
+    // SHA-256 of kind, parameters, header mapping and context as JSON, and of the contents of the
+    // input files by slot; stable across builds, unlike the std hashers
+    pub fn cache_key(
+        &self,
+        input: &HashMap<usize, String>,
+        context: &NodeContext,
+    ) -> Result<String> {
+        let mut hasher = Sha256::new();
+        let parameters: BTreeMap<&String, &Value> = self.parameters.iter().collect();
+        let description = json!({
+            "kind": format!("{:?}", self.kind),
+            "parameters": parameters,
+            "header_mapping": self.header_mapping,
+            "user_id": context.user_id,
+            "last_success": context.last_success.map(|ts| ts.timestamp()),
+        });
+        hasher.update(description.to_string());
+        let mut slots: Vec<&usize> = input.keys().collect();
+        slots.sort();
+        for slot in slots {
+            hasher.update(format!("\n{slot}\n"));
+            let path = DataFile::new_from_uuid(&input[slot])
+                .path()
+                .ok_or_else(|| anyhow!("No path for input file {}", input[slot]))?;
+            let mut reader = BufReader::new(File::open(path)?);
+            loop {
+                let buffer = reader.fill_buf()?;
+                if buffer.is_empty() {
+                    break;
+                }
+                hasher.update(buffer);
+                let length = buffer.len();
+                reader.consume(length);
+            }
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    // `timeout_secs` can only lower the global limit
+    pub fn timeout(&self) -> std::time::Duration {
+        let seconds = self
+            .param_u64("timeout_secs")
+            .unwrap_or(MAX_NODE_DURATION_SECONDS)
+            .clamp(1, MAX_NODE_DURATION_SECONDS);
+        std::time::Duration::from_secs(seconds)
+    }
+
+    // Parameters a node cannot run without, so a workflow can be checked before it runs
+    pub fn required_params(&self) -> Vec<(&'static str, ParamType)> {
+        use ParamType::*;
+        match self.kind {
+            WorkflowNodeKind::QuarryQueryLatest | WorkflowNodeKind::QuarryQueryRun => {
+                vec![("quarry_query_id", U64)]
+            }
+            WorkflowNodeKind::Sparql => vec![("sparql", String)],
+            WorkflowNodeKind::PetScan => vec![("psid", U64)],
+            WorkflowNodeKind::PagePile => vec![("pagepile_id", U64)],
+            WorkflowNodeKind::AListBuildingTool => vec![("wiki", String), ("qid", String)],
+            WorkflowNodeKind::UserEdits => vec![("user_edits_url", String)],
+            WorkflowNodeKind::WdFist => vec![("wdfist_url", String)],
+            WorkflowNodeKind::WikiTable => vec![("wiki", String), ("page", String)],
+            WorkflowNodeKind::CommonsCategory => vec![("category", String)],
+            WorkflowNodeKind::CsvUrl | WorkflowNodeKind::JsonUrl => vec![("url", String)],
+            WorkflowNodeKind::Join
+                if self.param_string("mode").ok().as_deref() == Some("concat") =>
+            {
+                vec![("mode", String)]
+            }
+            WorkflowNodeKind::Join => vec![("mode", String), ("join_key", String)],
+            WorkflowNodeKind::Filter => {
+                vec![("key", String), ("operator", Any), ("value", String)]
+            }
+            WorkflowNodeKind::FilterPetScan => vec![("key", String), ("psid", U64)],
+            WorkflowNodeKind::FilterSort => vec![("key", String), ("reverse", Bool)],
+            WorkflowNodeKind::FilterNamespace => {
+                vec![("key", String), ("namespaces", StringList)]
+            }
+            WorkflowNodeKind::FilterReplace => vec![
+                ("key", String),
+                ("pattern", String),
+                ("replacement", String),
+            ],
+            WorkflowNodeKind::FilterRange => {
+                vec![("key", String), ("min", Number), ("max", Number)]
+            }
+            WorkflowNodeKind::TopNPerGroup => vec![
+                ("group_keys", StringList),
+                ("order_key", String),
+                ("n", U64),
+            ],
+            WorkflowNodeKind::ToggleTalk
+            | WorkflowNodeKind::ParseCoordinate
+            | WorkflowNodeKind::MembershipMatrix => vec![("key", String)],
+            WorkflowNodeKind::ConstraintCheck | WorkflowNodeKind::ExternalIdResolve => {
+                vec![("key", String), ("property", String)]
+            }
+            WorkflowNodeKind::SitelinkResolve => vec![("key", String), ("wiki", String)],
+            WorkflowNodeKind::PageInfo => vec![("key", String)],
+            WorkflowNodeKind::WikidataLabels => vec![("key", String), ("language", StringList)],
+            WorkflowNodeKind::Compute => vec![("name", String), ("expression", String)],
+            WorkflowNodeKind::SubWorkflow => vec![("workflow_id", U64)],
+            WorkflowNodeKind::ChangedSinceLastRun => vec![("key", String), ("column", String)],
+            // QuickStatements batches are not tied to a page
+            WorkflowNodeKind::Generator
+                if self.param_string("mode").ok().as_deref() == Some("quickstatements") =>
+            {
+                vec![("mode", String)]
+            }
+            WorkflowNodeKind::Generator => {
+                vec![("mode", String), ("wiki", String), ("page", String)]
+            }
+            WorkflowNodeKind::FilterColumns
+            | WorkflowNodeKind::FilterDedup
+            | WorkflowNodeKind::Select
+            | WorkflowNodeKind::RowNumber => vec![],
+            #[cfg(test)]
+            WorkflowNodeKind::Mock => vec![],
+        }
+    }
+
+    // Lists every missing or mistyped required parameter, not just the first one
+    pub fn validate_params(&self) -> Result<()> {
+        let mut missing = vec![];
+        let mut errors = vec![];
+        for (key, param_type) in self.required_params() {
+            if !self.parameters.contains_key(key) {
+                missing.push(key);
+                continue;
+            }
+            let checked = match param_type {
+                ParamType::String => self.param_string(key).map(|_| ()),
+                ParamType::U64 => self.param_u64(key).map(|_| ()),
+                ParamType::Number => self.param_f64(key).map(|_| ()),
+                ParamType::Bool => self.param_bool(key).map(|_| ()),
+                ParamType::StringList => self.param_string_list(key).map(|_| ()),
+                ParamType::Any => Ok(()),
+            };
+            if let Err(e) = checked {
+                errors.push(e.to_string());
+            }
+        }
+        if !missing.is_empty() {
+            errors.insert(
+                0,
+                format!(
+                    "{:?} node: missing parameters {}",
+                    self.kind,
+                    missing.join(", ")
+                ),
+            );
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(errors.join("; ")))
+        }
+    }
+
+    // `sandbox` redirects the edit, for testing a workflow without touching the live page:
+    // true or "/sandbox" for a subpage of the target, or the title of a test page
+    fn generator_page(&self) -> Result<String> {
+        let page = self.param_string("page")?;
+        let sandbox = match self.parameters.get("sandbox") {
+            None | Some(Value::Null) => return Ok(page),
+            Some(Value::Bool(false)) => return Ok(page),
+            Some(Value::Bool(true)) => "/sandbox".to_string(),
+            Some(Value::String(s)) => s.trim().to_string(),
+            Some(other) => return Err(self.param_error("sandbox", "boolean or page title", other)),
+        };
+        if sandbox.is_empty() {
+            Ok(page)
+        } else if sandbox.starts_with('/') {
+            Ok(format!("{}{sandbox}", page.trim_end_matches('/')))
+        } else {
+            Ok(sandbox)
+        }
+    }
+
+    fn generator_summary(&self, uuid: &str) -> Result<String> {
+        let template = match self.param_string("summary") {
+            Ok(template) => template,
+            Err(_) => return Ok(DEFAULT_EDIT_SUMMARY.to_string()),
+        };
+        let mut rows = 0;
+        if template.contains("{rows}") {
+            let mut df = DataFile::default();
+            df.open_input_file(uuid)?;
+            df.load_header()?;
+            while df.read_row().is_some() {
+                rows += 1;
+            }
+        }
+        Ok(Generator::edit_summary(&template, rows))
+    }
+
+    // Links are rendered relative to the wiki the page is on
+    fn wikitext_renderer(&self, wiki: &str) -> Result<RendererWikitext> {
+        let mut renderer = RendererWikitext::new_with_default_wiki(wiki);
+        renderer.columns = self.param_string_list("columns").ok();
+        renderer.footer = self.param_bool("footer").unwrap_or(false);
+        if let Ok(number_formats) = self.param("number_formats") {
+            renderer.number_formats =
+                serde_json::from_value(number_formats.to_owned()).map_err(|_| {
+                    self.param_error("number_formats", "formats by column", number_formats)
+                })?;
+        }
+        Ok(renderer)
+    }
+
+    // Whether the workflow needs to supply the previous run's version of this node's slot 0 input
+    pub fn compares_with_previous_run(&self) -> bool {
+        matches!(self.kind, WorkflowNodeKind::ChangedSinceLastRun)
+    }
+
+    pub fn sub_workflow_id(&self) -> Option<usize> {
+        match self.kind {
+            WorkflowNodeKind::SubWorkflow => {
+                self.param_u64("workflow_id").ok().map(|id| id as usize)
+            }
+            _ => None,
+        }
+    }
+
+    // Labels for the "source" column of a union, aligned with `uuids`; `source_labels` are given
+    // in slot order, and `source_column` alone labels rows with their slot number
+    fn source_labels(&self, input: &HashMap<usize, String>, uuids: &[&str]) -> Vec<String> {
+        let names = self.param_string_list("source_labels").unwrap_or_default();
+        if names.is_empty() && !self.param_bool("source_column").unwrap_or(false) {
+            return vec![];
+        }
+        let mut slots: Vec<&usize> = input.keys().collect();
+        slots.sort();
+        let slot_labels: HashMap<&str, String> = slots
+            .iter()
+            .enumerate()
+            .map(|(pos, slot)| {
+                let label = names.get(pos).cloned().unwrap_or_else(|| format!("{slot}"));
+                (input[slot].as_str(), label)
+            })
+            .collect();
+        uuids
+            .iter()
+            .map(|uuid| slot_labels.get(uuid).cloned().unwrap_or_default())
+            .collect()
+    }
+
+    // Input UUIDs ordered by the given slots first, then the remaining ones by slot
+    fn uuids_by_priority<'a>(
+        input: &'a HashMap<usize, String>,
+        priority: &[usize],
+    ) -> Vec<&'a str> {
+        let mut slots: Vec<&usize> = input.keys().collect();
+        slots.sort_by_key(|slot| {
+            let rank = priority
+                .iter()
+                .position(|p| p == *slot)
+                .unwrap_or(priority.len());
+            (rank, **slot)
+        });
+        slots.into_iter().map(|slot| input[slot].as_str()).collect()
+    }
+
+    // Parameter errors end up in the run details, so they say which node and what was given
+    fn param_error(&self, key: &str, expected: &str, value: &Value) -> anyhow::Error {
+        anyhow!(
+            "{:?} node: parameter '{key}' expected {expected}, got {value}",
+            self.kind
+        )
+    }
+
     fn param(&self, key: &str) -> Result<&Value> {
         self.parameters
             .get(key)
-            .ok_or_else(|| anyhow!("Parameter '{key}' not found"))
+            .ok_or_else(|| anyhow!("{:?} node: parameter '{key}' not found", self.kind))
     }
 
     fn param_string(&self, key: &str) -> Result<String> {
-        self.param(key)?
+        let value = self.param(key)?;
+        value
             .as_str()
             .map(|s| s.to_string())
-            .ok_or_else(|| anyhow!("Parameter '{key}' not found"))
+            .ok_or_else(|| self.param_error(key, "string", value))
     }
 
     fn param_u64(&self, key: &str) -> Result<u64> {
-        if let Some(ret) = self.param(key)?.as_u64() {
+        let value = self.param(key)?;
+        if let Some(ret) = value.as_u64() {
+            return Ok(ret);
+        }
+        value
+            .as_str()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .ok_or_else(|| self.param_error(key, "u64", value))
+    }
+
+    fn param_f64(&self, key: &str) -> Result<f64> {
+        let value = self.param(key)?;
+        if let Some(ret) = value.as_f64() {
             return Ok(ret);
         }
-        let ret = self.param(key)?.as_str().map(|s| s.parse::<u64>().ok());
-        let ret = ret.ok_or_else(|| anyhow!("Parameter '{key}' not a str"))?;
-        ret.ok_or_else(|| anyhow!("Parameter '{key}' not a u64"))
+        value
+            .as_str()
+            .and_then(|s| s.trim().parse::<f64>().ok())
+            .ok_or_else(|| self.param_error(key, "number", value))
     }
 
     fn param_bool(&self, key: &str) -> Result<bool> {
-        if let Some(ret) = self.param(key)?.as_bool() {
+        let value = self.param(key)?;
+        if let Some(ret) = value.as_bool() {
             return Ok(ret);
         }
-        if let Ok(ret) = self.param_u64(key) {
-            return Ok(ret > 0);
+        self.param_u64(key)
+            .map(|ret| ret > 0)
+            .map_err(|_| self.param_error(key, "boolean or u64", value))
+    }
+
+    // Accepts either a JSON array of strings/numbers, or a comma-separated string
+    fn param_string_list(&self, key: &str) -> Result<Vec<String>> {
+        if let Some(list) = self.param(key)?.as_array() {
+            return list
+                .iter()
+                .map(|v| match v {
+                    Value::String(s) => Ok(s.trim().to_string()),
+                    Value::Number(n) => Ok(n.to_string()),
+                    _ => Err(self.param_error(key, "list of strings", v)),
+                })
+                .collect();
         }
-        Err(anyhow!("Parameter '{key}' not a boolean or u64"))
+        Ok(self
+            .param_string(key)?
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generator_sandbox_page() {
+        let page = |sandbox: Value| {
+            let node = WorkflowNode {
+                kind: WorkflowNodeKind::Generator,
+                parameters: [
+                    ("page".to_string(), json!("Wikipedia:Report")),
+                    ("sandbox".to_string(), sandbox),
+                ]
+                .into_iter()
+                .collect(),
+                header_mapping: HeaderMapping::default(),
+            };
+            node.generator_page()
+        };
+        assert_eq!(page(json!(false)).unwrap(), "Wikipedia:Report");
+        assert_eq!(page(json!("")).unwrap(), "Wikipedia:Report");
+        assert_eq!(page(json!(true)).unwrap(), "Wikipedia:Report/sandbox");
+        assert_eq!(page(json!("/test")).unwrap(), "Wikipedia:Report/test");
+        assert_eq!(page(json!("User:Me/Test")).unwrap(), "User:Me/Test");
+        assert!(page(json!(1)).is_err());
+    }
+
+    #[test]
+    fn test_param_error_mentions_node_and_value() {
+        let node = WorkflowNode {
+            kind: WorkflowNodeKind::PetScan,
+            parameters: [("psid".to_string(), json!("abc"))].into_iter().collect(),
+            header_mapping: HeaderMapping::default(),
+        };
+        let error = node.param_u64("psid").unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "PetScan node: parameter 'psid' expected u64, got \"abc\""
+        );
+        let error = node.param_string("depth").unwrap_err().to_string();
+        assert_eq!(error, "PetScan node: parameter 'depth' not found");
+    }
+
+    #[test]
+    fn test_validate_params() {
+        let node = |kind: WorkflowNodeKind, parameters: Value| WorkflowNode {
+            kind,
+            parameters: serde_json::from_value(parameters).unwrap(),
+            header_mapping: HeaderMapping::default(),
+        };
+        assert!(node(WorkflowNodeKind::PetScan, json!({"psid": "123"}))
+            .validate_params()
+            .is_ok());
+        let error = node(WorkflowNodeKind::Filter, json!({"operator": "Equal"}))
+            .validate_params()
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "Filter node: missing parameters key, value");
+        let error = node(
+            WorkflowNodeKind::FilterPetScan,
+            json!({"key": "page", "psid": "abc"}),
+        )
+        .validate_params()
+        .unwrap_err()
+        .to_string();
+        assert_eq!(
+            error,
+            "FilterPetScan node: parameter 'psid' expected u64, got \"abc\""
+        );
+        for language in [json!("de|en"), json!(["de", "en"])] {
+            assert!(node(
+                WorkflowNodeKind::WikidataLabels,
+                json!({"key": "item", "language": language})
+            )
+            .validate_params()
+            .is_ok());
+        }
+    }
+
+    #[test]
+    fn test_filter_distinct_alias() {
+        let kind: WorkflowNodeKind = serde_json::from_value(json!("FilterDistinct")).unwrap();
+        assert!(matches!(kind, WorkflowNodeKind::FilterDedup));
+    }
+
+    #[test]
+    fn test_uuids_by_priority() {
+        let input: HashMap<usize, String> = [(0, "a"), (1, "b"), (2, "c")]
+            .into_iter()
+            .map(|(slot, uuid)| (slot, uuid.to_string()))
+            .collect();
+        assert_eq!(
+            WorkflowNode::uuids_by_priority(&input, &[2]),
+            vec!["c", "a", "b"]
+        );
     }
 }
@@ -3,6 +3,7 @@ use anyhow::{anyhow, Result};
 use mysql_async::{from_row, params, prelude::*, Conn};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tracing::{debug, warn};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum WorkflowNodeStatusValue {
@@ -33,6 +34,8 @@ pub struct WorkflowNodeStatus {
     is_output_node: bool,
     #[serde(default)]
     error: Option<String>,
+    #[serde(default)]
+    rows_processed: usize,
 }
 
 impl WorkflowNodeStatus {
@@ -43,6 +46,7 @@ impl WorkflowNodeStatus {
             uuid: String::new(),
             is_output_node: false,
             error: None,
+            rows_processed: 0,
         }
     }
 
@@ -55,6 +59,14 @@ impl WorkflowNodeStatus {
         &self.uuid
     }
 
+    pub fn rows_processed(&self) -> usize {
+        self.rows_processed
+    }
+
+    pub fn set_rows_processed(&mut self, rows: usize) {
+        self.rows_processed = rows;
+    }
+
     pub fn set_status(&mut self, status: WorkflowNodeStatusValue, error: Option<String>) {
         self.status = status;
         self.error = error;
@@ -84,16 +96,22 @@ pub struct WorkflowRun {
 
 impl WorkflowRun {
     pub fn new(workflow: &Workflow) -> Self {
-        let mut ret = Self::default();
-        ret.workflow_id = workflow.id;
-        ret.nodes_total = workflow.nodes.len();
-        ret.edges = workflow.edges.to_owned();
+        let mut ret = Self {
+            workflow_id: workflow.id,
+            nodes_total: workflow.nodes.len(),
+            edges: workflow.edges.to_owned(),
+            ..Default::default()
+        };
         let node_ids = ret.get_all_node_ids(workflow);
         let output_node_ids = ret.get_output_nodes(workflow);
         ret.node_ids2status(&node_ids, &output_node_ids);
         ret
     }
 
+    pub fn id(&self) -> Option<u64> {
+        self.id
+    }
+
     pub fn set_id(&mut self, id: u64) {
         self.id = Some(id);
     }
@@ -137,7 +155,7 @@ impl WorkflowRun {
             .collect()
     }
 
-    fn node_ids2status(&mut self, node_ids: &Vec<usize>, output_node_ids: &Vec<usize>) {
+    fn node_ids2status(&mut self, node_ids: &[usize], output_node_ids: &[usize]) {
         self.node_status = node_ids
             .iter()
             .map(|node_id| WorkflowNodeStatus::new(*node_id))
@@ -150,10 +168,10 @@ impl WorkflowRun {
 
     async fn create_new_id(&mut self) -> Result<()> {
         let details = json!(self.node_status).to_string();
-        let sql = "INSERT INTO `run` (`status`,`workflow_id`,`ts_created`,`ts_last`,`nodes_total`,`details`) VALUES (':status',:workflow_id,NOW(),NOW(),:nodes_total,:details)";
+        let sql = "INSERT INTO `run` (`status`,`workflow_id`,`ts_created`,`ts_last`,`ts_started`,`nodes_total`,`details`) VALUES (':status',:workflow_id,NOW(),NOW(),NOW(),:nodes_total,:details)";
         let params = params! {"status" => WorkflowNodeStatusValue::RUNNING.as_str(), "workflow_id" => self.workflow_id , "nodes_total" => self.nodes_total, details};
         let mut conn = APP.get_db_connection().await?;
-        conn.exec_drop(&sql, params)
+        conn.exec_drop(sql, params)
             .await
             .map_err(|e| format!("{:?}", e))
             .map_err(|e| anyhow!("{e}"))?;
@@ -171,7 +189,7 @@ impl WorkflowRun {
         match self.id {
             Some(id) => Ok(id),
             None => {
-                let _ = self.create_new_id().await?;
+                self.create_new_id().await?;
                 let id = self.id.ok_or_else(|| {
                     anyhow!(
                         "Could not create a run ID for workflow {}",
@@ -185,7 +203,8 @@ impl WorkflowRun {
 
     pub async fn load_status(&mut self) -> Result<()> {
         let run_id = self.get_or_create_id().await?;
-        let sql = "SELECT `uuid`,`node_id` FROM `file` WHERE `run_id`=:run_id";
+        // Snapshots kept from the last finished execution are not part of this one
+        let sql = "SELECT `uuid`,`node_id` FROM `file` WHERE `run_id`=:run_id AND NOT (`is_output`=0 AND `expires` IS NULL)";
         let results: Vec<(String, usize)> = APP
             .get_db_connection()
             .await?
@@ -197,8 +216,7 @@ impl WorkflowRun {
             let ns = self
                 .node_status
                 .iter_mut()
-                .filter(|ns| ns.node_id == node_id)
-                .next()
+                .find(|ns| ns.node_id == node_id)
                 .ok_or_else(|| {
                     anyhow!("More nodes in files that in node_status for run {run_id}")
                 })?;
@@ -268,10 +286,32 @@ impl WorkflowRun {
             .any(|node_status| node_status.is_failed())
     }
 
+    // All output nodes are done, and no node has failed
     pub fn has_completed_succesfully(&self) -> bool {
-        self.node_status
-            .iter()
-            .any(|node_status| node_status.is_done())
+        !self.has_failed()
+            && self
+                .node_status
+                .iter()
+                .filter(|node_status| node_status.is_output_node)
+                .all(|node_status| node_status.is_done())
+    }
+
+    // Best-effort; runs without an ID (tests) are not in the database, so they can not be cancelled
+    pub async fn poll_cancelled(&mut self) -> bool {
+        if self.id.is_none() {
+            return false;
+        }
+        let cancelled = match APP.get_db_connection().await {
+            Ok(mut conn) => self.is_cancelled(&mut conn).await,
+            Err(e) => Err(e),
+        };
+        match cancelled {
+            Ok(cancelled) => cancelled,
+            Err(e) => {
+                warn!(run_id = self.id, error = %e, "Could not check for run cancellation");
+                false
+            }
+        }
     }
 
     pub async fn is_cancelled(&mut self, conn: &mut Conn) -> Result<bool> {
@@ -285,6 +325,52 @@ impl WorkflowRun {
             .is_empty())
     }
 
+    // Run duration in seconds; up to now if the run has not ended yet
+    pub async fn get_duration(&self, conn: &mut Conn) -> Result<Option<u64>> {
+        let run_id = self
+            .id
+            .ok_or_else(|| anyhow!("WorkflowRun::get_duration: No ID set"))?;
+        let sql = "SELECT TIMESTAMPDIFF(SECOND,`ts_started`,IFNULL(`ts_ended`,NOW())) FROM `run` WHERE `id`=? AND `ts_started` IS NOT NULL";
+        Ok(sql
+            .with((run_id,))
+            .map(conn, |duration: u64| duration)
+            .await?
+            .pop())
+    }
+
+    // Timestamps are set before `status`, as MySQL evaluates SET assignments left to right
+    fn status_timestamps(status: &WorkflowNodeStatusValue) -> &'static str {
+        match status {
+            WorkflowNodeStatusValue::RUNNING => {
+                "`ts_started`=IF(`status`='RUN',`ts_started`,NOW()),`ts_ended`=NULL,"
+            }
+            WorkflowNodeStatusValue::DONE => "`ts_ended`=NOW(),`ts_last_success`=`ts_started`,",
+            WorkflowNodeStatusValue::FAILED | WorkflowNodeStatusValue::CANCEL => {
+                "`ts_ended`=NOW(),"
+            }
+            WorkflowNodeStatusValue::WAITING => "",
+        }
+    }
+
+    // Only while the run is running, so a cancellation by the user is not overwritten
+    pub async fn update_progress(&self, conn: &mut Conn) -> Result<()> {
+        let run_id = self
+            .id
+            .ok_or_else(|| anyhow!("WorkflowRun::update_progress: No ID set"))?;
+        let details = json!(self.node_status).to_string();
+        let nodes_done = self.node_status.iter().filter(|ns| ns.is_done()).count();
+        "UPDATE `run` SET `nodes_done`=?,`details`=? WHERE `id`=? AND `status`=?"
+            .with((
+                nodes_done,
+                details,
+                run_id,
+                WorkflowNodeStatusValue::RUNNING.as_str(),
+            ))
+            .run(conn)
+            .await?;
+        Ok(())
+    }
+
     pub async fn update_status(
         &self,
         status: WorkflowNodeStatusValue,
@@ -295,13 +381,75 @@ impl WorkflowRun {
             .ok_or_else(|| anyhow!("WorkflowRun::is_cancelled: No ID set"))?;
         let details = json!(self.node_status).to_string();
         let nodes_done = self.node_status.iter().filter(|ns| ns.is_done()).count();
-        let sql ="UPDATE `run` SET `status`=:status,`nodes_done`=:nodes_done,`details`=:details WHERE `id`=:run_id";
+        let timestamps = Self::status_timestamps(&status);
+        let sql = format!("UPDATE `run` SET {timestamps}`status`=:status,`nodes_done`=:nodes_done,`details`=:details WHERE `id`=:run_id");
         conn.exec_drop(
-            sql,
+            &sql,
             params!("status" => status.as_str(), nodes_done, "details" => &details, run_id),
         )
         .await?;
-        println!("Workflow {} Run {:?}: {details}", self.workflow_id, self.id);
+        debug!(
+            workflow_id = self.workflow_id,
+            run_id,
+            status = status.as_str(),
+            nodes_done,
+            details,
+            "Updated run status"
+        );
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflow_node::WorkflowNode;
+
+    fn filter_node() -> WorkflowNode {
+        serde_json::from_value(
+            json!({"kind": "Filter", "parameters": {}, "header_mapping": {"data": []}}),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_has_completed_succesfully_needs_all_output_nodes() {
+        // Node 0 feeds node 1; nodes 1 and 2 are outputs
+        let edges = vec![WorkflowEdge {
+            source_node: 0,
+            target_node: 1,
+            target_slot: 0,
+        }];
+        let workflow = Workflow::new(vec![filter_node(), filter_node(), filter_node()], edges, 0);
+        let mut run = WorkflowRun::new(&workflow);
+        assert!(!run.has_completed_succesfully());
+
+        run.get_node_status_mut(0).done_with_uuid("a");
+        run.get_node_status_mut(1).done_with_uuid("b");
+        assert!(!run.has_completed_succesfully());
+        assert!(!run.has_ended());
+
+        run.get_node_status_mut(2).done_with_uuid("c");
+        assert!(run.has_completed_succesfully());
+
+        run.get_node_status_mut(0)
+            .set_status(WorkflowNodeStatusValue::FAILED, None);
+        assert!(!run.has_completed_succesfully());
+    }
+
+    #[test]
+    fn test_status_timestamps_keep_last_success_on_rerun() {
+        let last_success = "`ts_last_success`=`ts_started`";
+        assert!(
+            WorkflowRun::status_timestamps(&WorkflowNodeStatusValue::DONE).contains(last_success)
+        );
+        for status in [
+            WorkflowNodeStatusValue::WAITING,
+            WorkflowNodeStatusValue::RUNNING,
+            WorkflowNodeStatusValue::FAILED,
+            WorkflowNodeStatusValue::CANCEL,
+        ] {
+            assert!(!WorkflowRun::status_timestamps(&status).contains("ts_last_success"));
+        }
+    }
+}